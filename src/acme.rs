@@ -0,0 +1,606 @@
+//! ACME (RFC 8555) certificates for `[server.tls.acme]`. The account key
+//! and certificate live in `cache_dir`; a certificate is requested when none
+//! is cached and renewed `renew_before_days` before it expires, each domain
+//! proven with a TLS-ALPN-01 or HTTP-01 challenge that this server answers.
+
+mod der;
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fs;
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::Engine as _;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use bytes::Bytes;
+use http_body_util::{BodyExt as _, Full};
+use hyper::body::Incoming;
+use hyper::header::{CONTENT_TYPE, LOCATION};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use ring::digest::{SHA256, digest};
+use ring::rand::SystemRandom;
+use ring::signature::{
+    ECDSA_P256_SHA256_ASN1_SIGNING, ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair as _,
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::crypto::CryptoProvider;
+use tokio_rustls::rustls::pki_types::PrivateKeyDer;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tracing::{debug, info, warn};
+
+use crate::backend::remote;
+use crate::config::{AcmeChallenge, AcmeConfig};
+use crate::tls;
+
+type PostClient = Client<HttpsConnector<HttpConnector>, Full<Bytes>>;
+
+/// ALPN protocol of TLS-ALPN-01 validation handshakes.
+pub(crate) const ALPN: &[u8] = b"acme-tls/1";
+
+/// Wait after a failed request before trying again.
+const RETRY_DELAY: Duration = Duration::from_secs(3600);
+
+/// Pause between polls of a pending authorization or order, and how many.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: u32 = 30;
+
+/// The ACME certificate and the challenges being answered for it.
+pub struct Acme {
+    cfg: AcmeConfig,
+    provider: Arc<CryptoProvider>,
+    /// The current certificate and when it expires.
+    cert: RwLock<Option<(Arc<CertifiedKey>, SystemTime)>>,
+    /// TLS-ALPN-01 certificates by domain, while their challenge is pending.
+    alpn_certs: Mutex<HashMap<String, Arc<CertifiedKey>>>,
+    /// HTTP-01 key authorizations by token.
+    http_tokens: Mutex<HashMap<String, String>>,
+}
+
+impl Acme {
+    /// Load the cached certificate, if any; a missing or unreadable one is
+    /// requested once [`start`] runs.
+    pub(crate) fn new(cfg: &AcmeConfig, provider: Arc<CryptoProvider>) -> Self {
+        let acme = Self {
+            cfg: cfg.clone(),
+            provider,
+            cert: RwLock::new(None),
+            alpn_certs: Mutex::new(HashMap::new()),
+            http_tokens: Mutex::new(HashMap::new()),
+        };
+        let (cert, key) = acme.cert_paths();
+        if cert.exists() {
+            match acme.install(&cert, &key) {
+                Ok(expires) => {
+                    let expires = httpdate::fmt_http_date(expires);
+                    info!(cert = %cert.display(), %expires, "ACME certificate loaded");
+                }
+                Err(e) => warn!(error = %e, "cannot load cached ACME certificate; requesting anew"),
+            }
+        }
+        acme
+    }
+
+    /// The certificate for `name` when it is one of the ACME domains, or
+    /// for any name when `any` is set.
+    pub(crate) fn cert_for(&self, name: Option<&str>, any: bool) -> Option<Arc<CertifiedKey>> {
+        let ours = name.is_some_and(|name| {
+            let name = name.trim_end_matches('.');
+            self.cfg
+                .domains
+                .iter()
+                .any(|d| d.eq_ignore_ascii_case(name))
+        });
+        if !ours && !any {
+            return None;
+        }
+        let cert = self.cert.read().unwrap();
+        cert.as_ref().map(|(key, _)| key.clone())
+    }
+
+    /// The TLS-ALPN-01 certificate for a pending challenge on `name`.
+    pub(crate) fn challenge_cert(&self, name: &str) -> Option<Arc<CertifiedKey>> {
+        let certs = self.alpn_certs.lock().unwrap();
+        certs.get(&name.to_ascii_lowercase()).cloned()
+    }
+
+    /// `cert-<first domain>-<hash>.pem` and its `.key`, the hash covering
+    /// the directory and every domain so a changed list gets a new file.
+    fn cert_paths(&self) -> (PathBuf, PathBuf) {
+        let mut names = self.cfg.domains.join("\n").to_ascii_lowercase();
+        names.push('\n');
+        names.push_str(&self.cfg.directory);
+        let hash = digest(&SHA256, names.as_bytes());
+        let hex: String = hash.as_ref()[..4]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        let stem = format!("cert-{}-{hex}", self.cfg.domains[0].to_ascii_lowercase());
+        let dir = &self.cfg.cache_dir;
+        (
+            dir.join(format!("{stem}.pem")),
+            dir.join(format!("{stem}.key")),
+        )
+    }
+
+    /// Serve the certificate in `cert`/`key` from now on.
+    fn install(&self, cert: &Path, key: &Path) -> Result<SystemTime, String> {
+        let key = tls::load(cert, key, &self.provider)?;
+        let leaf = key.end_entity_cert().map_err(|e| e.to_string())?;
+        let expires = der::not_after(leaf).ok_or("cannot read the certificate's expiry")?;
+        *self.cert.write().unwrap() = Some((Arc::new(key), expires));
+        Ok(expires)
+    }
+
+    /// When to next request a certificate: now when there is none.
+    fn renew_at(&self) -> SystemTime {
+        let days = Duration::from_secs(self.cfg.renew_before_days * 86_400);
+        let cert = self.cert.read().unwrap();
+        cert.as_ref()
+            .and_then(|(_, expires)| expires.checked_sub(days))
+            .unwrap_or(UNIX_EPOCH)
+    }
+
+    /// Answer the challenge for `domain` until the returned guard drops.
+    fn arm(&self, domain: &str, token: &str, key_auth: &str) -> Result<Armed<'_>, String> {
+        let domain = domain.to_ascii_lowercase();
+        match self.cfg.challenge {
+            AcmeChallenge::TlsAlpn01 => {
+                let hash = digest(&SHA256, key_auth.as_bytes());
+                let (pkcs8, key) = generate_key(&ECDSA_P256_SHA256_ASN1_SIGNING)?;
+                let cert = der::challenge_cert(&domain, hash.as_ref(), &key)?;
+                // Not `from_der`: its key check rejects the critical
+                // acmeIdentifier extension.
+                let key = PrivateKeyDer::Pkcs8(pkcs8.into());
+                let key = self.provider.key_provider.load_private_key(key);
+                let cert = CertifiedKey::new(vec![cert.into()], key.map_err(|e| e.to_string())?);
+                let mut certs = self.alpn_certs.lock().unwrap();
+                certs.insert(domain.clone(), Arc::new(cert));
+            }
+            AcmeChallenge::Http01 => {
+                let mut tokens = self.http_tokens.lock().unwrap();
+                tokens.insert(token.to_owned(), key_auth.to_owned());
+            }
+        }
+        Ok(Armed {
+            acme: self,
+            domain,
+            token: token.to_owned(),
+        })
+    }
+
+    /// Plain-HTTP answer to `path`: the key authorization for a pending
+    /// HTTP-01 token, 404 for anything else.
+    fn http01_response(&self, path: &str) -> Response<Full<Bytes>> {
+        let token = path.strip_prefix("/.well-known/acme-challenge/");
+        let key_auth = token.and_then(|t| self.http_tokens.lock().unwrap().get(t).cloned());
+        let (status, body) = match key_auth {
+            Some(key_auth) => (StatusCode::OK, key_auth),
+            None => (StatusCode::NOT_FOUND, "Not Found".to_owned()),
+        };
+        let mut resp = Response::new(Full::new(body.into()));
+        *resp.status_mut() = status;
+        let text = hyper::header::HeaderValue::from_static("text/plain");
+        resp.headers_mut().insert(CONTENT_TYPE, text);
+        resp
+    }
+}
+
+/// A challenge being answered; withdrawn on drop.
+struct Armed<'a> {
+    acme: &'a Acme,
+    domain: String,
+    token: String,
+}
+
+impl Drop for Armed<'_> {
+    fn drop(&mut self) {
+        self.acme.alpn_certs.lock().unwrap().remove(&self.domain);
+        self.acme.http_tokens.lock().unwrap().remove(&self.token);
+    }
+}
+
+/// Start answering HTTP-01 challenges on `http_bind` (when that is the
+/// challenge type) and spawn the task that keeps the certificate current.
+/// Call before dropping privileges so port 80 can be bound.
+pub async fn start(acme: Arc<Acme>) -> io::Result<()> {
+    if acme.cfg.challenge == AcmeChallenge::Http01 {
+        let listener = TcpListener::bind(&acme.cfg.http_bind).await?;
+        info!(addr = %acme.cfg.http_bind, "ACME HTTP-01 listener started");
+        tokio::spawn(serve_http01(listener, acme.clone()));
+    }
+    let client: PostClient = Client::builder(TokioExecutor::new()).build(remote::connector());
+    tokio::spawn(async move {
+        loop {
+            let now = SystemTime::now();
+            let wait = acme.renew_at().duration_since(now).unwrap_or_default();
+            tokio::time::sleep(wait).await;
+            match issue(&acme, &client).await {
+                Ok(expires) => {
+                    let expires = httpdate::fmt_http_date(expires);
+                    info!(domains = ?acme.cfg.domains, %expires, "ACME certificate issued");
+                }
+                Err(e) => {
+                    warn!(error = %e, "ACME certificate request failed; retrying in an hour");
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn serve_http01(listener: TcpListener, acme: Arc<Acme>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                debug!(error = %e, "ACME HTTP-01 accept failed");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let acme = acme.clone();
+        tokio::spawn(async move {
+            let svc = service_fn(move |req: Request<Incoming>| {
+                let resp = acme.http01_response(req.uri().path());
+                async move { Ok::<_, Infallible>(resp) }
+            });
+            let io = TokioIo::new(stream);
+            if let Err(e) = http1::Builder::new().serve_connection(io, svc).await {
+                debug!(error = %e, "ACME HTTP-01 connection ended");
+            }
+        });
+    }
+}
+
+/// Order a certificate for every domain, prove control of each, and
+/// install the result. Returns when it expires.
+async fn issue(acme: &Acme, client: &PostClient) -> Result<SystemTime, String> {
+    let cfg = &acme.cfg;
+    fs::create_dir_all(&cfg.cache_dir).map_err(|e| format!("{}: {e}", cfg.cache_dir.display()))?;
+    let account_key = account_key(&cfg.cache_dir.join("account.key"))?;
+    let mut session = Session::open(client, cfg, account_key).await?;
+
+    let identifiers: Vec<Value> = cfg
+        .domains
+        .iter()
+        .map(|d| json!({ "type": "dns", "value": d }))
+        .collect();
+    let new_order = session.directory.new_order.clone();
+    let reply = session
+        .post(&new_order, Some(&json!({ "identifiers": identifiers })))
+        .await?;
+    let order_url = reply.location.ok_or("new order has no Location")?;
+    let order: Order = parse(&reply.body)?;
+    for authorization in &order.authorizations {
+        session.authorize(acme, authorization).await?;
+    }
+
+    let order: Order = parse(&session.poll(&order_url, &["pending"]).await?)?;
+    if order.status != "ready" {
+        return Err(format!("order is {} after authorization", order.status));
+    }
+    let (pkcs8, key) = generate_key(&ECDSA_P256_SHA256_ASN1_SIGNING)?;
+    let csr = URL_SAFE_NO_PAD.encode(der::csr(&cfg.domains, &key)?);
+    session
+        .post(&order.finalize, Some(&json!({ "csr": csr })))
+        .await?;
+    let order: Order = parse(&session.poll(&order_url, &["ready", "processing"]).await?)?;
+    let certificate = match (order.status.as_str(), order.certificate) {
+        ("valid", Some(url)) => url,
+        (status, _) => return Err(format!("order is {status} after finalizing")),
+    };
+    let chain = session.post(&certificate, None).await?.body;
+
+    let (cert_path, key_path) = acme.cert_paths();
+    write_private(&key_path, pem("PRIVATE KEY", pkcs8.as_ref()).as_bytes())?;
+    write_private(&cert_path, &chain)?;
+    acme.install(&cert_path, &key_path)
+}
+
+/// The account key in `path`, generated and saved on first use.
+fn account_key(path: &Path) -> Result<EcdsaKeyPair, String> {
+    let alg = &ECDSA_P256_SHA256_FIXED_SIGNING;
+    if !path.exists() {
+        let (pkcs8, key) = generate_key(alg)?;
+        write_private(path, pem("PRIVATE KEY", &pkcs8).as_bytes())?;
+        info!(path = %path.display(), "ACME account key created");
+        return Ok(key);
+    }
+    let failed = |e: &dyn std::fmt::Display| format!("{}: {e}", path.display());
+    let pkcs8 = match PrivateKeyDer::from_pem_file(path).map_err(|e| failed(&e))? {
+        PrivateKeyDer::Pkcs8(key) => key,
+        _ => return Err(failed(&"not a PKCS#8 key")),
+    };
+    let rng = SystemRandom::new();
+    EcdsaKeyPair::from_pkcs8(alg, pkcs8.secret_pkcs8_der(), &rng).map_err(|e| failed(&e))
+}
+
+/// A new P-256 key: its PKCS#8 document and the pair for signing.
+fn generate_key(
+    alg: &'static ring::signature::EcdsaSigningAlgorithm,
+) -> Result<(Vec<u8>, EcdsaKeyPair), String> {
+    let rng = SystemRandom::new();
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(alg, &rng).map_err(|_| "cannot generate a key")?;
+    let key = EcdsaKeyPair::from_pkcs8(alg, pkcs8.as_ref(), &rng).map_err(|e| e.to_string())?;
+    Ok((pkcs8.as_ref().to_vec(), key))
+}
+
+/// Write `contents` readable by the owner only, replacing `path` at once.
+fn write_private(path: &Path, contents: &[u8]) -> Result<(), String> {
+    let tmp = path.with_extension("tmp");
+    let mut opts = fs::OpenOptions::new();
+    opts.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut opts, 0o600);
+    let written = opts
+        .open(&tmp)
+        .and_then(|mut file| file.write_all(contents))
+        .and_then(|()| fs::rename(&tmp, path));
+    written.map_err(|e| format!("{}: {e}", path.display()))
+}
+
+fn pem(label: &str, der: &[u8]) -> String {
+    let base64 = STANDARD.encode(der);
+    let mut out = format!("-----BEGIN {label}-----\n");
+    for line in base64.as_bytes().chunks(64) {
+        out.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+        out.push('\n');
+    }
+    out.push_str(&format!("-----END {label}-----\n"));
+    out
+}
+
+fn parse<'a, T: Deserialize<'a>>(body: &'a [u8]) -> Result<T, String> {
+    serde_json::from_slice(body).map_err(|e| format!("unexpected ACME response: {e}"))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Order {
+    status: String,
+    #[serde(default)]
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Authorization {
+    status: String,
+    identifier: Identifier,
+    #[serde(default)]
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    #[serde(default)]
+    token: String,
+    error: Option<Problem>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Problem {
+    #[serde(default, rename = "type")]
+    kind: String,
+    #[serde(default)]
+    detail: String,
+}
+
+#[derive(Deserialize)]
+struct Status {
+    status: String,
+}
+
+/// A response from the CA.
+struct Reply {
+    location: Option<String>,
+    body: Bytes,
+}
+
+/// Requests to the CA, signed with the account key.
+struct Session<'a> {
+    client: &'a PostClient,
+    timeout: Duration,
+    key: EcdsaKeyPair,
+    /// The account key as a JWK, until the account URL is known.
+    jwk: Value,
+    /// Base64url SHA-256 of the JWK (RFC 7638), for key authorizations.
+    thumbprint: String,
+    directory: Directory,
+    account: Option<String>,
+    nonce: Option<String>,
+}
+
+impl<'a> Session<'a> {
+    /// Fetch the directory and register the account (or look up the one
+    /// already registered for the key).
+    async fn open(
+        client: &'a PostClient,
+        cfg: &AcmeConfig,
+        key: EcdsaKeyPair,
+    ) -> Result<Self, String> {
+        let point = key.public_key().as_ref();
+        let (x, y) = (
+            URL_SAFE_NO_PAD.encode(&point[1..33]),
+            URL_SAFE_NO_PAD.encode(&point[33..]),
+        );
+        // Members in lexicographic order, no whitespace, as RFC 7638 hashes it.
+        let canonical = format!(r#"{{"crv":"P-256","kty":"EC","x":"{x}","y":"{y}"}}"#);
+        let thumbprint = URL_SAFE_NO_PAD.encode(digest(&SHA256, canonical.as_bytes()));
+        let mut session = Self {
+            client,
+            timeout: Duration::from_millis(cfg.timeout_ms),
+            key,
+            jwk: json!({ "crv": "P-256", "kty": "EC", "x": x, "y": y }),
+            thumbprint,
+            directory: Directory::default(),
+            account: None,
+            nonce: None,
+        };
+        let req = Request::get(&cfg.directory).body(Full::default());
+        let reply = session.send(req.map_err(|e| e.to_string())?).await?;
+        session.directory = parse(&reply.body)?;
+
+        let account = json!({ "termsOfServiceAgreed": true, "contact": cfg.contact });
+        let new_account = session.directory.new_account.clone();
+        let reply = session.post(&new_account, Some(&account)).await?;
+        session.account = Some(reply.location.ok_or("new account has no Location")?);
+        Ok(session)
+    }
+
+    /// Answer the challenge of one authorization and wait for the CA to
+    /// validate it.
+    async fn authorize(&mut self, acme: &Acme, url: &str) -> Result<(), String> {
+        let authz: Authorization = parse(&self.post(url, None).await?.body)?;
+        if authz.status == "valid" {
+            return Ok(());
+        }
+        let domain = authz.identifier.value;
+        let kind = acme.cfg.challenge.name();
+        let challenge = authz
+            .challenges
+            .into_iter()
+            .find(|c| c.kind == kind)
+            .ok_or_else(|| format!("{domain}: the CA offers no {kind} challenge"))?;
+        let key_auth = format!("{}.{}", challenge.token, self.thumbprint);
+        let _armed = acme.arm(&domain, &challenge.token, &key_auth)?;
+        self.post(&challenge.url, Some(&json!({}))).await?;
+        debug!(domain, challenge = kind, "ACME challenge ready");
+
+        let authz: Authorization = parse(&self.poll(url, &["pending"]).await?)?;
+        if authz.status == "valid" {
+            return Ok(());
+        }
+        let challenge = authz.challenges.into_iter().find(|c| c.kind == kind);
+        let problem = challenge.and_then(|c| c.error).unwrap_or_default();
+        Err(format!(
+            "{domain}: authorization {}: {}",
+            authz.status, problem.detail
+        ))
+    }
+
+    /// POST-as-GET `url` until its status leaves `waiting`.
+    async fn poll(&mut self, url: &str, waiting: &[&str]) -> Result<Bytes, String> {
+        for _ in 0..POLL_ATTEMPTS {
+            let body = self.post(url, None).await?.body;
+            let status: Status = parse(&body)?;
+            if !waiting.contains(&status.status.as_str()) {
+                return Ok(body);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        Err(format!("{url} still pending after {POLL_ATTEMPTS} polls"))
+    }
+
+    /// POST `payload` (`None`: POST-as-GET) to `url` as a JWS, retrying
+    /// once when the CA rejects the nonce.
+    async fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<Reply, String> {
+        let mut retried = false;
+        loop {
+            let body = self.sign(url, payload).await?;
+            let req = Request::post(url)
+                .header(CONTENT_TYPE, "application/jose+json")
+                .body(Full::new(body.into()))
+                .map_err(|e| e.to_string())?;
+            match self.send(req).await {
+                Err(e) if e.ends_with(":badNonce)") && !retried => retried = true,
+                result => return result,
+            }
+        }
+    }
+
+    async fn sign(&mut self, url: &str, payload: Option<&Value>) -> Result<Vec<u8>, String> {
+        let nonce = match self.nonce.take() {
+            Some(nonce) => nonce,
+            None => {
+                let req = Request::head(&self.directory.new_nonce).body(Full::default());
+                self.send(req.map_err(|e| e.to_string())?).await?;
+                self.nonce.take().ok_or("newNonce sent no Replay-Nonce")?
+            }
+        };
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match &self.account {
+            Some(kid) => protected["kid"] = kid.as_str().into(),
+            None => protected["jwk"] = self.jwk.clone(),
+        }
+        let protected = URL_SAFE_NO_PAD.encode(protected.to_string());
+        let payload = payload.map_or(String::new(), |p| URL_SAFE_NO_PAD.encode(p.to_string()));
+        let signing_input = format!("{protected}.{payload}");
+        let signature = self
+            .key
+            .sign(&SystemRandom::new(), signing_input.as_bytes())
+            .map_err(|_| "cannot sign with the account key")?;
+        let signature = URL_SAFE_NO_PAD.encode(signature.as_ref());
+        let jws = json!({ "protected": protected, "payload": payload, "signature": signature });
+        Ok(jws.to_string().into_bytes())
+    }
+
+    /// Send `req`, keeping the next nonce. A non-2xx answer is an error
+    /// ending in the ACME problem type, e.g. `(urn:ietf:params:acme:error:badNonce)`.
+    async fn send(&mut self, req: Request<Full<Bytes>>) -> Result<Reply, String> {
+        let url = req.uri().to_string();
+        let resp = match tokio::time::timeout(self.timeout, self.client.request(req)).await {
+            Ok(Ok(resp)) => resp,
+            Ok(Err(e)) => return Err(format!("{url}: {e}")),
+            Err(_) => {
+                return Err(format!(
+                    "{url}: timed out after {}ms",
+                    self.timeout.as_millis()
+                ));
+            }
+        };
+        let header = |name| resp.headers().get(name)?.to_str().ok().map(str::to_owned);
+        if let Some(nonce) = header("replay-nonce") {
+            self.nonce = Some(nonce);
+        }
+        let location = header(LOCATION.as_str());
+        let status = resp.status();
+        let body = match tokio::time::timeout(self.timeout, resp.into_body().collect()).await {
+            Ok(Ok(body)) => body.to_bytes(),
+            Ok(Err(e)) => return Err(format!("{url}: {e}")),
+            Err(_) => {
+                return Err(format!(
+                    "{url}: timed out after {}ms",
+                    self.timeout.as_millis()
+                ));
+            }
+        };
+        if !status.is_success() {
+            let problem: Problem = serde_json::from_slice(&body).unwrap_or_default();
+            return Err(format!(
+                "{url} answered {status}: {} ({})",
+                problem.detail, problem.kind
+            ));
+        }
+        Ok(Reply { location, body })
+    }
+}
@@ -0,0 +1,212 @@
+//! Just enough DER for ACME: the CSR finalizing an order, the TLS-ALPN-01
+//! challenge certificate, and the expiry of an issued certificate.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair as _};
+
+const BOOLEAN: u8 = 0x01;
+const INTEGER: u8 = 0x02;
+const BIT_STRING: u8 = 0x03;
+const OCTET_STRING: u8 = 0x04;
+const OID: u8 = 0x06;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+/// `[0]`, `[3]`: certificate version and extensions, CSR attributes.
+const CONTEXT_0: u8 = 0xa0;
+const CONTEXT_3: u8 = 0xa3;
+/// `dNSName` in a GeneralName.
+const DNS_NAME: u8 = 0x82;
+
+// 1.2.840.10045.2.1, 1.2.840.10045.3.1.7, 1.2.840.10045.4.3.2
+const EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const PRIME256V1: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+// 1.2.840.113549.1.9.14
+const EXTENSION_REQUEST: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x0e];
+// 2.5.29.17
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+// 1.3.6.1.5.5.7.1.31 (RFC 8737)
+const ACME_IDENTIFIER: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x1f];
+
+/// A PKCS#10 request for `domains`, signed with `key` (an
+/// `ECDSA_P256_SHA256_ASN1_SIGNING` pair). The subject is left empty; CAs
+/// take the names from the subjectAltName extension.
+pub(super) fn csr(domains: &[String], key: &EcdsaKeyPair) -> Result<Vec<u8>, String> {
+    let extensions = seq(&[&subject_alt_name(domains)]);
+    let request = seq(&[&tlv(OID, EXTENSION_REQUEST), &tlv(SET, &extensions)]);
+    let info = seq(&[
+        &tlv(INTEGER, &[0]),
+        &seq(&[]),
+        &public_key_info(key),
+        &tlv(CONTEXT_0, &request),
+    ]);
+    signed(info, key)
+}
+
+/// A self-signed TLS-ALPN-01 certificate for `domain` carrying `digest`,
+/// the SHA-256 of the challenge's key authorization.
+pub(super) fn challenge_cert(
+    domain: &str,
+    digest: &[u8],
+    key: &EcdsaKeyPair,
+) -> Result<Vec<u8>, String> {
+    let critical = tlv(BOOLEAN, &[0xff]);
+    let identifier = tlv(OCTET_STRING, &tlv(OCTET_STRING, digest));
+    let acme = seq(&[&tlv(OID, ACME_IDENTIFIER), &critical, &identifier]);
+    let extensions = seq(&[&subject_alt_name(&[domain.to_owned()]), &acme]);
+    // Validity is not checked for challenge certificates.
+    let validity = seq(&[
+        &tlv(UTC_TIME, b"250101000000Z"),
+        &tlv(UTC_TIME, b"491231235959Z"),
+    ]);
+    let tbs = seq(&[
+        &tlv(CONTEXT_0, &tlv(INTEGER, &[2])),
+        &tlv(INTEGER, &[1]),
+        &signature_algorithm(),
+        &seq(&[]),
+        &validity,
+        &seq(&[]),
+        &public_key_info(key),
+        &tlv(CONTEXT_3, &extensions),
+    ]);
+    signed(tbs, key)
+}
+
+/// The `notAfter` time of a DER certificate.
+pub(super) fn not_after(cert: &[u8]) -> Option<SystemTime> {
+    let (_, cert, _) = read(cert)?;
+    let (_, mut tbs, _) = read(cert)?;
+    // The version is optional; then serial, signature and issuer.
+    let (tag, _, rest) = read(tbs)?;
+    if tag == CONTEXT_0 {
+        tbs = rest;
+    }
+    for _ in 0..3 {
+        tbs = read(tbs)?.2;
+    }
+    let (_, validity, _) = read(tbs)?;
+    let (_, _, validity) = read(validity)?;
+    let (tag, time, _) = read(validity)?;
+    parse_time(tag, std::str::from_utf8(time).ok()?)
+}
+
+fn signed(tbs: Vec<u8>, key: &EcdsaKeyPair) -> Result<Vec<u8>, String> {
+    let sig = key
+        .sign(&SystemRandom::new(), &tbs)
+        .map_err(|_| "cannot sign with the certificate key")?;
+    Ok(seq(&[
+        &tbs,
+        &signature_algorithm(),
+        &bit_string(sig.as_ref()),
+    ]))
+}
+
+fn signature_algorithm() -> Vec<u8> {
+    seq(&[&tlv(OID, ECDSA_WITH_SHA256)])
+}
+
+fn public_key_info(key: &EcdsaKeyPair) -> Vec<u8> {
+    let algorithm = seq(&[&tlv(OID, EC_PUBLIC_KEY), &tlv(OID, PRIME256V1)]);
+    seq(&[&algorithm, &bit_string(key.public_key().as_ref())])
+}
+
+fn subject_alt_name(domains: &[String]) -> Vec<u8> {
+    let names: Vec<u8> = domains
+        .iter()
+        .flat_map(|d| tlv(DNS_NAME, d.as_bytes()))
+        .collect();
+    seq(&[
+        &tlv(OID, SUBJECT_ALT_NAME),
+        &tlv(OCTET_STRING, &tlv(SEQUENCE, &names)),
+    ])
+}
+
+fn bit_string(bytes: &[u8]) -> Vec<u8> {
+    tlv(BIT_STRING, &[&[0], bytes].concat())
+}
+
+fn seq(parts: &[&[u8]]) -> Vec<u8> {
+    tlv(SEQUENCE, &parts.concat())
+}
+
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let len = content.len();
+    let mut out = vec![tag];
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let skip = bytes.iter().take_while(|&&b| b == 0).count();
+        out.push(0x80 | (bytes.len() - skip) as u8);
+        out.extend(&bytes[skip..]);
+    }
+    out.extend(content);
+    out
+}
+
+/// Split the first element off `der`: its tag, contents and what follows.
+fn read(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = der.split_first()?;
+    let (&len, rest) = rest.split_first()?;
+    let (len, rest) = if len < 0x80 {
+        (len as usize, rest)
+    } else {
+        let n = (len & 0x7f) as usize;
+        if n == 0 || n > 4 || rest.len() < n {
+            return None;
+        }
+        let len = rest[..n].iter().fold(0, |acc, &b| acc << 8 | b as usize);
+        (len, &rest[n..])
+    };
+    (rest.len() >= len).then(|| (tag, &rest[..len], &rest[len..]))
+}
+
+/// A UTCTime (`YYMMDDHHMMSSZ`) or GeneralizedTime (`YYYYMMDDHHMMSSZ`).
+fn parse_time(tag: u8, time: &str) -> Option<SystemTime> {
+    let (year, rest) = match tag {
+        UTC_TIME => {
+            let yy: i64 = time.get(..2)?.parse().ok()?;
+            (if yy < 50 { 2000 + yy } else { 1900 + yy }, &time[2..])
+        }
+        GENERALIZED_TIME => (time.get(..4)?.parse().ok()?, &time[4..]),
+        _ => return None,
+    };
+    let field = |i: usize| rest.get(i..i + 2)?.parse::<i64>().ok();
+    let days = days_from_civil(year, field(0)?, field(2)?);
+    let secs = days * 86_400 + field(4)? * 3600 + field(6)? * 60 + field(8)?;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::ECDSA_P256_SHA256_ASN1_SIGNING;
+
+    #[test]
+    fn builds_challenge_cert() {
+        let rng = SystemRandom::new();
+        let alg = &ECDSA_P256_SHA256_ASN1_SIGNING;
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(alg, &rng).unwrap();
+        let key = EcdsaKeyPair::from_pkcs8(alg, pkcs8.as_ref(), &rng).unwrap();
+        let cert = challenge_cert("a.test", &[7; 32], &key).unwrap();
+        // 2049-12-31T23:59:59Z
+        let expires = UNIX_EPOCH + Duration::from_secs(2_524_607_999);
+        assert_eq!(not_after(&cert), Some(expires));
+        assert!(csr(&["a.test".into(), "b.test".into()], &key).is_ok());
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+    }
+}
@@ -0,0 +1,111 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Probes count half as much after this long.
+const HALF_LIFE: Duration = Duration::from_secs(60);
+/// Weight of the newest sample in a root's probe latency average.
+const LATENCY_WEIGHT: f64 = 0.2;
+
+/// Decaying probe record of one root, for `SearchMode::Adaptive`.
+#[derive(Default)]
+pub(crate) struct Ranking {
+    state: Mutex<Option<Probes>>,
+}
+
+struct Probes {
+    /// Decayed counts as of `updated`.
+    probes: f64,
+    hits: f64,
+    /// Moving average, in microseconds.
+    latency_us: f64,
+    updated: Instant,
+}
+
+impl Ranking {
+    /// Record a probe of the root that took `elapsed`.
+    pub(crate) fn record(&self, hit: bool, elapsed: Duration) {
+        let us = elapsed.as_secs_f64() * 1e6;
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let probes = state.get_or_insert(Probes {
+            probes: 0.0,
+            hits: 0.0,
+            latency_us: us,
+            updated: now,
+        });
+        let weight = decay(now - probes.updated);
+        probes.probes = probes.probes * weight + 1.0;
+        probes.hits = probes.hits * weight + f64::from(u8::from(hit));
+        probes.latency_us += LATENCY_WEIGHT * (us - probes.latency_us);
+        probes.updated = now;
+    }
+
+    /// `(hit probability, mean probe latency in microseconds)` at `now`.
+    /// The probability starts at one half and drifts back there as probes
+    /// age; the latency is `None` until the first probe.
+    fn estimate(&self, now: Instant) -> (f64, Option<f64>) {
+        match &*self.state.lock().unwrap() {
+            None => (0.5, None),
+            Some(p) => {
+                let weight = decay(now.saturating_duration_since(p.updated));
+                let chance = (p.hits * weight + 1.0) / (p.probes * weight + 2.0);
+                (chance, Some(p.latency_us))
+            }
+        }
+    }
+}
+
+fn decay(age: Duration) -> f64 {
+    0.5f64.powf(age.as_secs_f64() / HALF_LIFE.as_secs_f64())
+}
+
+/// Order `items` so the roots most likely to hit per unit of probe latency
+/// come first. Roots without probes are costed like the slowest probed one;
+/// ties keep the given order.
+pub(crate) fn rank<T>(items: Vec<T>, ranking: impl Fn(&T) -> &Ranking) -> Vec<T> {
+    let now = Instant::now();
+    let estimates: Vec<_> = items.iter().map(|i| ranking(i).estimate(now)).collect();
+    let slowest = estimates
+        .iter()
+        .filter_map(|&(_, latency)| latency)
+        .fold(1.0, f64::max);
+    let mut ranked: Vec<(f64, T)> = estimates
+        .into_iter()
+        .map(|(chance, latency)| chance / latency.unwrap_or(slowest).max(1.0))
+        .zip(items)
+        .collect();
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+    ranked.into_iter().map(|(_, item)| item).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(roots: &[(&'static str, Ranking)]) -> Vec<&'static str> {
+        rank(roots.iter().collect(), |r| &r.1)
+            .into_iter()
+            .map(|r| r.0)
+            .collect()
+    }
+
+    #[test]
+    fn winning_root_moves_first() {
+        let roots: Vec<(&str, Ranking)> = ["a", "b", "c"]
+            .into_iter()
+            .map(|name| (name, Ranking::default()))
+            .collect();
+        assert_eq!(order(&roots), ["a", "b", "c"]);
+
+        // `a` keeps missing, `c` keeps hitting at the same latency.
+        for _ in 0..5 {
+            roots[0].1.record(false, Duration::from_micros(200));
+            roots[2].1.record(true, Duration::from_micros(200));
+        }
+        assert_eq!(order(&roots), ["c", "b", "a"]);
+
+        // A much slower hit rate loses to a fast one.
+        roots[1].1.record(true, Duration::from_micros(20));
+        assert_eq!(order(&roots), ["b", "c", "a"]);
+    }
+}
@@ -0,0 +1,328 @@
+use std::path::PathBuf;
+
+use bytes::Bytes;
+use hyper::body::Body;
+use hyper::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use hyper::{HeaderMap, Method, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::auth::constant_time_eq;
+use crate::config::{SearchMode, SearchPath};
+use crate::connections::SortKey;
+use crate::server::{
+    collect_body, full_bytes, json_response, text_response, unix_secs, FileSearcher,
+    ResponseBody,
+};
+use crate::warmup;
+
+// ---------------------------------------------------------------------------
+// Authorization
+// ---------------------------------------------------------------------------
+
+/// Check `Authorization: Bearer <token>` against the configured admin token.
+///
+/// Comparison runs in constant time with respect to the token contents.
+pub(crate) fn authorized(headers: &HeaderMap, token: &str) -> bool {
+    let Some(presented) = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+
+    constant_time_eq(presented.as_bytes(), token.as_bytes())
+}
+
+fn unauthorized() -> Response<ResponseBody> {
+    let mut resp = text_response(StatusCode::UNAUTHORIZED, "Unauthorized");
+    resp.headers_mut()
+        .insert(WWW_AUTHENTICATE, "Bearer".parse().unwrap());
+    resp
+}
+
+// ---------------------------------------------------------------------------
+// GET /_matches/<path>
+// ---------------------------------------------------------------------------
+
+#[derive(Serialize)]
+struct MatchesReport<'a> {
+    path: &'a str,
+    location: &'a str,
+    mode: SearchMode,
+    matches: Vec<MatchEntry>,
+}
+
+#[derive(Serialize)]
+struct MatchEntry {
+    root: String,
+    path: String,
+    size: u64,
+    /// Modification time as Unix seconds.
+    modified: u64,
+}
+
+/// Report every root where `target` exists instead of serving the body.
+pub(crate) async fn matches(
+    headers: &HeaderMap,
+    searcher: &FileSearcher,
+    token: &str,
+    target: &str,
+) -> Response<ResponseBody> {
+    if !authorized(headers, token) {
+        warn!(path = target, "admin request rejected (bad token)");
+        return unauthorized();
+    }
+
+    let Some(all) = searcher.find_all(target).await else {
+        debug!(status = 404, path = target, "matches request handled");
+        return text_response(StatusCode::NOT_FOUND, "Not Found");
+    };
+
+    let report = MatchesReport {
+        path: target,
+        location: all.prefix,
+        mode: all.mode,
+        matches: all
+            .matches
+            .into_iter()
+            .map(|(root, path, size, modified)| MatchEntry {
+                root: root.display().to_string(),
+                path: path.display().to_string(),
+                size,
+                modified: unix_secs(modified),
+            })
+            .collect(),
+    };
+
+    debug!(
+        status = 200, path = target, matches = report.matches.len(),
+        "matches request handled"
+    );
+    json_response(StatusCode::OK, &report)
+}
+
+// ---------------------------------------------------------------------------
+// GET /_explain/<path>
+// ---------------------------------------------------------------------------
+
+/// Trace how `target` resolves (matched location, each root's outcome in
+/// probe order, the winner) instead of serving the body.
+pub(crate) async fn explain(
+    headers: &HeaderMap,
+    searcher: &FileSearcher,
+    token: &str,
+    target: &str,
+) -> Response<ResponseBody> {
+    if !authorized(headers, token) {
+        warn!(path = target, "admin request rejected (bad token)");
+        return unauthorized();
+    }
+
+    let report = searcher.explain(target).await;
+    debug!(
+        status = 200, path = target, winner = report.winner,
+        "explain request handled"
+    );
+    json_response(StatusCode::OK, &report)
+}
+
+// ---------------------------------------------------------------------------
+// /_admin/* — runtime management
+// ---------------------------------------------------------------------------
+
+/// Route a request under `/_admin/`. Authorization is checked first.
+pub(crate) async fn handle<B>(
+    req: Request<B>,
+    searcher: &FileSearcher,
+    token: &str,
+) -> Response<ResponseBody>
+where
+    B: Body,
+{
+    if !authorized(req.headers(), token) {
+        warn!(path = %req.uri().path(), "admin request rejected (bad token)");
+        return unauthorized();
+    }
+
+    let method = req.method().clone();
+    let route = req.uri().path().trim_start_matches("/_admin").to_owned();
+    let resp = match (&method, route.as_str()) {
+        (&Method::GET, "/roots") => json_response(StatusCode::OK, &searcher.status()),
+        (&Method::POST, "/roots") | (&Method::DELETE, "/roots") => {
+            let body = match collect_body(req.into_body(), searcher.max_body_size()).await {
+                Ok(b) => b,
+                Err(status) => return text_response(status, "Invalid Request Body"),
+            };
+            let resp = update_roots(&method, &body, searcher);
+            // The change may move paths to other roots.
+            if resp.status() == StatusCode::OK {
+                warmup::warm_paths(searcher).await;
+            }
+            resp
+        }
+        (_, "/roots") => text_response(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed"),
+        (&Method::GET, "/stats") => json_response(StatusCode::OK, &searcher.stats()),
+        (&Method::GET, "/metrics") => Response::builder()
+            .header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+            .header("X-Content-Type-Options", "nosniff")
+            .body(full_bytes(Bytes::from(searcher.metrics())))
+            .unwrap(),
+        (&Method::GET, "/misses") => list_misses(req.uri().query(), searcher),
+        (&Method::GET, "/denylist") => list_denylist(searcher),
+        (&Method::POST, "/denylist/reload") => reload_denylist(searcher),
+        (&Method::GET, "/connections") => list_connections(req.uri().query(), searcher),
+        (&Method::DELETE, r) if r.starts_with("/connections/") => {
+            close_connection(&r["/connections/".len()..], searcher)
+        }
+        _ => text_response(StatusCode::NOT_FOUND, "Not Found"),
+    };
+    debug!(status = resp.status().as_u16(), %method, route, "admin request handled");
+    resp
+}
+
+#[derive(Deserialize)]
+struct RootChange {
+    /// Location prefix, e.g. `"/imgs"`.
+    location: String,
+    /// `root`, plus `extensions` and `auth` for attached roots.
+    #[serde(flatten)]
+    path: SearchPath,
+}
+
+#[derive(Serialize)]
+struct RootChangeResult {
+    location: String,
+    root: PathBuf,
+    attached: bool,
+}
+
+/// `POST /_admin/roots` attaches, `DELETE /_admin/roots` detaches.
+fn update_roots(method: &Method, body: &[u8], searcher: &FileSearcher) -> Response<ResponseBody> {
+    let change: RootChange = match serde_json::from_slice(body) {
+        Ok(c) => c,
+        Err(_) => return text_response(StatusCode::BAD_REQUEST, "Invalid JSON"),
+    };
+
+    let attach = method == Method::POST;
+    let result = if attach {
+        searcher.attach_root(&change.location, &change.path)
+    } else {
+        searcher.detach_root(&change.location, &change.path.root)
+    };
+
+    match result {
+        Ok(root) => {
+            info!(location = %change.location, root = %root.display(), attach, "admin root change applied");
+            json_response(
+                StatusCode::OK,
+                &RootChangeResult {
+                    location: change.location,
+                    root,
+                    attached: attach,
+                },
+            )
+        }
+        Err(error) => {
+            warn!(location = %change.location, error, attach, "admin root change rejected");
+            json_response(StatusCode::CONFLICT, &serde_json::json!({ "error": error }))
+        }
+    }
+}
+
+/// `GET /_admin/connections?sort=bytes|age|streams|requests|buffered&limit=N`
+fn list_connections(query: Option<&str>, searcher: &FileSearcher) -> Response<ResponseBody> {
+    let mut sort = SortKey::BytesSent;
+    let mut limit = 20;
+    for (key, value) in query.unwrap_or("").split('&').filter_map(|kv| kv.split_once('=')) {
+        match key {
+            "sort" => match SortKey::parse(value) {
+                Some(s) => sort = s,
+                None => return text_response(StatusCode::BAD_REQUEST, "Invalid sort key"),
+            },
+            "limit" => match value.parse() {
+                Ok(n) => limit = n,
+                Err(_) => return text_response(StatusCode::BAD_REQUEST, "Invalid limit"),
+            },
+            _ => {}
+        }
+    }
+    let registry = searcher.connections();
+    json_response(
+        StatusCode::OK,
+        &serde_json::json!({
+            "total": registry.len(),
+            "connections": registry.heaviest(sort, limit),
+        }),
+    )
+}
+
+/// `DELETE /_admin/connections/<id>`
+fn close_connection(id: &str, searcher: &FileSearcher) -> Response<ResponseBody> {
+    let Ok(id) = id.parse::<u64>() else {
+        return text_response(StatusCode::BAD_REQUEST, "Invalid connection id");
+    };
+    if searcher.connections().close(id) {
+        info!(id, "connection close requested by admin");
+        json_response(StatusCode::OK, &serde_json::json!({ "closed": id }))
+    } else {
+        text_response(StatusCode::NOT_FOUND, "Not Found")
+    }
+}
+
+/// `GET /_admin/misses?limit=20`
+fn list_misses(query: Option<&str>, searcher: &FileSearcher) -> Response<ResponseBody> {
+    let Some(misses) = searcher.miss_log() else {
+        return text_response(StatusCode::NOT_FOUND, "Not Found");
+    };
+    let mut limit = 20;
+    for (key, value) in query
+        .unwrap_or("")
+        .split('&')
+        .filter_map(|kv| kv.split_once('='))
+    {
+        if key == "limit" {
+            match value.parse() {
+                Ok(n) => limit = n,
+                Err(_) => return text_response(StatusCode::BAD_REQUEST, "Invalid limit"),
+            }
+        }
+    }
+    json_response(
+        StatusCode::OK,
+        &serde_json::json!({
+            "top": misses.top(limit),
+            "recent": misses.recent(limit),
+        }),
+    )
+}
+
+/// `GET /_admin/denylist`
+fn list_denylist(searcher: &FileSearcher) -> Response<ResponseBody> {
+    let Some(denylist) = searcher.denylist() else {
+        return text_response(StatusCode::NOT_FOUND, "Not Found");
+    };
+    let entries: Vec<String> = denylist.entries().iter().map(ToString::to_string).collect();
+    json_response(StatusCode::OK, &serde_json::json!({ "entries": entries }))
+}
+
+/// `POST /_admin/denylist/reload` re-reads the denylist file.
+fn reload_denylist(searcher: &FileSearcher) -> Response<ResponseBody> {
+    let Some(denylist) = searcher.denylist() else {
+        return text_response(StatusCode::NOT_FOUND, "Not Found");
+    };
+    match denylist.reload() {
+        Ok(entries) => {
+            info!(entries, "denylist reloaded by admin");
+            json_response(StatusCode::OK, &serde_json::json!({ "entries": entries }))
+        }
+        Err(error) => {
+            warn!(error, "denylist reload failed; keeping current entries");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &serde_json::json!({ "error": error }),
+            )
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! Antivirus scans of local files through a ClamAV daemon (`INSTREAM`) or
+//! an ICAP server (`RESPMOD`), with verdicts cached per file version.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use hyper::StatusCode;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::{debug, warn};
+
+use crate::config::{AntivirusConfig, AntivirusProtocol};
+
+/// Bytes sent to the scanner per chunk.
+const CHUNK: usize = 64 * 1024;
+
+/// Longest ICAP response head read.
+const MAX_ICAP_HEAD: usize = 64 * 1024;
+
+/// What the scanner said about a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Verdict {
+    Clean,
+    /// The signature found.
+    Infected(String),
+}
+
+/// The configured scanner and its verdicts.
+pub(crate) struct Antivirus {
+    protocol: AntivirusProtocol,
+    address: String,
+    icap_service: String,
+    timeout: Duration,
+    max_size: u64,
+    capacity: usize,
+    /// Whether uploads are scanned before they are moved into place.
+    pub(crate) scan_uploads: bool,
+    fail_open: bool,
+    verdicts: Mutex<HashMap<PathBuf, Entry>>,
+}
+
+/// A verdict and the file version it was reached on.
+struct Entry {
+    modified: SystemTime,
+    size: u64,
+    verdict: Verdict,
+}
+
+impl Antivirus {
+    pub(crate) fn new(cfg: &AntivirusConfig) -> Self {
+        Self {
+            protocol: cfg.protocol,
+            address: cfg.address.clone(),
+            icap_service: cfg.icap_service.trim_matches('/').to_owned(),
+            timeout: Duration::from_millis(cfg.timeout_ms),
+            max_size: cfg.max_size.as_u64(),
+            capacity: cfg.cache_entries,
+            scan_uploads: cfg.scan_uploads,
+            fail_open: cfg.fail_open,
+            verdicts: Mutex::default(),
+        }
+    }
+
+    /// The verdict on the local file at `path`, scanned on first request and
+    /// reused until its mtime or size changes. Files larger than `max_size`
+    /// pass unscanned.
+    pub(crate) async fn check(
+        &self,
+        path: &Path,
+        size: u64,
+        modified: SystemTime,
+    ) -> io::Result<Verdict> {
+        if size > self.max_size {
+            return Ok(Verdict::Clean);
+        }
+        if let Some(entry) = self.verdicts.lock().unwrap().get(path)
+            && entry.modified == modified
+            && entry.size == size
+        {
+            return Ok(entry.verdict.clone());
+        }
+        let verdict = self.scan(path).await?;
+        debug!(path = %path.display(), size, ?verdict, "file scanned");
+        let mut verdicts = self.verdicts.lock().unwrap();
+        if verdicts.len() >= self.capacity
+            && !verdicts.contains_key(path)
+            && let Some(evict) = verdicts.keys().next().cloned()
+        {
+            verdicts.remove(&evict);
+        }
+        let entry = Entry {
+            modified,
+            size,
+            verdict: verdict.clone(),
+        };
+        verdicts.insert(path.to_path_buf(), entry);
+        Ok(verdict)
+    }
+
+    /// Whether a scan of the file for `request_path` found it infected, or
+    /// the status to answer when the scan failed and `fail_open` is unset.
+    pub(crate) fn infected(
+        &self,
+        verdict: io::Result<Verdict>,
+        request_path: &str,
+    ) -> Result<bool, StatusCode> {
+        match verdict {
+            Ok(Verdict::Clean) => Ok(false),
+            Ok(Verdict::Infected(signature)) => {
+                warn!(request_path, signature, "infected file refused");
+                Ok(true)
+            }
+            Err(e) if self.fail_open => {
+                warn!(request_path, error = %e, "antivirus scan failed; passing unscanned");
+                Ok(false)
+            }
+            Err(e) => {
+                warn!(request_path, error = %e, "antivirus scan failed");
+                Err(StatusCode::SERVICE_UNAVAILABLE)
+            }
+        }
+    }
+
+    /// Scan the local file at `path` without the cache. Files larger than
+    /// `max_size` pass unscanned.
+    pub(crate) async fn scan(&self, path: &Path) -> io::Result<Verdict> {
+        let mut file = File::open(path).await?;
+        let size = file.metadata().await?.len();
+        if size > self.max_size {
+            return Ok(Verdict::Clean);
+        }
+        let scanned = async {
+            match self.protocol {
+                AntivirusProtocol::Clamd => self.clamd(&mut file).await,
+                AntivirusProtocol::Icap => self.icap(&mut file, size).await,
+            }
+        };
+        tokio::time::timeout(self.timeout, scanned)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "scan timed out"))?
+    }
+
+    async fn clamd(&self, file: &mut File) -> io::Result<Verdict> {
+        match self.address.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(socket) => instream(tokio::net::UnixStream::connect(socket).await?, file).await,
+            #[cfg(not(unix))]
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unix sockets are not supported on this platform",
+            )),
+            None => instream(TcpStream::connect(&self.address).await?, file).await,
+        }
+    }
+
+    /// Send the file as an ICAP `RESPMOD` of a `200 OK` response. A `204`
+    /// means clean; a `200` naming an infection means infected.
+    async fn icap(&self, file: &mut File, size: u64) -> io::Result<Verdict> {
+        let mut stream = TcpStream::connect(&self.address).await?;
+        let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {size}\r\n\r\n");
+        let head = format!(
+            "RESPMOD icap://{address}/{service} ICAP/1.0\r\n\
+             Host: {address}\r\n\
+             Allow: 204\r\n\
+             Encapsulated: res-hdr=0, res-body={}\r\n\r\n\
+             {response}",
+            response.len(),
+            address = self.address,
+            service = self.icap_service,
+        );
+        stream.write_all(head.as_bytes()).await?;
+        let mut buf = vec![0u8; CHUNK];
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            stream.write_all(format!("{n:x}\r\n").as_bytes()).await?;
+            stream.write_all(&buf[..n]).await?;
+            stream.write_all(b"\r\n").await?;
+        }
+        stream.write_all(b"0\r\n\r\n").await?;
+
+        let mut reply = Vec::new();
+        // The head is all that is needed; a body may follow it.
+        while !reply.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 || reply.len() > MAX_ICAP_HEAD {
+                break;
+            }
+            reply.extend_from_slice(&buf[..n]);
+        }
+        parse_icap(&String::from_utf8_lossy(&reply))
+    }
+}
+
+/// Stream `file` to clamd with `zINSTREAM` and read its verdict.
+async fn instream<S>(mut stream: S, file: &mut File) -> io::Result<Verdict>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(b"zINSTREAM\0").await?;
+    let mut buf = vec![0u8; CHUNK];
+    loop {
+        let n = file.read(&mut buf).await?;
+        // Each chunk is prefixed with its length; a zero length ends the stream.
+        stream.write_all(&(n as u32).to_be_bytes()).await?;
+        if n == 0 {
+            break;
+        }
+        stream.write_all(&buf[..n]).await?;
+    }
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await?;
+    parse_clamd(&String::from_utf8_lossy(&reply))
+}
+
+/// `stream: OK` or `stream: <signature> FOUND`; anything else is an error.
+fn parse_clamd(reply: &str) -> io::Result<Verdict> {
+    let reply = reply.trim_end_matches(['\0', '\n']);
+    let result = reply.strip_prefix("stream: ").unwrap_or(reply);
+    if result == "OK" {
+        return Ok(Verdict::Clean);
+    }
+    match result.strip_suffix(" FOUND") {
+        Some(signature) => Ok(Verdict::Infected(signature.to_owned())),
+        None => Err(io::Error::other(format!("clamd: {reply}"))),
+    }
+}
+
+/// The verdict in the head of an ICAP response.
+fn parse_icap(head: &str) -> io::Result<Verdict> {
+    let mut lines = head.lines();
+    let status = lines.next().unwrap_or_default();
+    let code = status.split(' ').nth(1);
+    if code == Some("204") {
+        return Ok(Verdict::Clean);
+    }
+    if code != Some("200") {
+        return Err(io::Error::other(format!("icap: {status:?}")));
+    }
+    // Servers name the threat in one of these; a 200 without one echoes
+    // the content unchanged.
+    let infection = lines.find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        let known = ["x-infection-found", "x-virus-id", "x-violations-found"];
+        known
+            .contains(&name.trim().to_ascii_lowercase().as_str())
+            .then(|| value.trim().to_owned())
+    });
+    Ok(infection.map_or(Verdict::Clean, Verdict::Infected))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::config::ByteSize;
+
+    /// Answer one connection with `reply` after reading `request_end`.
+    async fn fake_scanner(request_end: &'static [u8], reply: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(request_end) {
+                let mut buf = [0u8; 1024];
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(reply.as_bytes()).await.unwrap();
+        });
+        address
+    }
+
+    #[tokio::test]
+    async fn scans_through_clamd_and_icap() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("eicar.com");
+        std::fs::write(&path, b"X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR").unwrap();
+        let mut cfg = AntivirusConfig {
+            enabled: true,
+            address: fake_scanner(b"\0\0\0\0", "stream: Eicar-Signature FOUND\0").await,
+            ..Default::default()
+        };
+        let clamd = Antivirus::new(&cfg);
+        let infected = Verdict::Infected("Eicar-Signature".into());
+        assert_eq!(clamd.scan(&path).await.unwrap(), infected);
+
+        cfg.protocol = AntivirusProtocol::Icap;
+        cfg.address = fake_scanner(b"0\r\n\r\n", "ICAP/1.0 204 No Content\r\n\r\n").await;
+        assert_eq!(
+            Antivirus::new(&cfg).scan(&path).await.unwrap(),
+            Verdict::Clean
+        );
+
+        cfg.max_size = ByteSize(4);
+        assert_eq!(
+            Antivirus::new(&cfg).scan(&path).await.unwrap(),
+            Verdict::Clean
+        );
+    }
+
+    #[test]
+    fn parses_replies() {
+        assert_eq!(parse_clamd("stream: OK\0").unwrap(), Verdict::Clean);
+        assert!(parse_clamd("INSTREAM size limit exceeded. ERROR\0").is_err());
+        let infected = "ICAP/1.0 200 OK\r\nX-Infection-Found: Type=0; Threat=Eicar;\r\n\r\n";
+        assert_eq!(
+            parse_icap(infected).unwrap(),
+            Verdict::Infected("Type=0; Threat=Eicar;".into())
+        );
+        assert_eq!(
+            parse_icap("ICAP/1.0 200 OK\r\n\r\n").unwrap(),
+            Verdict::Clean
+        );
+        assert!(parse_icap("ICAP/1.0 500 Server Error\r\n\r\n").is_err());
+    }
+}
@@ -0,0 +1,578 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use bytes::{Bytes, BytesMut};
+use futures_util::Stream;
+use hyper::{Response, StatusCode};
+use regex_automata::meta::Regex;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, warn};
+use zip::write::SimpleFileOptions;
+
+use crate::backend::{FoundObject, ObjectBody};
+use crate::config::{HiddenFiles, SymlinkPolicy};
+use crate::server::{
+    FileSearcher, ResponseBody, SearchRoot, empty_body, stream_body, text_response, unix_secs,
+};
+
+/// Container format, for directory downloads and for serving members.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ArchiveFormat {
+    Tar,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        match s {
+            "tar" => Some(Self::Tar),
+            "zip" => Some(Self::Zip),
+            _ => None,
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Tar => "application/x-tar",
+            Self::Zip => "application/zip",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Tar => "tar",
+            Self::Zip => "zip",
+        }
+    }
+}
+
+/// What an archive download of one request path covers, as seen by
+/// [`FileSearcher::archive_plan`].
+pub(crate) struct ArchivePlan {
+    /// Top-level folder inside the archive, also the download's file name.
+    pub name: String,
+    /// Directory below each root (empty for the location itself).
+    pub relative: PathBuf,
+    /// Healthy local roots of the matching location, in config order.
+    pub roots: Vec<Arc<SearchRoot>>,
+    pub max_file_size: u64,
+    /// `extension_rules` max sizes, keyed by lowercase extension; they
+    /// replace `max_file_size` like they do for a direct request.
+    pub max_sizes: HashMap<String, u64>,
+    pub symlinks: SymlinkPolicy,
+    /// The location's `deny_patterns`, matched like a direct request's path.
+    pub deny_patterns: Option<Regex>,
+    /// The location's `hidden_files` rule for dot-prefixed names.
+    pub hidden_files: HiddenFiles,
+}
+
+impl ArchivePlan {
+    /// `max_file_size` for files with extension `ext`.
+    fn max_size(&self, ext: &str) -> u64 {
+        let rule = self.max_sizes.get(&ext.to_ascii_lowercase());
+        rule.copied().unwrap_or(self.max_file_size)
+    }
+}
+
+/// One file to be written, keyed by its name inside the archive.
+pub(crate) struct Entry {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+pub(crate) enum CollectError {
+    NotFound,
+    TooMany,
+}
+
+/// `GET /<dir>?archive=tar|zip`: stream every file under `<dir>` as a single
+/// archive. Roots are merged like a sequential search: when several roots
+/// hold the same relative path, the first one in config order wins.
+pub(crate) async fn handle(
+    searcher: &FileSearcher,
+    path: &str,
+    format: ArchiveFormat,
+    max_entries: usize,
+    is_head: bool,
+    client_ip: IpAddr,
+) -> Response<ResponseBody> {
+    let Some(plan) = searcher.archive_plan(path) else {
+        debug!(status = 404, path, "archive request handled");
+        return text_response(StatusCode::NOT_FOUND, "Not Found");
+    };
+
+    let name = plan.name.clone();
+    let collected = tokio::task::spawn_blocking(move || collect(&plan, max_entries)).await;
+    let mut entries = match collected {
+        Ok(Ok(entries)) => entries,
+        Ok(Err(CollectError::NotFound)) | Err(_) => {
+            debug!(status = 404, path, "archive request handled");
+            return text_response(StatusCode::NOT_FOUND, "Not Found");
+        }
+        Ok(Err(CollectError::TooMany)) => {
+            debug!(status = 413, path, max_entries, "archive request handled");
+            return text_response(StatusCode::PAYLOAD_TOO_LARGE, "Too Many Files");
+        }
+    };
+    // Each file gets the checks a direct request for it would.
+    let dir = path.trim_end_matches('/');
+    let mut refused = Vec::new();
+    for (key, entry) in &entries {
+        let entry_path = format!("{dir}/{key}");
+        if !searcher.screen_entry(&entry_path, entry, client_ip).await {
+            refused.push(key.clone());
+        }
+    }
+    for key in refused {
+        entries.remove(&key);
+    }
+    debug!(
+        status = 200,
+        path,
+        files = entries.len(),
+        ?format,
+        "archive request handled"
+    );
+
+    let body = if is_head {
+        empty_body()
+    } else {
+        let (tx, rx) = mpsc::channel(4);
+        let buffer_size = searcher.stream_buffer_size();
+        let top = name.clone();
+        tokio::task::spawn_blocking(move || {
+            let out = ChannelWriter {
+                tx: tx.clone(),
+                buf: BytesMut::with_capacity(buffer_size),
+                chunk: buffer_size,
+            };
+            let result = match format {
+                ArchiveFormat::Tar => write_tar(&top, &entries, out),
+                ArchiveFormat::Zip => write_zip(&top, &entries, out),
+            };
+            if let Err(e) = result {
+                if e.kind() != io::ErrorKind::BrokenPipe {
+                    warn!(archive = top, error = %e, "archive stream aborted");
+                }
+                // Fails the response body so the client sees a truncated download.
+                let _ = tx.blocking_send(Err(e));
+            }
+        });
+        stream_body(ObjectBody::Stream(Box::pin(ArchiveStream(rx))), buffer_size)
+    };
+
+    // Names that cannot go in a quoted header value fall back to a generic one.
+    let file_name = if name
+        .chars()
+        .all(|c| c.is_ascii_graphic() && c != '"' && c != '\\')
+    {
+        name
+    } else {
+        "archive".into()
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", format.content_type())
+        .header(
+            "Content-Disposition",
+            format!(
+                "attachment; filename=\"{file_name}.{}\"",
+                format.extension()
+            ),
+        )
+        .header("X-Content-Type-Options", "nosniff")
+        .body(body)
+        .unwrap()
+}
+
+// ---------------------------------------------------------------------------
+// Directory walk
+// ---------------------------------------------------------------------------
+
+/// Blocking: list every servable file under the plan's directory across all
+/// roots. Hidden files, symlinks the location does not follow, denied paths,
+/// filtered extensions and oversized files are left out, exactly as a direct
+/// request would be.
+pub(crate) fn collect(
+    plan: &ArchivePlan,
+    max_entries: usize,
+) -> Result<BTreeMap<String, Entry>, CollectError> {
+    let mut entries = BTreeMap::new();
+    let mut found_dir = false;
+
+    for root in &plan.roots {
+        let Some(base) = root.local_dir() else {
+            continue;
+        };
+        let start = base.join(&plan.relative);
+        let dir = match std::fs::canonicalize(&start) {
+            Ok(dir) if plan.symlinks.permits(base, &start, &dir) && dir.is_dir() => dir,
+            _ => continue,
+        };
+        found_dir = true;
+
+        let mut visited = HashSet::from([dir.clone()]);
+        let mut pending = vec![(dir, String::new())];
+        while let Some((dir, prefix)) = pending.pop() {
+            let Ok(read_dir) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            for item in read_dir.flatten() {
+                let file_name = item.file_name();
+                let Some(name) = file_name
+                    .to_str()
+                    .filter(|n| !n.starts_with('.') || plan.hidden_files.allows(n))
+                else {
+                    continue;
+                };
+                let Ok(canonical) = std::fs::canonicalize(item.path()) else {
+                    continue;
+                };
+                if !plan.symlinks.permits(base, &item.path(), &canonical) {
+                    continue;
+                }
+                let Ok(meta) = std::fs::metadata(&canonical) else {
+                    continue;
+                };
+
+                let key = format!("{prefix}{name}");
+                if meta.is_dir() {
+                    if visited.insert(canonical.clone()) {
+                        pending.push((canonical, format!("{key}/")));
+                    }
+                    continue;
+                }
+                let ext = Path::new(name)
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or("");
+                let max_size = plan.max_size(ext);
+                let too_large = max_size > 0 && meta.len() > max_size;
+                if !meta.is_file() || !root.accepts(ext) || too_large {
+                    continue;
+                }
+                if let Some(re) = &plan.deny_patterns
+                    && re.is_match(plan.relative.join(&key).to_string_lossy().as_ref())
+                {
+                    continue;
+                }
+
+                entries.entry(key).or_insert_with(|| Entry {
+                    path: canonical,
+                    size: meta.len(),
+                    modified: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                });
+                if entries.len() > max_entries {
+                    return Err(CollectError::TooMany);
+                }
+            }
+        }
+    }
+
+    if found_dir {
+        Ok(entries)
+    } else {
+        Err(CollectError::NotFound)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Archive writers
+// ---------------------------------------------------------------------------
+
+fn write_tar(top: &str, entries: &BTreeMap<String, Entry>, out: ChannelWriter) -> io::Result<()> {
+    let mut builder = tar::Builder::new(out);
+    for (name, entry) in entries {
+        let file = File::open(&entry.path)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(entry.size);
+        header.set_mode(0o644);
+        header.set_mtime(unix_secs(entry.modified));
+        builder.append_data(&mut header, format!("{top}/{name}"), file.take(entry.size))?;
+    }
+    builder.into_inner()?.flush()
+}
+
+/// Entries are stored uncompressed; most downloads are already-compressed media.
+fn write_zip(top: &str, entries: &BTreeMap<String, Entry>, out: ChannelWriter) -> io::Result<()> {
+    let mut zip = zip::ZipWriter::new_stream(out);
+    for (name, entry) in entries {
+        let mut file = File::open(&entry.path)?.take(entry.size);
+        let options = SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored)
+            .large_file(entry.size >= u64::from(u32::MAX))
+            .unix_permissions(0o644)
+            .last_modified_time(zip_time(entry.modified));
+        zip.start_file(format!("{top}/{name}"), options)
+            .map_err(io::Error::other)?;
+        io::copy(&mut file, &mut zip)?;
+    }
+    zip.finish().map_err(io::Error::other)?.into_inner().flush()
+}
+
+/// DOS timestamp for a zip entry (UTC); times outside 1980–2107 fall back
+/// to the format's epoch.
+fn zip_time(t: SystemTime) -> zip::DateTime {
+    let secs = unix_secs(t);
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+
+    // Days since 1970-01-01 to a civil date (H. Hinnant's `civil_from_days`).
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    zip::DateTime::from_date_and_time(
+        year.try_into().unwrap_or(0),
+        month as u8,
+        day as u8,
+        (rem / 3_600) as u8,
+        (rem % 3_600 / 60) as u8,
+        (rem % 60) as u8,
+    )
+    .unwrap_or_default()
+}
+
+/// Inverse of [`zip_time`].
+fn system_time(dt: zip::DateTime) -> SystemTime {
+    // Civil date to days since 1970-01-01 (H. Hinnant's `days_from_civil`).
+    let (month, day) = (i64::from(dt.month()), i64::from(dt.day()));
+    let year = i64::from(dt.year()) - i64::from(month <= 2);
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    let secs = days * 86_400
+        + i64::from(dt.hour()) * 3_600
+        + i64::from(dt.minute()) * 60
+        + i64::from(dt.second());
+    SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs.max(0) as u64)
+}
+
+// ---------------------------------------------------------------------------
+// Files inside containers
+// ---------------------------------------------------------------------------
+
+/// Chunk size for member bodies.
+const MEMBER_CHUNK: usize = 64 * 1024;
+
+/// Look for `relative` inside a `.zip` or `.tar` container under `root_dir`:
+/// `foo/img.png` is served from `foo.zip!/img.png` (then `foo.tar`), deeper
+/// containers first (`a/b.zip!/c.png` before `a.zip!/b/c.png`). The member
+/// streams straight out of the container; nothing is extracted to disk.
+pub(crate) async fn find_member(
+    root_dir: &Path,
+    relative: &Path,
+    max_file_size: u64,
+    symlinks: SymlinkPolicy,
+    request_path: &str,
+) -> Option<FoundObject> {
+    let (found_tx, found_rx) = oneshot::channel();
+    let (tx, rx) = mpsc::channel(4);
+    let root_dir = root_dir.to_path_buf();
+    let relative = relative.to_path_buf();
+    let request_path = request_path.to_owned();
+
+    tokio::task::spawn_blocking(move || {
+        let out = ChannelWriter {
+            tx: tx.clone(),
+            buf: BytesMut::with_capacity(MEMBER_CHUNK),
+            chunk: MEMBER_CHUNK,
+        };
+        let result = stream_member(&root_dir, &relative, max_file_size, symlinks, found_tx, out);
+        if let Err(e) = result {
+            if e.kind() != io::ErrorKind::BrokenPipe {
+                warn!(request_path, error = %e, "archive member stream aborted");
+            }
+            let _ = tx.blocking_send(Err(e));
+        }
+    });
+
+    let (path, size, modified) = found_rx.await.ok()?;
+    Some(FoundObject {
+        path,
+        size,
+        modified,
+        body: ObjectBody::Stream(Box::pin(ArchiveStream(rx))),
+    })
+}
+
+/// `(container, member)` splits of a relative path, deepest container first.
+fn member_candidates(relative: &Path) -> Vec<(PathBuf, String)> {
+    let parts: Vec<&str> = relative.iter().filter_map(|c| c.to_str()).collect();
+    (1..parts.len())
+        .rev()
+        .map(|split| (parts[..split].iter().collect(), parts[split..].join("/")))
+        .collect()
+}
+
+type MemberInfo = (PathBuf, u64, SystemTime);
+
+/// Blocking: find the member, report `(identity, size, mtime)` on `found`,
+/// then copy its bytes to `out`. Returns `Ok` without reporting on a miss.
+fn stream_member(
+    root_dir: &Path,
+    relative: &Path,
+    max_file_size: u64,
+    symlinks: SymlinkPolicy,
+    found: oneshot::Sender<MemberInfo>,
+    mut out: ChannelWriter,
+) -> io::Result<()> {
+    let fits = |size: u64| max_file_size == 0 || size <= max_file_size;
+
+    for (container, member) in member_candidates(relative) {
+        for format in [ArchiveFormat::Zip, ArchiveFormat::Tar] {
+            let mut name = container.clone().into_os_string();
+            name.push(".");
+            name.push(format.extension());
+            let path = root_dir.join(name);
+            let canonical = match std::fs::canonicalize(&path) {
+                Ok(c) if symlinks.permits(root_dir, &path, &c) && c.is_file() => c,
+                _ => continue,
+            };
+            let identity = PathBuf::from(format!("{}!/{member}", canonical.display()));
+
+            match format {
+                ArchiveFormat::Zip => {
+                    let Ok(mut archive) = zip::ZipArchive::new(File::open(&canonical)?) else {
+                        continue;
+                    };
+                    let Ok(mut entry) = archive.by_name(&member) else {
+                        continue;
+                    };
+                    if !entry.is_file() || !fits(entry.size()) {
+                        continue;
+                    }
+                    let modified = entry
+                        .last_modified()
+                        .map_or(SystemTime::UNIX_EPOCH, system_time);
+                    if found.send((identity, entry.size(), modified)).is_err() {
+                        return Ok(());
+                    }
+                    io::copy(&mut entry, &mut out)?;
+                    return out.flush();
+                }
+                ArchiveFormat::Tar => {
+                    let mut archive = tar::Archive::new(File::open(&canonical)?);
+                    let Ok(entries) = archive.entries_with_seek() else {
+                        continue;
+                    };
+                    for mut entry in entries.flatten() {
+                        let matches = entry.path().is_ok_and(|p| {
+                            p.strip_prefix("./").unwrap_or(&p) == Path::new(&member)
+                        });
+                        if !matches || !entry.header().entry_type().is_file() {
+                            continue;
+                        }
+                        if !fits(entry.size()) {
+                            break;
+                        }
+                        let mtime = entry.header().mtime().unwrap_or(0);
+                        let modified =
+                            SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(mtime);
+                        if found.send((identity, entry.size(), modified)).is_err() {
+                            return Ok(());
+                        }
+                        io::copy(&mut entry, &mut out)?;
+                        return out.flush();
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Blocking writer → response body
+// ---------------------------------------------------------------------------
+
+/// `Write` side of the response body, used from the blocking writer task.
+/// Fails with `BrokenPipe` once the client has gone away.
+struct ChannelWriter {
+    tx: mpsc::Sender<io::Result<Bytes>>,
+    buf: BytesMut,
+    chunk: usize,
+}
+
+impl ChannelWriter {
+    fn send(&mut self) -> io::Result<()> {
+        let chunk = self.buf.split().freeze();
+        self.tx
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client went away"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= self.chunk {
+            self.send()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        self.send()
+    }
+}
+
+struct ArchiveStream(mpsc::Receiver<io::Result<Bytes>>);
+
+impl Stream for ArchiveStream {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zip_time_converts_to_civil_utc() {
+        // 2024-02-29 13:45:30 UTC
+        let t = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_709_214_330);
+        let dt = zip_time(t);
+        assert_eq!((dt.year(), dt.month(), dt.day()), (2024, 2, 29));
+        assert_eq!((dt.hour(), dt.minute(), dt.second()), (13, 45, 30));
+
+        assert_eq!(zip_time(SystemTime::UNIX_EPOCH), zip::DateTime::default());
+        assert_eq!(system_time(dt), t);
+    }
+
+    #[test]
+    fn member_candidates_prefer_deepest_container() {
+        let splits = member_candidates(Path::new("a/b/c.png"));
+        assert_eq!(
+            splits,
+            [
+                (PathBuf::from("a/b"), "c.png".to_string()),
+                (PathBuf::from("a"), "b/c.png".to_string()),
+            ]
+        );
+        assert!(member_candidates(Path::new("c.png")).is_empty());
+    }
+}
@@ -0,0 +1,157 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::config::AuditLogConfig;
+
+/// Why a request was blocked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// `..`, a null byte or another non-normal path component.
+    PathTraversal,
+    /// A dot-prefixed segment the location does not serve.
+    HiddenFile,
+    /// A path matching the location's `deny_patterns`.
+    DeniedPattern,
+    /// A client on the denylist.
+    DeniedClient,
+    /// Missing or rejected credentials (location auth or admin token).
+    AuthFailure,
+    /// A file whose leading bytes contradict its extension (`magic_bytes`).
+    MislabeledFile,
+    /// A file the antivirus scan found infected.
+    InfectedFile,
+    /// A request the WASM request filter rejected.
+    FilterRejected,
+}
+
+impl AuditEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::PathTraversal => "path_traversal",
+            Self::HiddenFile => "hidden_file",
+            Self::DeniedPattern => "denied_pattern",
+            Self::DeniedClient => "denied_client",
+            Self::AuthFailure => "auth_failure",
+            Self::MislabeledFile => "mislabeled_file",
+            Self::InfectedFile => "infected_file",
+            Self::FilterRejected => "filter_rejected",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    /// Unix seconds.
+    time: u64,
+    event: AuditEvent,
+    client_ip: IpAddr,
+    path: &'a str,
+    /// Quarantine entry of the file the request hit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quarantined: Option<&'a Path>,
+}
+
+/// Writes audit records to the `filehunter::audit` log target and, when
+/// configured, appends them to a file as JSON lines.
+pub struct AuditLog {
+    file: Option<Mutex<File>>,
+}
+
+impl AuditLog {
+    /// Open `cfg.file` for appending. An unusable file leaves only the log
+    /// target.
+    pub fn open(cfg: &AuditLogConfig) -> Self {
+        let file = (!cfg.file.as_os_str().is_empty())
+            .then(|| OpenOptions::new().create(true).append(true).open(&cfg.file))
+            .and_then(|opened| match opened {
+                Ok(file) => Some(Mutex::new(file)),
+                Err(e) => {
+                    warn!(path = %cfg.file.display(), error = %e, "cannot open audit log file");
+                    None
+                }
+            });
+        Self { file }
+    }
+
+    /// Record that a request for the raw path `path` from `client_ip` was blocked.
+    pub fn record(&self, event: AuditEvent, client_ip: IpAddr, path: &str) {
+        warn!(
+            target: "filehunter::audit",
+            event = event.as_str(), client_ip = %client_ip, path,
+            "request blocked"
+        );
+        self.append(event, client_ip, path, None);
+    }
+
+    /// Record a blocked request whose file was quarantined as `entry`.
+    pub fn record_quarantined(
+        &self,
+        event: AuditEvent,
+        client_ip: IpAddr,
+        path: &str,
+        entry: &Path,
+    ) {
+        warn!(
+            target: "filehunter::audit",
+            event = event.as_str(), client_ip = %client_ip, path, quarantined = %entry.display(),
+            "request blocked, file quarantined"
+        );
+        self.append(event, client_ip, path, Some(entry));
+    }
+
+    fn append(&self, event: AuditEvent, client_ip: IpAddr, path: &str, quarantined: Option<&Path>) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let record = Record {
+            time: crate::server::unix_secs(SystemTime::now()),
+            event,
+            client_ip,
+            path,
+            quarantined,
+        };
+        let mut line = serde_json::to_vec(&record).expect("JSON serialization cannot fail");
+        line.push(b'\n');
+        // One write per record so concurrent appends never interleave.
+        if let Err(e) = file.lock().unwrap().write_all(&line) {
+            warn!(error = %e, "cannot write audit record");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appends_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = AuditLogConfig {
+            enabled: true,
+            file: dir.path().join("audit.jsonl"),
+        };
+        let audit = AuditLog::open(&cfg);
+        let ip: IpAddr = "192.0.2.7".parse().unwrap();
+        audit.record(AuditEvent::PathTraversal, ip, "/../etc/passwd");
+        audit.record(AuditEvent::AuthFailure, ip, "/private/a.txt");
+
+        let text = std::fs::read_to_string(&cfg.file).unwrap();
+        let records: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["event"], "path_traversal");
+        assert_eq!(records[0]["client_ip"], "192.0.2.7");
+        assert_eq!(records[0]["path"], "/../etc/passwd");
+        assert_eq!(records[1]["event"], "auth_failure");
+    }
+}
@@ -0,0 +1,192 @@
+use hyper::HeaderMap;
+#[cfg(any(feature = "jwt", feature = "basic-auth"))]
+use hyper::header::AUTHORIZATION;
+use hyper::header::HeaderValue;
+use percent_encoding::percent_decode_str;
+#[cfg(feature = "jwt")]
+use tracing::debug;
+
+use crate::backend::Connector;
+use crate::config::LocationAuth;
+#[cfg(feature = "basic-auth")]
+use crate::htpasswd::Htpasswd;
+#[cfg(feature = "jwt")]
+use crate::jwt::JwtValidator;
+
+/// Credential check for one location, built from its `auth` setting.
+pub(crate) enum Guard {
+    ApiKey {
+        keys: Vec<String>,
+        header: String,
+        query_param: String,
+    },
+    #[cfg(feature = "jwt")]
+    Jwt(Box<JwtValidator>),
+    #[cfg(feature = "basic-auth")]
+    Basic {
+        users: Box<Htpasswd>,
+        challenge: HeaderValue,
+    },
+}
+
+impl Guard {
+    pub(crate) fn new(auth: &LocationAuth, connector: &Connector) -> Self {
+        #[cfg(not(feature = "jwt"))]
+        let _ = connector;
+        match auth {
+            LocationAuth::ApiKey {
+                keys,
+                header,
+                query_param,
+            } => Self::ApiKey {
+                keys: keys.clone(),
+                header: header.clone(),
+                query_param: query_param.clone(),
+            },
+            #[cfg(feature = "jwt")]
+            LocationAuth::Jwt {
+                secret,
+                jwks_url,
+                issuer,
+                audience,
+                prefix_claim,
+            } => Self::Jwt(Box::new(JwtValidator::new(
+                secret,
+                jwks_url,
+                issuer,
+                audience,
+                prefix_claim,
+                connector.http_client(),
+            ))),
+            #[cfg(feature = "basic-auth")]
+            LocationAuth::Basic { htpasswd, realm } => Self::Basic {
+                users: Box::new(Htpasswd::load(htpasswd)),
+                challenge: HeaderValue::from_str(&format!(
+                    "Basic realm=\"{}\", charset=\"UTF-8\"",
+                    realm.replace(['"', '\\'], "")
+                ))
+                .unwrap_or_else(|_| HeaderValue::from_static("Basic")),
+            },
+            // Rejected by `Config::validate` in builds without the feature.
+            #[cfg(not(feature = "jwt"))]
+            LocationAuth::Jwt { .. } => unreachable!("jwt auth needs the `jwt` feature"),
+            #[cfg(not(feature = "basic-auth"))]
+            LocationAuth::Basic { .. } => unreachable!("basic auth needs the `basic-auth` feature"),
+        }
+    }
+
+    /// `WWW-Authenticate` value sent with a 401 from this guard.
+    pub(crate) fn challenge(&self) -> Option<HeaderValue> {
+        match self {
+            Self::ApiKey { .. } => None,
+            #[cfg(feature = "jwt")]
+            Self::Jwt(_) => Some(HeaderValue::from_static("Bearer")),
+            #[cfg(feature = "basic-auth")]
+            Self::Basic { challenge, .. } => Some(challenge.clone()),
+        }
+    }
+
+    /// Whether a request to `request_path` carries credentials this guard accepts.
+    pub(crate) async fn accepts(
+        &self,
+        request_path: &str,
+        headers: &HeaderMap,
+        query: Option<&str>,
+    ) -> bool {
+        #[cfg(not(feature = "jwt"))]
+        let _ = request_path;
+        match self {
+            Self::ApiKey {
+                keys,
+                header,
+                query_param,
+            } => {
+                let from_header = headers.get(header.as_str()).map(|v| v.as_bytes().to_vec());
+                let from_query = || {
+                    query?
+                        .split('&')
+                        .filter_map(|kv| kv.split_once('='))
+                        .find(|(k, _)| !query_param.is_empty() && k == query_param)
+                        .map(|(_, v)| percent_decode_str(v).collect::<Vec<u8>>())
+                };
+                from_header.or_else(from_query).is_some_and(|presented| {
+                    keys.iter()
+                        .any(|k| constant_time_eq(k.as_bytes(), &presented))
+                })
+            }
+            #[cfg(feature = "jwt")]
+            Self::Jwt(validator) => {
+                let Some(token) = credentials(headers, "Bearer") else {
+                    return false;
+                };
+                match validator.validate(token, request_path).await {
+                    Ok(()) => true,
+                    Err(reason) => {
+                        debug!(path = request_path, reason, "bearer token rejected");
+                        false
+                    }
+                }
+            }
+            #[cfg(feature = "basic-auth")]
+            Self::Basic { users, .. } => match credentials(headers, "Basic") {
+                Some(encoded) => users.verify(encoded).await,
+                None => false,
+            },
+        }
+    }
+}
+
+/// The credentials of an `Authorization: <scheme> <credentials>` header.
+#[cfg(any(feature = "jwt", feature = "basic-auth"))]
+fn credentials<'a>(headers: &'a HeaderMap, scheme: &str) -> Option<&'a str> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (given, credentials) = value.split_once(' ')?;
+    given.eq_ignore_ascii_case(scheme).then_some(credentials)
+}
+
+/// Compare secrets in time independent of where they first differ.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn api_key_from_header_or_query() {
+        let connector = Connector::new(&Default::default());
+        let guard = |query_param: &str| {
+            let auth = LocationAuth::ApiKey {
+                keys: vec!["k1".into(), "s3cr/t".into()],
+                header: "X-Api-Key".into(),
+                query_param: query_param.into(),
+            };
+            Guard::new(&auth, &connector)
+        };
+        let auth = guard("api_key");
+        let mut headers = HeaderMap::new();
+        assert!(!auth.accepts("/", &headers, None).await);
+        assert!(
+            auth.accepts("/", &headers, Some("v=1&api_key=s3cr%2Ft"))
+                .await
+        );
+        assert!(!auth.accepts("/", &headers, Some("api_key=k2")).await);
+
+        headers.insert("x-api-key", "k1".parse().unwrap());
+        assert!(auth.accepts("/", &headers, None).await);
+        headers.insert("x-api-key", "k1x".parse().unwrap());
+        let query = Some("api_key=k1");
+        assert!(!auth.accepts("/", &headers, query).await, "header wins");
+
+        let header_only = guard("");
+        assert!(
+            !header_only
+                .accepts("/", &HeaderMap::new(), Some("=k1"))
+                .await
+        );
+    }
+}
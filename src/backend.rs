@@ -0,0 +1,454 @@
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt, stream};
+use tokio::fs::File;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::config::{SearchPath, ServerConfig, SymlinkPolicy};
+
+#[cfg(feature = "remote")]
+mod cache;
+#[cfg(feature = "upstream")]
+mod http;
+#[cfg(feature = "remote")]
+pub(crate) mod remote;
+#[cfg(feature = "s3")]
+mod s3;
+
+/// Boxed future returned by [`StorageBackend`] methods (keeps the trait object-safe).
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Byte stream of a non-file object. `Sync` because response bodies are shared.
+pub type ObjectStream = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send + Sync>>;
+
+/// Pass `body` through while copying each chunk to the returned channel.
+/// `None` follows the last chunk of a body that ends cleanly; after an
+/// error, or when the response is dropped, the channel just closes.
+pub(crate) fn tee(body: ObjectStream) -> (ObjectStream, mpsc::UnboundedReceiver<Option<Bytes>>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let chunks = stream::unfold((body, Some(tx)), |(mut body, mut tx)| async move {
+        let item = body.next().await;
+        match &item {
+            Some(Ok(chunk)) => {
+                if let Some(tx) = &tx {
+                    let _ = tx.send(Some(chunk.clone()));
+                }
+            }
+            Some(Err(_)) => tx = None,
+            None => {
+                if let Some(tx) = tx.take() {
+                    let _ = tx.send(None);
+                }
+            }
+        }
+        item.map(|item| (item, (body, tx)))
+    });
+    (Box::pin(chunks), rx)
+}
+
+/// Readable content of a found object.
+pub enum ObjectBody {
+    /// A local file, already open.
+    File(File),
+    /// Any other byte stream (remote or in-memory objects).
+    Stream(ObjectStream),
+}
+
+/// An object located by a backend probe. The body handle is opened during the
+/// probe so the object cannot change between lookup and response.
+pub struct FoundObject {
+    /// Resolved identity of the object (canonical path or URL), used in logs.
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+    pub body: ObjectBody,
+}
+
+/// Why a probe has no answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeError {
+    /// Path traversal detected; the search must stop.
+    Traversal,
+    /// The root could not answer (I/O error, failed upstream request). The
+    /// search moves on to the next root; repeated failures open the root's
+    /// circuit breaker.
+    Failed(String),
+}
+
+/// Where the files of a single search root live.
+///
+/// Every search mode works on any mix of backends. Besides the built-in
+/// local, S3 and HTTP backends, embedders can implement this trait and add
+/// their own roots with [`FileSearcher::attach_backend`].
+///
+/// [`FileSearcher::attach_backend`]: crate::server::FileSearcher::attach_backend
+pub trait StorageBackend: Send + Sync {
+    /// Look up an already-sanitized relative path under this root and open
+    /// its body. `relative` only contains normal components (no `..`, no
+    /// dotfiles); `request_path` is the original path, for logging.
+    ///
+    /// Returns:
+    /// - `Ok(Some(...))` — object found
+    /// - `Ok(None)` — not found or not a regular file
+    /// - `Err(...)` — see [`ProbeError`]
+    fn probe<'a>(
+        &'a self,
+        relative: &'a Path,
+        request_path: &'a str,
+    ) -> BoxFuture<'a, Result<Option<FoundObject>, ProbeError>>;
+
+    /// Liveness probe run by the health checker; `Err` carries the reason.
+    fn check(&self) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// The canonical directory this root serves, if it is on the local
+    /// filesystem. Directory-level features (archive downloads, upload
+    /// replicas) only cover roots that return `Some`.
+    fn local_dir(&self) -> Option<&Path> {
+        None
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Root resolution
+// ---------------------------------------------------------------------------
+
+/// Builds the backend for each configured root and holds whatever remote
+/// roots share (HTTP client, credentials).
+pub(crate) struct Connector {
+    #[cfg(feature = "remote")]
+    client: remote::HttpClient,
+    #[cfg(feature = "s3")]
+    s3: Arc<s3::S3Settings>,
+    #[cfg(feature = "upstream")]
+    upstream_timeout: std::time::Duration,
+    /// `Some` when remote roots are cached on disk.
+    #[cfg(feature = "remote")]
+    cache: Option<Arc<cache::DiskCache>>,
+}
+
+impl Connector {
+    pub(crate) fn new(server: &ServerConfig) -> Self {
+        #[cfg(not(any(feature = "s3", feature = "upstream")))]
+        let _ = server;
+        Self {
+            #[cfg(feature = "remote")]
+            client: remote::client(),
+            #[cfg(feature = "s3")]
+            s3: Arc::new(s3::S3Settings::new(&server.s3)),
+            #[cfg(feature = "upstream")]
+            upstream_timeout: std::time::Duration::from_millis(server.upstream.timeout_ms),
+            #[cfg(feature = "remote")]
+            cache: open_cache(server),
+        }
+    }
+
+    /// The shared HTTP(S) client, for fetching JWT signing keys.
+    #[cfg(feature = "jwt")]
+    pub(crate) fn http_client(&self) -> remote::HttpClient {
+        self.client.clone()
+    }
+
+    /// Resolve a configured root to its identity (canonical path or URL)
+    /// and backend. `Err` carries the reason the root cannot be used.
+    /// `symlinks` only applies to local roots.
+    pub(crate) fn open(
+        &self,
+        entry: &SearchPath,
+        symlinks: SymlinkPolicy,
+    ) -> Result<(PathBuf, Arc<dyn StorageBackend>), String> {
+        let root = &entry.root;
+        if let Some(location) = root.to_str().and_then(|r| r.strip_prefix("s3://")) {
+            return self.open_s3(location);
+        }
+        if entry.is_http() {
+            return self.open_http(entry);
+        }
+
+        let backend = LocalBackend::new(root)
+            .map_err(|e| e.to_string())?
+            .with_symlinks(symlinks);
+        Ok((backend.root.clone(), Arc::new(backend)))
+    }
+
+    #[cfg(feature = "s3")]
+    fn open_s3(&self, location: &str) -> Result<(PathBuf, Arc<dyn StorageBackend>), String> {
+        let backend = s3::S3Backend::new(self.client.clone(), self.s3.clone(), location)?;
+        Ok(self.cached(backend.identity(), Arc::new(backend)))
+    }
+
+    #[cfg(not(feature = "s3"))]
+    fn open_s3(&self, _location: &str) -> Result<(PathBuf, Arc<dyn StorageBackend>), String> {
+        Err("s3 roots require the `s3` feature".into())
+    }
+
+    #[cfg(feature = "upstream")]
+    fn open_http(&self, entry: &SearchPath) -> Result<(PathBuf, Arc<dyn StorageBackend>), String> {
+        let url = entry.root.to_str().unwrap_or_default();
+        let backend = http::HttpBackend::new(
+            self.client.clone(),
+            url,
+            entry.auth.as_ref(),
+            self.upstream_timeout,
+        )?;
+        Ok(self.cached(backend.identity(), Arc::new(backend)))
+    }
+
+    /// Put the disk cache, if any, in front of a remote root.
+    #[cfg(feature = "remote")]
+    fn cached(
+        &self,
+        identity: PathBuf,
+        backend: Arc<dyn StorageBackend>,
+    ) -> (PathBuf, Arc<dyn StorageBackend>) {
+        match &self.cache {
+            Some(cache) => {
+                let backend = cache.wrap(&identity, backend);
+                (identity, backend)
+            }
+            None => (identity, backend),
+        }
+    }
+
+    #[cfg(not(feature = "upstream"))]
+    fn open_http(&self, _entry: &SearchPath) -> Result<(PathBuf, Arc<dyn StorageBackend>), String> {
+        Err("http(s) and webdav roots require the `upstream` feature".into())
+    }
+}
+
+/// The `remote_cache`, if configured and usable.
+#[cfg(feature = "remote")]
+fn open_cache(server: &ServerConfig) -> Option<Arc<cache::DiskCache>> {
+    let cfg = &server.remote_cache;
+    if cfg.dir.as_os_str().is_empty() {
+        return None;
+    }
+    match cache::DiskCache::open(cfg) {
+        Ok(cache) => Some(Arc::new(cache)),
+        Err(e) => {
+            warn!(dir = %cfg.dir.display(), error = %e, "cannot open remote_cache, not caching");
+            None
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Local filesystem
+// ---------------------------------------------------------------------------
+
+/// A directory on the local filesystem.
+pub struct LocalBackend {
+    /// Canonical root path.
+    pub(crate) root: PathBuf,
+    /// Which symlinks resolved candidates may pass through.
+    pub(crate) symlinks: SymlinkPolicy,
+}
+
+impl LocalBackend {
+    /// Serve files below `root`, which must be an existing directory.
+    pub fn new(root: &Path) -> io::Result<Self> {
+        let root = root.canonicalize()?;
+        if !root.is_dir() {
+            return Err(io::Error::other("not a directory"));
+        }
+        Ok(Self {
+            root,
+            symlinks: SymlinkPolicy::default(),
+        })
+    }
+
+    /// Follow symlinks according to `policy` instead of only within the root.
+    pub fn with_symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+}
+
+impl StorageBackend for LocalBackend {
+    fn probe<'a>(
+        &'a self,
+        relative: &'a Path,
+        request_path: &'a str,
+    ) -> BoxFuture<'a, Result<Option<FoundObject>, ProbeError>> {
+        Box::pin(probe_local(
+            &self.root,
+            self.symlinks,
+            self.root.join(relative),
+            request_path,
+        ))
+    }
+
+    fn check(&self) -> BoxFuture<'_, Result<(), String>> {
+        let root = self.root.clone();
+        Box::pin(async move {
+            match tokio::task::spawn_blocking(move || check_dir(&root)).await {
+                Ok(result) => result.map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            }
+        })
+    }
+
+    fn local_dir(&self) -> Option<&Path> {
+        Some(&self.root)
+    }
+}
+
+/// Blocking probe: the root must still be a directory and readable.
+fn check_dir(path: &Path) -> io::Result<()> {
+    let meta = std::fs::metadata(path)?;
+    if !meta.is_dir() {
+        return Err(io::Error::other("not a directory"));
+    }
+    std::fs::read_dir(path)?.next().transpose()?;
+    Ok(())
+}
+
+/// Core file probe: canonicalize, open, check metadata.
+async fn probe_local(
+    root_path: &Path,
+    symlinks: SymlinkPolicy,
+    candidate: PathBuf,
+    request_path: &str,
+) -> Result<Option<FoundObject>, ProbeError> {
+    let canonical = match tokio::fs::canonicalize(&candidate).await {
+        Ok(c) if symlinks.permits(root_path, &candidate, &c) => c,
+        Ok(_) if symlinks == SymlinkPolicy::Deny => {
+            warn!(request_path, "symlink refused");
+            return Err(ProbeError::Traversal);
+        }
+        Ok(_) => {
+            warn!(request_path, "path traversal blocked");
+            return Err(ProbeError::Traversal);
+        }
+        Err(e) => return local_miss(e, request_path),
+    };
+
+    let file = match File::open(&canonical).await {
+        Ok(f) => f,
+        Err(e) => return local_miss(e, request_path),
+    };
+    let meta = match file.metadata().await {
+        Ok(m) if m.is_file() => m,
+        Ok(_) => return Ok(None),
+        Err(e) => return local_miss(e, request_path),
+    };
+
+    Ok(Some(FoundObject {
+        path: canonical,
+        size: meta.len(),
+        modified: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+        body: ObjectBody::File(file),
+    }))
+}
+
+/// A failed lookup of a local file: a miss when the file is absent or
+/// unreadable, a failure of the root for anything else (`EIO`, a stale
+/// mount).
+fn local_miss(e: io::Error, request_path: &str) -> Result<Option<FoundObject>, ProbeError> {
+    match e.kind() {
+        io::ErrorKind::NotFound
+        | io::ErrorKind::NotADirectory
+        | io::ErrorKind::PermissionDenied
+        | io::ErrorKind::InvalidFilename => Ok(None),
+        _ => {
+            warn!(request_path, error = %e, "local probe failed");
+            Err(ProbeError::Failed(e.to_string()))
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// In-memory backend for deterministic simulation tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+pub(crate) mod memory {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Shared, ordered record of probe events: `"<root>:<event>"`.
+    pub(crate) type EventLog = Arc<Mutex<Vec<String>>>;
+
+    /// A fake root whose probes take a fixed (virtual) latency. Run under a
+    /// paused tokio clock so latencies are exact and ordering reproducible.
+    pub(crate) struct MemoryBackend {
+        name: String,
+        latency: Duration,
+        files: HashMap<PathBuf, (Bytes, SystemTime)>,
+        log: EventLog,
+    }
+
+    impl MemoryBackend {
+        pub(crate) fn new(name: &str, latency: Duration, log: &EventLog) -> Self {
+            Self {
+                name: name.into(),
+                latency,
+                files: HashMap::new(),
+                log: log.clone(),
+            }
+        }
+
+        pub(crate) fn with_file(mut self, path: &str, data: &str, modified: SystemTime) -> Self {
+            self.files
+                .insert(PathBuf::from(path), (Bytes::from(data.to_owned()), modified));
+            self
+        }
+
+        fn record(&self, event: &str) {
+            self.log.lock().unwrap().push(format!("{}:{event}", self.name));
+        }
+    }
+
+    /// Logs `cancelled` if a probe future is dropped before completing.
+    struct CancelGuard<'a> {
+        backend: &'a MemoryBackend,
+        done: bool,
+    }
+
+    impl Drop for CancelGuard<'_> {
+        fn drop(&mut self) {
+            if !self.done {
+                self.backend.record("cancelled");
+            }
+        }
+    }
+
+    impl StorageBackend for MemoryBackend {
+        fn probe<'a>(
+            &'a self,
+            relative: &'a Path,
+            _request_path: &'a str,
+        ) -> BoxFuture<'a, Result<Option<FoundObject>, ProbeError>> {
+            Box::pin(async move {
+                let mut guard = CancelGuard { backend: self, done: false };
+                self.record("start");
+                tokio::time::sleep(self.latency).await;
+                guard.done = true;
+
+                let Some((data, modified)) = self.files.get(relative) else {
+                    self.record("miss");
+                    return Ok(None);
+                };
+                self.record("found");
+                let chunk: io::Result<Bytes> = Ok(data.clone());
+                Ok(Some(FoundObject {
+                    path: PathBuf::from(format!("mem://{}/{}", self.name, relative.display())),
+                    size: data.len() as u64,
+                    modified: *modified,
+                    body: ObjectBody::Stream(Box::pin(futures_util::stream::once(async { chunk }))),
+                }))
+            })
+        }
+    }
+}
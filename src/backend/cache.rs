@@ -0,0 +1,658 @@
+// With only the `jwt` feature there are no remote roots to cache.
+#![cfg_attr(not(any(feature = "s3", feature = "upstream")), allow(dead_code))]
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use bytes::Bytes;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Semaphore, mpsc};
+use tracing::{debug, info, warn};
+
+use super::{BoxFuture, FoundObject, ObjectBody, ProbeError, StorageBackend, tee};
+use crate::config::RemoteCacheConfig;
+use crate::server::unix_secs;
+
+/// Distinguishes the temporary files of concurrent fills.
+static FILLS: AtomicU64 = AtomicU64::new(0);
+
+/// On-disk copies of remote objects, shared by every S3 and HTTP root.
+/// Each copy is a body file named after a hash of its key, plus a `.meta`
+/// sidecar with the key, size, origin mtime and checksum. Copies are
+/// served as local files; the least recently used are evicted to stay
+/// within `max_size`. Expired copies may still be served for a while as
+/// a background refresh fetches the object again.
+pub(crate) struct DiskCache {
+    dir: PathBuf,
+    max_size: u64,
+    /// `None` = copies stay fresh until evicted.
+    ttl: Option<Duration>,
+    /// How long past `ttl` a copy is served while it is refreshed.
+    stale: Duration,
+    /// Permits for background refreshes.
+    refreshes: Arc<Semaphore>,
+    state: Mutex<State>,
+}
+
+/// Where a copy stands relative to its TTL.
+#[derive(Debug, PartialEq, Eq)]
+enum Age {
+    Fresh,
+    /// Expired, but within the stale-while-revalidate window.
+    Stale,
+    Expired,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<String, Entry>,
+    /// Keys by last use, oldest first.
+    lru: BTreeMap<u64, String>,
+    tick: u64,
+    /// Bytes held by `entries`.
+    size: u64,
+    /// Keys being written, so concurrent misses fill a copy once.
+    filling: HashSet<String>,
+}
+
+struct Entry {
+    meta: Meta,
+    used: u64,
+    /// Whether the body has been checked against `meta.checksum` since the
+    /// sidecar was loaded. Copies written by this process start verified.
+    verified: bool,
+}
+
+/// The `.meta` sidecar of a copy.
+#[derive(Clone, Serialize, Deserialize)]
+struct Meta {
+    /// Root identity and relative path of the object.
+    key: String,
+    size: u64,
+    /// Origin modification time, seconds since the epoch.
+    modified: u64,
+    /// When the copy was fetched, seconds since the epoch.
+    fetched: u64,
+    /// FNV-1a of the body.
+    checksum: u64,
+}
+
+impl DiskCache {
+    /// Open the cache in `cfg.dir`, creating it if needed. Sidecars whose
+    /// body is missing or of the wrong size, bodies without a sidecar and
+    /// leftover temporary files are removed.
+    pub(crate) fn open(cfg: &RemoteCacheConfig) -> io::Result<Self> {
+        std::fs::create_dir_all(&cfg.dir)?;
+        let dir = std::fs::canonicalize(&cfg.dir)?;
+
+        let mut loaded = Vec::new();
+        let mut stray = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "meta") {
+                match read_meta(&path) {
+                    Some(meta) if body_size(&dir, &meta.key) == Some(meta.size) => {
+                        loaded.push(meta)
+                    }
+                    _ => stray.push(path),
+                }
+            } else if path.extension().is_some_and(|e| e == "tmp") {
+                stray.push(path);
+            }
+        }
+        let known: HashSet<PathBuf> = loaded.iter().map(|m| dir.join(file_name(&m.key))).collect();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let sidecar = path.extension().is_some_and(|e| e == "meta");
+            if !sidecar && !known.contains(&path) && !stray.contains(&path) {
+                stray.push(path);
+            }
+        }
+        for path in stray {
+            let _ = std::fs::remove_file(path);
+        }
+
+        let cache = Self {
+            dir,
+            max_size: cfg.max_size.as_u64(),
+            ttl: (cfg.ttl_secs > 0).then(|| Duration::from_secs(cfg.ttl_secs)),
+            stale: Duration::from_secs(cfg.stale_while_revalidate_secs),
+            refreshes: Arc::new(Semaphore::new(cfg.max_refreshes)),
+            state: Mutex::default(),
+        };
+        // Oldest fetches are evicted first.
+        loaded.sort_by_key(|m| m.fetched);
+        let mut state = cache.state.lock().unwrap();
+        for meta in loaded {
+            cache.insert(&mut state, meta, false);
+        }
+        cache.evict(&mut state);
+        info!(
+            dir = %cache.dir.display(), entries = state.entries.len(),
+            size = %crate::config::ByteSize(state.size), "remote cache opened"
+        );
+        drop(state);
+        Ok(cache)
+    }
+
+    /// Wrap a remote root so its objects are served from the cache.
+    pub(crate) fn wrap(
+        self: &Arc<Self>,
+        identity: &Path,
+        inner: Arc<dyn StorageBackend>,
+    ) -> Arc<dyn StorageBackend> {
+        Arc::new(CachedBackend {
+            identity: identity.to_string_lossy().into_owned(),
+            inner,
+            cache: self.clone(),
+        })
+    }
+
+    /// The usable copy of `key`, opened as a local file, and whether it is
+    /// stale. Its integrity is checked if this is its first use since
+    /// startup; a damaged copy is dropped.
+    async fn get(&self, key: &str) -> Option<(FoundObject, bool)> {
+        let (meta, verified, age) = {
+            let mut state = self.state.lock().unwrap();
+            let entry = state.entries.get(key)?;
+            let age = self.age(&entry.meta);
+            if age == Age::Expired {
+                return None;
+            }
+            let (meta, verified) = (entry.meta.clone(), entry.verified);
+            self.touch(&mut state, key);
+            (meta, verified, age)
+        };
+        let path = self.dir.join(file_name(key));
+
+        if !verified {
+            let owned = path.clone();
+            let checksum = tokio::task::spawn_blocking(move || checksum_file(&owned)).await;
+            if !matches!(checksum, Ok(Ok(sum)) if sum == meta.checksum) {
+                warn!(key, path = %path.display(), "cached copy failed its integrity check; dropping it");
+                self.remove(&mut self.state.lock().unwrap(), key);
+                return None;
+            }
+            if let Some(entry) = self.state.lock().unwrap().entries.get_mut(key) {
+                entry.verified = true;
+            }
+        }
+
+        let file = match File::open(&path).await {
+            Ok(file) => file,
+            Err(_) => {
+                self.remove(&mut self.state.lock().unwrap(), key);
+                return None;
+            }
+        };
+        if !file.metadata().await.is_ok_and(|m| m.len() == meta.size) {
+            warn!(key, path = %path.display(), "cached copy changed size; dropping it");
+            self.remove(&mut self.state.lock().unwrap(), key);
+            return None;
+        }
+        let found = FoundObject {
+            path,
+            size: meta.size,
+            modified: SystemTime::UNIX_EPOCH + Duration::from_secs(meta.modified),
+            body: ObjectBody::File(file),
+        };
+        Some((found, age == Age::Stale))
+    }
+
+    /// Fetch a stale copy's object again in the background, unless it is
+    /// already being filled or every refresh permit is taken.
+    fn refresh(
+        self: &Arc<Self>,
+        key: String,
+        inner: Arc<dyn StorageBackend>,
+        relative: PathBuf,
+        request_path: String,
+    ) {
+        if self.state.lock().unwrap().filling.contains(&key) {
+            return;
+        }
+        let Ok(permit) = self.refreshes.clone().try_acquire_owned() else {
+            debug!(key, "refresh limit reached; serving stale copy");
+            return;
+        };
+        let cache = self.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            match inner.probe(&relative, &request_path).await {
+                Ok(Some(found)) => cache.revalidate(key, found).await,
+                Ok(None) => {
+                    debug!(key, "object gone from origin; dropping cached copy");
+                    cache.remove(&mut cache.state.lock().unwrap(), &key);
+                }
+                Err(_) => {}
+            }
+        });
+    }
+
+    /// Renew the copy of `key` if `found` is the same version; otherwise
+    /// replace it with `found`'s body.
+    async fn revalidate(self: &Arc<Self>, key: String, found: FoundObject) {
+        let renewed = {
+            let mut state = self.state.lock().unwrap();
+            match state.entries.get_mut(&key) {
+                Some(entry)
+                    if entry.meta.size == found.size
+                        && entry.meta.modified == unix_secs(found.modified) =>
+                {
+                    entry.meta.fetched = unix_secs(SystemTime::now());
+                    Some(entry.meta.clone())
+                }
+                _ => None,
+            }
+        };
+        if let Some(meta) = renewed {
+            debug!(key, "cached copy revalidated");
+            if let Err(e) = self.write_meta(&file_name(&key), &meta).await {
+                warn!(key, error = %e, "cannot update cached copy");
+            }
+            return;
+        }
+        // Nobody reads this body; draining it fills the cache.
+        if let ObjectBody::Stream(mut body) = self.fill(key, found).body {
+            while let Some(Ok(_)) = body.next().await {}
+        }
+    }
+
+    /// Copy `found`'s body into the cache as it streams to the client.
+    /// Objects of unknown size or larger than the whole cache pass through.
+    fn fill(self: &Arc<Self>, key: String, mut found: FoundObject) -> FoundObject {
+        if found.size == 0 || found.size > self.max_size {
+            return found;
+        }
+        let ObjectBody::Stream(body) = found.body else {
+            return found;
+        };
+        if !self.state.lock().unwrap().filling.insert(key.clone()) {
+            found.body = ObjectBody::Stream(body);
+            return found;
+        }
+        let (body, rx) = tee(body);
+        found.body = ObjectBody::Stream(body);
+        let meta = Meta {
+            key,
+            size: found.size,
+            modified: unix_secs(found.modified),
+            fetched: unix_secs(SystemTime::now()),
+            checksum: 0,
+        };
+        tokio::spawn(self.clone().store(rx, meta));
+        found
+    }
+
+    /// Write a filled copy and its sidecar, then index it.
+    async fn store(self: Arc<Self>, rx: mpsc::UnboundedReceiver<Option<Bytes>>, mut meta: Meta) {
+        let name = file_name(&meta.key);
+        let temp = self.dir.join(format!(
+            "{name}.{}-{}.tmp",
+            std::process::id(),
+            FILLS.fetch_add(1, Ordering::Relaxed),
+        ));
+        let written = write_body(&temp, rx).await;
+        let stored = match written {
+            Ok(Some((size, checksum))) if size == meta.size => {
+                meta.checksum = checksum;
+                self.place(&temp, &name, &meta).await
+            }
+            Ok(_) => Err(io::Error::other("body ended early")),
+            Err(e) => Err(e),
+        };
+
+        let mut state = self.state.lock().unwrap();
+        state.filling.remove(&meta.key);
+        match stored {
+            Ok(()) => {
+                debug!(key = meta.key, size = meta.size, "remote object cached");
+                self.insert(&mut state, meta, true);
+                self.evict(&mut state);
+            }
+            Err(e) => {
+                debug!(key = meta.key, error = %e, "remote object not cached");
+                let _ = std::fs::remove_file(&temp);
+            }
+        }
+    }
+
+    /// Move a complete body into place, then write its sidecar. A crash in
+    /// between leaves a mismatch the integrity check catches.
+    async fn place(&self, temp: &Path, name: &str, meta: &Meta) -> io::Result<()> {
+        fs::rename(temp, self.dir.join(name)).await?;
+        self.write_meta(name, meta).await
+    }
+
+    async fn write_meta(&self, name: &str, meta: &Meta) -> io::Result<()> {
+        let sidecar = serde_json::to_vec(meta).map_err(io::Error::other)?;
+        fs::write(self.dir.join(format!("{name}.meta")), sidecar).await
+    }
+
+    fn age(&self, meta: &Meta) -> Age {
+        let Some(ttl) = self.ttl else {
+            return Age::Fresh;
+        };
+        let age = unix_secs(SystemTime::now()).saturating_sub(meta.fetched);
+        if age < ttl.as_secs() {
+            Age::Fresh
+        } else if age < (ttl + self.stale).as_secs() {
+            Age::Stale
+        } else {
+            Age::Expired
+        }
+    }
+
+    fn insert(&self, state: &mut State, meta: Meta, verified: bool) {
+        // Two keys hashing to the same file cannot both be kept.
+        let name = file_name(&meta.key);
+        let clash = state
+            .entries
+            .keys()
+            .find(|k| **k != meta.key && file_name(k) == name)
+            .cloned();
+        if let Some(clash) = clash {
+            self.remove(state, &clash);
+        }
+        if let Some(old) = state.entries.remove(&meta.key) {
+            state.lru.remove(&old.used);
+            state.size -= old.meta.size;
+        }
+        state.tick += 1;
+        let used = state.tick;
+        state.lru.insert(used, meta.key.clone());
+        state.size += meta.size;
+        let entry = Entry {
+            meta,
+            used,
+            verified,
+        };
+        state.entries.insert(entry.meta.key.clone(), entry);
+    }
+
+    fn touch(&self, state: &mut State, key: &str) {
+        state.tick += 1;
+        let tick = state.tick;
+        if let Some(entry) = state.entries.get_mut(key) {
+            state.lru.remove(&entry.used);
+            entry.used = tick;
+            state.lru.insert(tick, key.to_owned());
+        }
+    }
+
+    /// Drop `key` from the index and delete its files.
+    fn remove(&self, state: &mut State, key: &str) {
+        let Some(entry) = state.entries.remove(key) else {
+            return;
+        };
+        state.lru.remove(&entry.used);
+        state.size -= entry.meta.size;
+        let name = file_name(key);
+        let _ = std::fs::remove_file(self.dir.join(format!("{name}.meta")));
+        let _ = std::fs::remove_file(self.dir.join(name));
+    }
+
+    /// Remove the least recently used copies until the cache fits
+    /// `max_size`.
+    fn evict(&self, state: &mut State) {
+        while state.size > self.max_size {
+            let Some((_, key)) = state.lru.pop_first() else {
+                break;
+            };
+            debug!(key, "evicting cached copy");
+            self.remove(state, &key);
+        }
+    }
+}
+
+/// Stream `rx` into a new file at `temp`. `Ok(None)` when the body did not
+/// end cleanly; otherwise its size and checksum.
+async fn write_body(
+    temp: &Path,
+    mut rx: mpsc::UnboundedReceiver<Option<Bytes>>,
+) -> io::Result<Option<(u64, u64)>> {
+    let mut file = File::create_new(temp).await?;
+    let (mut size, mut checksum) = (0, FNV_OFFSET);
+    while let Some(chunk) = rx.recv().await {
+        let Some(chunk) = chunk else {
+            file.sync_all().await?;
+            return Ok(Some((size, checksum)));
+        };
+        file.write_all(&chunk).await?;
+        size += chunk.len() as u64;
+        checksum = fnv1a(checksum, &chunk);
+    }
+    Ok(None)
+}
+
+/// A remote root whose probes check the cache first and fill it on a miss
+/// or, in the background, when the copy is stale.
+struct CachedBackend {
+    identity: String,
+    inner: Arc<dyn StorageBackend>,
+    cache: Arc<DiskCache>,
+}
+
+impl StorageBackend for CachedBackend {
+    fn probe<'a>(
+        &'a self,
+        relative: &'a Path,
+        request_path: &'a str,
+    ) -> BoxFuture<'a, Result<Option<FoundObject>, ProbeError>> {
+        Box::pin(async move {
+            let key = format!("{}/{}", self.identity, relative.to_string_lossy());
+            if let Some((found, stale)) = self.cache.get(&key).await {
+                debug!(request_path, key, stale, "served from remote cache");
+                if stale {
+                    let (relative, request_path) =
+                        (relative.to_path_buf(), request_path.to_owned());
+                    self.cache
+                        .refresh(key, self.inner.clone(), relative, request_path);
+                }
+                return Ok(Some(found));
+            }
+            let found = self.inner.probe(relative, request_path).await?;
+            Ok(found.map(|found| self.cache.fill(key, found)))
+        })
+    }
+
+    fn check(&self) -> BoxFuture<'_, Result<(), String>> {
+        self.inner.check()
+    }
+}
+
+fn read_meta(path: &Path) -> Option<Meta> {
+    serde_json::from_slice(&std::fs::read(path).ok()?).ok()
+}
+
+fn body_size(dir: &Path, key: &str) -> Option<u64> {
+    std::fs::metadata(dir.join(file_name(key)))
+        .ok()
+        .map(|m| m.len())
+}
+
+/// The body file of `key`: its hash, plus its extension so the copy gets
+/// the object's content type.
+fn file_name(key: &str) -> String {
+    let hash = fnv1a(FNV_OFFSET, key.as_bytes());
+    match Path::new(key).extension().and_then(|e| e.to_str()) {
+        Some(ext) if ext.len() <= 16 && ext.bytes().all(|b| b.is_ascii_alphanumeric()) => {
+            format!("{hash:016x}.{ext}")
+        }
+        _ => format!("{hash:016x}"),
+    }
+}
+
+fn checksum_file(path: &Path) -> io::Result<u64> {
+    let mut file = std::fs::File::open(path)?;
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut checksum = FNV_OFFSET;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(checksum);
+        }
+        checksum = fnv1a(checksum, &buf[..n]);
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &b in bytes {
+        hash ^= u64::from(b);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ByteSize;
+    use futures_util::{StreamExt, stream};
+    use tokio::io::AsyncReadExt;
+
+    fn object(data: &'static str) -> FoundObject {
+        let chunks = data
+            .as_bytes()
+            .chunks(3)
+            .map(|c| Ok(Bytes::from_static(c)))
+            .collect::<Vec<_>>();
+        FoundObject {
+            path: PathBuf::from("s3://bucket/x"),
+            size: data.len() as u64,
+            modified: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            body: ObjectBody::Stream(Box::pin(stream::iter(chunks))),
+        }
+    }
+
+    async fn drain(found: FoundObject) -> String {
+        let mut data = Vec::new();
+        match found.body {
+            ObjectBody::File(mut file) => {
+                file.read_to_end(&mut data).await.unwrap();
+            }
+            ObjectBody::Stream(mut body) => {
+                while let Some(chunk) = body.next().await {
+                    data.extend_from_slice(&chunk.unwrap());
+                }
+            }
+        }
+        String::from_utf8(data).unwrap()
+    }
+
+    /// Wait for the background fill of `key`.
+    async fn filled(cache: &DiskCache, key: &str) {
+        for _ in 0..100 {
+            if cache.state.lock().unwrap().entries.contains_key(key) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("{key} was not cached");
+    }
+
+    #[tokio::test]
+    async fn fills_evicts_and_verifies_copies() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = RemoteCacheConfig {
+            dir: dir.path().to_path_buf(),
+            max_size: ByteSize(12),
+            ttl_secs: 0,
+            ..Default::default()
+        };
+        let cache = Arc::new(DiskCache::open(&cfg).unwrap());
+
+        let a = cache.fill("s3://b/a.txt".into(), object("aaaaaaa"));
+        assert_eq!(drain(a).await, "aaaaaaa");
+        filled(&cache, "s3://b/a.txt").await;
+        let (hit, stale) = cache.get("s3://b/a.txt").await.unwrap();
+        assert!(!stale);
+        assert!(hit.path.to_string_lossy().ends_with(".txt"));
+        assert_eq!(hit.modified, object("").modified);
+        assert_eq!(drain(hit).await, "aaaaaaa");
+
+        // A second copy overflows the budget and evicts the first.
+        drain(cache.fill("s3://b/b.txt".into(), object("bbbbbbb"))).await;
+        filled(&cache, "s3://b/b.txt").await;
+        assert!(cache.get("s3://b/a.txt").await.is_none());
+
+        // A reopened cache checks copies before serving them.
+        drop(cache);
+        let copy = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .find(|p| p.extension().is_some_and(|e| e == "txt"))
+            .unwrap();
+        std::fs::write(&copy, "bbbbbbB").unwrap();
+        let cache = DiskCache::open(&cfg).unwrap();
+        assert!(cache.get("s3://b/b.txt").await.is_none());
+        assert!(!copy.exists());
+    }
+
+    #[tokio::test]
+    async fn serves_stale_copies_while_refreshing() {
+        use crate::backend::memory::MemoryBackend;
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Arc::new(
+            DiskCache::open(&RemoteCacheConfig {
+                dir: dir.path().to_path_buf(),
+                ttl_secs: 60,
+                stale_while_revalidate_secs: 600,
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+        let origin = MemoryBackend::new("mem", Duration::ZERO, &Default::default())
+            .with_file("a.txt", "new", modified);
+        let root = cache.wrap(Path::new("mem"), Arc::new(origin));
+        let age = |cache: &DiskCache, secs: u64| {
+            let mut state = cache.state.lock().unwrap();
+            state.entries.get_mut("mem/a.txt").unwrap().meta.fetched -= secs;
+        };
+
+        drain(cache.fill("mem/a.txt".into(), object("old-data"))).await;
+        filled(&cache, "mem/a.txt").await;
+
+        // Past its TTL, the copy is served while the origin is asked again.
+        age(&cache, 120);
+        let found = root
+            .probe(Path::new("a.txt"), "/a.txt")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(drain(found).await, "old-data");
+        for _ in 0..100 {
+            if cache.state.lock().unwrap().entries["mem/a.txt"].meta.size == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let found = root
+            .probe(Path::new("a.txt"), "/a.txt")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(found.body, ObjectBody::File(_)));
+        assert_eq!(drain(found).await, "new");
+
+        // Past the stale window, the client waits for the origin.
+        age(&cache, 10_000);
+        let found = root
+            .probe(Path::new("a.txt"), "/a.txt")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(found.body, ObjectBody::Stream(_)));
+    }
+}
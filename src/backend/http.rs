@@ -0,0 +1,218 @@
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use base64::Engine as _;
+use bytes::Bytes;
+use http_body_util::Empty;
+use hyper::body::Incoming;
+use hyper::header::{HeaderValue, AUTHORIZATION};
+use hyper::{Method, Request, Response, StatusCode, Uri};
+use tracing::{debug, warn};
+
+use super::remote::{self, HttpClient};
+use super::{BoxFuture, FoundObject, ProbeError, StorageBackend};
+use crate::config::RemoteAuth;
+
+/// An HTTP(S) origin or WebDAV collection used as a search root: a request
+/// for `/a/b.jpg` becomes `GET <base>/a/b.jpg`, and the response body is
+/// streamed through.
+pub(crate) struct HttpBackend {
+    client: HttpClient,
+    /// The root as configured (trailing `/` removed), shown in logs and status.
+    identity: String,
+    /// `http(s)://` base URL without trailing `/`.
+    base: String,
+    authorization: Option<HeaderValue>,
+    /// WebDAV roots send `HEAD` first and only `GET` resources that exist,
+    /// which keeps misses cheap on archives that are slow to start a body.
+    head_first: bool,
+    timeout: Duration,
+}
+
+impl HttpBackend {
+    /// `root` is an `http(s)://` or `webdav(s)://` URL.
+    pub(crate) fn new(
+        client: HttpClient,
+        root: &str,
+        auth: Option<&RemoteAuth>,
+        timeout: Duration,
+    ) -> Result<Self, String> {
+        let identity = root.trim_end_matches('/');
+        let (base, head_first) = if let Some(rest) = identity.strip_prefix("webdav://") {
+            (format!("http://{rest}"), true)
+        } else if let Some(rest) = identity.strip_prefix("webdavs://") {
+            (format!("https://{rest}"), true)
+        } else {
+            (identity.to_owned(), false)
+        };
+
+        let uri: Uri = base.parse().map_err(|e| format!("invalid URL: {e}"))?;
+        if uri.host().is_none() {
+            return Err("URL has no host".into());
+        }
+        if uri.query().is_some() {
+            return Err("URL must not have a query string".into());
+        }
+
+        let authorization = match auth {
+            None => None,
+            Some(RemoteAuth::Basic { username, password }) => {
+                let encoded = base64::engine::general_purpose::STANDARD
+                    .encode(format!("{username}:{password}"));
+                Some(format!("Basic {encoded}"))
+            }
+            Some(RemoteAuth::Bearer { token }) => Some(format!("Bearer {token}")),
+        }
+        .map(|value| {
+            let mut value = HeaderValue::try_from(value)
+                .map_err(|_| "auth contains characters not allowed in a header".to_string())?;
+            value.set_sensitive(true);
+            Ok::<_, String>(value)
+        })
+        .transpose()?;
+
+        Ok(Self {
+            client,
+            identity: identity.to_owned(),
+            base,
+            authorization,
+            head_first,
+            timeout,
+        })
+    }
+
+    /// The root as shown in logs and status: the configured URL.
+    pub(crate) fn identity(&self) -> PathBuf {
+        PathBuf::from(&self.identity)
+    }
+
+    /// Upstream URL for a sanitized relative path, or `None` if it is not UTF-8.
+    fn url(&self, relative: &Path) -> Option<String> {
+        let mut url = self.base.clone();
+        for component in relative.components() {
+            if let Component::Normal(part) = component {
+                url.push('/');
+                url.push_str(&remote::encode_path(part.to_str()?));
+            }
+        }
+        Some(url)
+    }
+
+    async fn send(&self, method: Method, url: &str) -> Result<Response<Incoming>, String> {
+        let mut builder = Request::builder().method(method).uri(url);
+        if let Some(auth) = &self.authorization {
+            builder = builder.header(AUTHORIZATION, auth.clone());
+        }
+        let req = builder.body(Empty::<Bytes>::new()).map_err(|e| e.to_string())?;
+        remote::send(&self.client, req, self.timeout).await
+    }
+
+    /// Whether a probe response means "found"; logs misses and surprises.
+    fn is_hit(
+        &self,
+        status: StatusCode,
+        request_path: &str,
+        url: &str,
+    ) -> Result<bool, ProbeError> {
+        match status {
+            StatusCode::OK => Ok(true),
+            StatusCode::NOT_FOUND | StatusCode::GONE => {
+                debug!(request_path, url, "upstream miss");
+                Ok(false)
+            }
+            status => {
+                warn!(request_path, url, status = status.as_u16(), "unexpected upstream response");
+                if status.is_server_error() {
+                    return Err(ProbeError::Failed(format!("upstream returned {status}")));
+                }
+                Ok(false)
+            }
+        }
+    }
+}
+
+impl StorageBackend for HttpBackend {
+    fn probe<'a>(
+        &'a self,
+        relative: &'a Path,
+        request_path: &'a str,
+    ) -> BoxFuture<'a, Result<Option<FoundObject>, ProbeError>> {
+        Box::pin(async move {
+            let Some(url) = self.url(relative) else {
+                return Ok(None);
+            };
+            let methods: &[Method] = if self.head_first {
+                &[Method::HEAD, Method::GET]
+            } else {
+                &[Method::GET]
+            };
+
+            let mut last = None;
+            for method in methods {
+                let resp = match self.send(method.clone(), &url).await {
+                    Ok(resp) => resp,
+                    Err(error) => {
+                        warn!(request_path, url, %method, error, "upstream request failed");
+                        return Err(ProbeError::Failed(error));
+                    }
+                };
+                if !self.is_hit(resp.status(), request_path, &url)? {
+                    return Ok(None);
+                }
+                last = Some(resp);
+            }
+            Ok(last.map(|resp| remote::found_object(PathBuf::from(url), resp)))
+        })
+    }
+
+    fn check(&self) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            let status = self.send(Method::HEAD, &format!("{}/", self.base)).await?.status();
+            let rejected = status == StatusCode::UNAUTHORIZED && self.authorization.is_some();
+            if status.is_server_error() || rejected {
+                return Err(format!("upstream returned {status}"));
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend(url: &str) -> Result<HttpBackend, String> {
+        HttpBackend::new(remote::client(), url, None, Duration::from_secs(1))
+    }
+
+    #[test]
+    fn builds_encoded_urls_under_base() {
+        let b = backend("https://origin.internal/files/").unwrap();
+        assert_eq!(b.identity(), PathBuf::from("https://origin.internal/files"));
+        assert_eq!(
+            b.url(Path::new("photos/a b#1.jpg")).unwrap(),
+            "https://origin.internal/files/photos/a%20b%231.jpg"
+        );
+        assert!(backend("https://origin.internal/files?x=1").is_err());
+        assert!(backend("/not/a/url").is_err());
+    }
+
+    #[test]
+    fn webdav_roots_map_to_http_with_auth() {
+        let auth = RemoteAuth::Basic {
+            username: "aladdin".into(),
+            password: "opensesame".into(),
+        };
+        let b = HttpBackend::new(
+            remote::client(),
+            "webdavs://dav.example/archive/",
+            Some(&auth),
+            Duration::from_secs(1),
+        )
+        .unwrap();
+        assert!(b.head_first);
+        assert_eq!(b.identity(), PathBuf::from("webdavs://dav.example/archive"));
+        assert_eq!(b.url(Path::new("x.pdf")).unwrap(), "https://dav.example/archive/x.pdf");
+        assert_eq!(b.authorization.unwrap(), "Basic YWxhZGRpbjpvcGVuc2VzYW1l");
+    }
+}
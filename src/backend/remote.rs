@@ -0,0 +1,109 @@
+// With only the `jwt` feature, just the client is used (to fetch JWKS).
+#![cfg_attr(not(any(feature = "s3", feature = "upstream")), allow(dead_code))]
+
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+
+use bytes::Bytes;
+use futures_util::Stream;
+use http_body_util::{BodyDataStream, Empty};
+use hyper::body::{Body, Incoming};
+use hyper::header::{CONTENT_LENGTH, LAST_MODIFIED};
+use hyper::{Request, Response};
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use super::{FoundObject, ObjectBody};
+
+/// Pooled HTTP(S) client shared by every remote root. Probes never send a body.
+pub(crate) type HttpClient = Client<HttpsConnector<HttpConnector>, Empty<Bytes>>;
+
+pub(crate) fn client() -> HttpClient {
+    Client::builder(TokioExecutor::new()).build(connector())
+}
+
+/// HTTP(S) connector trusting the webpki roots.
+pub(crate) fn connector() -> HttpsConnector<HttpConnector> {
+    hyper_rustls::HttpsConnectorBuilder::new()
+        .with_webpki_roots()
+        .https_or_http()
+        .enable_http1()
+        .enable_http2()
+        .build()
+}
+
+/// Characters left unescaped in a path segment (RFC 3986 unreserved).
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Percent-encode each `/`-separated segment of `path`.
+pub(crate) fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(|seg| utf8_percent_encode(seg, PATH_SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Send a request, giving up after `timeout` (headers only; the body streams
+/// without a deadline).
+pub(crate) async fn send(
+    client: &HttpClient,
+    req: Request<Empty<Bytes>>,
+    timeout: Duration,
+) -> Result<Response<Incoming>, String> {
+    match tokio::time::timeout(timeout, client.request(req)).await {
+        Ok(Ok(resp)) => Ok(resp),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("timed out after {}ms", timeout.as_millis())),
+    }
+}
+
+/// Turn a successful GET response into a found object whose body streams
+/// straight from the upstream connection.
+pub(crate) fn found_object(identity: PathBuf, resp: Response<Incoming>) -> FoundObject {
+    let headers = resp.headers();
+    let size = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .or_else(|| resp.body().size_hint().exact())
+        .unwrap_or(0);
+    let modified = headers
+        .get(LAST_MODIFIED)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok())
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+
+    let stream = IncomingStream(Mutex::new(BodyDataStream::new(resp.into_body())));
+    FoundObject {
+        path: identity,
+        size,
+        modified,
+        body: ObjectBody::Stream(Box::pin(stream)),
+    }
+}
+
+/// `Incoming` is not `Sync`; the mutex is only ever reached through `&mut`,
+/// so it never actually locks.
+struct IncomingStream(Mutex<BodyDataStream<Incoming>>);
+
+impl Stream for IncomingStream {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let inner = self.get_mut().0.get_mut().unwrap();
+        Pin::new(inner)
+            .poll_next(cx)
+            .map(|item| item.map(|r| r.map_err(io::Error::other)))
+    }
+}
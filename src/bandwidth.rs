@@ -0,0 +1,154 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, ready};
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use hyper::Response;
+use hyper::body::{Body, Frame, SizeHint};
+use tokio::time::{Instant, Sleep};
+
+use crate::server::ResponseBody;
+
+/// Token bucket on response body bytes, shared by every response it throttles.
+/// Holds up to one second of tokens, so an idle limiter allows a short burst.
+pub(crate) struct TokenBucket {
+    /// Bytes per second.
+    rate: f64,
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    /// May go negative: senders reserve ahead and wait off the debt.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec as f64;
+        Self {
+            rate,
+            state: Mutex::new(BucketState {
+                tokens: rate,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Reserve `n` bytes, returning how long to wait before sending them.
+    fn reserve(&self, n: usize) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let refill = now.duration_since(state.updated).as_secs_f64() * self.rate;
+        state.tokens = (state.tokens + refill).min(self.rate) - n as f64;
+        state.updated = now;
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.tokens / self.rate)
+        }
+    }
+}
+
+/// Pace the body of `resp` through `buckets`; each data frame waits for the
+/// slowest of them.
+pub(crate) fn throttle(
+    resp: Response<ResponseBody>,
+    buckets: Vec<Arc<TokenBucket>>,
+) -> Response<ResponseBody> {
+    if buckets.is_empty() {
+        return resp;
+    }
+    resp.map(|inner| {
+        Throttled {
+            inner,
+            buckets,
+            held: None,
+        }
+        .boxed()
+    })
+}
+
+struct Throttled {
+    inner: ResponseBody,
+    buckets: Vec<Arc<TokenBucket>>,
+    /// A frame waiting for its reservation to come due.
+    held: Option<(Pin<Box<Sleep>>, Frame<Bytes>)>,
+}
+
+impl Body for Throttled {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, io::Error>>> {
+        let this = self.get_mut();
+        if let Some((sleep, _)) = &mut this.held {
+            ready!(sleep.as_mut().poll(cx));
+            return Poll::Ready(this.held.take().map(|(_, frame)| Ok(frame)));
+        }
+
+        let frame = match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+            Some(Ok(frame)) => frame,
+            other => return Poll::Ready(other),
+        };
+        let len = frame.data_ref().map_or(0, Bytes::len);
+        let wait = this
+            .buckets
+            .iter()
+            .map(|bucket| bucket.reserve(len))
+            .max()
+            .unwrap_or_default();
+        if !wait.is_zero() {
+            let mut sleep = Box::pin(tokio::time::sleep(wait));
+            if sleep.as_mut().poll(cx).is_pending() {
+                this.held = Some((sleep, frame));
+                return Poll::Pending;
+            }
+        }
+        Poll::Ready(Some(Ok(frame)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.held.is_none() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let mut hint = self.inner.size_hint();
+        if let Some((_, frame)) = &self.held {
+            let held = frame.data_ref().map_or(0, |d| d.len() as u64);
+            if let Some(upper) = hint.upper() {
+                hint.set_upper(upper + held);
+            }
+            hint.set_lower(hint.lower() + held);
+        }
+        hint
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+    use http_body_util::StreamBody;
+
+    #[tokio::test(start_paused = true)]
+    async fn paces_body_to_rate() {
+        let chunks = (0..4).map(|_| Ok(Frame::data(Bytes::from(vec![0u8; 1000]))));
+        let body = StreamBody::new(stream::iter(chunks)).boxed();
+        let bucket = Arc::new(TokenBucket::new(1000));
+
+        let start = Instant::now();
+        let resp = throttle(Response::new(body), vec![bucket]);
+        let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(bytes.len(), 4000);
+        // One second of burst, then 3000 bytes at 1000 B/s.
+        assert_eq!(start.elapsed().as_secs(), 3);
+    }
+}
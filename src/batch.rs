@@ -0,0 +1,97 @@
+use futures_util::{StreamExt, stream};
+use hyper::body::Body;
+use hyper::{Method, Request, Response, StatusCode};
+use serde::Serialize;
+use tracing::debug;
+
+use crate::server::{
+    FileSearcher, ResponseBody, collect_body, json_response, text_response, unix_secs,
+};
+
+/// Paths resolved at the same time within one batch.
+const CONCURRENCY: usize = 16;
+
+#[derive(Serialize)]
+struct BatchEntry {
+    path: String,
+    exists: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    /// Modification time as Unix seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    modified: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_type: Option<String>,
+}
+
+/// `POST /_batch` with a JSON array of request paths. Responds with one entry
+/// per path, in request order; missing files have `"exists": false`.
+pub(crate) async fn handle<B>(
+    req: Request<B>,
+    searcher: &FileSearcher,
+    max_paths: usize,
+) -> Response<ResponseBody>
+where
+    B: Body,
+{
+    if req.method() != Method::POST {
+        return text_response(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed");
+    }
+    let (parts, body) = req.into_parts();
+    let body = match collect_body(body, searcher.max_body_size()).await {
+        Ok(b) => b,
+        Err(status) => return text_response(status, "Invalid Request Body"),
+    };
+    let paths: Vec<String> = match serde_json::from_slice(&body) {
+        Ok(p) => p,
+        Err(_) => return text_response(StatusCode::BAD_REQUEST, "Invalid JSON"),
+    };
+    if paths.len() > max_paths {
+        debug!(
+            status = 413,
+            paths = paths.len(),
+            max_paths,
+            "batch request handled"
+        );
+        return text_response(StatusCode::PAYLOAD_TOO_LARGE, "Too Many Paths");
+    }
+
+    let parts = &parts;
+    let entries: Vec<BatchEntry> = stream::iter(paths)
+        .map(|path| async move {
+            // Paths in locations the caller may not read are reported missing.
+            let hit = if searcher.authorized(&path, &parts.headers, parts.uri.query()).await {
+                searcher.search(&path).await
+            } else {
+                None
+            };
+            match hit {
+                // The body handle is dropped unread.
+                Some(hit) => BatchEntry {
+                    path,
+                    exists: true,
+                    size: Some(hit.size),
+                    modified: Some(unix_secs(hit.modified)),
+                    content_type: Some(hit.mime.to_string()),
+                },
+                None => BatchEntry {
+                    path,
+                    exists: false,
+                    size: None,
+                    modified: None,
+                    content_type: None,
+                },
+            }
+        })
+        .buffered(CONCURRENCY)
+        .collect()
+        .await;
+
+    debug!(
+        status = 200,
+        paths = entries.len(),
+        found = entries.iter().filter(|e| e.exists).count(),
+        "batch request handled"
+    );
+    json_response(StatusCode::OK, &entries)
+}
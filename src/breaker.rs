@@ -0,0 +1,252 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::config::CircuitBreakerConfig;
+use crate::metrics::escape_label;
+
+/// Where a root's circuit breaker stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// The root is searched.
+    Closed,
+    /// Too many recent probes failed; the root is skipped.
+    Open,
+    /// The cooldown is over and a trial probe decides.
+    HalfOpen,
+}
+
+/// Circuit breaker of one root: opens when too many of its latest probes
+/// failed or timed out, skips the root while open, and after the cooldown
+/// lets a single trial probe decide whether it closes again.
+pub(crate) struct CircuitBreaker {
+    cfg: CircuitBreakerConfig,
+    state: Mutex<State>,
+    /// Times the circuit opened, for `/_admin/metrics`.
+    opened: AtomicU64,
+}
+
+struct State {
+    circuit: Circuit,
+    /// Outcomes of the latest probes, `true` for failures; at most `window`.
+    recent: VecDeque<bool>,
+    failures: usize,
+}
+
+enum Circuit {
+    Closed,
+    Open {
+        until: Instant,
+    },
+    /// A trial probe was let through at `since`.
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(cfg: CircuitBreakerConfig) -> Self {
+        Self {
+            cfg,
+            state: Mutex::new(State {
+                circuit: Circuit::Closed,
+                recent: VecDeque::with_capacity(cfg.window),
+                failures: 0,
+            }),
+            opened: AtomicU64::new(0),
+        }
+    }
+
+    /// How long a probe may take before it counts as failed.
+    pub(crate) fn probe_timeout(&self) -> Option<Duration> {
+        (self.cfg.probe_timeout_ms > 0).then(|| Duration::from_millis(self.cfg.probe_timeout_ms))
+    }
+
+    fn cooldown(&self) -> Duration {
+        Duration::from_secs(self.cfg.cooldown_secs)
+    }
+
+    pub(crate) fn state(&self) -> CircuitState {
+        match self.state.lock().unwrap().circuit {
+            Circuit::Closed => CircuitState::Closed,
+            Circuit::Open { until } if Instant::now() < until => CircuitState::Open,
+            Circuit::Open { .. } | Circuit::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Whether the root may be probed now. Once the cooldown is over the
+    /// first caller gets the trial probe; another is let through if that
+    /// one never reports back (its request was dropped).
+    pub(crate) fn allow(&self) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        match state.circuit {
+            Circuit::Closed => true,
+            Circuit::Open { until } if now < until => false,
+            Circuit::HalfOpen { since } if now < since + self.cooldown() => false,
+            _ => {
+                state.circuit = Circuit::HalfOpen { since: now };
+                true
+            }
+        }
+    }
+
+    /// Record the outcome of a probe [`allow`](Self::allow) let through.
+    pub(crate) fn record(&self, failed: bool, root: &Path) {
+        let mut state = self.state.lock().unwrap();
+        match state.circuit {
+            Circuit::Closed => {
+                if state.recent.len() == self.cfg.window && state.recent.pop_front() == Some(true) {
+                    state.failures -= 1;
+                }
+                state.recent.push_back(failed);
+                state.failures += usize::from(failed);
+                let probes = state.recent.len();
+                if probes >= self.cfg.min_probes
+                    && state.failures as f64 >= self.cfg.failure_rate * probes as f64
+                {
+                    warn!(
+                        root = %root.display(), failures = state.failures, probes,
+                        cooldown_secs = self.cfg.cooldown_secs,
+                        "root failing, circuit opened"
+                    );
+                    self.open(&mut state);
+                }
+            }
+            Circuit::HalfOpen { .. } if failed => {
+                warn!(root = %root.display(), "trial probe failed, circuit opened again");
+                self.open(&mut state);
+            }
+            Circuit::HalfOpen { .. } => {
+                info!(root = %root.display(), "trial probe succeeded, circuit closed");
+                state.circuit = Circuit::Closed;
+                state.recent.clear();
+                state.failures = 0;
+            }
+            // A probe started before the circuit opened.
+            Circuit::Open { .. } => {}
+        }
+    }
+
+    fn open(&self, state: &mut State) {
+        state.circuit = Circuit::Open {
+            until: Instant::now() + self.cooldown(),
+        };
+        self.opened.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Render the state and open count of every `(location prefix, root,
+/// breaker)` in the Prometheus text exposition format.
+pub(crate) fn render<'a>(
+    out: &mut String,
+    roots: impl IntoIterator<Item = (&'a str, &'a Path, &'a CircuitBreaker)>,
+) {
+    let roots: Vec<_> = roots.into_iter().collect();
+    let _ = writeln!(
+        out,
+        "# HELP filehunter_root_circuit_state Circuit breaker of a root: 0 closed, 1 open, 2 half open.\n\
+         # TYPE filehunter_root_circuit_state gauge"
+    );
+    for (prefix, root, breaker) in &roots {
+        let labels = labels(prefix, root);
+        let _ = writeln!(
+            out,
+            "filehunter_root_circuit_state{{{labels}}} {}",
+            breaker.state() as u8
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP filehunter_root_circuit_opens_total Times a root's circuit breaker opened.\n\
+         # TYPE filehunter_root_circuit_opens_total counter"
+    );
+    for (prefix, root, breaker) in &roots {
+        let labels = labels(prefix, root);
+        let opened = breaker.opened.load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "filehunter_root_circuit_opens_total{{{labels}}} {opened}"
+        );
+    }
+}
+
+fn labels(prefix: &str, root: &Path) -> String {
+    let (prefix, root) = (escape_label(prefix), escape_label(&root.to_string_lossy()));
+    format!(r#"location="{prefix}",root="{root}""#)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_on_failure_rate_and_closes_after_trial() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            enabled: true,
+            window: 4,
+            min_probes: 3,
+            failure_rate: 0.5,
+            probe_timeout_ms: 0,
+            cooldown_secs: 1,
+        });
+        let root = Path::new("/srv/a");
+        let record = |outcomes: &[bool]| {
+            for &failed in outcomes {
+                assert!(breaker.allow());
+                breaker.record(failed, root);
+            }
+        };
+
+        // Too few probes yet, then a success rate that keeps it closed.
+        record(&[true, false]);
+        record(&[false, false, true]);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        // The window now holds [false, false, true, true].
+        record(&[true]);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.allow());
+
+        // Past the cooldown a single trial probe is let through.
+        let mut state = breaker.state.lock().unwrap();
+        state.circuit = Circuit::Open {
+            until: Instant::now(),
+        };
+        drop(state);
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+        breaker.record(true, root);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(breaker.opened.load(Ordering::Relaxed), 2);
+
+        breaker.state.lock().unwrap().circuit = Circuit::HalfOpen {
+            since: Instant::now(),
+        };
+        breaker.record(false, root);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        // The old failures are forgotten.
+        record(&[true, false]);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn renders_metrics() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig::default());
+        let mut out = String::new();
+        render(&mut out, [("/imgs", Path::new("/srv/a"), &breaker)]);
+        assert!(out.contains(r#"filehunter_root_circuit_state{location="/imgs",root="/srv/a"} 0"#));
+        assert!(
+            out.contains(
+                r#"filehunter_root_circuit_opens_total{location="/imgs",root="/srv/a"} 0"#
+            )
+        );
+    }
+}
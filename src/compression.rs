@@ -0,0 +1,367 @@
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::path::Path;
+use std::sync::Arc;
+
+use futures_util::TryFutureExt as _;
+use http_body_util::BodyExt as _;
+use hyper::header::{ACCEPT_ENCODING, HeaderMap};
+use hyper::{Request, Response, StatusCode};
+use tower::util::BoxCloneService;
+use tower::{ServiceBuilder, ServiceExt as _};
+use tower_http::compression::predicate::{And, DefaultPredicate, Predicate, SizeAbove};
+use tower_http::compression::{CompressionBody, CompressionLayer, CompressionLevel};
+
+use crate::config::{CompressionConfig, CompressionPreset};
+use crate::server::{ResponseBody, add_vary};
+
+/// A type-erased service, what [`Compression::wrap`] takes and returns.
+pub type BoxedService<B> = BoxCloneService<Request<B>, Response<ResponseBody>, Infallible>;
+
+/// Predicate: the configured content types + user `min_size`.
+type CompPredicate = And<ContentTypes, SizeAbove>;
+
+/// Which `Content-Type`s are compressed: without `content_types`, those
+/// `DefaultPredicate` allows (no images but SVG, no gRPC or event
+/// streams); with it, only the listed ones. `exclude_content_types` always
+/// wins. Partial responses are never compressed: their `Content-Range`
+/// counts bytes of the file itself.
+#[derive(Clone)]
+struct ContentTypes {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl Predicate for ContentTypes {
+    fn should_compress<B>(&self, response: &Response<B>) -> bool
+    where
+        B: hyper::body::Body,
+    {
+        if response.status() == StatusCode::PARTIAL_CONTENT {
+            return false;
+        }
+        let essence = response
+            .headers()
+            .get(hyper::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase();
+        if self.exclude.iter().any(|p| type_matches(p, &essence)) {
+            return false;
+        }
+        if self.include.is_empty() {
+            return DefaultPredicate::new().should_compress(response);
+        }
+        self.include.iter().any(|p| type_matches(p, &essence))
+    }
+}
+
+/// `text/*` matches every `text/` type; anything else matches exactly.
+fn type_matches(pattern: &str, essence: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) if prefix.ends_with('/') => essence.starts_with(prefix),
+        _ => pattern == essence,
+    }
+}
+
+/// Least preferred first, the order tower-http breaks ties in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Algorithm {
+    Deflate,
+    Gzip,
+    Br,
+    Zstd,
+}
+
+impl Algorithm {
+    /// A config name or `Accept-Encoding` token.
+    fn parse(token: &str) -> Option<Self> {
+        match token.to_ascii_lowercase().as_str() {
+            "deflate" => Some(Algorithm::Deflate),
+            "gzip" | "x-gzip" => Some(Algorithm::Gzip),
+            "br" => Some(Algorithm::Br),
+            "zstd" => Some(Algorithm::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// Response compression from `[server.compression]`.
+///
+/// tower-http applies one level to every algorithm of a layer, so each
+/// algorithm gets a layer of its own, and each request is sent to the layer
+/// of the encoding a single layer would have picked.
+#[derive(Clone)]
+pub struct Compression {
+    layers: Vec<(Algorithm, CompressionLayer<CompPredicate>)>,
+    /// Lowercase extensions whose requests bypass every layer.
+    skip_extensions: Arc<HashSet<String>>,
+}
+
+impl Compression {
+    pub fn new(cfg: &CompressionConfig) -> Self {
+        let min_size = cfg.min_size.as_u64().min(u16::MAX as u64) as u16;
+        let lowercase = |types: &[String]| types.iter().map(|t| t.to_ascii_lowercase()).collect();
+        let content_types = ContentTypes {
+            include: lowercase(&cfg.content_types),
+            exclude: lowercase(&cfg.exclude_content_types),
+        };
+        let predicate = content_types.and(SizeAbove::new(min_size));
+
+        let mut algorithms: Vec<Algorithm> = cfg
+            .algorithms
+            .iter()
+            .filter_map(|a| Algorithm::parse(a))
+            .collect();
+        algorithms.sort();
+        algorithms.dedup();
+        let layers = algorithms
+            .into_iter()
+            .map(|algo| {
+                // The encoding toggles only exist before `compress_when()`.
+                let layer = CompressionLayer::new()
+                    .deflate(algo == Algorithm::Deflate)
+                    .gzip(algo == Algorithm::Gzip)
+                    .br(algo == Algorithm::Br)
+                    .zstd(algo == Algorithm::Zstd)
+                    .quality(level(cfg, algo))
+                    .compress_when(predicate.clone());
+                (algo, layer)
+            })
+            .collect();
+        let skip_extensions = cfg
+            .skip_extensions
+            .iter()
+            .map(|e| e.trim_start_matches('.').to_ascii_lowercase())
+            .collect();
+        Self {
+            layers,
+            skip_extensions: Arc::new(skip_extensions),
+        }
+    }
+
+    /// Compress the responses of `inner`. Requests for paths with a
+    /// `skip_extensions` extension go straight to `inner`, before there is
+    /// a response to check.
+    pub fn wrap<B: Send + 'static>(&self, inner: BoxedService<B>) -> BoxedService<B> {
+        let compressed: Vec<(Algorithm, BoxedService<B>)> = self
+            .layers
+            .iter()
+            .map(|(algo, layer)| {
+                let service = ServiceBuilder::new()
+                    .map_response(rebox_response)
+                    .layer(layer.clone())
+                    .service(inner.clone());
+                (*algo, BoxCloneService::new(service))
+            })
+            .collect();
+        let skip_extensions = self.skip_extensions.clone();
+        BoxCloneService::new(tower::service_fn(move |req: Request<B>| {
+            let skipped = Path::new(req.uri().path())
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| skip_extensions.contains(&e.to_ascii_lowercase()));
+            let offered = compressed.iter().map(|(algo, _)| *algo);
+            let chosen = negotiate(req.headers(), offered);
+            // Without an acceptable encoding any layer serves identity, and
+            // still marks compressible responses `Vary: Accept-Encoding`.
+            let service = match compressed.iter().find(|(algo, _)| Some(*algo) == chosen) {
+                _ if skipped => &inner,
+                Some((_, service)) => service,
+                None => compressed.first().map_or(&inner, |(_, service)| service),
+            };
+            service.clone().oneshot(req).map_ok(move |mut resp| {
+                // A 304 stands in for a response that may have been
+                // compressed, but has no body for the layer to judge.
+                if !skipped && resp.status() == StatusCode::NOT_MODIFIED {
+                    add_vary(resp.headers_mut(), ACCEPT_ENCODING);
+                }
+                resp
+            })
+        }))
+    }
+}
+
+/// `levels.<algo>` if set, otherwise the `level` preset.
+fn level(cfg: &CompressionConfig, algo: Algorithm) -> CompressionLevel {
+    let levels = &cfg.levels;
+    let precise = match algo {
+        Algorithm::Deflate => levels.deflate,
+        Algorithm::Gzip => levels.gzip,
+        Algorithm::Br => levels.br,
+        Algorithm::Zstd => levels.zstd,
+    };
+    match (precise, cfg.level) {
+        (Some(level), _) => CompressionLevel::Precise(level as i32),
+        (None, CompressionPreset::Fastest) => CompressionLevel::Fastest,
+        (None, CompressionPreset::Balanced) => CompressionLevel::Default,
+        (None, CompressionPreset::Best) => CompressionLevel::Best,
+    }
+}
+
+/// The encoding tower-http would choose among `offered`: the highest
+/// non-zero `q`, ties going to the preferred algorithm. Entries with a
+/// malformed `q` are ignored, as are `*` and `identity`.
+fn negotiate(headers: &HeaderMap, offered: impl Iterator<Item = Algorithm>) -> Option<Algorithm> {
+    let offered: Vec<Algorithm> = offered.collect();
+    headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|item| {
+            let mut parts = item.splitn(2, ';');
+            let algo = Algorithm::parse(parts.next()?.trim()).filter(|a| offered.contains(a))?;
+            let q = match parts.next() {
+                Some(param) => quality(param.trim())?,
+                None => 1000,
+            };
+            Some((algo, q))
+        })
+        .filter(|&(_, q)| q > 0)
+        .max_by_key(|&(algo, q)| (q, algo))
+        .map(|(algo, _)| algo)
+}
+
+/// `q=0.5` in thousandths.
+fn quality(param: &str) -> Option<u16> {
+    let value = param
+        .strip_prefix("q=")
+        .or_else(|| param.strip_prefix("Q="))?;
+    let q: f32 = value.trim().parse().ok()?;
+    (0.0..=1.0)
+        .contains(&q)
+        .then(|| (q * 1000.0).round() as u16)
+}
+
+/// Re-box the compressed response body back into our erased `ResponseBody` type.
+///
+/// `CompressionBody` unifies errors into `BoxError`; we wrap it back into
+/// `std::io::Error` via `Error::other()` to match our `ResponseBody` alias.
+fn rebox_response(resp: Response<CompressionBody<ResponseBody>>) -> Response<ResponseBody> {
+    resp.map(|body| body.map_err(std::io::Error::other).boxed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CompressionLevels;
+    use crate::server::{empty_body, full_bytes};
+
+    #[test]
+    fn negotiates_like_a_single_layer() {
+        use Algorithm::*;
+        let pick = |accept: &str, offered: &[Algorithm]| {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT_ENCODING, accept.parse().unwrap());
+            negotiate(&headers, offered.iter().copied())
+        };
+        let all = [Deflate, Gzip, Br, Zstd];
+        assert_eq!(pick("gzip, deflate, br, zstd", &all), Some(Zstd));
+        assert_eq!(pick("gzip, deflate, br, zstd", &[Gzip, Br]), Some(Br));
+        assert_eq!(pick("gzip;q=1, br;q=0.5", &all), Some(Gzip));
+        assert_eq!(pick("br;q=0, x-gzip", &all), Some(Gzip));
+        assert_eq!(pick("br;q=nope", &all), None);
+        assert_eq!(pick("identity, *", &all), None);
+
+        let cfg = CompressionConfig {
+            level: CompressionPreset::Fastest,
+            levels: CompressionLevels {
+                zstd: Some(3),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(level(&cfg, Gzip), CompressionLevel::Fastest);
+        assert_eq!(level(&cfg, Zstd), CompressionLevel::Precise(3));
+    }
+
+    #[tokio::test]
+    async fn compresses_with_the_negotiated_layer() {
+        let cfg = CompressionConfig {
+            algorithms: vec!["gzip".into(), "zstd".into()],
+            levels: CompressionLevels {
+                gzip: Some(1),
+                ..Default::default()
+            },
+            skip_extensions: vec![".GZ".into(), "mp4".into()],
+            ..Default::default()
+        };
+        // 304 for any `If-None-Match`, otherwise 4 KiB of text.
+        async fn respond(req: Request<()>) -> Result<Response<ResponseBody>, Infallible> {
+            let resp = Response::builder().header("Content-Type", "text/plain");
+            Ok(match req.headers().contains_key("if-none-match") {
+                true => resp.status(StatusCode::NOT_MODIFIED).body(empty_body()),
+                false => resp.body(full_bytes("x".repeat(4096).into())),
+            }
+            .unwrap())
+        }
+        let inner: BoxedService<()> = BoxCloneService::new(tower::service_fn(respond));
+        let service = Compression::new(&cfg).wrap(inner);
+        let fetch = |uri: &'static str, accept: &'static str| {
+            let req = Request::builder()
+                .uri(uri)
+                .header(ACCEPT_ENCODING, accept)
+                .body(())
+                .unwrap();
+            service.clone().oneshot(req)
+        };
+        let get = |accept| fetch("/notes.txt", accept);
+
+        let resp = get("gzip, br").await.unwrap();
+        assert_eq!(resp.headers()["content-encoding"], "gzip");
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert!(body.len() < 4096);
+        let resp = get("gzip;q=0.5, zstd").await.unwrap();
+        assert_eq!(resp.headers()["content-encoding"], "zstd");
+        let resp = get("br").await.unwrap();
+        assert!(resp.headers().get("content-encoding").is_none());
+        assert_eq!(resp.headers()["vary"], "accept-encoding");
+        let req = Request::builder()
+            .header("If-None-Match", "*")
+            .body(())
+            .unwrap();
+        let resp = service.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers()["vary"], "accept-encoding");
+        // Skipped by extension whatever the response looks like.
+        let resp = fetch("/logs/app.log.gz", "gzip").await.unwrap();
+        assert!(resp.headers().get("content-encoding").is_none());
+        assert!(resp.headers().get("vary").is_none());
+    }
+
+    #[test]
+    fn content_type_lists_pick_what_is_compressed() {
+        let response = |content_type: &str| {
+            let body = full_bytes("x".repeat(4096).into());
+            let resp = Response::builder().header("Content-Type", content_type);
+            resp.body(body).unwrap()
+        };
+        let defaults = ContentTypes {
+            include: vec![],
+            exclude: vec!["application/zstd".into()],
+        };
+        assert!(defaults.should_compress(&response("text/html; charset=utf-8")));
+        assert!(defaults.should_compress(&response("application/wasm")));
+        assert!(!defaults.should_compress(&response("image/png")));
+        assert!(!defaults.should_compress(&response("application/zstd")));
+
+        let listed = ContentTypes {
+            include: vec!["text/*".into(), "application/wasm".into()],
+            exclude: vec!["text/event-stream".into()],
+        };
+        assert!(listed.should_compress(&response("text/css")));
+        assert!(listed.should_compress(&response("application/wasm")));
+        assert!(!listed.should_compress(&response("application/json")));
+        assert!(!listed.should_compress(&response("text/event-stream")));
+
+        let mut partial = response("multipart/byteranges; boundary=b");
+        *partial.status_mut() = StatusCode::PARTIAL_CONTENT;
+        assert!(!defaults.should_compress(&partial));
+        let css = "text/css".parse().unwrap();
+        partial.headers_mut().insert("Content-Type", css);
+        assert!(!listed.should_compress(&partial));
+    }
+}
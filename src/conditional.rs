@@ -0,0 +1,217 @@
+use std::time::{Duration, SystemTime};
+
+use hyper::header::{HeaderMap, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_UNMODIFIED_SINCE};
+use hyper::{Response, StatusCode};
+
+use crate::server::{ResponseBody, empty_body, text_response, unix_secs};
+
+/// The validators sent with a file: an `ETag`, by default a weak one from
+/// its modification time and size (a rewrite within the same second keeps
+/// both), and `Last-Modified` at the one-second precision HTTP dates have.
+pub(crate) struct Validators {
+    /// `None` when the location sends no `ETag`.
+    etag: Option<String>,
+    modified: SystemTime,
+}
+
+/// What the request's preconditions make of a `GET` or `HEAD`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Precondition {
+    /// None given, or all hold: serve the file.
+    Proceed,
+    /// `If-None-Match` or `If-Modified-Since` failed: 304.
+    NotModified,
+    /// `If-Match` or `If-Unmodified-Since` failed: 412.
+    Failed,
+}
+
+impl Validators {
+    pub(crate) fn new(size: u64, modified: SystemTime) -> Self {
+        let secs = unix_secs(modified);
+        Self {
+            etag: Some(format!("W/\"{secs:x}-{size:x}\"")),
+            modified: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+        }
+    }
+
+    /// Replace the `ETag` (an already quoted tag, `W/` when weak), or drop
+    /// it.
+    pub(crate) fn with_etag(mut self, etag: Option<String>) -> Self {
+        self.etag = etag;
+        self
+    }
+
+    /// The validators of a sidecar standing in for the file: the `ETag`
+    /// gains `-<suffix>` (`"<hash>-gz"`), as the bytes are not the file's.
+    pub(crate) fn encoded(mut self, suffix: &str) -> Self {
+        if let Some(etag) = &mut self.etag {
+            etag.pop();
+            etag.push_str(&format!("-{suffix}\""));
+        }
+        self
+    }
+
+    pub(crate) fn etag(&self) -> Option<&str> {
+        self.etag.as_deref()
+    }
+
+    pub(crate) fn last_modified(&self) -> String {
+        httpdate::fmt_http_date(self.modified)
+    }
+
+    /// Evaluate the preconditions in the order of RFC 9110 §13.2.2: a
+    /// date condition only counts when the matching entity-tag condition
+    /// is absent, and unparseable dates are ignored.
+    pub(crate) fn evaluate(&self, headers: &HeaderMap) -> Precondition {
+        if let Some(value) = header(headers, IF_MATCH) {
+            if !self.matches(value, false) {
+                return Precondition::Failed;
+            }
+        } else if let Some(since) = date(headers, IF_UNMODIFIED_SINCE)
+            && self.modified > since
+        {
+            return Precondition::Failed;
+        }
+
+        if let Some(value) = header(headers, IF_NONE_MATCH) {
+            if self.matches(value, true) {
+                return Precondition::NotModified;
+            }
+        } else if let Some(since) = date(headers, IF_MODIFIED_SINCE)
+            && self.modified <= since
+        {
+            return Precondition::NotModified;
+        }
+        Precondition::Proceed
+    }
+
+    /// Whether an `If-Range` value still describes the file, so its
+    /// `Range` applies. Only strong comparisons count: an exact date or
+    /// the current entity tag when it is strong.
+    pub(crate) fn if_range(&self, value: &str) -> bool {
+        let value = value.trim();
+        if value.starts_with('"') {
+            return self.strong_etag() == Some(value);
+        }
+        httpdate::parse_http_date(value).is_ok_and(|date| date == self.modified)
+    }
+
+    /// Whether an entity-tag list (`*` or `"a", W/"b"`) names this file.
+    /// The weak comparison ignores `W/` prefixes; the strong one skips weak
+    /// tags, ours included. Without an `ETag` only `*` matches.
+    fn matches(&self, list: &str, weak: bool) -> bool {
+        if list.trim() == "*" {
+            return true;
+        }
+        let etag = match weak {
+            true => self.etag().map(|etag| etag.trim_start_matches("W/")),
+            false => self.strong_etag(),
+        };
+        let Some(etag) = etag else {
+            return false;
+        };
+        list.split(',')
+            .map(str::trim)
+            .any(|tag| match tag.strip_prefix("W/") {
+                Some(tag) => weak && tag == etag,
+                None => tag == etag,
+            })
+    }
+
+    fn strong_etag(&self) -> Option<&str> {
+        self.etag().filter(|etag| !etag.starts_with("W/"))
+    }
+
+    /// 304 with the validators a cache needs to refresh its copy.
+    pub(crate) fn not_modified(&self) -> Response<ResponseBody> {
+        let mut builder = Response::builder().status(StatusCode::NOT_MODIFIED);
+        if let Some(etag) = self.etag() {
+            builder = builder.header("ETag", etag);
+        }
+        builder
+            .header("Last-Modified", self.last_modified())
+            .body(empty_body())
+            .unwrap()
+    }
+}
+
+pub(crate) fn failed() -> Response<ResponseBody> {
+    text_response(StatusCode::PRECONDITION_FAILED, "Precondition Failed")
+}
+
+fn header(headers: &HeaderMap, name: hyper::header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+fn date(headers: &HeaderMap, name: hyper::header::HeaderName) -> Option<SystemTime> {
+    header(headers, name).and_then(|v| httpdate::parse_http_date(v).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evaluates_preconditions_in_order() {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_500);
+        let v = Validators::new(10, modified);
+        assert_eq!(v.etag(), Some("W/\"6553f100-a\""));
+        assert_eq!(v.last_modified(), "Tue, 14 Nov 2023 22:13:20 GMT");
+        let check = |pairs: &[(&'static str, &str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(*name, value.parse().unwrap());
+            }
+            v.evaluate(&headers)
+        };
+        let (earlier, same) = ("Tue, 14 Nov 2023 22:13:19 GMT", v.last_modified());
+        use Precondition::*;
+
+        assert_eq!(check(&[]), Proceed);
+        // A weak tag never passes the strong comparison of If-Match.
+        assert_eq!(check(&[("if-match", "\"x\", \"6553f100-a\"")]), Failed);
+        assert_eq!(check(&[("if-match", "W/\"6553f100-a\"")]), Failed);
+        assert_eq!(check(&[("if-match", "*")]), Proceed);
+        assert_eq!(check(&[("if-unmodified-since", earlier)]), Failed);
+        assert_eq!(check(&[("if-unmodified-since", &same)]), Proceed);
+        // If-Match wins over If-Unmodified-Since.
+        assert_eq!(
+            check(&[("if-match", "*"), ("if-unmodified-since", earlier)]),
+            Proceed
+        );
+        assert_eq!(check(&[("if-unmodified-since", "yesterday")]), Proceed);
+
+        assert_eq!(check(&[("if-none-match", "W/\"6553f100-a\"")]), NotModified);
+        assert_eq!(check(&[("if-none-match", "\"6553f100-a\"")]), NotModified);
+        assert_eq!(check(&[("if-modified-since", &same)]), NotModified);
+        assert_eq!(check(&[("if-modified-since", earlier)]), Proceed);
+        // If-None-Match wins over If-Modified-Since.
+        assert_eq!(
+            check(&[("if-none-match", "\"x\""), ("if-modified-since", &same)]),
+            Proceed
+        );
+        // A failed If-Match is reported before a matching If-None-Match.
+        assert_eq!(
+            check(&[("if-match", "\"x\""), ("if-none-match", "*")]),
+            Failed
+        );
+
+        assert!(!v.if_range("\"6553f100-a\""));
+        assert!(!v.if_range("W/\"6553f100-a\""));
+        let hashed = Validators::new(10, modified).with_etag(Some("\"h\"".into()));
+        assert!(hashed.if_range("\"h\""));
+        let if_match = [(IF_MATCH, "\"h\"".parse().unwrap())].into_iter().collect();
+        assert_eq!(hashed.evaluate(&if_match), Proceed);
+        // A sidecar's tag is its own; the file's no longer matches it.
+        let gzip = Validators::new(10, modified)
+            .with_etag(Some("\"h\"".into()))
+            .encoded("gz");
+        assert_eq!(gzip.etag(), Some("\"h-gz\""));
+        assert!(!gzip.if_range("\"h\""));
+        assert_eq!(gzip.evaluate(&if_match), Failed);
+        let brotli = Validators::new(10, modified).encoded("br");
+        assert_eq!(brotli.etag(), Some("W/\"6553f100-a-br\""));
+        assert!(v.if_range(&same));
+        assert!(!v.if_range(earlier));
+    }
+}
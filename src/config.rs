@@ -1,9 +1,11 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
-use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 
+use regex_automata::meta::Regex;
 use serde::de;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 // ---------------------------------------------------------------------------
 // ByteSize — human-friendly byte size with serde support
@@ -51,6 +53,13 @@ impl fmt::Display for ByteSize {
     }
 }
 
+/// Serializes as a plain byte count so reports stay machine-readable.
+impl Serialize for ByteSize {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.0)
+    }
+}
+
 impl<'de> Deserialize<'de> for ByteSize {
     fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         struct Visitor;
@@ -116,9 +125,24 @@ fn parse_byte_size(s: &str) -> Result<ByteSize, String> {
 // Config
 // ---------------------------------------------------------------------------
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
     pub server: ServerConfig,
+    /// Locations for requests whose `Host` names no `[[vhosts]]` entry.
+    #[serde(default)]
+    pub locations: Vec<LocationConfig>,
+    /// Sites with their own locations, picked by the request's `Host`.
+    #[serde(default)]
+    pub vhosts: Vec<VhostConfig>,
+}
+
+/// A site served for a set of `Host` names.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct VhostConfig {
+    /// Host names, without port, e.g. `["img.example.com"]`. A leading
+    /// `*.` matches any subdomain: `"*.example.com"`.
+    pub hosts: Vec<String>,
     pub locations: Vec<LocationConfig>,
 }
 
@@ -126,7 +150,7 @@ pub struct Config {
 // CORS & Rate Limit configuration
 // ---------------------------------------------------------------------------
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(default)]
 pub struct CorsConfig {
     pub enabled: bool,
@@ -152,13 +176,100 @@ impl Default for CorsConfig {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// Response headers hardening browsers' handling of served files, added to
+/// every response. Empty values are omitted.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SecurityHeadersConfig {
+    pub enabled: bool,
+    /// `Strict-Transport-Security`, e.g. `"max-age=31536000; includeSubDomains"`.
+    /// Only meaningful when clients reach the server over HTTPS.
+    pub strict_transport_security: String,
+    pub x_frame_options: String,
+    pub referrer_policy: String,
+    /// `Cross-Origin-Resource-Policy`; `"same-origin"` stops other sites
+    /// embedding the files.
+    pub cross_origin_resource_policy: String,
+    pub content_security_policy: String,
+    pub permissions_policy: String,
+    /// Further headers by name.
+    pub custom: BTreeMap<String, String>,
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            strict_transport_security: String::new(),
+            x_frame_options: "DENY".into(),
+            referrer_policy: "strict-origin-when-cross-origin".into(),
+            cross_origin_resource_policy: String::new(),
+            content_security_policy: String::new(),
+            permissions_policy: String::new(),
+            custom: BTreeMap::new(),
+        }
+    }
+}
+
+impl SecurityHeadersConfig {
+    /// The configured headers; empty when disabled.
+    pub fn header_map(&self) -> Result<hyper::HeaderMap, String> {
+        let mut headers = hyper::HeaderMap::new();
+        if !self.enabled {
+            return Ok(headers);
+        }
+        let named = [
+            ("Strict-Transport-Security", &self.strict_transport_security),
+            ("X-Frame-Options", &self.x_frame_options),
+            ("Referrer-Policy", &self.referrer_policy),
+            ("Cross-Origin-Resource-Policy", &self.cross_origin_resource_policy),
+            ("Content-Security-Policy", &self.content_security_policy),
+            ("Permissions-Policy", &self.permissions_policy),
+        ];
+        let custom = self.custom.iter().map(|(k, v)| (k.as_str(), v));
+        for (name, value) in named.into_iter().chain(custom) {
+            if value.is_empty() {
+                continue;
+            }
+            let name = hyper::header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("security_headers: {name:?} is not a valid header name"))?;
+            let value = hyper::header::HeaderValue::from_str(value)
+                .map_err(|_| format!("security_headers: invalid value for {name}: {value:?}"))?;
+            headers.insert(name, value);
+        }
+        Ok(headers)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(default)]
 pub struct RateLimitConfig {
     pub enabled: bool,
     pub requests_per_second: u32,
     pub burst_size: u32,
     pub cleanup_interval: u64,
+    /// Client IPs/CIDRs never throttled (health checkers, CDN fetch ranges).
+    pub exempt: Vec<String>,
+    pub algorithm: RateLimitAlgorithm,
+    /// Window length in seconds for the windowed algorithms, which allow
+    /// `requests_per_second * window` requests per window.
+    pub window: u64,
+}
+
+/// How the per-IP rate limiter counts requests.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitAlgorithm {
+    /// Generic cell rate algorithm: `requests_per_second` sustained, with up
+    /// to `burst_size` requests at once after a quiet period.
+    #[default]
+    Gcra,
+    /// A counter per client that resets at each window boundary.
+    FixedWindow,
+    /// Like `fixed_window`, but the previous window's count still applies in
+    /// proportion to its overlap with the last `window` seconds, smoothing
+    /// out bursts at the boundaries.
+    SlidingWindow,
 }
 
 impl Default for RateLimitConfig {
@@ -168,16 +279,32 @@ impl Default for RateLimitConfig {
             requests_per_second: 10,
             burst_size: 30,
             cleanup_interval: 600,
+            exempt: Vec::new(),
+            algorithm: RateLimitAlgorithm::Gcra,
+            window: 1,
         }
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(default)]
 pub struct CompressionConfig {
     pub enabled: bool,
     pub algorithms: Vec<String>,
     pub min_size: ByteSize,
+    /// Level of every algorithm without one in `levels`.
+    pub level: CompressionPreset,
+    /// Levels for single algorithms, e.g. `{ gzip = 4, zstd = 3 }`.
+    pub levels: CompressionLevels,
+    /// Compress only these `Content-Type`s (`"text/*"` covers a whole
+    /// type). Empty: everything but images (SVG aside), gRPC and event
+    /// streams.
+    pub content_types: Vec<String>,
+    /// Never compress these `Content-Type`s, whatever `content_types` says.
+    pub exclude_content_types: Vec<String>,
+    /// Request path extensions never compressed, e.g. `["gz", "zip",
+    /// "mp4"]`; decided from the request alone.
+    pub skip_extensions: Vec<String>,
 }
 
 impl Default for CompressionConfig {
@@ -186,206 +313,2005 @@ impl Default for CompressionConfig {
             enabled: false,
             algorithms: vec!["gzip".into(), "br".into()],
             min_size: ByteSize(1024), // 1KB
+            level: CompressionPreset::default(),
+            levels: CompressionLevels::default(),
+            content_types: Vec::new(),
+            exclude_content_types: Vec::new(),
+            skip_extensions: Vec::new(),
         }
     }
 }
 
-/// All fields except `bind` have sensible defaults — existing configs keep working.
-#[derive(Debug, Clone, Deserialize)]
-#[serde(default)]
-pub struct ServerConfig {
-    /// Bind address, e.g. "0.0.0.0:8080".
-    pub bind: String,
+/// How hard response compression works, for all algorithms at once.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionPreset {
+    /// Each algorithm's lowest level.
+    Fastest,
+    /// Each algorithm's usual level (br 4, as nginx uses on the fly).
+    #[default]
+    Balanced,
+    /// Each algorithm's highest level; smallest output, most CPU.
+    Best,
+}
 
-    /// Enable HTTP/1.1 keep-alive.
-    pub keepalive: bool,
+/// Per-algorithm compression levels; unset ones follow `level`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CompressionLevels {
+    /// 0-9.
+    pub gzip: Option<u32>,
+    /// 0-9.
+    pub deflate: Option<u32>,
+    /// 0-11.
+    pub br: Option<u32>,
+    /// 1-22.
+    pub zstd: Option<u32>,
+}
 
-    /// Maximum connection lifetime in seconds (0 = unlimited).
-    pub connection_timeout: u64,
+/// `.gz` / `.br` / `.zst` sidecars next to local files, written ahead of
+/// time by `filehunter precompress`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PrecompressConfig {
+    /// Serve a sidecar instead of the file to clients that accept its
+    /// encoding, when it is at least as new as the file.
+    pub enabled: bool,
+    /// Sidecars to write and serve: "gzip", "br", "zstd".
+    pub algorithms: Vec<String>,
+    /// Files smaller than this get no sidecar.
+    pub min_size: ByteSize,
+}
 
-    /// Maximum size for the request line + headers. e.g. "8KB"
-    pub max_header_size: ByteSize,
+impl Default for PrecompressConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            algorithms: vec!["gzip".into(), "br".into()],
+            min_size: ByteSize(1024), // 1KB
+        }
+    }
+}
 
-    /// Maximum number of request headers.
-    pub max_headers: usize,
+/// Token-protected operator endpoints (e.g. `/_matches/<path>`).
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    pub enabled: bool,
+    /// Bearer token expected in `Authorization: Bearer <token>`.
+    #[serde(skip_serializing)]
+    pub token: String,
+}
 
-    /// Maximum allowed Content-Length. e.g. "1MB"
-    pub max_body_size: ByteSize,
+/// `GET /<dir>?archive=tar|zip`: download a directory as one archive.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ArchiveConfig {
+    pub enabled: bool,
+    /// Most files a single archive may contain; larger directories get 413.
+    pub max_entries: usize,
+}
 
-    /// HTTP/2 maximum concurrent streams per connection.
-    pub http2_max_streams: u32,
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_entries: 10_000,
+        }
+    }
+}
 
-    /// Maximum file size that can be served. e.g. "10MB"
-    /// Files exceeding this are skipped during search.
-    pub max_file_size: ByteSize,
+/// Read-only S3-style API (ListObjectsV2, GetObject, HeadObject) where each
+/// location without `auth` is a bucket.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct S3ApiConfig {
+    pub enabled: bool,
+    /// Path the API is mounted at: `<prefix>/<bucket>/<key>`.
+    pub prefix: String,
+    /// Most files one listing may walk; broader prefixes get 400.
+    pub max_scan: usize,
+}
 
-    /// Response streaming buffer size. e.g. "64KB"
-    pub stream_buffer_size: ByteSize,
+impl Default for S3ApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            prefix: "/_s3".into(),
+            max_scan: 100_000,
+        }
+    }
+}
 
-    /// CORS configuration.
-    pub cors: CorsConfig,
+/// `POST /_batch`: resolve many paths in one request.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BatchConfig {
+    pub enabled: bool,
+    /// Most paths accepted in a single request.
+    pub max_paths: usize,
+}
 
-    /// Per-IP rate limiting configuration.
-    pub rate_limit: RateLimitConfig,
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_paths: 500,
+        }
+    }
+}
 
-    /// Response compression configuration.
-    pub compression: CompressionConfig,
+/// SHA-256 `Repr-Digest` / `Digest` headers on responses for local files.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DigestConfig {
+    pub enabled: bool,
+    /// Files larger than this are served without a digest.
+    pub max_size: ByteSize,
+    /// Most digests kept in memory.
+    pub cache_entries: usize,
+    /// Compute digests on background workers instead of during the first
+    /// request; files are served without a digest (and with an mtime
+    /// `ETag` under `etag = "hash"`) until theirs is ready.
+    pub background: bool,
+    /// Files hashed at once in background mode.
+    pub workers: usize,
 }
 
-impl Default for ServerConfig {
+impl Default for DigestConfig {
     fn default() -> Self {
         Self {
-            bind: "0.0.0.0:8080".into(),
-            keepalive: true,
-            connection_timeout: 300,
-            max_header_size: ByteSize(8192),
-            max_headers: 64,
-            max_body_size: ByteSize(1_048_576),
-            http2_max_streams: 128,
-            max_file_size: ByteSize(10 * 1024 * 1024),
-            stream_buffer_size: ByteSize(65536),
-            cors: CorsConfig::default(),
-            rate_limit: RateLimitConfig::default(),
-            compression: CompressionConfig::default(),
+            enabled: false,
+            max_size: ByteSize(64 * 1024 * 1024), // 64MB
+            cache_entries: 10_000,
+            background: false,
+            workers: 2,
         }
     }
 }
 
-/// Controls how multiple search roots are probed.
-#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+/// Client IPs and CIDRs refused with 403 before rate limiting, read from
+/// `file` (one entry per line, `#` comments) and reloadable at runtime via
+/// `POST /_admin/denylist/reload`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DenylistConfig {
+    pub enabled: bool,
+    pub file: PathBuf,
+}
+
+/// Structured records of blocked requests (traversal attempts, dotfile
+/// probes, denied names and clients, failed auth). Records go to the
+/// `filehunter::audit` log target and, when `file` is set, are appended to
+/// it as JSON lines.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AuditLogConfig {
+    pub enabled: bool,
+    pub file: PathBuf,
+}
+
+/// Files flagged by `magic_bytes` or the antivirus scan, or matching
+/// `deny_patterns`, are moved or symlinked into `dir` (and audited) for
+/// operators to review.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct QuarantineConfig {
+    pub enabled: bool,
+    pub dir: PathBuf,
+    pub action: QuarantineAction,
+}
+
+/// How a flagged file goes into quarantine.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
-pub enum SearchMode {
-    /// Check each root sequentially in config order; first match wins.
-    /// Deterministic: config order defines priority.
+pub enum QuarantineAction {
+    /// Link it, leaving the file in place.
     #[default]
-    Sequential,
-    /// Probe all eligible roots concurrently; the fastest match wins.
-    /// Remaining searches are cancelled as soon as a result is found.
-    Concurrent,
-    /// Check all eligible roots and return the file with the most recent
-    /// modification time. Useful when the same filename exists in multiple
-    /// roots and the latest version should always be served.
-    LatestModified,
+    Symlink,
+    /// Move it out of the root, so it is no longer found.
+    Move,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct LocationConfig {
-    /// URL prefix for this location, e.g. "/imgs1".
-    pub prefix: String,
+/// Scans of local files by a ClamAV daemon or an ICAP server before they are
+/// first served, and of uploads with `scan_uploads`. Verdicts are cached per
+/// file version; infected files are refused with 403 and audited.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AntivirusConfig {
+    pub enabled: bool,
+    pub protocol: AntivirusProtocol,
+    /// clamd: `host:port` or `unix:/path/to/clamd.ctl`; ICAP: `host:port`.
+    pub address: String,
+    /// ICAP service, as in `icap://host:port/<service>`.
+    pub icap_service: String,
+    /// Longest a scan may take before it counts as failed.
+    pub timeout_ms: u64,
+    /// Files larger than this are served unscanned.
+    pub max_size: ByteSize,
+    /// Most verdicts kept in memory.
+    pub cache_entries: usize,
+    /// Scan uploads before they are moved into place; infected ones are
+    /// discarded with 403.
+    pub scan_uploads: bool,
+    /// Serve files when the scanner fails instead of answering 503.
+    pub fail_open: bool,
+}
 
-    /// Search strategy. Default: `"sequential"`.
-    #[serde(default)]
-    pub mode: SearchMode,
+impl Default for AntivirusConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            protocol: AntivirusProtocol::Clamd,
+            address: String::new(),
+            icap_service: "avscan".into(),
+            timeout_ms: 30_000,
+            max_size: ByteSize(25 * 1024 * 1024), // 25MB, clamd's StreamMaxLength
+            cache_entries: 10_000,
+            scan_uploads: false,
+            fail_open: false,
+        }
+    }
+}
 
-    /// Per-location maximum file size override.
-    /// If omitted, falls back to `[server].max_file_size`.
-    pub max_file_size: Option<ByteSize>,
+/// How files reach the antivirus scanner.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AntivirusProtocol {
+    /// clamd's `INSTREAM` command.
+    #[default]
+    Clamd,
+    /// ICAP `RESPMOD` (RFC 3507).
+    Icap,
+}
 
-    /// Search paths for this location.
-    pub paths: Vec<SearchPath>,
+/// Recent 404s (path, location, client, time) kept in memory for
+/// `GET /_admin/misses` and, when `dump_file` is set, appended to it as
+/// JSON lines every `dump_interval` seconds.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MissLogConfig {
+    pub enabled: bool,
+    /// Misses kept; the oldest are dropped first.
+    pub capacity: usize,
+    pub dump_file: PathBuf,
+    pub dump_interval: u64,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct SearchPath {
-    /// Root directory for this search entry.
-    pub root: PathBuf,
+impl Default for MissLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            capacity: 10_000,
+            dump_file: PathBuf::new(),
+            dump_interval: 60,
+        }
+    }
+}
 
-    /// Allowed file extensions (without leading dot), e.g. ["jpg", "jpeg", "png"].
-    /// If omitted or empty, all file types are allowed.
-    #[serde(default)]
-    pub extensions: Vec<String>,
+/// Root warm-up before the server starts listening, so the first requests
+/// do not pay for cold caches (NFS attribute and dentry caches especially).
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WarmupConfig {
+    pub enabled: bool,
+    /// Also walk each local root's tree, stat'ing entries to prime the caches.
+    pub walk: bool,
+    /// Entries stat'ed per root by a walk.
+    pub max_entries: usize,
+    /// Request paths resolved and read in full after the roots are warm,
+    /// and again whenever the roots change at runtime.
+    pub paths: Vec<String>,
+    /// File listing more such paths, one per line (`#` comments).
+    pub paths_file: PathBuf,
+    /// Milliseconds to wait for warm-up before listening anyway.
+    pub timeout_ms: u64,
 }
 
-impl SearchPath {
-    /// Pre-compute a normalized `HashSet` of lowercase extensions for fast lookup.
-    pub fn extension_set(&self) -> Option<HashSet<String>> {
-        if self.extensions.is_empty() {
-            return None; // None means "allow all"
+impl Default for WarmupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            walk: false,
+            max_entries: 100_000,
+            paths: Vec::new(),
+            paths_file: PathBuf::new(),
+            timeout_ms: 30_000,
         }
-        Some(
-            self.extensions
-                .iter()
-                .map(|e| e.trim_start_matches('.').to_ascii_lowercase())
-                .collect(),
-        )
     }
 }
 
-/// Normalize a location prefix: ensure it starts with `/` and has no trailing `/`.
-pub fn normalize_prefix(raw: &str) -> String {
-    let mut p = raw.to_string();
-    if !p.starts_with('/') {
-        p.insert(0, '/');
+/// TLS termination on the listener. Needs the `tls` feature.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// Serve HTTPS instead of plain HTTP on `bind`.
+    pub enabled: bool,
+    /// Certificates, picked by the name the client sends in SNI. The first
+    /// one answers clients sending no name or a name none of them lists.
+    pub certificates: Vec<TlsCertificate>,
+    /// Connections that have not finished the handshake by then are closed.
+    pub handshake_timeout_ms: u64,
+    /// A certificate obtained and renewed automatically over ACME.
+    pub acme: AcmeConfig,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            certificates: Vec::new(),
+            handshake_timeout_ms: 10_000,
+            acme: AcmeConfig::default(),
+        }
     }
-    while p.len() > 1 && p.ends_with('/') {
-        p.pop();
+}
+
+/// A certificate chain and its key, for a set of host names.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TlsCertificate {
+    /// Names served with this certificate, e.g. `["img.example.com"]`; a
+    /// leading `*.` matches any subdomain.
+    pub hosts: Vec<String>,
+    /// PEM certificate chain, leaf first.
+    pub cert: PathBuf,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1).
+    pub key: PathBuf,
+}
+
+/// A certificate for `domains` from an ACME CA such as Let's Encrypt,
+/// requested at startup when none is cached and renewed before it expires.
+/// Needs the `acme` feature.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AcmeConfig {
+    pub enabled: bool,
+    /// The CA's directory URL (default: Let's Encrypt production).
+    pub directory: String,
+    /// Names the certificate covers. Wildcards need DNS-01 and are refused.
+    pub domains: Vec<String>,
+    /// Account contact URLs, e.g. `["mailto:admin@example.com"]`.
+    pub contact: Vec<String>,
+    /// Keeps the account key and certificate across restarts; must stay
+    /// writable after privileges are dropped.
+    pub cache_dir: PathBuf,
+    /// How the CA checks that this server answers for each domain.
+    pub challenge: AcmeChallenge,
+    /// Plain-HTTP listener answering HTTP-01 challenges (the CA connects to
+    /// port 80).
+    pub http_bind: String,
+    /// Renew this many days before the certificate expires.
+    pub renew_before_days: u64,
+    /// Per request to the CA.
+    pub timeout_ms: u64,
+}
+
+impl Default for AcmeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: "https://acme-v02.api.letsencrypt.org/directory".into(),
+            domains: Vec::new(),
+            contact: Vec::new(),
+            cache_dir: PathBuf::new(),
+            challenge: AcmeChallenge::TlsAlpn01,
+            http_bind: "0.0.0.0:80".into(),
+            renew_before_days: 30,
+            timeout_ms: 30_000,
+        }
     }
-    p
 }
 
-/// Minimum value hyper accepts for HTTP/1.1 read buffer size.
-const MIN_HEADER_SIZE: u64 = 8192;
+/// ACME challenge type.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum AcmeChallenge {
+    /// Answered in the TLS handshake on `bind` (RFC 8737).
+    #[default]
+    #[serde(rename = "tls-alpn-01")]
+    TlsAlpn01,
+    /// Answered over plain HTTP on `http_bind`.
+    #[serde(rename = "http-01")]
+    Http01,
+}
 
-impl Config {
-    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let content = std::fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&content)?;
-        config.validate()?;
-        Ok(config)
+impl AcmeChallenge {
+    /// The challenge `type` in ACME authorizations.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::TlsAlpn01 => "tls-alpn-01",
+            Self::Http01 => "http-01",
+        }
     }
+}
 
-    pub(crate) fn validate(&self) -> Result<(), String> {
-        if self.server.max_header_size.0 < MIN_HEADER_SIZE {
-            return Err(format!(
-                "max_header_size must be >= {} (got {})",
-                ByteSize(MIN_HEADER_SIZE),
-                self.server.max_header_size,
-            ));
+/// Batched JSON events POSTed to an HTTP sink for each file served from a
+/// location with `served_events = true`, as a download audit trail.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WebhooksConfig {
+    pub enabled: bool,
+    /// `http(s)://` URL the batches are POSTed to.
+    pub url: String,
+    pub format: WebhookFormat,
+    /// Extra request headers, e.g. `Authorization`.
+    #[serde(skip_serializing)]
+    pub headers: BTreeMap<String, String>,
+    /// Events per request.
+    pub batch_size: usize,
+    /// Longest an event waits for its batch to fill, in milliseconds.
+    pub flush_interval_ms: u64,
+    pub timeout_ms: u64,
+    /// Events held while the sink is slow or down; newer ones are dropped.
+    pub queue_capacity: usize,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: String::new(),
+            format: WebhookFormat::Array,
+            headers: BTreeMap::new(),
+            batch_size: 100,
+            flush_interval_ms: 1000,
+            timeout_ms: 5000,
+            queue_capacity: 10_000,
+        }
+    }
+}
+
+/// Body of a webhook batch.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookFormat {
+    /// A JSON array of events.
+    #[default]
+    Array,
+    /// One JSON event per line.
+    Ndjson,
+    /// `{"records": [{"value": event}, ...]}`, as the Kafka REST proxy takes.
+    KafkaRest,
+}
+
+/// A WebAssembly module that sees each request before it is routed and
+/// may rewrite its path, reject it, or pick the root it is served from.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WasmFilterConfig {
+    pub enabled: bool,
+    /// `.wasm` (or `.wat`) file exporting `memory`, `alloc` and `filter`.
+    pub module: PathBuf,
+    /// Instructions a single call may execute before it is aborted.
+    pub fuel: u64,
+    /// Largest linear memory the module may grow to.
+    pub max_memory: ByteSize,
+    /// Let requests through unfiltered when the module fails (traps, runs
+    /// out of fuel, returns garbage) instead of answering 500.
+    pub fail_open: bool,
+}
+
+impl Default for WasmFilterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            module: PathBuf::new(),
+            fuel: 10_000_000,
+            max_memory: ByteSize(16 * 1024 * 1024),
+            fail_open: false,
+        }
+    }
+}
+
+/// Periodic push of usage counters to a statsd or DogStatsD agent over UDP,
+/// for fleets that cannot scrape `/_admin/metrics`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StatsdConfig {
+    pub enabled: bool,
+    /// Agent address, `host:port`.
+    pub host: String,
+    /// Prepended to every metric name, followed by a dot.
+    pub prefix: String,
+    /// DogStatsD tags (`"env:prod"`) added to every metric. Needs `dogstatsd`.
+    pub tags: Vec<String>,
+    /// Send DogStatsD tags (location, root and `tags`). Plain statsd has no
+    /// tags, so the location is folded into the metric name instead.
+    pub dogstatsd: bool,
+    /// Seconds between pushes.
+    pub interval: u64,
+}
+
+impl Default for StatsdConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "127.0.0.1:8125".into(),
+            prefix: "filehunter".into(),
+            tags: Vec::new(),
+            dogstatsd: true,
+            interval: 10,
+        }
+    }
+}
+
+/// Resolve a sample of requests against a second, candidate configuration
+/// as well and report where the two disagree, to validate a config change
+/// against live traffic before switching to it. Only the candidate's
+/// `[[locations]]` are used.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ShadowConfig {
+    pub enabled: bool,
+    /// The candidate config file.
+    pub config: PathBuf,
+    /// Fraction of file requests compared, in (0, 1].
+    pub sample_rate: f64,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            config: PathBuf::new(),
+            sample_rate: 0.01,
+        }
+    }
+}
+
+/// Periodic root probing; failing roots are excluded from searches until
+/// they pass again.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HealthCheckConfig {
+    pub enabled: bool,
+    /// Seconds between probe rounds.
+    pub interval: u64,
+    /// Milliseconds a single root probe may take before the root is marked unhealthy.
+    pub timeout_ms: u64,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: 10,
+            timeout_ms: 2000,
+        }
+    }
+}
+
+/// Per-root circuit breakers: a root whose recent probes mostly failed or
+/// timed out is skipped for `cooldown_secs`, then one trial probe decides
+/// whether it is searched again.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    pub enabled: bool,
+    /// Latest probes of a root the failure rate is taken over.
+    pub window: usize,
+    /// Probes in the window before the rate can open the circuit.
+    pub min_probes: usize,
+    /// Share of failed probes in the window (0 to 1) that opens the circuit.
+    pub failure_rate: f64,
+    /// Milliseconds a probe may take before it counts as failed and the
+    /// search moves on (0 = no limit).
+    pub probe_timeout_ms: u64,
+    /// Seconds an open circuit skips the root.
+    pub cooldown_secs: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            window: 20,
+            min_probes: 10,
+            failure_rate: 0.5,
+            probe_timeout_ms: 2000,
+            cooldown_secs: 30,
+        }
+    }
+}
+
+/// Endpoint and credentials shared by all `s3://bucket/prefix` roots.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct S3Config {
+    /// Endpoint URL, e.g. `"http://minio.internal:9000"`. Empty = AWS
+    /// (`https://s3.<region>.amazonaws.com`).
+    pub endpoint: String,
+    pub region: String,
+    /// Address buckets as `<endpoint>/<bucket>/<key>` (MinIO and most
+    /// self-hosted stores) instead of `<bucket>.<endpoint host>/<key>`.
+    pub path_style: bool,
+    /// Empty = use `AWS_ACCESS_KEY_ID` / `AWS_SECRET_ACCESS_KEY` /
+    /// `AWS_SESSION_TOKEN`; without any credentials requests are unsigned.
+    pub access_key: String,
+    #[serde(skip_serializing)]
+    pub secret_key: String,
+    #[serde(skip_serializing)]
+    pub session_token: String,
+    /// Milliseconds to wait for an object store response before treating
+    /// the root as a miss.
+    pub timeout_ms: u64,
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        Self {
+            endpoint: String::new(),
+            region: "us-east-1".into(),
+            path_style: false,
+            access_key: String::new(),
+            secret_key: String::new(),
+            session_token: String::new(),
+            timeout_ms: 5000,
+        }
+    }
+}
+
+/// Settings for `http(s)://` upstream roots.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct UpstreamConfig {
+    /// Milliseconds to wait for upstream response headers before treating
+    /// the root as a miss.
+    pub timeout_ms: u64,
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        Self { timeout_ms: 5000 }
+    }
+}
+
+/// On-disk copies of objects fetched from `s3://` and `http(s)://` roots.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RemoteCacheConfig {
+    /// Directory holding the copies (empty = no cache). Needs the `s3` or
+    /// `upstream` feature.
+    pub dir: PathBuf,
+    /// Total size of the copies; the least recently used are evicted
+    /// beyond it. Larger objects are never cached.
+    pub max_size: ByteSize,
+    /// Seconds a copy is served before the object is fetched again
+    /// (0 = until evicted).
+    pub ttl_secs: u64,
+    /// Seconds past `ttl_secs` during which an expired copy is still served
+    /// at once while a background refresh fetches the object (0 = clients
+    /// wait for the origin).
+    pub stale_while_revalidate_secs: u64,
+    /// Most background refreshes running at once; requests for other stale
+    /// copies are served without starting one.
+    pub max_refreshes: usize,
+}
+
+impl Default for RemoteCacheConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::new(),
+            max_size: ByteSize(1024 * 1024 * 1024), // 1GB
+            ttl_secs: 300,
+            stale_while_revalidate_secs: 0,
+            max_refreshes: 4,
+        }
+    }
+}
+
+/// All fields except `bind` have sensible defaults — existing configs keep working.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Bind address, e.g. "0.0.0.0:8080".
+    pub bind: String,
+
+    /// Account to switch to once the listener is bound (name or uid; empty
+    /// keeps the current one). Unix only.
+    pub user: String,
+
+    /// Group to switch to once the listener is bound (name or gid); defaults
+    /// to the primary group of `user`. Unix only.
+    pub group: String,
+
+    /// Directory to chroot into once the listener is bound, before the
+    /// privilege drop (empty = none). Every path in the config then
+    /// resolves inside it. Unix only.
+    pub chroot: PathBuf,
+
+    /// Enable HTTP/1.1 keep-alive.
+    pub keepalive: bool,
+
+    /// Maximum connection lifetime in seconds (0 = unlimited).
+    pub connection_timeout: u64,
+
+    /// Close a connection after this many seconds with no request in flight
+    /// (0 = never, the default). Unlike `connection_timeout`, busy connections are kept.
+    pub keepalive_timeout: u64,
+
+    /// Requests served on one connection before it is closed: HTTP/1 gets
+    /// `Connection: close`, HTTP/2 a GOAWAY (0 = unlimited).
+    pub max_requests_per_connection: u64,
+
+    /// Maximum concurrently open connections (0 = unlimited). At the cap,
+    /// accepting pauses until a connection closes.
+    pub max_connections: usize,
+
+    /// Maximum size for the request line + headers. e.g. "8KB"
+    pub max_header_size: ByteSize,
+
+    /// Maximum number of request headers.
+    pub max_headers: usize,
+
+    /// Maximum allowed Content-Length. e.g. "1MB"
+    pub max_body_size: ByteSize,
+
+    /// Maximum length in bytes of the percent-decoded request path; longer
+    /// requests get 414 (0 = unlimited).
+    pub max_path_length: usize,
+
+    /// Maximum number of segments in the request path; deeper requests get
+    /// 400 (0 = unlimited).
+    pub max_path_segments: usize,
+
+    /// HTTP/2 maximum concurrent streams per connection.
+    pub http2_max_streams: u32,
+
+    /// HTTP/2 initial per-stream flow-control window. e.g. "1MB"
+    /// Larger windows help large downloads over high-latency links.
+    pub http2_initial_stream_window: ByteSize,
+
+    /// HTTP/2 initial connection-wide flow-control window. e.g. "1MB"
+    pub http2_initial_connection_window: ByteSize,
+
+    /// Largest HTTP/2 frame payload the server accepts. e.g. "16KB"
+    pub http2_max_frame_size: ByteSize,
+
+    /// Size the HTTP/2 windows from measured bandwidth-delay product,
+    /// overriding the two initial window settings.
+    pub http2_adaptive_window: bool,
+
+    /// HTTP/2 without TLS (h2c) when `[server.tls]` is off.
+    pub h2c: H2cMode,
+
+    /// Maximum file size that can be served. e.g. "10MB"
+    /// Files exceeding this are skipped during search.
+    pub max_file_size: ByteSize,
+
+    /// Response streaming buffer size. e.g. "64KB"
+    pub stream_buffer_size: ByteSize,
+
+    /// Server-wide cap on response body bytes per second, e.g. "100MB"
+    /// (0 = unlimited).
+    pub egress_limit: ByteSize,
+
+    /// HTTPS on `bind`, with certificates picked by SNI.
+    pub tls: TlsConfig,
+
+    /// CORS configuration.
+    pub cors: CorsConfig,
+
+    /// Security response headers (HSTS, X-Frame-Options, ...).
+    pub security_headers: SecurityHeadersConfig,
+
+    /// Body of filehunter's own error responses (404, 405, 413, 429...):
+    /// `"text"` (default), `"json"` problem details or `"html"`. Clients
+    /// whose `Accept` asks for JSON get problem details regardless.
+    pub error_format: ErrorFormat,
+
+    /// `html` format: page with `{{status}}`, `{{title}}`, `{{message}}`
+    /// and `{{path}}` placeholders. Empty uses a built-in page.
+    pub error_template: PathBuf,
+
+    /// Per-IP rate limiting configuration.
+    pub rate_limit: RateLimitConfig,
+
+    /// Client IP denylist configuration.
+    pub denylist: DenylistConfig,
+
+    /// Audit log of blocked requests.
+    pub audit_log: AuditLogConfig,
+
+    /// Quarantine of suspicious files.
+    pub quarantine: QuarantineConfig,
+
+    /// Antivirus scans of served and uploaded files.
+    pub antivirus: AntivirusConfig,
+
+    /// In-memory log of 404s.
+    pub miss_log: MissLogConfig,
+
+    /// Response compression configuration.
+    pub compression: CompressionConfig,
+
+    /// Precompressed sidecar configuration.
+    pub precompress: PrecompressConfig,
+
+    /// Operator endpoints configuration.
+    pub admin: AdminConfig,
+
+    /// Directory archive download configuration.
+    pub archive: ArchiveConfig,
+
+    /// Batch resolution endpoint configuration.
+    pub batch: BatchConfig,
+
+    /// S3-style API configuration.
+    pub s3_api: S3ApiConfig,
+
+    /// Integrity digest header configuration.
+    pub digest: DigestConfig,
+
+    /// Root health checking configuration.
+    pub health_check: HealthCheckConfig,
+
+    /// Per-root circuit breaker configuration.
+    pub circuit_breaker: CircuitBreakerConfig,
+
+    /// Startup root warm-up configuration.
+    pub warmup: WarmupConfig,
+
+    /// statsd/DogStatsD metrics push configuration.
+    pub statsd: StatsdConfig,
+
+    /// Served-file events pushed to an HTTP sink.
+    pub webhooks: WebhooksConfig,
+
+    /// WASM module run on each request before it is routed.
+    pub wasm_filter: WasmFilterConfig,
+
+    /// Candidate config comparison.
+    pub shadow: ShadowConfig,
+
+    /// Object store settings for `s3://` roots.
+    pub s3: S3Config,
+
+    /// Settings for `http(s)://` upstream roots.
+    pub upstream: UpstreamConfig,
+
+    /// On-disk cache for remote roots.
+    pub remote_cache: RemoteCacheConfig,
+
+    /// Serve `GET /_meta/<path>`: JSON metadata about the resolved file,
+    /// including the root that holds it, instead of the body.
+    pub meta_endpoint: bool,
+
+    /// Add an `X-Resolved-Root` header naming the root that served each file.
+    /// Meant for debugging replicas; it reveals server-side paths.
+    pub resolved_root_header: bool,
+
+    /// Requests sending this token in `X-Filehunter-Debug` get debug
+    /// headers: the resolved root, per-root probe timings and the remote
+    /// cache status. Empty (default) disables it.
+    #[serde(skip_serializing)]
+    pub debug_token: String,
+
+    /// Where to write the JSON startup report once the server is listening:
+    /// `"-"` for a single line on stdout, otherwise a file path.
+    pub startup_report: Option<String>,
+
+    /// Refuse to start when any configured root cannot be used, instead of
+    /// logging a warning and serving from the rest.
+    pub strict_startup: bool,
+
+    /// Seconds between attempts to open roots that were skipped at startup;
+    /// each is added to its location once it opens (0 = never retry).
+    pub root_retry_interval: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: "0.0.0.0:8080".into(),
+            user: String::new(),
+            group: String::new(),
+            chroot: PathBuf::new(),
+            keepalive: true,
+            connection_timeout: 300,
+            keepalive_timeout: 0,
+            max_requests_per_connection: 0,
+            max_connections: 0,
+            max_header_size: ByteSize(8192),
+            max_headers: 64,
+            max_body_size: ByteSize(1_048_576),
+            max_path_length: 0,
+            max_path_segments: 0,
+            http2_max_streams: 128,
+            http2_initial_stream_window: ByteSize(1024 * 1024),
+            http2_initial_connection_window: ByteSize(1024 * 1024),
+            http2_max_frame_size: ByteSize(16 * 1024),
+            http2_adaptive_window: false,
+            h2c: H2cMode::PriorKnowledge,
+            max_file_size: ByteSize(10 * 1024 * 1024),
+            stream_buffer_size: ByteSize(65536),
+            egress_limit: ByteSize(0),
+            tls: TlsConfig::default(),
+            cors: CorsConfig::default(),
+            security_headers: SecurityHeadersConfig::default(),
+            error_format: ErrorFormat::Text,
+            error_template: PathBuf::new(),
+            rate_limit: RateLimitConfig::default(),
+            denylist: DenylistConfig::default(),
+            audit_log: AuditLogConfig::default(),
+            quarantine: QuarantineConfig::default(),
+            antivirus: AntivirusConfig::default(),
+            miss_log: MissLogConfig::default(),
+            compression: CompressionConfig::default(),
+            precompress: PrecompressConfig::default(),
+            admin: AdminConfig::default(),
+            archive: ArchiveConfig::default(),
+            batch: BatchConfig::default(),
+            s3_api: S3ApiConfig::default(),
+            digest: DigestConfig::default(),
+            health_check: HealthCheckConfig::default(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            warmup: WarmupConfig::default(),
+            statsd: StatsdConfig::default(),
+            webhooks: WebhooksConfig::default(),
+            wasm_filter: WasmFilterConfig::default(),
+            shadow: ShadowConfig::default(),
+            s3: S3Config::default(),
+            upstream: UpstreamConfig::default(),
+            remote_cache: RemoteCacheConfig::default(),
+            meta_endpoint: false,
+            resolved_root_header: false,
+            debug_token: String::new(),
+            startup_report: None,
+            strict_startup: false,
+            root_retry_interval: 30,
+        }
+    }
+}
+
+/// Which dot-prefixed path segments (`.env`, `.git`, `.well-known`) a
+/// location serves.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HiddenFiles {
+    /// Reject any path with a dot-prefixed segment.
+    #[default]
+    Deny,
+    /// Serve only dot-prefixed segments named here, e.g.
+    /// `{ allowlist = [".well-known"] }`.
+    Allowlist(Vec<String>),
+    /// Serve dot-prefixed segments like any other.
+    Allow,
+}
+
+impl HiddenFiles {
+    /// Whether the dot-prefixed segment `name` may be served.
+    pub fn allows(&self, name: &str) -> bool {
+        match self {
+            Self::Deny => false,
+            Self::Allowlist(names) => names.iter().any(|n| n == name),
+            Self::Allow => true,
+        }
+    }
+}
+
+/// How a location treats `/dir` and `/dir/`.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TrailingSlash {
+    /// Serve both forms alike: files are found with or without the slash.
+    #[default]
+    Ignore,
+    /// Redirect to the canonical form: files without the slash,
+    /// directories with it.
+    Redirect,
+}
+
+/// How a location derives the `ETag` of the files it serves.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EtagMode {
+    /// From the modification time and size; free, but only as reliable as
+    /// the root's mtimes.
+    #[default]
+    Mtime,
+    /// SHA-256 of the content (needs the `digest` feature). Files over
+    /// `digest.max_size` and remote objects fall back to `mtime`.
+    Hash,
+    /// No `ETag`; conditional requests use `Last-Modified` only.
+    Off,
+}
+
+/// Most verbose log events a location emits, overriding `RUST_LOG`.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        }
+    }
+}
+
+/// Which symlinks a location follows when resolving a file.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    /// Refuse any path that passes through a symlink.
+    Deny,
+    /// Follow symlinks whose target stays inside the root.
+    #[default]
+    SameRoot,
+    /// Follow symlinks wherever they point.
+    Allow,
+}
+
+impl SymlinkPolicy {
+    /// Whether `link`, a path below the canonical `root`, may be served as
+    /// `canonical` (what it resolves to).
+    pub fn permits(self, root: &Path, link: &Path, canonical: &Path) -> bool {
+        match self {
+            Self::Deny => canonical == link,
+            Self::SameRoot => canonical.starts_with(root),
+            Self::Allow => true,
+        }
+    }
+}
+
+/// Controls how multiple search roots are probed.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMode {
+    /// Check each root sequentially in config order; first match wins.
+    /// Deterministic: config order defines priority.
+    #[default]
+    Sequential,
+    /// Probe all eligible roots concurrently; the fastest match wins.
+    /// Remaining searches are cancelled as soon as a result is found.
+    Concurrent,
+    /// Check all eligible roots and return the file with the most recent
+    /// modification time. Useful when the same filename exists in multiple
+    /// roots and the latest version should always be served.
+    LatestModified,
+    /// Like `Sequential`, but roots are tried in order of recent hit rate
+    /// per unit of probe latency, so the root that usually wins is probed
+    /// first. Starts in config order; old probes fade with a one-minute
+    /// half-life.
+    Adaptive,
+    /// Probe the first root; if it has not answered within `hedge_delay_ms`
+    /// (or missed), probe the others concurrently too. The first match
+    /// wins, so a responsive first root keeps `Sequential` priority while
+    /// a slow one costs no more than the delay.
+    Hedged,
+    /// Check all roots like `LatestModified`, but serve the newest copy
+    /// only if its mtime is within `newest_window_secs` of now or it is
+    /// more than `newest_lead_secs` newer than the first copy in config
+    /// order; otherwise the first copy wins. Keeps replicas with skewed
+    /// clocks from flapping.
+    NewestWithin,
+    /// The strategy an embedding crate registered as `search_plugin` with
+    /// [`strategy::register`](crate::strategy::register).
+    Plugin,
+}
+
+impl SearchMode {
+    /// The mode as written in the config file.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Sequential => "sequential",
+            Self::Concurrent => "concurrent",
+            Self::LatestModified => "latest_modified",
+            Self::Adaptive => "adaptive",
+            Self::Hedged => "hedged",
+            Self::NewestWithin => "newest_within",
+            Self::Plugin => "plugin",
+        }
+    }
+}
+
+/// On-the-fly image resizing (`?w=200&h=200&fit=cover`) and re-encoding
+/// (`?format=webp`) for one location.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ImageConfig {
+    pub enabled: bool,
+    /// Directory holding resized variants; required when enabled.
+    pub cache_dir: PathBuf,
+    /// Largest width or height a request may ask for.
+    pub max_dimension: u32,
+    /// Decode/encode jobs allowed to run at once; further requests wait.
+    pub max_concurrent_encodes: usize,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cache_dir: PathBuf::new(),
+            max_dimension: 2048,
+            max_concurrent_encodes: 4,
+        }
+    }
+}
+
+/// A redirect for requests in a location (`[[locations.redirects]]`).
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RedirectRule {
+    /// Request path to match, e.g. `"/docs/old.pdf"`, compared as sent
+    /// (still percent-encoded).
+    pub from: String,
+    /// Target path or URL.
+    pub to: String,
+    /// Also match every path below `from`, appending the rest to `to`.
+    /// Default: false (exact match).
+    #[serde(default)]
+    pub prefix: bool,
+    /// 301 (default), 302, 307 or 308.
+    #[serde(default = "default_redirect_status")]
+    pub status: u16,
+}
+
+fn default_redirect_status() -> u16 {
+    301
+}
+
+impl RedirectRule {
+    /// Where this rule sends `path`, if it matches. Prefix rules match at
+    /// segment boundaries only.
+    pub fn target(&self, path: &str) -> Option<String> {
+        if path == self.from {
+            return Some(self.to.clone());
+        }
+        if !self.prefix {
+            return None;
+        }
+        let rest = path.strip_prefix(self.from.trim_end_matches('/'))?;
+        rest.starts_with('/')
+            .then(|| format!("{}{rest}", self.to.trim_end_matches('/')))
+    }
+}
+
+/// Byte-range support for one location (`[locations.ranges]`).
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RangesConfig {
+    /// Answer `Range` requests. When false, files are always sent whole
+    /// with `Accept-Ranges: none`.
+    pub enabled: bool,
+    /// Most ranges one request may list; more get 416.
+    pub max_ranges: usize,
+    /// Most bytes the ranges of one request may cover together; more get
+    /// 416. 0 = unlimited.
+    pub max_size: ByteSize,
+}
+
+impl Default for RangesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_ranges: 32,
+            max_size: ByteSize(0),
+        }
+    }
+}
+
+/// `Cache-Control` for one location's files (`[locations.cache_control]`).
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CacheControlConfig {
+    /// Regular expression matched against the path relative to the
+    /// location; matching (fingerprinted) files are sent with
+    /// `public, max-age=31536000, immutable`. Example: `"\\.[0-9a-f]{8,}\\."`.
+    /// Empty = none.
+    pub fingerprint: String,
+    /// `max-age` of other files, in seconds. 0 (default) sends no header.
+    pub max_age_secs: u64,
+}
+
+impl CacheControlConfig {
+    /// `fingerprint` compiled, or `None` when empty.
+    pub fn fingerprint_regex(&self) -> Result<Option<Regex>, String> {
+        if self.fingerprint.is_empty() {
+            return Ok(None);
+        }
+        let pattern = &self.fingerprint;
+        Regex::new(pattern)
+            .map(Some)
+            .map_err(|e| format!("invalid cache_control.fingerprint {pattern:?}: {e}"))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct LocationConfig {
+    /// URL prefix for this location, e.g. "/imgs1".
+    pub prefix: String,
+
+    /// Search strategy. Default: `"sequential"`.
+    #[serde(default)]
+    pub mode: SearchMode,
+
+    /// `plugin` mode: name the strategy was registered under.
+    #[serde(default)]
+    pub search_plugin: String,
+
+    /// `hedged` mode: milliseconds the first root gets before the others
+    /// are probed too. Default: 20.
+    pub hedge_delay_ms: Option<u64>,
+
+    /// `newest_within` mode: the newest copy beats the first one (in
+    /// config order) when its mtime is within this many seconds of now...
+    pub newest_window_secs: Option<u64>,
+
+    /// ...or when it is newer than the first copy by more than this many
+    /// seconds. At least one of the two must be set.
+    pub newest_lead_secs: Option<u64>,
+
+    /// Per-location maximum file size override.
+    /// If omitted, falls back to `[server].max_file_size`.
+    pub max_file_size: Option<ByteSize>,
+
+    /// Cap on this location's response body bytes per second, shared by all
+    /// its clients, e.g. "20MB". Applies on top of `[server].egress_limit`.
+    pub egress_limit: Option<ByteSize>,
+
+    /// On a miss, serve `dir/file` from `dir.zip` or `dir.tar` in a local
+    /// root (member `file`), streamed from the container. Needs the
+    /// `archive` feature. Default: false.
+    #[serde(default)]
+    pub search_archives: bool,
+
+    /// Image resizing for this location. Needs the `images` feature.
+    #[serde(default)]
+    pub images: ImageConfig,
+
+    /// Byte ranges for local files in this location.
+    #[serde(default)]
+    pub ranges: RangesConfig,
+
+    /// Dot-prefixed path segments this location serves: `"deny"` (default),
+    /// `"allow"`, or `{ allowlist = [".well-known"] }`.
+    #[serde(default)]
+    pub hidden_files: HiddenFiles,
+
+    /// Symlinks this location follows: `"deny"`, `"same_root"` (default;
+    /// only targets inside the root) or `"allow"`.
+    #[serde(default)]
+    pub symlinks: SymlinkPolicy,
+
+    /// `/dir` vs `/dir/`: `"ignore"` (default) or `"redirect"` to the
+    /// canonical form with `trailing_slash_status`.
+    #[serde(default)]
+    pub trailing_slash: TrailingSlash,
+
+    /// 301 (default) or 308 for trailing-slash redirects.
+    pub trailing_slash_status: Option<u16>,
+
+    /// `ETag` scheme: `"mtime"` (default), `"hash"` or `"off"`.
+    #[serde(default)]
+    pub etag: EtagMode,
+
+    /// Regular expressions matched against the sanitized path relative to
+    /// the location (e.g. `docs/notes.txt.bak`); matching files are never
+    /// served. Example: `["\\.bak$", "~$", "password"]`.
+    #[serde(default)]
+    pub deny_patterns: Vec<String>,
+
+    /// Extensions always served with `Content-Disposition: attachment`
+    /// (e.g. `["html", "svg", "exe"]`), so browsers download them instead of
+    /// rendering them in this origin. Other files stay inline.
+    #[serde(default)]
+    pub attachment_extensions: Vec<String>,
+
+    /// Check that files start with the signature their extension promises
+    /// (jpg, png, pdf, zip...): `"off"` (default), `"refuse"` mismatches
+    /// (404) or serve them as a `"download"` of `application/octet-stream`.
+    #[serde(default)]
+    pub magic_bytes: MagicBytes,
+
+    /// `Cache-Control` of served files: immutable for fingerprinted paths,
+    /// a short `max-age` for the rest.
+    #[serde(default)]
+    pub cache_control: CacheControlConfig,
+
+    /// Per-extension overrides of `max_file_size`, `cache_control.max_age_secs`
+    /// and `attachment_extensions`, e.g.
+    /// `pdf = { max_size = "50MB", disposition = "attachment" }`.
+    #[serde(default)]
+    pub extension_rules: BTreeMap<String, ExtensionRule>,
+
+    /// Replaces `[server.security_headers]` for this location
+    /// (`enabled = false` turns them off here).
+    pub security_headers: Option<SecurityHeadersConfig>,
+
+    /// Replaces `[server].error_format` for this location.
+    pub error_format: Option<ErrorFormat>,
+
+    /// Replaces `[server].error_template` for this location.
+    pub error_template: Option<PathBuf>,
+
+    /// Client authentication required before anything in this location is
+    /// searched, e.g. `auth = { type = "api_key", keys = ["..."] }`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<LocationAuth>,
+
+    /// Accept `PUT <prefix>/<path>` uploads into `upload_root`. Needs
+    /// `auth`. Default: false.
+    #[serde(default)]
+    pub writable: bool,
+
+    /// Where uploads are written: the `root` of one of the local `paths`,
+    /// whose `extensions` also limit what may be uploaded.
+    #[serde(default)]
+    pub upload_root: PathBuf,
+
+    /// Also copy each upload to up to this many other healthy local roots
+    /// whose `extensions` accept it, in `paths` order, so every search mode
+    /// finds it at once. Default: 0.
+    #[serde(default)]
+    pub upload_replicas: usize,
+
+    /// On a miss in every root, fetch the file from this `http(s)://` base
+    /// URL (the path below the location appended) and stream it to the
+    /// client. Needs the `upstream` feature.
+    #[serde(default)]
+    pub fallback_upstream: String,
+
+    /// Also store files fetched from `fallback_upstream` under this root,
+    /// the `root` of one of the local `paths`, so later requests hit locally.
+    #[serde(default)]
+    pub fallback_cache_root: PathBuf,
+
+    /// Redirects checked before searching, first match wins.
+    #[serde(default)]
+    pub redirects: Vec<RedirectRule>,
+
+    /// Methods accepted besides `GET` and `HEAD`: `"OPTIONS"`, and `"PUT"`
+    /// / `"POST"` on writable locations. Others get 405 with `Allow`.
+    /// Default: `PUT` and `POST` when writable, none otherwise.
+    #[serde(default)]
+    pub methods: Vec<String>,
+
+    /// Log level of requests in this location, replacing `RUST_LOG` for
+    /// filehunter's own events: `"off"`, `"error"`, `"warn"`, `"info"`,
+    /// `"debug"` or `"trace"`.
+    pub log_level: Option<LogLevel>,
+
+    /// Keep the debug and trace events of one in this many requests to
+    /// this location. 0 or 1 (default) keeps all.
+    #[serde(default)]
+    pub log_sample: u32,
+
+    /// Send an event to `[server.webhooks]` for each file this location
+    /// serves (GET with a 200 or 206).
+    #[serde(default)]
+    pub served_events: bool,
+
+    /// Search paths for this location.
+    pub paths: Vec<SearchPath>,
+}
+
+/// How clients authenticate to a location.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LocationAuth {
+    /// Any of `keys`, presented in the `header` header or the `query_param`
+    /// query parameter (empty disables the query parameter).
+    ApiKey {
+        #[serde(skip_serializing)]
+        keys: Vec<String>,
+        #[serde(default = "default_api_key_header")]
+        header: String,
+        #[serde(default = "default_api_key_query_param")]
+        query_param: String,
+    },
+    /// `Authorization: Bearer` JWTs signed with `secret` (HS256) or a key
+    /// from `jwks_url` (RS256). Non-empty `issuer` / `audience` must match
+    /// `iss` / `aud`; a non-empty `prefix_claim` names a claim (string or
+    /// array) whose path prefixes must cover the request path. Needs the
+    /// `jwt` feature.
+    Jwt {
+        #[serde(default, skip_serializing)]
+        secret: String,
+        #[serde(default)]
+        jwks_url: String,
+        #[serde(default)]
+        issuer: String,
+        #[serde(default)]
+        audience: String,
+        #[serde(default)]
+        prefix_claim: String,
+    },
+    /// HTTP Basic against an htpasswd file of bcrypt hashes
+    /// (`htpasswd -B`), read at startup. Needs the `basic-auth` feature.
+    Basic {
+        htpasswd: PathBuf,
+        #[serde(default = "default_basic_realm")]
+        realm: String,
+    },
+}
+
+fn default_api_key_header() -> String {
+    "X-Api-Key".into()
+}
+
+fn default_api_key_query_param() -> String {
+    "api_key".into()
+}
+
+fn default_basic_realm() -> String {
+    "filehunter".into()
+}
+
+/// Which HTTP/2 cleartext (h2c) connections a plaintext listener accepts.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum H2cMode {
+    /// HTTP/1.1 only.
+    Off,
+    /// Clients that open with the HTTP/2 preface.
+    #[default]
+    PriorKnowledge,
+    /// Prior knowledge, and HTTP/1.1 requests asking for `Upgrade: h2c`.
+    Upgrade,
+}
+
+/// How filehunter renders the body of its own error responses.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorFormat {
+    #[default]
+    Text,
+    /// RFC 7807 `application/problem+json`.
+    Json,
+    Html,
+}
+
+/// What a location does with files whose leading bytes contradict their
+/// extension.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MagicBytes {
+    #[default]
+    Off,
+    Refuse,
+    Download,
+}
+
+/// Overrides for the files of one extension in a location.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ExtensionRule {
+    /// Replaces the location's `max_file_size`.
+    pub max_size: Option<ByteSize>,
+    /// Replaces `cache_control.max_age_secs`; fingerprinted paths stay
+    /// immutable.
+    pub max_age_secs: Option<u64>,
+    /// `"attachment"` forces a download, `"inline"` exempts the extension
+    /// from `attachment_extensions`.
+    pub disposition: Option<Disposition>,
+}
+
+/// `Content-Disposition` type of served files.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Disposition {
+    Inline,
+    Attachment,
+}
+
+impl LocationConfig {
+    /// `extension_rules` keyed by normalized (lowercase, no leading dot)
+    /// extension.
+    pub fn extension_rule_map(&self) -> HashMap<String, ExtensionRule> {
+        self.extension_rules
+            .iter()
+            .map(|(ext, rule)| {
+                let ext = ext.trim_start_matches('.').to_ascii_lowercase();
+                (ext, rule.clone())
+            })
+            .collect()
+    }
+
+    /// Normalized (lowercase, no leading dot) `attachment_extensions`.
+    pub fn attachment_extension_set(&self) -> HashSet<String> {
+        self.attachment_extensions
+            .iter()
+            .map(|e| e.trim_start_matches('.').to_ascii_lowercase())
+            .collect()
+    }
+
+    /// `deny_patterns` compiled into one regex, or `None` when empty.
+    pub fn deny_regex(&self) -> Result<Option<Regex>, String> {
+        if self.deny_patterns.is_empty() {
+            return Ok(None);
+        }
+        Regex::new_many(&self.deny_patterns).map(Some).map_err(|e| {
+            let culprit = e
+                .pattern()
+                .and_then(|id| self.deny_patterns.get(id.as_usize()));
+            match culprit {
+                Some(pattern) => format!("invalid deny_patterns entry {pattern:?}: {e}"),
+                None => format!("invalid deny_patterns: {e}"),
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SearchPath {
+    /// Root directory for this search entry, an object store location such
+    /// as `"s3://bucket/prefix"`, an upstream base URL (`"https://..."`), or
+    /// a WebDAV collection (`"webdav://..."` / `"webdavs://..."`).
+    pub root: PathBuf,
+
+    /// Allowed file extensions (without leading dot), e.g. ["jpg", "jpeg", "png"].
+    /// If omitted or empty, all file types are allowed.
+    #[serde(default)]
+    pub extensions: Vec<String>,
+
+    /// Credentials sent to HTTP(S) and WebDAV roots.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<RemoteAuth>,
+
+    /// Milliseconds a `sequential` or `adaptive` search waits for this root
+    /// before moving on to the next one as if it missed (0 = no limit).
+    #[serde(default)]
+    pub soft_timeout_ms: u64,
+
+    /// Past `soft_timeout_ms`, let the probe finish in the background and
+    /// count whether it found the file instead of cancelling it.
+    #[serde(default)]
+    pub finish_late: bool,
+}
+
+/// `Authorization` for a remote root, e.g.
+/// `auth = { type = "basic", username = "u", password = "p" }`.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RemoteAuth {
+    Basic {
+        username: String,
+        #[serde(skip_serializing)]
+        password: String,
+    },
+    Bearer {
+        #[serde(skip_serializing)]
+        token: String,
+    },
+}
+
+impl SearchPath {
+    /// Whether `root` is a URL (served by a remote backend) rather than a directory.
+    pub fn is_remote(&self) -> bool {
+        self.root.to_str().is_some_and(|r| r.contains("://"))
+    }
+
+    /// Whether `root` is an HTTP(S) or WebDAV URL, the roots that accept `auth`.
+    pub fn is_http(&self) -> bool {
+        self.root.to_str().is_some_and(|r| {
+            ["http://", "https://", "webdav://", "webdavs://"]
+                .iter()
+                .any(|scheme| r.starts_with(scheme))
+        })
+    }
+
+    /// Pre-compute a normalized `HashSet` of lowercase extensions for fast lookup.
+    pub fn extension_set(&self) -> Option<HashSet<String>> {
+        if self.extensions.is_empty() {
+            return None; // None means "allow all"
+        }
+        Some(
+            self.extensions
+                .iter()
+                .map(|e| e.trim_start_matches('.').to_ascii_lowercase())
+                .collect(),
+        )
+    }
+}
+
+/// Normalize a location prefix: ensure it starts with `/` and has no trailing `/`.
+pub fn normalize_prefix(raw: &str) -> String {
+    let mut p = raw.to_string();
+    if !p.starts_with('/') {
+        p.insert(0, '/');
+    }
+    while p.len() > 1 && p.ends_with('/') {
+        p.pop();
+    }
+    p
+}
+
+/// Minimum value hyper accepts for HTTP/1.1 read buffer size.
+const MIN_HEADER_SIZE: u64 = 8192;
+
+/// HTTP/2 protocol bounds (RFC 9113 §6.5.2).
+const MAX_HTTP2_WINDOW: u64 = (1 << 31) - 1;
+const MIN_HTTP2_FRAME: u64 = 16 * 1024;
+const MAX_HTTP2_FRAME: u64 = (1 << 24) - 1;
+
+/// A host name, or `*.` and one, as `[[vhosts]]` and TLS certificates list.
+fn is_host_pattern(host: &str) -> bool {
+    let name = host.strip_prefix("*.").unwrap_or(host);
+    !name.is_empty() && !name.contains(['*', ':', '/'])
+}
+
+impl Config {
+    /// Every location: the top-level ones, then each vhost's.
+    pub fn all_locations(&self) -> impl Iterator<Item = &LocationConfig> {
+        self.site_locations().map(|(_, loc)| loc)
+    }
+
+    /// [`all_locations`](Self::all_locations) with their sites: 0 for the
+    /// top-level ones, `n` for those of the `n`th vhost.
+    pub(crate) fn site_locations(&self) -> impl Iterator<Item = (usize, &LocationConfig)> {
+        let vhosts = self.vhosts.iter().enumerate();
+        let vhost_locations =
+            vhosts.flat_map(|(i, vhost)| vhost.locations.iter().map(move |loc| (i + 1, loc)));
+        let top_level = self.locations.iter().map(|loc| (0, loc));
+        top_level.chain(vhost_locations)
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = std::fs::read_to_string(path)?;
+        let config: Config = toml::from_str(&content)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.server.max_header_size.0 < MIN_HEADER_SIZE {
+            return Err(format!(
+                "max_header_size must be >= {} (got {})",
+                ByteSize(MIN_HEADER_SIZE),
+                self.server.max_header_size,
+            ));
+        }
+        if self.server.stream_buffer_size.0 == 0 {
+            return Err("stream_buffer_size must be > 0".into());
+        }
+        for (name, window) in [
+            ("http2_initial_stream_window", self.server.http2_initial_stream_window),
+            ("http2_initial_connection_window", self.server.http2_initial_connection_window),
+        ] {
+            if window.0 == 0 || window.0 > MAX_HTTP2_WINDOW {
+                return Err(format!("{name} must be between 1 and {MAX_HTTP2_WINDOW} bytes"));
+            }
+        }
+        if !(MIN_HTTP2_FRAME..=MAX_HTTP2_FRAME).contains(&self.server.http2_max_frame_size.0) {
+            return Err(format!(
+                "http2_max_frame_size must be between {} and {MAX_HTTP2_FRAME} bytes (got {})",
+                ByteSize(MIN_HTTP2_FRAME),
+                self.server.http2_max_frame_size,
+            ));
+        }
+        if self.all_locations().next().is_none() {
+            return Err("at least one [[locations]] must be configured".into());
+        }
+        let mut seen_hosts = HashSet::new();
+        for vhost in &self.vhosts {
+            if vhost.hosts.is_empty() || vhost.locations.is_empty() {
+                return Err("each [[vhosts]] needs hosts and at least one location".into());
+            }
+            for host in &vhost.hosts {
+                if !is_host_pattern(host) {
+                    return Err(format!("vhost host {host:?} is not a host name"));
+                }
+                if !seen_hosts.insert(host.to_ascii_lowercase()) {
+                    return Err(format!("duplicate vhost host {host:?}"));
+                }
+            }
+        }
+
+        if self.server.cors.enabled
+            && self.server.cors.allow_credentials
+            && self.server.cors.allow_origins.iter().any(|o| o == "*")
+        {
+            return Err(
+                "CORS: allow_credentials=true is incompatible with allow_origins=[\"*\"]".into(),
+            );
+        }
+
+        self.server.security_headers.header_map()?;
+
+        if self.server.rate_limit.enabled {
+            if self.server.rate_limit.requests_per_second == 0 {
+                return Err("rate_limit.requests_per_second must be > 0".into());
+            }
+            if self.server.rate_limit.algorithm == RateLimitAlgorithm::Gcra
+                && self.server.rate_limit.burst_size == 0
+            {
+                return Err("rate_limit.burst_size must be > 0".into());
+            }
+            if self.server.rate_limit.window == 0 {
+                return Err("rate_limit.window must be > 0".into());
+            }
+            for entry in &self.server.rate_limit.exempt {
+                crate::denylist::parse_net(entry).map_err(|e| format!("rate_limit.exempt: {e}"))?;
+            }
+        }
+
+        if self.server.compression.enabled {
+            let valid = ["gzip", "deflate", "br", "zstd"];
+            for algo in &self.server.compression.algorithms {
+                if !valid.contains(&algo.as_str()) {
+                    return Err(format!(
+                        "unknown compression algorithm: {:?} (valid: gzip, deflate, br, zstd)",
+                        algo,
+                    ));
+                }
+            }
+            if self.server.compression.algorithms.is_empty() {
+                return Err(
+                    "compression.algorithms must not be empty when compression is enabled".into(),
+                );
+            }
+            let compression = &self.server.compression;
+            let lists = [
+                ("content_types", &compression.content_types),
+                ("exclude_content_types", &compression.exclude_content_types),
+            ];
+            for (field, list) in lists {
+                for content_type in list {
+                    let valid = match content_type.split_once('/') {
+                        Some((ty, sub)) => !ty.is_empty() && !ty.contains('*') && !sub.is_empty(),
+                        None => false,
+                    };
+                    if !valid {
+                        return Err(format!(
+                            "compression.{field}: {content_type:?} is not a type/subtype or type/*"
+                        ));
+                    }
+                }
+            }
+            let levels = &compression.levels;
+            let ranges = [
+                ("gzip", levels.gzip, 0..=9),
+                ("deflate", levels.deflate, 0..=9),
+                ("br", levels.br, 0..=11),
+                ("zstd", levels.zstd, 1..=22),
+            ];
+            for (algo, level, range) in ranges {
+                if let Some(level) = level
+                    && !range.contains(&level)
+                {
+                    return Err(format!(
+                        "compression.levels.{algo} must be {}-{} (got {level})",
+                        range.start(),
+                        range.end()
+                    ));
+                }
+            }
+        }
+
+        for algo in &self.server.precompress.algorithms {
+            if !["gzip", "br", "zstd"].contains(&algo.as_str()) {
+                return Err(format!(
+                    "unknown precompress algorithm: {algo:?} (valid: gzip, br, zstd)"
+                ));
+            }
+        }
+        if self.server.precompress.enabled && self.server.precompress.algorithms.is_empty() {
+            return Err(
+                "precompress.algorithms must not be empty when precompress is enabled".into(),
+            );
+        }
+
+        if self.server.admin.enabled && self.server.admin.token.is_empty() {
+            return Err("admin.token must not be empty when admin is enabled".into());
+        }
+
+        if self.server.denylist.enabled && self.server.denylist.file.as_os_str().is_empty() {
+            return Err("denylist.file must be set when denylist is enabled".into());
+        }
+        if self.server.quarantine.enabled && self.server.quarantine.dir.as_os_str().is_empty() {
+            return Err("quarantine.dir must be set when quarantine is enabled".into());
+        }
+        let antivirus = &self.server.antivirus;
+        if antivirus.enabled && (antivirus.address.is_empty() || antivirus.timeout_ms == 0) {
+            return Err("antivirus.address must be set and antivirus.timeout_ms > 0".into());
+        }
+
+        let miss_log = &self.server.miss_log;
+        if miss_log.enabled && (miss_log.capacity == 0 || miss_log.dump_interval == 0) {
+            return Err("miss_log.capacity and miss_log.dump_interval must be > 0".into());
+        }
+
+        if self.server.archive.enabled && self.server.archive.max_entries == 0 {
+            return Err("archive.max_entries must be > 0 when archive is enabled".into());
+        }
+
+        if self.server.batch.enabled && self.server.batch.max_paths == 0 {
+            return Err("batch.max_paths must be > 0 when batch is enabled".into());
+        }
+
+        let s3_api = &self.server.s3_api;
+        if s3_api.enabled {
+            if normalize_prefix(&s3_api.prefix) == "/" {
+                return Err("s3_api.prefix must not be \"/\"".into());
+            }
+            if s3_api.max_scan == 0 {
+                return Err("s3_api.max_scan must be > 0 when s3_api is enabled".into());
+            }
+            // Bucket names fold `/` to `-`, so `/a/b` and `/a-b` would share one.
+            #[cfg(feature = "s3-api")]
+            {
+                let mut buckets = HashMap::new();
+                for (site, loc) in self.site_locations().filter(|(_, loc)| loc.auth.is_none()) {
+                    let prefix = normalize_prefix(&loc.prefix);
+                    let name = crate::s3api::bucket_name(&prefix);
+                    if let Some(other) = buckets.insert((site, name.clone()), prefix.clone())
+                        && other != prefix
+                    {
+                        return Err(format!(
+                            "s3_api bucket \"{name}\" would serve both {other} and {prefix}"
+                        ));
+                    }
+                }
+            }
+        }
+
+        if self.server.digest.enabled && self.server.digest.cache_entries == 0 {
+            return Err("digest.cache_entries must be > 0 when digest is enabled".into());
+        }
+        if self.server.digest.background && self.server.digest.workers == 0 {
+            return Err("digest.workers must be > 0 in background mode".into());
+        }
+
+        if self.server.health_check.enabled
+            && (self.server.health_check.interval == 0 || self.server.health_check.timeout_ms == 0)
+        {
+            return Err("health_check.interval and health_check.timeout_ms must be > 0".into());
+        }
+
+        let breaker = &self.server.circuit_breaker;
+        if breaker.enabled {
+            if breaker.min_probes == 0 || breaker.min_probes > breaker.window {
+                return Err("circuit_breaker.min_probes must be between 1 and window".into());
+            }
+            if !(breaker.failure_rate > 0.0 && breaker.failure_rate <= 1.0) {
+                return Err("circuit_breaker.failure_rate must be > 0 and <= 1".into());
+            }
+            if breaker.cooldown_secs == 0 {
+                return Err("circuit_breaker.cooldown_secs must be > 0".into());
+            }
         }
-        if self.server.stream_buffer_size.0 == 0 {
-            return Err("stream_buffer_size must be > 0".into());
+
+        if self.server.warmup.enabled && self.server.warmup.timeout_ms == 0 {
+            return Err("warmup.timeout_ms must be > 0 when warmup is enabled".into());
         }
-        if self.locations.is_empty() {
-            return Err("at least one [[locations]] must be configured".into());
+        let warmup = &self.server.warmup;
+        if let Some(path) = warmup.paths.iter().find(|p| !p.starts_with('/')) {
+            return Err(format!("warmup.paths: {path:?} must start with '/'"));
         }
 
-        if self.server.cors.enabled
-            && self.server.cors.allow_credentials
-            && self.server.cors.allow_origins.iter().any(|o| o == "*")
+        let statsd = &self.server.statsd;
+        if statsd.enabled && (statsd.host.is_empty() || statsd.interval == 0) {
+            return Err("statsd.host must not be empty and statsd.interval must be > 0".into());
+        }
+        if let Some(tag) = statsd
+            .tags
+            .iter()
+            .find(|t| t.is_empty() || t.contains([',', '|', '#']))
         {
-            return Err(
-                "CORS: allow_credentials=true is incompatible with allow_origins=[\"*\"]".into(),
-            );
+            return Err(format!("statsd.tags: invalid tag {tag:?}"));
         }
 
-        if self.server.rate_limit.enabled {
-            if self.server.rate_limit.requests_per_second == 0 {
-                return Err("rate_limit.requests_per_second must be > 0".into());
+        let webhooks = &self.server.webhooks;
+        let http = webhooks.url.starts_with("http://") || webhooks.url.starts_with("https://");
+        if webhooks.enabled && !http {
+            let url = &webhooks.url;
+            return Err(format!("webhooks.url: {url:?} must be an http(s) URL"));
+        }
+        let intervals = [webhooks.flush_interval_ms, webhooks.timeout_ms];
+        if webhooks.enabled && (webhooks.batch_size == 0 || intervals.contains(&0)) {
+            return Err("webhooks.batch_size, flush_interval_ms and timeout_ms must be > 0".into());
+        }
+
+        let tls = &self.server.tls;
+        if tls.enabled {
+            if cfg!(not(feature = "tls")) {
+                return Err("tls.enabled needs the `tls` feature".into());
             }
-            if self.server.rate_limit.burst_size == 0 {
-                return Err("rate_limit.burst_size must be > 0".into());
+            let no_certs = tls.certificates.is_empty() && !tls.acme.enabled;
+            if no_certs || tls.handshake_timeout_ms == 0 {
+                return Err("tls needs certificates and handshake_timeout_ms > 0".into());
+            }
+            for cert in &tls.certificates {
+                if cert.cert.as_os_str().is_empty() || cert.key.as_os_str().is_empty() {
+                    return Err("each tls certificate needs a cert and a key".into());
+                }
+                if let Some(host) = cert.hosts.iter().find(|h| !is_host_pattern(h)) {
+                    return Err(format!("tls certificate host {host:?} is not a host name"));
+                }
             }
         }
 
-        if self.server.compression.enabled {
-            let valid = ["gzip", "deflate", "br", "zstd"];
-            for algo in &self.server.compression.algorithms {
-                if !valid.contains(&algo.as_str()) {
-                    return Err(format!(
-                        "unknown compression algorithm: {:?} (valid: gzip, deflate, br, zstd)",
-                        algo,
-                    ));
-                }
+        let acme = &tls.acme;
+        if acme.enabled {
+            if cfg!(not(feature = "acme")) {
+                return Err("tls.acme.enabled needs the `acme` feature".into());
             }
-            if self.server.compression.algorithms.is_empty() {
+            if !tls.enabled || acme.domains.is_empty() || acme.cache_dir.as_os_str().is_empty() {
+                return Err("tls.acme needs tls.enabled, domains and a cache_dir".into());
+            }
+            let wildcard_or_invalid = |d: &&String| d.starts_with('*') || !is_host_pattern(d);
+            if let Some(d) = acme.domains.iter().find(wildcard_or_invalid) {
+                return Err(format!("tls.acme domain {d:?} is not a host name"));
+            }
+            if acme.renew_before_days == 0 || acme.timeout_ms == 0 {
+                return Err("tls.acme.renew_before_days and timeout_ms must be > 0".into());
+            }
+            let http01 = acme.challenge == AcmeChallenge::Http01;
+            if http01 && acme.http_bind.parse::<SocketAddr>().is_err() {
+                let bind = &acme.http_bind;
+                return Err(format!("tls.acme.http_bind {bind:?} is not an address"));
+            }
+        }
+
+        let wasm = &self.server.wasm_filter;
+        if wasm.enabled && (wasm.module.as_os_str().is_empty() || wasm.fuel == 0) {
+            return Err("wasm_filter.module must be set and wasm_filter.fuel must be > 0".into());
+        }
+
+        let shadow = &self.server.shadow;
+        if shadow.enabled
+            && (shadow.config.as_os_str().is_empty()
+                || !(shadow.sample_rate > 0.0 && shadow.sample_rate <= 1.0))
+        {
+            return Err("shadow.config must be set and shadow.sample_rate in (0, 1]".into());
+        }
+
+        let s3 = &self.server.s3;
+        let http_url = s3.endpoint.starts_with("http://") || s3.endpoint.starts_with("https://");
+        if !s3.endpoint.is_empty() && !http_url {
+            return Err(format!("s3.endpoint must be an http(s) URL (got {:?})", s3.endpoint));
+        }
+        if s3.region.is_empty() || s3.timeout_ms == 0 {
+            return Err("s3.region must not be empty and s3.timeout_ms must be > 0".into());
+        }
+        if cfg!(not(unix)) && !(self.server.user.is_empty() && self.server.group.is_empty()) {
+            return Err("user and group are only supported on Unix".into());
+        }
+        if !self.server.chroot.as_os_str().is_empty() {
+            if cfg!(not(unix)) {
+                return Err("chroot is only supported on Unix".into());
+            }
+            if !self.server.chroot.is_absolute() {
+                return Err("chroot must be an absolute path".into());
+            }
+        }
+        if self.server.upstream.timeout_ms == 0 {
+            return Err("upstream.timeout_ms must be > 0".into());
+        }
+        let cache = &self.server.remote_cache;
+        if !cache.dir.as_os_str().is_empty() {
+            if cfg!(not(any(feature = "s3", feature = "upstream"))) {
+                return Err("remote_cache needs the `s3` or `upstream` feature".into());
+            }
+            if cache.max_size.as_u64() == 0 {
+                return Err("remote_cache.max_size must be > 0".into());
+            }
+            if cache.stale_while_revalidate_secs > 0
+                && (cache.ttl_secs == 0 || cache.max_refreshes == 0)
+            {
                 return Err(
-                    "compression.algorithms must not be empty when compression is enabled".into(),
+                    "remote_cache.stale_while_revalidate_secs needs ttl_secs and max_refreshes > 0"
+                        .into(),
                 );
             }
         }
 
         let mut seen_prefixes = HashSet::new();
-        for loc in &self.locations {
+        for (site, loc) in self.site_locations() {
             if loc.paths.is_empty() {
                 return Err(format!(
                     "location prefix={:?} must have at least one path",
@@ -399,12 +2325,250 @@ impl Config {
                 ));
             }
             let normalized = normalize_prefix(&loc.prefix);
-            if !seen_prefixes.insert(normalized) {
+            if !seen_prefixes.insert((site, normalized)) {
                 return Err(format!(
                     "duplicate location prefix={:?}",
                     loc.prefix,
                 ));
             }
+            let empty = |ext: &String| ext.trim_start_matches('.').is_empty();
+            if loc.extension_rules.keys().any(empty) {
+                return Err(format!(
+                    "location prefix={:?}: extension_rules has an empty extension",
+                    loc.prefix,
+                ));
+            }
+            if loc.mode == SearchMode::NewestWithin
+                && loc.newest_window_secs.is_none()
+                && loc.newest_lead_secs.is_none()
+            {
+                return Err(format!(
+                    "location prefix={:?}: mode newest_within needs newest_window_secs or newest_lead_secs",
+                    loc.prefix,
+                ));
+            }
+            let unregistered = crate::strategy::get(&loc.search_plugin).is_none();
+            if loc.mode == SearchMode::Plugin && unregistered {
+                return Err(format!(
+                    "location prefix={:?}: no search strategy registered as {:?}",
+                    loc.prefix, loc.search_plugin,
+                ));
+            }
+            if loc.images.enabled
+                && (loc.images.cache_dir.as_os_str().is_empty() || loc.images.max_dimension == 0)
+            {
+                return Err(format!(
+                    "location prefix={:?}: images.cache_dir must be set and images.max_dimension > 0",
+                    loc.prefix,
+                ));
+            }
+            if loc.images.enabled && loc.images.max_concurrent_encodes == 0 {
+                return Err(format!(
+                    "location prefix={:?}: images.max_concurrent_encodes must be > 0",
+                    loc.prefix,
+                ));
+            }
+            loc.deny_regex()
+                .map_err(|e| format!("location prefix={:?}: {e}", loc.prefix))?;
+            loc.cache_control
+                .fingerprint_regex()
+                .map_err(|e| format!("location prefix={:?}: {e}", loc.prefix))?;
+            if let Some(security) = &loc.security_headers {
+                security
+                    .header_map()
+                    .map_err(|e| format!("location prefix={:?}: {e}", loc.prefix))?;
+            }
+            match &loc.auth {
+                Some(LocationAuth::ApiKey { keys, header, .. }) => {
+                    if keys.is_empty() || keys.iter().any(String::is_empty) {
+                        return Err(format!(
+                            "location prefix={:?}: auth.keys must list at least one key and no empty keys",
+                            loc.prefix,
+                        ));
+                    }
+                    if hyper::header::HeaderName::from_bytes(header.as_bytes()).is_err() {
+                        return Err(format!(
+                            "location prefix={:?}: auth.header {header:?} is not a valid header name",
+                            loc.prefix,
+                        ));
+                    }
+                }
+                Some(LocationAuth::Jwt { secret, jwks_url, .. }) => {
+                    if cfg!(not(feature = "jwt")) {
+                        return Err(format!(
+                            "location prefix={:?}: auth type \"jwt\" needs the `jwt` feature",
+                            loc.prefix,
+                        ));
+                    }
+                    if secret.is_empty() && jwks_url.is_empty() {
+                        return Err(format!(
+                            "location prefix={:?}: jwt auth needs a secret or a jwks_url",
+                            loc.prefix,
+                        ));
+                    }
+                    if !jwks_url.is_empty()
+                        && !jwks_url.starts_with("https://")
+                        && !jwks_url.starts_with("http://")
+                    {
+                        return Err(format!(
+                            "location prefix={:?}: auth.jwks_url must be an http(s) URL",
+                            loc.prefix,
+                        ));
+                    }
+                }
+                Some(LocationAuth::Basic { htpasswd, .. }) => {
+                    if cfg!(not(feature = "basic-auth")) {
+                        return Err(format!(
+                            "location prefix={:?}: auth type \"basic\" needs the `basic-auth` feature",
+                            loc.prefix,
+                        ));
+                    }
+                    if htpasswd.as_os_str().is_empty() {
+                        return Err(format!(
+                            "location prefix={:?}: basic auth needs an htpasswd file",
+                            loc.prefix,
+                        ));
+                    }
+                }
+                None => {}
+            }
+            if loc.ranges.enabled && loc.ranges.max_ranges == 0 {
+                return Err(format!(
+                    "location prefix={:?}: ranges.max_ranges must be > 0 (or set ranges.enabled = false)",
+                    loc.prefix,
+                ));
+            }
+            if let Some(status) = loc.trailing_slash_status
+                && status != 301
+                && status != 308
+            {
+                return Err(format!(
+                    "location prefix={:?}: trailing_slash_status must be 301 or 308",
+                    loc.prefix,
+                ));
+            }
+            if loc.etag == EtagMode::Hash && cfg!(not(feature = "digest")) {
+                return Err(format!(
+                    "location prefix={:?}: etag = \"hash\" needs the `digest` feature",
+                    loc.prefix,
+                ));
+            }
+            for method in &loc.methods {
+                match method.to_ascii_uppercase().as_str() {
+                    "GET" | "HEAD" | "OPTIONS" => {}
+                    "PUT" | "POST" if loc.writable => {}
+                    "PUT" | "POST" => {
+                        return Err(format!(
+                            "location prefix={:?}: method {method} needs writable = true",
+                            loc.prefix,
+                        ));
+                    }
+                    _ => {
+                        return Err(format!(
+                            "location prefix={:?}: unsupported method {method:?} \
+                             (expected GET, HEAD, OPTIONS, PUT or POST)",
+                            loc.prefix,
+                        ));
+                    }
+                }
+            }
+            if loc.upload_replicas > 0 && !loc.writable {
+                return Err(format!(
+                    "location prefix={:?}: upload_replicas needs writable = true",
+                    loc.prefix,
+                ));
+            }
+            if loc.writable {
+                if loc.auth.is_none() {
+                    return Err(format!(
+                        "location prefix={:?}: writable locations need auth",
+                        loc.prefix,
+                    ));
+                }
+                if !loc
+                    .paths
+                    .iter()
+                    .any(|sp| sp.root == loc.upload_root && !sp.is_remote())
+                {
+                    return Err(format!(
+                        "location prefix={:?}: upload_root must be the root of one of its local paths",
+                        loc.prefix,
+                    ));
+                }
+            }
+            if !loc.fallback_upstream.is_empty() {
+                if cfg!(not(feature = "upstream")) {
+                    return Err(format!(
+                        "location prefix={:?}: fallback_upstream needs the `upstream` feature",
+                        loc.prefix,
+                    ));
+                }
+                if !loc.fallback_upstream.starts_with("http://")
+                    && !loc.fallback_upstream.starts_with("https://")
+                {
+                    return Err(format!(
+                        "location prefix={:?}: fallback_upstream must be an http(s) URL",
+                        loc.prefix,
+                    ));
+                }
+            }
+            if !loc.fallback_cache_root.as_os_str().is_empty() {
+                if loc.fallback_upstream.is_empty() {
+                    return Err(format!(
+                        "location prefix={:?}: fallback_cache_root needs fallback_upstream",
+                        loc.prefix,
+                    ));
+                }
+                if !loc
+                    .paths
+                    .iter()
+                    .any(|sp| sp.root == loc.fallback_cache_root && !sp.is_remote())
+                {
+                    return Err(format!(
+                        "location prefix={:?}: fallback_cache_root must be the root of one of its local paths",
+                        loc.prefix,
+                    ));
+                }
+            }
+            let prefix = normalize_prefix(&loc.prefix);
+            for rule in &loc.redirects {
+                let below = rule.from.strip_prefix(&prefix);
+                let inside = prefix == "/"
+                    || rule.from == prefix
+                    || below.is_some_and(|rest| rest.starts_with('/'));
+                if !inside {
+                    return Err(format!(
+                        "location prefix={:?}: redirect from {:?} is outside the location",
+                        loc.prefix, rule.from,
+                    ));
+                }
+                if rule.to.is_empty() {
+                    return Err(format!(
+                        "location prefix={:?}: redirect from {:?} needs a target",
+                        loc.prefix, rule.from,
+                    ));
+                }
+                if ![301, 302, 307, 308].contains(&rule.status) {
+                    return Err(format!(
+                        "location prefix={:?}: redirect status must be 301, 302, 307 or 308",
+                        loc.prefix,
+                    ));
+                }
+            }
+            for sp in &loc.paths {
+                if sp.auth.is_some() && !sp.is_http() {
+                    return Err(format!(
+                        "root {} has auth set, but auth only applies to http(s) and webdav roots",
+                        sp.root.display(),
+                    ));
+                }
+                if sp.finish_late && sp.soft_timeout_ms == 0 {
+                    return Err(format!(
+                        "root {} has finish_late set, but no soft_timeout_ms",
+                        sp.root.display(),
+                    ));
+                }
+            }
         }
         Ok(())
     }
@@ -525,6 +2689,7 @@ mod tests {
         let sp = SearchPath {
             root: PathBuf::from("/tmp"),
             extensions: vec![],
+            ..Default::default()
         };
         assert!(sp.extension_set().is_none());
     }
@@ -534,6 +2699,7 @@ mod tests {
         let sp = SearchPath {
             root: PathBuf::from("/tmp"),
             extensions: vec![".JPG".into(), "Png".into()],
+            ..Default::default()
         };
         let set = sp.extension_set().unwrap();
         assert!(set.contains("jpg"));
@@ -546,13 +2712,34 @@ mod tests {
         let sp = SearchPath {
             root: PathBuf::from("/tmp"),
             extensions: vec!["jpg".into(), "JPG".into()],
+            ..Default::default()
         };
         let set = sp.extension_set().unwrap();
         assert_eq!(set.len(), 1);
     }
 
     // -----------------------------------------------------------------------
-    // Config::validate (6 tests)
+    // HiddenFiles deserialization (1 test)
+    // -----------------------------------------------------------------------
+
+    #[test]
+    fn hidden_files_parses_each_form() {
+        let parse = |value: &str| {
+            toml::from_str::<LocationConfig>(&format!(
+                "prefix = \"/\"\npaths = []\nhidden_files = {value}"
+            ))
+            .unwrap()
+            .hidden_files
+        };
+        assert_eq!(parse(r#""deny""#), HiddenFiles::Deny);
+        assert_eq!(parse(r#""allow""#), HiddenFiles::Allow);
+        let list = parse(r#"{ allowlist = [".well-known"] }"#);
+        assert_eq!(list, HiddenFiles::Allowlist(vec![".well-known".into()]));
+        assert!(list.allows(".well-known") && !list.allows(".git"));
+    }
+
+    // -----------------------------------------------------------------------
+    // Config::validate (21 tests)
     // -----------------------------------------------------------------------
 
     /// Build a minimal valid Config for mutation-based tests.
@@ -562,12 +2749,14 @@ mod tests {
             locations: vec![LocationConfig {
                 prefix: "/".into(),
                 mode: SearchMode::Sequential,
-                max_file_size: None,
                 paths: vec![SearchPath {
                     root: PathBuf::from("/tmp"),
                     extensions: vec![],
+                    ..Default::default()
                 }],
+                ..Default::default()
             }],
+            vhosts: Vec::new(),
         }
     }
 
@@ -579,6 +2768,96 @@ mod tests {
         assert!(err.contains("must be >= 8KB"), "error: {err}");
     }
 
+    #[test]
+    fn validate_rejects_out_of_range_http2_settings() {
+        let mut cfg = valid_config();
+        cfg.server.http2_initial_stream_window = ByteSize(16 * 1024 * 1024);
+        cfg.server.http2_max_frame_size = ByteSize(1024 * 1024);
+        assert!(cfg.validate().is_ok());
+
+        cfg.server.http2_max_frame_size = ByteSize(8192);
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("http2_max_frame_size"), "error: {err}");
+
+        cfg.server.http2_max_frame_size = ByteSize(16384);
+        cfg.server.http2_initial_connection_window = ByteSize(4 * 1024 * 1024 * 1024);
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("http2_initial_connection_window"), "error: {err}");
+    }
+
+    #[test]
+    fn validate_checks_vhosts() {
+        let mut cfg = valid_config();
+        let site = VhostConfig {
+            hosts: vec!["img.example.com".into(), "*.cdn.example.com".into()],
+            locations: cfg.locations.clone(),
+        };
+        // The same prefix may appear once per site.
+        cfg.vhosts.push(site.clone());
+        assert!(cfg.validate().is_ok());
+
+        cfg.vhosts.push(site);
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("duplicate vhost host"), "error: {err}");
+
+        cfg.vhosts[1].hosts = vec!["files.example.com:8080".into()];
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("is not a host name"), "error: {err}");
+
+        cfg.locations.clear();
+        cfg.vhosts.truncate(1);
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_checks_acme() {
+        let mut cfg = valid_config();
+        cfg.server.tls.enabled = true;
+        cfg.server.tls.acme = AcmeConfig {
+            enabled: true,
+            domains: vec!["files.example.com".into()],
+            cache_dir: "/var/lib/filehunter/acme".into(),
+            ..Default::default()
+        };
+        // ACME alone stands in for tls.certificates.
+        assert_eq!(cfg.validate().is_ok(), cfg!(feature = "acme"));
+        if cfg!(not(feature = "acme")) {
+            return;
+        }
+
+        cfg.server.tls.acme.domains.push("*.example.com".into());
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("not a host name"), "error: {err}");
+
+        cfg.server.tls.acme.domains.pop();
+        cfg.server.tls.acme.challenge = AcmeChallenge::Http01;
+        cfg.server.tls.acme.http_bind = "port 80".into();
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("http_bind"), "error: {err}");
+    }
+
+    #[test]
+    fn validate_rejects_invalid_deny_pattern() {
+        let mut cfg = valid_config();
+        cfg.locations[0].deny_patterns = vec![r"\.bak$".into(), "~$".into()];
+        assert!(cfg.validate().is_ok());
+
+        cfg.locations[0].deny_patterns.push("(unclosed".into());
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("\"(unclosed\""), "error: {err}");
+    }
+
+    #[test]
+    fn validate_rejects_unbounded_newest_within() {
+        let mut cfg = valid_config();
+        cfg.locations[0].mode = SearchMode::NewestWithin;
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("newest_window_secs"), "error: {err}");
+
+        cfg.locations[0].newest_lead_secs = Some(60);
+        assert!(cfg.validate().is_ok());
+    }
+
     #[test]
     fn validate_rejects_zero_stream_buffer() {
         let mut cfg = valid_config();
@@ -587,6 +2866,29 @@ mod tests {
         assert!(err.contains("must be > 0"), "error: {err}");
     }
 
+    #[cfg(feature = "s3-api")]
+    #[test]
+    fn validate_rejects_colliding_s3_buckets() {
+        let mut cfg = valid_config();
+        cfg.server.s3_api.enabled = true;
+        for prefix in ["/a/b", "/a-b"] {
+            cfg.locations.push(LocationConfig {
+                prefix: prefix.into(),
+                ..cfg.locations[0].clone()
+            });
+        }
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("bucket \"a-b\""), "error: {err}");
+
+        // Locations with `auth` are not exposed, so they cannot collide.
+        cfg.locations[2].auth = Some(LocationAuth::ApiKey {
+            keys: vec!["k".into()],
+            header: "X-Api-Key".into(),
+            query_param: String::new(),
+        });
+        assert!(cfg.validate().is_ok());
+    }
+
     #[test]
     fn validate_rejects_no_locations() {
         let mut cfg = valid_config();
@@ -614,17 +2916,168 @@ mod tests {
         assert!(err.contains("requests_per_second"), "error: {err}");
     }
 
+    #[test]
+    fn validate_rejects_bad_rate_limit_exempt_entry() {
+        let mut cfg = valid_config();
+        cfg.server.rate_limit.enabled = true;
+        cfg.server.rate_limit.exempt = vec!["10.0.0.0/8".into(), "10.0.0.0/33".into()];
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("rate_limit.exempt"), "error: {err}");
+    }
+
+    #[test]
+    fn validate_windowed_rate_limit_ignores_burst_size() {
+        let mut cfg = valid_config();
+        cfg.server.rate_limit.enabled = true;
+        cfg.server.rate_limit.burst_size = 0;
+        assert!(cfg.validate().is_err());
+
+        cfg.server.rate_limit.algorithm = RateLimitAlgorithm::SlidingWindow;
+        assert!(cfg.validate().is_ok());
+        cfg.server.rate_limit.window = 0;
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("rate_limit.window"), "error: {err}");
+    }
+
+    #[test]
+    fn validate_rejects_admin_empty_token() {
+        let mut cfg = valid_config();
+        cfg.server.admin.enabled = true;
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("admin.token"), "error: {err}");
+    }
+
+    #[test]
+    fn validate_rejects_auth_on_local_root() {
+        let mut cfg = valid_config();
+        cfg.locations[0].paths[0].auth = Some(RemoteAuth::Bearer { token: "t".into() });
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("auth only applies"), "error: {err}");
+
+        cfg.locations[0].paths[0].root = "webdavs://dav.example/archive".into();
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_location_api_key_without_keys() {
+        let mut cfg = valid_config();
+        cfg.locations[0].auth = Some(LocationAuth::ApiKey {
+            keys: vec![],
+            header: default_api_key_header(),
+            query_param: default_api_key_query_param(),
+        });
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("auth.keys"), "error: {err}");
+
+        cfg.locations[0].auth = toml::from_str::<LocationConfig>(
+            "prefix = \"/\"\npaths = []\nauth = { type = \"api_key\", keys = [\"k1\"] }",
+        )
+        .unwrap()
+        .auth;
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_writable_location_without_auth_or_root() {
+        let mut cfg = valid_config();
+        cfg.locations[0].writable = true;
+        cfg.locations[0].upload_root = PathBuf::from("/tmp");
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("need auth"), "error: {err}");
+
+        cfg.locations[0].auth = Some(LocationAuth::ApiKey {
+            keys: vec!["k1".into()],
+            header: default_api_key_header(),
+            query_param: default_api_key_query_param(),
+        });
+        assert!(cfg.validate().is_ok());
+        cfg.locations[0].upload_root = PathBuf::from("/var/tmp");
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("upload_root"), "error: {err}");
+
+        cfg.locations[0].writable = false;
+        cfg.locations[0].upload_replicas = 1;
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("upload_replicas"), "error: {err}");
+    }
+
+    #[test]
+    fn validate_rejects_stray_redirects() {
+        let mut cfg = valid_config();
+        cfg.locations[0].prefix = "/docs".into();
+        cfg.locations[0].redirects = vec![RedirectRule {
+            from: "/docs/old".into(),
+            to: "/docs/new".into(),
+            prefix: true,
+            status: 308,
+        }];
+        assert!(cfg.validate().is_ok());
+        cfg.locations[0].redirects[0].from = "/docsold".into();
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("outside the location"), "error: {err}");
+        cfg.locations[0].redirects[0].from = "/docs/old".into();
+        cfg.locations[0].redirects[0].status = 303;
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("redirect status"), "error: {err}");
+    }
+
+    #[test]
+    fn validate_checks_fallback_upstream() {
+        let mut cfg = valid_config();
+        cfg.locations[0].fallback_upstream = "ftp://origin/files".into();
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("http(s) URL"), "error: {err}");
+        cfg.locations[0].fallback_upstream = "https://origin/files/".into();
+        cfg.locations[0].fallback_cache_root = "/elsewhere".into();
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("local paths"), "error: {err}");
+        cfg.locations[0].fallback_cache_root = cfg.locations[0].paths[0].root.clone();
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_checks_compression_settings() {
+        let mut cfg = valid_config();
+        cfg.server.compression.enabled = true;
+        cfg.server.compression.levels.br = Some(12);
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("levels.br must be 0-11"), "error: {err}");
+        cfg.server.compression.levels.br = Some(5);
+        cfg.server.compression.content_types = vec!["text/*".into(), "wasm".into()];
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("is not a type/subtype"), "error: {err}");
+        cfg.server.compression.content_types = vec!["text/*".into(), "application/wasm".into()];
+        cfg.server.compression.exclude_content_types = vec!["*/*".into()];
+        assert!(cfg.validate().is_err());
+        cfg.server.compression.exclude_content_types = vec!["application/zstd".into()];
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_unsupported_methods() {
+        let mut cfg = valid_config();
+        cfg.locations[0].methods = vec!["options".into(), "HEAD".into()];
+        assert!(cfg.validate().is_ok());
+        cfg.locations[0].methods.push("PUT".into());
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("PUT needs writable"), "error: {err}");
+        cfg.locations[0].methods = vec!["DELETE".into()];
+        let err = cfg.validate().unwrap_err();
+        assert!(err.contains("unsupported method"), "error: {err}");
+    }
+
     #[test]
     fn validate_rejects_duplicate_prefix() {
         let mut cfg = valid_config();
         cfg.locations.push(LocationConfig {
             prefix: "/".into(),
             mode: SearchMode::Sequential,
-            max_file_size: None,
             paths: vec![SearchPath {
                 root: PathBuf::from("/tmp"),
                 extensions: vec![],
+                ..Default::default()
             }],
+            ..Default::default()
         });
         let err = cfg.validate().unwrap_err();
         assert!(err.contains("duplicate"), "error: {err}");
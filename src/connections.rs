@@ -0,0 +1,415 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Frame, SizeHint};
+use hyper::Response;
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::Notify;
+
+use crate::server::ResponseBody;
+
+// ---------------------------------------------------------------------------
+// Registry
+// ---------------------------------------------------------------------------
+
+/// Live connections and their resource usage, for the admin API.
+#[derive(Default)]
+pub struct ConnectionRegistry {
+    next_id: AtomicU64,
+    conns: Mutex<HashMap<u64, Arc<ConnectionStats>>>,
+}
+
+/// Counters for a single accepted connection.
+pub struct ConnectionStats {
+    pub id: u64,
+    pub remote: SocketAddr,
+    opened: Instant,
+    active_streams: AtomicU64,
+    requests: AtomicU64,
+    /// Bytes read from / written to the socket (headers included).
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    /// Response body bytes handed to hyper; the gap to `bytes_sent` is
+    /// what is still sitting in buffers.
+    body_bytes: AtomicU64,
+    /// When the last stream ended, in milliseconds since `opened`.
+    last_stream_end: AtomicU64,
+    close: Notify,
+}
+
+/// Serializable snapshot of a connection.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionInfo {
+    pub id: u64,
+    pub remote: SocketAddr,
+    pub age_secs: u64,
+    pub active_streams: u64,
+    pub requests: u64,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    /// Approximate: body bytes produced but not yet written to the socket.
+    pub buffered_bytes: u64,
+}
+
+/// Ordering for [`ConnectionRegistry::heaviest`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    BytesSent,
+    Age,
+    Streams,
+    Requests,
+    Buffered,
+}
+
+impl SortKey {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "bytes" => Some(Self::BytesSent),
+            "age" => Some(Self::Age),
+            "streams" => Some(Self::Streams),
+            "requests" => Some(Self::Requests),
+            "buffered" => Some(Self::Buffered),
+            _ => None,
+        }
+    }
+
+    fn key(self, c: &ConnectionInfo) -> u64 {
+        match self {
+            Self::BytesSent => c.bytes_sent,
+            Self::Age => c.age_secs,
+            Self::Streams => c.active_streams,
+            Self::Requests => c.requests,
+            Self::Buffered => c.buffered_bytes,
+        }
+    }
+}
+
+impl ConnectionRegistry {
+    /// Register an accepted connection. It is removed when the handle drops.
+    pub fn register(self: &Arc<Self>, remote: SocketAddr) -> ConnectionHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let stats = Arc::new(ConnectionStats {
+            id,
+            remote,
+            opened: Instant::now(),
+            active_streams: AtomicU64::new(0),
+            requests: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            body_bytes: AtomicU64::new(0),
+            last_stream_end: AtomicU64::new(0),
+            close: Notify::new(),
+        });
+        self.conns.lock().unwrap().insert(id, stats.clone());
+        ConnectionHandle {
+            registry: self.clone(),
+            stats,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.conns.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Up to `limit` connections, largest `sort` value first.
+    pub fn heaviest(&self, sort: SortKey, limit: usize) -> Vec<ConnectionInfo> {
+        let mut all: Vec<ConnectionInfo> = self
+            .conns
+            .lock()
+            .unwrap()
+            .values()
+            .map(|c| c.snapshot())
+            .collect();
+        all.sort_by_key(|c| std::cmp::Reverse(sort.key(c)));
+        all.truncate(limit);
+        all
+    }
+
+    /// Ask the connection with `id` to close. Returns `false` if unknown.
+    pub fn close(&self, id: u64) -> bool {
+        match self.conns.lock().unwrap().get(&id) {
+            Some(c) => {
+                c.close.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl ConnectionStats {
+    /// How long the connection has had no open streams; `None` while busy.
+    fn idle(&self) -> Option<Duration> {
+        if self.active_streams.load(Ordering::Relaxed) > 0 {
+            return None;
+        }
+        let since = Duration::from_millis(self.last_stream_end.load(Ordering::Relaxed));
+        Some(self.opened.elapsed().saturating_sub(since))
+    }
+
+    fn snapshot(&self) -> ConnectionInfo {
+        let bytes_sent = self.bytes_sent.load(Ordering::Relaxed);
+        ConnectionInfo {
+            id: self.id,
+            remote: self.remote,
+            age_secs: self.opened.elapsed().as_secs(),
+            active_streams: self.active_streams.load(Ordering::Relaxed),
+            requests: self.requests.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            bytes_sent,
+            buffered_bytes: self
+                .body_bytes
+                .load(Ordering::Relaxed)
+                .saturating_sub(bytes_sent),
+        }
+    }
+}
+
+/// Owned registration of a connection; unregisters on drop.
+pub struct ConnectionHandle {
+    registry: Arc<ConnectionRegistry>,
+    stats: Arc<ConnectionStats>,
+}
+
+impl Drop for ConnectionHandle {
+    fn drop(&mut self) {
+        self.registry.conns.lock().unwrap().remove(&self.stats.id);
+    }
+}
+
+impl ConnectionHandle {
+    pub fn id(&self) -> u64 {
+        self.stats.id
+    }
+
+    /// Requests started on this connection so far.
+    pub fn requests(&self) -> u64 {
+        self.stats.requests.load(Ordering::Relaxed)
+    }
+
+    /// Resolves once an operator asked for this connection to be closed.
+    pub async fn closed(&self) {
+        self.stats.close.notified().await
+    }
+
+    /// Resolves once the connection has had no open streams for `timeout`.
+    pub async fn idle_for(&self, timeout: Duration) {
+        loop {
+            let wait = match self.stats.idle() {
+                Some(idle) if idle >= timeout => return,
+                Some(idle) => timeout - idle,
+                None => timeout,
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Mark the start of a request; the stream counts as open until the
+    /// returned guard (moved into the response body) is dropped.
+    pub fn begin_stream(&self) -> StreamGuard {
+        self.stats.requests.fetch_add(1, Ordering::Relaxed);
+        self.stats.active_streams.fetch_add(1, Ordering::Relaxed);
+        StreamGuard {
+            stats: self.stats.clone(),
+        }
+    }
+
+    /// Wrap the connection's IO to count socket bytes.
+    pub fn wrap_io<T>(&self, io: T) -> CountingIo<T> {
+        CountingIo {
+            inner: io,
+            stats: self.stats.clone(),
+        }
+    }
+}
+
+pub struct StreamGuard {
+    stats: Arc<ConnectionStats>,
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        let ended = self.stats.opened.elapsed().as_millis() as u64;
+        self.stats.last_stream_end.store(ended, Ordering::Relaxed);
+        self.stats.active_streams.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Response body & IO wrappers
+// ---------------------------------------------------------------------------
+
+/// Count body bytes of `resp` and keep the stream open until the body is done.
+pub fn track_response(resp: Response<ResponseBody>, guard: StreamGuard) -> Response<ResponseBody> {
+    resp.map(|inner| TrackedBody { inner, guard }.boxed())
+}
+
+struct TrackedBody {
+    inner: ResponseBody,
+    guard: StreamGuard,
+}
+
+impl Body for TrackedBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, io::Error>>> {
+        let this = self.get_mut();
+        let polled = Pin::new(&mut this.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &polled
+            && let Some(data) = frame.data_ref()
+        {
+            this.guard
+                .stats
+                .body_bytes
+                .fetch_add(data.len() as u64, Ordering::Relaxed);
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Transparent IO wrapper counting bytes read and written.
+pub struct CountingIo<T> {
+    inner: T,
+    stats: Arc<ConnectionStats>,
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for CountingIo<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let polled = Pin::new(&mut this.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - before;
+        this.stats
+            .bytes_received
+            .fetch_add(read as u64, Ordering::Relaxed);
+        polled
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for CountingIo<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let polled = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = polled {
+            this.stats.bytes_sent.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        polled
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let polled = Pin::new(&mut this.inner).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(n)) = polled {
+            this.stats.bytes_sent.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        polled
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn handle_drop_unregisters() {
+        let reg = Arc::new(ConnectionRegistry::default());
+        let h = reg.register(addr(1));
+        assert_eq!(reg.len(), 1);
+        drop(h);
+        assert!(reg.is_empty());
+    }
+
+    #[test]
+    fn heaviest_sorts_by_streams() {
+        let reg = Arc::new(ConnectionRegistry::default());
+        let a = reg.register(addr(1));
+        let b = reg.register(addr(2));
+        let _s1 = b.begin_stream();
+        let _s2 = b.begin_stream();
+        let _s3 = a.begin_stream();
+
+        let top = reg.heaviest(SortKey::Streams, 1);
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].id, b.id());
+        assert_eq!(top[0].active_streams, 2);
+    }
+
+    #[tokio::test]
+    async fn close_notifies_connection() {
+        let reg = Arc::new(ConnectionRegistry::default());
+        let h = reg.register(addr(1));
+        assert!(reg.close(h.id()));
+        assert!(!reg.close(h.id() + 100));
+        // The permit is stored, so waiting after the request still resolves.
+        h.closed().await;
+    }
+
+    #[tokio::test]
+    async fn idle_for_waits_while_streams_are_open() {
+        let reg = Arc::new(ConnectionRegistry::default());
+        let h = reg.register(addr(1));
+        let timeout = Duration::from_millis(50);
+
+        let stream = h.begin_stream();
+        let busy = tokio::time::timeout(timeout * 3, h.idle_for(timeout)).await;
+        assert!(busy.is_err(), "an open stream keeps the connection busy");
+
+        drop(stream);
+        let start = Instant::now();
+        h.idle_for(timeout).await;
+        assert!(start.elapsed() >= timeout);
+    }
+}
@@ -0,0 +1,121 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Detach from the terminal and keep running in the background: fork twice
+/// around `setsid` so no controlling terminal can be reacquired, then point
+/// stdin at `/dev/null` and stdout/stderr at `log_file` (appended) or
+/// `/dev/null`. The working directory is kept so relative paths in the
+/// config still resolve.
+///
+/// Must run before the async runtime starts any threads.
+pub fn daemonize(log_file: Option<&Path>) -> Result<(), String> {
+    // Opened up front so a bad path is reported on the terminal.
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .map_err(|e| format!("cannot open /dev/null: {e}"))?;
+    let out = match log_file {
+        Some(path) => OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("cannot open log file {}: {e}", path.display()))?,
+        None => null.try_clone().map_err(|e| e.to_string())?,
+    };
+
+    fork_and_exit_parent()?;
+    // SAFETY: plain syscall in the single-threaded child.
+    if unsafe { libc::setsid() } < 0 {
+        return Err(format!("setsid: {}", io::Error::last_os_error()));
+    }
+    fork_and_exit_parent()?;
+
+    redirect(&null, libc::STDIN_FILENO)?;
+    redirect(&out, libc::STDOUT_FILENO)?;
+    redirect(&out, libc::STDERR_FILENO)
+}
+
+/// Fork; the parent exits successfully and the child returns.
+fn fork_and_exit_parent() -> Result<(), String> {
+    // SAFETY: called before any other thread exists.
+    match unsafe { libc::fork() } {
+        -1 => Err(format!("fork: {}", io::Error::last_os_error())),
+        0 => Ok(()),
+        _ => std::process::exit(0),
+    }
+}
+
+fn redirect(file: &File, fd: libc::c_int) -> Result<(), String> {
+    // SAFETY: both descriptors are open for the duration of the call.
+    if unsafe { libc::dup2(file.as_raw_fd(), fd) } < 0 {
+        return Err(format!("dup2: {}", io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// PID file
+// ---------------------------------------------------------------------------
+
+/// A file holding the server's pid, for init scripts; removed on drop.
+/// Removal fails once a chroot hides the path, leaving a stale file that
+/// the next start overwrites.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Write the current pid to `path`. Refuses when the file names a
+    /// process that is still running.
+    pub fn create(path: &Path) -> Result<Self, String> {
+        if let Ok(text) = fs::read_to_string(path)
+            && let Ok(pid) = text.trim().parse::<libc::pid_t>()
+            && pid > 0
+            && is_running(pid)
+        {
+            return Err(format!("{} names running process {pid}", path.display()));
+        }
+        fs::write(path, format!("{}\n", std::process::id()))
+            .map_err(|e| format!("cannot write pid file {}: {e}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn is_running(pid: libc::pid_t) -> bool {
+    // SAFETY: signal 0 only checks that the process exists.
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    alive || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pid_file_refuses_live_process() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("filehunter.pid");
+
+        let pid_file = PidFile::create(&path).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        assert_eq!(written.trim(), std::process::id().to_string());
+        assert!(PidFile::create(&path).is_err());
+        drop(pid_file);
+        assert!(!path.exists());
+
+        // Above any pid_max, so never running.
+        fs::write(&path, "2147483647\n").unwrap();
+        let _pid_file = PidFile::create(&path).unwrap();
+    }
+}
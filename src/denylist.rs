@@ -0,0 +1,109 @@
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use ipnet::IpNet;
+use tracing::{info, warn};
+
+/// Client addresses refused before rate limiting, loaded from a file of IPs
+/// and CIDRs and replaceable at runtime via `POST /_admin/denylist/reload`.
+pub struct Denylist {
+    path: PathBuf,
+    nets: RwLock<Vec<IpNet>>,
+}
+
+impl Denylist {
+    /// Load `path`. An unreadable file starts the list empty.
+    pub fn load(path: &Path) -> Self {
+        let denylist = Self {
+            path: path.to_path_buf(),
+            nets: RwLock::default(),
+        };
+        if let Err(e) = denylist.reload() {
+            warn!(path = %path.display(), error = %e, "cannot read denylist; starting empty");
+        }
+        denylist
+    }
+
+    /// Re-read the file, replacing the list. On error the current list is kept.
+    pub fn reload(&self) -> Result<usize, String> {
+        let text = std::fs::read_to_string(&self.path).map_err(|e| e.to_string())?;
+        let nets = parse(&text, &self.path);
+        let count = nets.len();
+        *self.nets.write().unwrap() = nets;
+        info!(path = %self.path.display(), entries = count, "denylist loaded");
+        Ok(count)
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.nets
+            .read()
+            .unwrap()
+            .iter()
+            .any(|net| net.contains(&ip))
+    }
+
+    /// Current entries, in file order.
+    pub fn entries(&self) -> Vec<IpNet> {
+        self.nets.read().unwrap().clone()
+    }
+}
+
+/// Parse `"10.0.0.0/8"` or a bare address (a single-host network).
+pub fn parse_net(s: &str) -> Result<IpNet, String> {
+    s.parse::<IpNet>()
+        .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+        .map(|net| net.trunc())
+        .map_err(|_| format!("invalid IP or CIDR {s:?}"))
+}
+
+/// One entry per line; `#` starts a comment. Bad lines are skipped.
+fn parse(text: &str, path: &Path) -> Vec<IpNet> {
+    let mut nets = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let entry = line.split('#').next().unwrap_or("").trim();
+        if entry.is_empty() {
+            continue;
+        }
+        match parse_net(entry) {
+            Ok(net) => nets.push(net),
+            Err(e) => {
+                warn!(path = %path.display(), line = n + 1, error = %e, "skipping denylist entry")
+            }
+        }
+    }
+    nets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_addresses_and_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("deny.txt");
+        std::fs::write(
+            &path,
+            "# scrapers\n203.0.113.0/24\n198.51.100.7 # one host\nnot-an-ip\n",
+        )
+        .unwrap();
+        let denylist = Denylist::load(&path);
+        assert_eq!(denylist.entries().len(), 2);
+
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert!(denylist.contains(ip("203.0.113.99")));
+        assert!(denylist.contains(ip("::ffff:198.51.100.7")), "IPv4-mapped");
+        assert!(!denylist.contains(ip("198.51.100.8")));
+
+        std::fs::write(&path, "2001:db8::/32\n").unwrap();
+        assert_eq!(denylist.reload(), Ok(1));
+        assert!(!denylist.contains(ip("203.0.113.99")));
+        assert!(denylist.contains(ip("2001:db8::1")));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(denylist.reload().is_err());
+        assert_eq!(denylist.entries().len(), 1, "kept on error");
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use base64::Engine as _;
+use sha2::{Digest as _, Sha256};
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
+
+use crate::config::DigestConfig;
+
+/// SHA-256 digests of local files, computed on first request and reused
+/// until the file's mtime or size changes. In background mode requests
+/// never wait: a missing digest is queued for the hashing workers and the
+/// file is served without one meanwhile.
+pub(crate) struct DigestCache {
+    max_size: u64,
+    capacity: usize,
+    entries: Mutex<HashMap<PathBuf, Entry>>,
+    /// `Some` in background mode: one permit per hashing worker.
+    workers: Option<Arc<Semaphore>>,
+    /// Files queued or being hashed in the background.
+    queued: Mutex<HashSet<PathBuf>>,
+}
+
+/// A digest and the file version it was computed from.
+struct Entry {
+    modified: SystemTime,
+    size: u64,
+    /// Base64 SHA-256.
+    digest: Arc<str>,
+}
+
+impl DigestCache {
+    pub(crate) fn new(cfg: &DigestConfig) -> Self {
+        Self {
+            max_size: cfg.max_size.as_u64(),
+            capacity: cfg.cache_entries,
+            entries: Mutex::default(),
+            workers: cfg
+                .background
+                .then(|| Arc::new(Semaphore::new(cfg.workers))),
+            queued: Mutex::default(),
+        }
+    }
+
+    /// Whether digests are computed in the background.
+    pub(crate) fn is_background(&self) -> bool {
+        self.workers.is_some()
+    }
+
+    /// Base64 SHA-256 of the file at `path`, or `None` if it is larger than
+    /// `max_size` or cannot be read. In background mode, `None` until the
+    /// queued computation finishes.
+    pub(crate) async fn get(
+        self: &Arc<Self>,
+        path: &Path,
+        size: u64,
+        modified: SystemTime,
+    ) -> Option<Arc<str>> {
+        if size > self.max_size {
+            return None;
+        }
+        if let Some(entry) = self.entries.lock().unwrap().get(path)
+            && entry.modified == modified
+            && entry.size == size
+        {
+            return Some(entry.digest.clone());
+        }
+        if self.is_background() {
+            self.queue(path.to_path_buf(), size, modified);
+            return None;
+        }
+
+        let owned = path.to_path_buf();
+        let digest: Arc<str> = match tokio::task::spawn_blocking(move || sha256_file(&owned)).await
+        {
+            Ok(Ok(digest)) => digest.into(),
+            Ok(Err(e)) => {
+                warn!(path = %path.display(), error = %e, "cannot compute digest");
+                return None;
+            }
+            Err(_) => return None,
+        };
+        debug!(path = %path.display(), size, "digest computed");
+        self.insert(path, size, modified, digest.clone());
+        Some(digest)
+    }
+
+    /// Hash `path` on a background worker unless its digest is known or
+    /// already queued. The digest is kept only if the file still has
+    /// `size` and `modified` once hashed.
+    pub(crate) fn queue(self: &Arc<Self>, path: PathBuf, size: u64, modified: SystemTime) {
+        let Some(workers) = self.workers.clone() else {
+            return;
+        };
+        if size > self.max_size {
+            return;
+        }
+        if self
+            .entries
+            .lock()
+            .unwrap()
+            .get(&path)
+            .is_some_and(|e| e.modified == modified && e.size == size)
+        {
+            return;
+        }
+        if !self.queued.lock().unwrap().insert(path.clone()) {
+            return;
+        }
+        let cache = self.clone();
+        tokio::spawn(async move {
+            let _permit = workers.acquire_owned().await;
+            let owned = path.clone();
+            let hashed = tokio::task::spawn_blocking(move || {
+                let digest = sha256_file(&owned)?;
+                let meta = std::fs::metadata(&owned)?;
+                let unchanged = meta.len() == size && meta.modified()? == modified;
+                Ok::<_, io::Error>(unchanged.then_some(digest))
+            })
+            .await;
+            match hashed {
+                Ok(Ok(Some(digest))) => {
+                    debug!(path = %path.display(), size, "digest computed in background");
+                    cache.insert(&path, size, modified, digest.into());
+                }
+                Ok(Ok(None)) => debug!(path = %path.display(), "file changed while hashing"),
+                Ok(Err(e)) => warn!(path = %path.display(), error = %e, "cannot compute digest"),
+                Err(_) => {}
+            }
+            cache.queued.lock().unwrap().remove(&path);
+        });
+    }
+
+    fn insert(&self, path: &Path, size: u64, modified: SystemTime, digest: Arc<str>) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity
+            && !entries.contains_key(path)
+            && let Some(evict) = entries.keys().next().cloned()
+        {
+            entries.remove(&evict);
+        }
+        let entry = Entry {
+            modified,
+            size,
+            digest,
+        };
+        entries.insert(path.to_path_buf(), entry);
+    }
+}
+
+fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(base64::engine::general_purpose::STANDARD.encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ByteSize;
+
+    #[tokio::test]
+    async fn digest_is_cached_until_mtime_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("f.txt");
+        std::fs::write(&path, b"hello").unwrap();
+        let cache = Arc::new(DigestCache::new(&DigestConfig {
+            enabled: true,
+            max_size: ByteSize(5),
+            cache_entries: 1,
+            ..Default::default()
+        }));
+
+        let t0 = SystemTime::UNIX_EPOCH;
+        let hello = cache.get(&path, 5, t0).await.unwrap();
+        assert_eq!(&*hello, "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=");
+
+        // Same mtime: the stale cached digest is served without rereading.
+        std::fs::write(&path, b"world").unwrap();
+        assert_eq!(cache.get(&path, 5, t0).await.unwrap(), hello);
+
+        let t1 = t0 + std::time::Duration::from_secs(1);
+        assert_ne!(cache.get(&path, 5, t1).await.unwrap(), hello);
+        assert!(cache.get(&path, 6, t1).await.is_none(), "over max_size");
+    }
+
+    #[tokio::test]
+    async fn background_digests_never_block() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("f.txt");
+        std::fs::write(&path, b"hello").unwrap();
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        let cache = Arc::new(DigestCache::new(&DigestConfig {
+            enabled: true,
+            background: true,
+            ..Default::default()
+        }));
+
+        assert!(cache.get(&path, 5, modified).await.is_none());
+        for _ in 0..100 {
+            if cache.queued.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let hello = cache.get(&path, 5, modified).await.unwrap();
+        assert_eq!(&*hello, "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=");
+
+        // A digest of a different version of the file is not kept.
+        let t0 = SystemTime::UNIX_EPOCH;
+        cache.queue(path.clone(), 5, t0);
+        while !cache.queued.lock().unwrap().is_empty() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(cache.get(&path, 5, t0).await.is_none());
+    }
+}
@@ -0,0 +1,98 @@
+use hyper::header::HeaderValue;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+
+/// RFC 8187 `attr-char`s that need no escaping in `filename*`.
+const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// Download name requested by `?download` or `?filename=<name>`, if any.
+///
+/// `?filename=` wins; a bare `?download` (or an unusable `filename`) uses the
+/// last segment of the request path.
+pub(crate) fn requested_filename(query: Option<&str>, request_path: &str) -> Option<String> {
+    let mut download = false;
+    let mut filename = None;
+    for kv in query?.split('&') {
+        let (key, value) = kv.split_once('=').unwrap_or((kv, ""));
+        match key {
+            "download" => download = true,
+            "filename" if filename.is_none() => filename = Some(value),
+            _ => {}
+        }
+    }
+    if let Some(name) = filename.and_then(|v| clean(&decode(v))) {
+        return Some(name);
+    }
+    if !download && filename.is_none() {
+        return None;
+    }
+    Some(path_filename(request_path))
+}
+
+/// The decoded last segment of a request path, as a download name.
+pub(crate) fn path_filename(request_path: &str) -> String {
+    let last = request_path.rsplit('/').next().unwrap_or("");
+    let last = percent_decode_str(last).decode_utf8_lossy();
+    clean(&last).unwrap_or_else(|| "download".to_string())
+}
+
+/// `Content-Disposition` value for `disposition` (`attachment` or `inline`),
+/// with an ASCII `filename` fallback and the exact name in `filename*`.
+pub(crate) fn header(disposition: &str, filename: &str) -> HeaderValue {
+    let fallback: String = filename
+        .chars()
+        .map(|c| if c.is_ascii() && c != '"' && c != '\\' { c } else { '_' })
+        .collect();
+    let encoded = utf8_percent_encode(filename, ATTR_CHAR);
+    HeaderValue::from_str(&format!(
+        "{disposition}; filename=\"{fallback}\"; filename*=UTF-8''{encoded}"
+    ))
+    .expect("control characters are removed by clean()")
+}
+
+/// Query values use `+` for spaces, as form submissions do.
+fn decode(raw: &str) -> String {
+    let raw = raw.replace('+', " ");
+    percent_decode_str(&raw).decode_utf8_lossy().into_owned()
+}
+
+/// Drop control characters and anything that reads as a path.
+pub(crate) fn clean(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or("");
+    let name: String = name.chars().filter(|c| !c.is_control()).collect();
+    let name = name.trim();
+    (!name.is_empty() && name != "." && name != "..").then(|| name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_and_encodes_download_names() {
+        let name = |q| requested_filename(Some(q), "/docs/Q3%20report.pdf");
+        assert_eq!(name("v=1"), None);
+        assert_eq!(name("download").as_deref(), Some("Q3 report.pdf"));
+        assert_eq!(name("filename=summary+2024.pdf").as_deref(), Some("summary 2024.pdf"));
+        assert_eq!(name("filename=..%2F..%2Fetc%2Fpasswd").as_deref(), Some("passwd"));
+        assert_eq!(name("filename=").as_deref(), Some("Q3 report.pdf"));
+        assert_eq!(requested_filename(None, "/a.txt"), None);
+
+        assert_eq!(
+            header("attachment", "résumé \"v2\".pdf"),
+            "attachment; filename=\"r_sum_ _v2_.pdf\"; \
+             filename*=UTF-8''r%C3%A9sum%C3%A9%20%22v2%22.pdf"
+        );
+    }
+}
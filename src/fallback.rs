@@ -0,0 +1,73 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use crate::backend::{self, ObjectStream, StorageBackend};
+use crate::upload::Pending;
+
+/// A location's `fallback_upstream`, asked when every root misses.
+pub(crate) struct Fallback {
+    /// The upstream's identity (its URL), reported as the hit's root.
+    pub identity: PathBuf,
+    pub backend: Arc<dyn StorageBackend>,
+    /// `fallback_cache_root`: where fetched files are kept.
+    pub cache: Option<PathBuf>,
+}
+
+/// Pass `body` through while copying it to `relative` under the cache
+/// `root`. The copy is moved into place only if the body ends cleanly with
+/// `size` bytes (any length when `size` is 0); a failed or abandoned
+/// response leaves nothing behind.
+pub(crate) fn cache(
+    body: ObjectStream,
+    root: PathBuf,
+    relative: PathBuf,
+    size: u64,
+) -> ObjectStream {
+    let (body, rx) = backend::tee(body);
+    tokio::spawn(store(rx, root, relative, size));
+    body
+}
+
+async fn store(
+    mut rx: mpsc::UnboundedReceiver<Option<Bytes>>,
+    root: PathBuf,
+    relative: PathBuf,
+    size: u64,
+) {
+    // Unlike uploads, the cache creates the directories it needs.
+    if let Some(parent) = relative.parent()
+        && let Err(e) = tokio::fs::create_dir_all(root.join(parent)).await
+    {
+        warn!(root = %root.display(), path = %relative.display(), error = %e, "cannot create fallback cache directory");
+        return;
+    }
+    let mut pending = match Pending::create(&root, &relative).await {
+        Ok(pending) => pending,
+        Err(status) => {
+            debug!(root = %root.display(), path = %relative.display(), status = status.as_u16(), "fallback not cached");
+            return;
+        }
+    };
+    let mut written = 0u64;
+    while let Some(chunk) = rx.recv().await {
+        let Some(chunk) = chunk else {
+            if size > 0 && written != size {
+                break;
+            }
+            if pending.finish().await.is_ok() {
+                debug!(root = %root.display(), path = %relative.display(), size = written, "fallback cached");
+            }
+            return;
+        };
+        if pending.write(&chunk).await.is_err() {
+            break;
+        }
+        written += chunk.len() as u64;
+    }
+    debug!(root = %root.display(), path = %relative.display(), "fallback copy discarded");
+    let _ = pending.discard::<()>(hyper::StatusCode::OK).await;
+}
@@ -0,0 +1,256 @@
+//! HTTP/2 cleartext upgrades (RFC 7540 §3.2). An HTTP/1.1 request with
+//! `Upgrade: h2c` gets 101 Switching Protocols and is then answered over
+//! HTTP/2 as stream 1: the request is replayed to the HTTP/2 server as a
+//! HEADERS frame spliced in after the client's connection preface.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use hyper::header::{CONNECTION, CONTENT_LENGTH, HOST, TRANSFER_ENCODING, UPGRADE};
+use hyper::upgrade::{OnUpgrade, Upgraded};
+use hyper::{HeaderMap, Method, Request, Response, StatusCode, Version};
+use hyper_util::rt::TokioIo;
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, ReadBuf};
+
+use crate::server::{ResponseBody, empty_body};
+
+/// What an HTTP/2 connection opens with (RFC 7540 §3.5).
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Frame header length, and the largest payload every server accepts.
+const FRAME_HEADER: usize = 9;
+const MAX_FRAME: usize = 16 * 1024;
+
+const HEADERS: u8 = 0x1;
+const SETTINGS: u8 = 0x4;
+const END_STREAM: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+
+/// Headers with no HTTP/2 equivalent, left out of the replayed request.
+const CONNECTION_HEADERS: &[&str] = &[
+    "connection",
+    "http2-settings",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// An accepted h2c upgrade, waiting for the 101 to go out.
+pub struct Upgrade {
+    on_upgrade: OnUpgrade,
+    /// The request as an HTTP/2 HEADERS frame on stream 1.
+    headers: Vec<u8>,
+}
+
+impl Upgrade {
+    /// Take the upgrade from `req` when it asks for h2c. Requests with a
+    /// body, or headers too large for one frame, are left to be answered
+    /// over HTTP/1.1, as RFC 7540 allows.
+    pub fn from_request<B>(req: &mut Request<B>) -> Option<Self> {
+        let headers = req.headers();
+        let asks = req.version() == Version::HTTP_11
+            && req.method() != Method::CONNECT
+            && has_token(headers, UPGRADE.as_str(), "h2c")
+            && has_token(headers, CONNECTION.as_str(), "upgrade")
+            && headers.contains_key("http2-settings");
+        let bodyless = !headers.contains_key(TRANSFER_ENCODING)
+            && headers.get(CONTENT_LENGTH).is_none_or(|len| len == "0");
+        if !asks || !bodyless {
+            return None;
+        }
+        let headers = headers_frame(req)?;
+        Some(Self {
+            on_upgrade: hyper::upgrade::on(req),
+            headers,
+        })
+    }
+
+    /// The 101 response accepting the upgrade.
+    pub fn response() -> Response<ResponseBody> {
+        let mut resp = Response::new(empty_body());
+        *resp.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+        let headers = resp.headers_mut();
+        headers.insert(CONNECTION, "Upgrade".parse().unwrap());
+        headers.insert(UPGRADE, "h2c".parse().unwrap());
+        resp
+    }
+
+    /// Wait for the switch, then read the client's preface and return the
+    /// connection for the HTTP/2 server with the request spliced in.
+    pub async fn into_io(self) -> io::Result<H2cIo> {
+        let upgraded = self.on_upgrade.await.map_err(io::Error::other)?;
+        let mut inner = TokioIo::new(upgraded);
+        // Stream 1 may only follow the preface and the client's SETTINGS.
+        let mut prefix = vec![0; PREFACE.len() + FRAME_HEADER];
+        inner.read_exact(&mut prefix).await?;
+        let frame = &prefix[PREFACE.len()..];
+        let len = u32::from_be_bytes([0, frame[0], frame[1], frame[2]]) as usize;
+        if !prefix.starts_with(PREFACE) || frame[3] != SETTINGS || len > MAX_FRAME {
+            let msg = "no HTTP/2 preface after the h2c upgrade";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        }
+        let start = prefix.len();
+        prefix.resize(start + len, 0);
+        inner.read_exact(&mut prefix[start..]).await?;
+        prefix.extend(self.headers);
+        Ok(H2cIo {
+            prefix,
+            pos: 0,
+            inner,
+        })
+    }
+}
+
+/// An upgraded connection that first reads back the client's preface and
+/// the replayed request.
+pub struct H2cIo {
+    prefix: Vec<u8>,
+    pos: usize,
+    inner: TokioIo<Upgraded>,
+}
+
+impl AsyncRead for H2cIo {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.pos < this.prefix.len() {
+            let n = buf.remaining().min(this.prefix.len() - this.pos);
+            buf.put_slice(&this.prefix[this.pos..this.pos + n]);
+            this.pos += n;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for H2cIo {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Whether a comma-separated header lists `token` (case-insensitively).
+fn has_token(headers: &HeaderMap, name: &str, token: &str) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|t| t.trim().eq_ignore_ascii_case(token))
+}
+
+/// `req` as a HEADERS frame ending stream 1, `None` past one frame.
+fn headers_frame<B>(req: &Request<B>) -> Option<Vec<u8>> {
+    let path = req.uri().path_and_query().map_or("/", |p| p.as_str());
+    let mut block = Vec::new();
+    literal(&mut block, b":method", req.method().as_str().as_bytes());
+    literal(&mut block, b":scheme", b"http");
+    literal(&mut block, b":path", path.as_bytes());
+    if let Some(host) = req.headers().get(HOST) {
+        literal(&mut block, b":authority", host.as_bytes());
+    }
+    for (name, value) in req.headers() {
+        if name != HOST && !CONNECTION_HEADERS.contains(&name.as_str()) {
+            literal(&mut block, name.as_str().as_bytes(), value.as_bytes());
+        }
+    }
+    if block.len() > MAX_FRAME {
+        return None;
+    }
+    let mut frame = (block.len() as u32).to_be_bytes()[1..].to_vec();
+    frame.extend([HEADERS, END_STREAM | END_HEADERS]);
+    frame.extend(1u32.to_be_bytes());
+    frame.extend(block);
+    Some(frame)
+}
+
+/// An HPACK literal header field without indexing, with a literal name
+/// (RFC 7541 §6.2.2); strings are not Huffman-coded.
+fn literal(block: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+    block.push(0);
+    string(block, name);
+    string(block, value);
+}
+
+fn string(block: &mut Vec<u8>, s: &[u8]) {
+    // The length as a 7-bit prefix integer (RFC 7541 §5.1).
+    let mut len = s.len();
+    if len < 0x7f {
+        block.push(len as u8);
+    } else {
+        block.push(0x7f);
+        len -= 0x7f;
+        while len >= 0x80 {
+            block.push(len as u8 | 0x80);
+            len >>= 7;
+        }
+        block.push(len as u8);
+    }
+    block.extend_from_slice(s);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_request_as_stream_one() {
+        let mut req = Request::get("/imgs/a.jpg?w=10")
+            .header(HOST, "files.internal")
+            .header(CONNECTION, "Upgrade, HTTP2-Settings")
+            .header(UPGRADE, "h2c")
+            .header("http2-settings", "AAMAAABkAAQAAP__")
+            .header("x-long", "v".repeat(200))
+            .body(())
+            .unwrap();
+        let frame = headers_frame(&req).unwrap();
+        let flags = END_STREAM | END_HEADERS;
+        assert_eq!(&frame[3..9], &[HEADERS, flags, 0, 0, 0, 1]);
+        let len = u32::from_be_bytes([0, frame[0], frame[1], frame[2]]) as usize;
+        assert_eq!(len, frame.len() - FRAME_HEADER);
+        let block = &frame[FRAME_HEADER..];
+        assert!(block.starts_with(b"\x00\x07:method\x03GET\x00\x07:scheme\x04http"));
+        // A 200-byte value: 127, then 73 in the continuation byte.
+        let long = [b"\x00\x06x-long\x7f\x49".as_slice(), &[b'v'; 200]].concat();
+        assert!(block.windows(long.len()).any(|w| w == long));
+        assert!(!block.windows(7).any(|w| w == b"upgrade"));
+        assert!(Upgrade::from_request(&mut req).is_some());
+
+        let mut post = Request::post("/").header(UPGRADE, "h2c").body(()).unwrap();
+        let headers = post.headers_mut();
+        headers.insert(CONNECTION, "upgrade".parse().unwrap());
+        headers.insert("http2-settings", "".parse().unwrap());
+        headers.insert(CONTENT_LENGTH, "5".parse().unwrap());
+        assert!(Upgrade::from_request(&mut post).is_none());
+    }
+}
@@ -0,0 +1,190 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tracing::{debug, info, warn};
+
+use crate::backend::StorageBackend;
+use crate::config::HealthCheckConfig;
+use crate::server::FileSearcher;
+use crate::warmup;
+
+/// Shared health state of a single search root.
+#[derive(Debug)]
+pub struct RootHealth {
+    healthy: AtomicBool,
+    /// Set while a blocking probe is running, so a hung mount never
+    /// accumulates more than one stuck thread.
+    probing: AtomicBool,
+}
+
+impl Default for RootHealth {
+    fn default() -> Self {
+        Self {
+            healthy: AtomicBool::new(true),
+            probing: AtomicBool::new(false),
+        }
+    }
+}
+
+impl RootHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    /// Record a probe result, returning `true` if the state changed.
+    fn set_healthy(&self, healthy: bool) -> bool {
+        self.healthy.swap(healthy, Ordering::Relaxed) != healthy
+    }
+}
+
+/// Probe one root with a deadline and update its health.
+async fn check_root(
+    path: PathBuf,
+    health: Arc<RootHealth>,
+    backend: Arc<dyn StorageBackend>,
+    timeout: Duration,
+) {
+    let result = if health.probing.swap(true, Ordering::AcqRel) {
+        Err("previous probe still running".to_string())
+    } else {
+        let probe_health = health.clone();
+        let task = tokio::spawn(async move {
+            let r = backend.check().await;
+            probe_health.probing.store(false, Ordering::Release);
+            r
+        });
+        match tokio::time::timeout(timeout, task).await {
+            Ok(Ok(r)) => r,
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(format!("probe timed out after {}ms", timeout.as_millis())),
+        }
+    };
+
+    match result {
+        Ok(()) => {
+            if health.set_healthy(true) {
+                info!(path = %path.display(), "root healthy again, re-added to search set");
+            }
+        }
+        Err(error) => {
+            if health.set_healthy(false) {
+                warn!(path = %path.display(), error, "root unhealthy, excluded from search set");
+            } else {
+                debug!(path = %path.display(), error, "root still unhealthy");
+            }
+        }
+    }
+}
+
+/// Run one probe round over every active root concurrently.
+pub(crate) async fn check_all(searcher: &FileSearcher, timeout: Duration) {
+    let checks = searcher
+        .root_health()
+        .into_iter()
+        .map(|(path, health, backend)| check_root(path, health, backend, timeout));
+    futures_util::future::join_all(checks).await;
+}
+
+/// Spawn the background task that periodically probes all roots.
+pub fn spawn_health_checks(searcher: Arc<FileSearcher>, cfg: &HealthCheckConfig) {
+    let interval = Duration::from_secs(cfg.interval);
+    let timeout = Duration::from_millis(cfg.timeout_ms);
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            check_all(&searcher, timeout).await;
+        }
+    });
+
+    info!(interval_secs = cfg.interval, timeout_ms = cfg.timeout_ms, "root health checks started");
+}
+
+/// Spawn the background task that periodically retries roots that could not
+/// be opened at startup, adding each to its location once it opens. The task
+/// ends when none are left; nothing is spawned if none were skipped.
+pub fn spawn_root_retry(searcher: Arc<FileSearcher>, interval_secs: u64) {
+    let status = searcher.status();
+    let mut skipped = status
+        .iter()
+        .flat_map(|loc| &loc.roots)
+        .filter(|root| !root.active)
+        .count();
+    if skipped == 0 {
+        return;
+    }
+    let interval = Duration::from_secs(interval_secs);
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            let retry = searcher.clone();
+            // Awaited, so a hung mount holds one blocking thread at most.
+            match tokio::task::spawn_blocking(move || retry.retry_skipped_roots()).await {
+                Ok(left) => {
+                    // Roots that just opened may now serve listed paths.
+                    if left < skipped {
+                        warmup::warm_paths(&searcher).await;
+                    }
+                    if left == 0 {
+                        break;
+                    }
+                    skipped = left;
+                }
+                Err(e) => warn!(error = %e, "skipped root retry failed"),
+            }
+        }
+        info!("all skipped roots registered; re-resolution stopped");
+    });
+
+    info!(interval_secs, "skipped root re-resolution started");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn searcher_for(root: &Path) -> FileSearcher {
+        FileSearcher::builder().root(root).build().unwrap()
+    }
+
+    #[tokio::test]
+    async fn removed_root_is_excluded_and_readded() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir(&root).unwrap();
+        let searcher = searcher_for(&root);
+        let timeout = Duration::from_secs(5);
+
+        check_all(&searcher, timeout).await;
+        assert!(searcher.status()[0].roots[0].healthy);
+
+        std::fs::remove_dir(&root).unwrap();
+        check_all(&searcher, timeout).await;
+        assert!(!searcher.status()[0].roots[0].healthy);
+
+        std::fs::create_dir(&root).unwrap();
+        check_all(&searcher, timeout).await;
+        assert!(searcher.status()[0].roots[0].healthy);
+    }
+
+    #[test]
+    fn skipped_root_is_registered_once_available() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("late");
+        let searcher = searcher_for(&root);
+        assert!(!searcher.status()[0].roots[0].active);
+
+        assert_eq!(searcher.retry_skipped_roots(), 1);
+        std::fs::create_dir(&root).unwrap();
+        assert_eq!(searcher.retry_skipped_roots(), 0);
+        let roots = &searcher.status()[0].roots;
+        assert_eq!(roots.len(), 1);
+        assert!(roots[0].active);
+        assert_eq!(roots[0].path, root.canonicalize().unwrap());
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Mutex;
+
+use base64::Engine as _;
+use ring::hmac;
+use tracing::{debug, info, warn};
+
+/// Verified `user:password` pairs kept so repeat requests skip bcrypt.
+const VERIFIED_CAPACITY: usize = 1024;
+
+/// Users and bcrypt hashes from an htpasswd file.
+pub(crate) struct Htpasswd {
+    users: HashMap<String, String>,
+    /// Checked in place of a missing user's hash, so unknown names take as
+    /// long to refuse as wrong passwords.
+    dummy_hash: String,
+    /// Per-process key for [`Self::verified`]; credentials are never kept.
+    key: hmac::Key,
+    /// HMACs of credentials that already passed bcrypt; cleared when full.
+    verified: Mutex<HashSet<Vec<u8>>>,
+}
+
+impl Htpasswd {
+    /// Load `path`. Lines are `user:hash`; only bcrypt hashes (`$2a$`, `$2b$`,
+    /// `$2y$`) are accepted. An unreadable file yields no users, so every
+    /// request is refused.
+    pub(crate) fn load(path: &Path) -> Self {
+        let users = match std::fs::read_to_string(path) {
+            Ok(text) => parse(&text, path),
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "cannot read htpasswd file; denying all");
+                HashMap::new()
+            }
+        };
+        info!(path = %path.display(), users = users.len(), "htpasswd loaded");
+        Self::new(users)
+    }
+
+    fn new(users: HashMap<String, String>) -> Self {
+        // As costly as the file's own hashes.
+        let cost = users
+            .values()
+            .find_map(|hash| hash.get(4..6)?.parse().ok())
+            .unwrap_or(bcrypt::DEFAULT_COST);
+        let rng = ring::rand::SystemRandom::new();
+        Self {
+            users,
+            dummy_hash: bcrypt::hash("", cost).unwrap_or_default(),
+            key: hmac::Key::generate(hmac::HMAC_SHA256, &rng).expect("system random source"),
+            verified: Mutex::default(),
+        }
+    }
+
+    /// Check an `Authorization: Basic ...` credential (the part after `Basic `).
+    pub(crate) async fn verify(&self, encoded: &str) -> bool {
+        let Some(credential) = base64::engine::general_purpose::STANDARD
+            .decode(encoded.trim())
+            .ok()
+            .and_then(|c| String::from_utf8(c).ok())
+        else {
+            return false;
+        };
+        let Some((user, password)) = credential.split_once(':') else {
+            return false;
+        };
+        let tag = hmac::sign(&self.key, credential.as_bytes());
+        let tag = tag.as_ref().to_vec();
+        let known = self.users.get(user);
+        if known.is_some() && self.verified.lock().unwrap().contains(&tag) {
+            return true;
+        }
+
+        // bcrypt is deliberately slow; keep it off the async workers.
+        let hash = known.unwrap_or(&self.dummy_hash).clone();
+        let password = password.to_string();
+        let matched = tokio::task::spawn_blocking(move || bcrypt::verify(password, &hash))
+            .await
+            .is_ok_and(|r| r.unwrap_or(false));
+        let ok = matched && known.is_some();
+        if ok {
+            let mut verified = self.verified.lock().unwrap();
+            if verified.len() >= VERIFIED_CAPACITY {
+                verified.clear();
+            }
+            verified.insert(tag);
+        } else if known.is_none() {
+            debug!(user, "unknown basic auth user");
+        } else {
+            debug!(user, "basic auth password mismatch");
+        }
+        ok
+    }
+}
+
+fn parse(text: &str, path: &Path) -> HashMap<String, String> {
+    let mut users = HashMap::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once(':') {
+            Some((user, hash)) if ["$2a$", "$2b$", "$2y$"].iter().any(|p| hash.starts_with(p)) => {
+                users.insert(user.to_string(), hash.to_string());
+            }
+            _ => warn!(
+                path = %path.display(), line = n + 1,
+                "skipping htpasswd entry: only bcrypt hashes are supported"
+            ),
+        }
+    }
+    users
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn verifies_bcrypt_entries() {
+        let hash = bcrypt::hash("hunter2", 4).unwrap();
+        let text = format!("# team\nalice:{hash}\nbob:{{SHA}}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\n");
+        let htpasswd = Htpasswd::new(parse(&text, Path::new("test")));
+        assert_eq!(htpasswd.users.len(), 1, "non-bcrypt entries are skipped");
+        // Dummy checks cost as much as the file's own hashes.
+        assert!(htpasswd.dummy_hash.starts_with("$2b$04$"));
+
+        let b64 = |s: &str| base64::engine::general_purpose::STANDARD.encode(s);
+        assert!(htpasswd.verify(&b64("alice:hunter2")).await);
+        assert!(htpasswd.verify(&b64("alice:hunter2")).await, "cached");
+        assert!(!htpasswd.verify(&b64("alice:hunter3")).await);
+        assert!(!htpasswd.verify(&b64("bob:password")).await);
+        assert!(!htpasswd.verify("not base64").await);
+        // An unknown user never matches, not even the dummy hash.
+        assert!(!htpasswd.verify(&b64("carol:")).await);
+
+        let verified = htpasswd.verified.lock().unwrap();
+        assert_eq!(verified.len(), 1);
+        assert!(verified.iter().all(|tag| tag.len() == 32));
+        assert!(!verified.contains(b"alice:hunter2".as_slice()));
+    }
+}
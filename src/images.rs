@@ -0,0 +1,424 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, Cursor};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use futures_util::TryStreamExt;
+use hyper::{Response, StatusCode};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, ImageReader, Limits};
+use tokio::io::AsyncReadExt;
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
+
+use crate::backend::ObjectBody;
+use crate::config::ImageConfig;
+use crate::server::{
+    FileSearcher, ResponseBody, SearchHit, empty_body, full_bytes, stream_body, text_response,
+};
+
+/// Sources larger than this in either dimension are not decoded.
+const MAX_SOURCE_DIMENSION: u32 = 16_384;
+
+const JPEG_QUALITY: u8 = 85;
+
+/// How the image is fitted into the requested box.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Fit {
+    /// Scale down to fit inside `w`×`h`, keeping the aspect ratio. Never enlarges.
+    Contain,
+    /// Scale and crop to exactly `w`×`h`, keeping the aspect ratio.
+    Cover,
+    /// Stretch to exactly `w`×`h`.
+    Fill,
+}
+
+/// Output encoding requested with `?format=`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Target {
+    /// WebP if the client's `Accept` lists it, otherwise the source format.
+    Auto,
+    Format(ImageFormat),
+}
+
+/// `?w=&h=&fit=&format=` from a request query string.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct ImageParams {
+    width: Option<u32>,
+    height: Option<u32>,
+    fit: Fit,
+    format: Option<Target>,
+}
+
+impl ImageParams {
+    /// `None` when the query asks for no processing (no `w`, `h` or `format`).
+    pub(crate) fn from_query(query: &str) -> Option<Result<Self, &'static str>> {
+        let (mut width, mut height, mut fit, mut format) = (None, None, None, None);
+        for (key, value) in query.split('&').filter_map(|kv| kv.split_once('=')) {
+            match key {
+                "w" => width = Some(value),
+                "h" => height = Some(value),
+                "fit" => fit = Some(value),
+                "format" => format = Some(value),
+                _ => {}
+            }
+        }
+        if width.is_none() && height.is_none() && format.is_none() {
+            return None;
+        }
+        Some(Self::parse(width, height, fit, format))
+    }
+
+    fn parse(
+        width: Option<&str>,
+        height: Option<&str>,
+        fit: Option<&str>,
+        format: Option<&str>,
+    ) -> Result<Self, &'static str> {
+        let dimension = |v: Option<&str>| match v {
+            None => Ok(None),
+            Some(v) => match v.parse::<u32>() {
+                Ok(n) if n > 0 => Ok(Some(n)),
+                _ => Err("Invalid image dimension"),
+            },
+        };
+        let fit = match fit {
+            None | Some("contain") => Fit::Contain,
+            Some("cover") => Fit::Cover,
+            Some("fill") => Fit::Fill,
+            Some(_) => return Err("Invalid fit (expected contain, cover or fill)"),
+        };
+        let format = match format {
+            None => None,
+            Some("auto") => Some(Target::Auto),
+            Some("webp") => Some(Target::Format(ImageFormat::WebP)),
+            Some("png") => Some(Target::Format(ImageFormat::Png)),
+            Some("jpeg" | "jpg") => Some(Target::Format(ImageFormat::Jpeg)),
+            Some(_) => return Err("Invalid format (expected auto, webp, png or jpeg)"),
+        };
+        let params = Self {
+            width: dimension(width)?,
+            height: dimension(height)?,
+            fit,
+            format,
+        };
+        if fit != Fit::Contain && (params.width.is_none() || params.height.is_none()) {
+            return Err("fit=cover and fit=fill need both w and h");
+        }
+        Ok(params)
+    }
+}
+
+/// Resizes and re-encodes images for one location and keeps the results in
+/// its cache directory, keyed by source file version and parameters.
+pub(crate) struct ImageProcessor {
+    cache_dir: PathBuf,
+    max_dimension: u32,
+    /// Bounds the decode/encode jobs running on the blocking pool.
+    encodes: Semaphore,
+}
+
+impl ImageProcessor {
+    pub(crate) fn new(cfg: &ImageConfig) -> Self {
+        Self {
+            cache_dir: cfg.cache_dir.clone(),
+            max_dimension: cfg.max_dimension,
+            encodes: Semaphore::new(cfg.max_concurrent_encodes),
+        }
+    }
+
+    /// Serve a resized and/or re-encoded variant of the file `path` resolves to.
+    /// `accept` is the request's `Accept` header, used by `format=auto`.
+    pub(crate) async fn respond(
+        &self,
+        searcher: &FileSearcher,
+        path: &str,
+        params: Result<ImageParams, &'static str>,
+        accept: Option<&str>,
+        is_head: bool,
+    ) -> Response<ResponseBody> {
+        let params = match params {
+            Ok(p) => p,
+            Err(message) => return text_response(StatusCode::BAD_REQUEST, message),
+        };
+        if params.width.max(params.height).unwrap_or(0) > self.max_dimension {
+            return text_response(StatusCode::BAD_REQUEST, "Image dimension too large");
+        }
+
+        let Some(hit) = searcher.search(path).await else {
+            debug!(status = 404, path, "image request handled");
+            return text_response(StatusCode::NOT_FOUND, "Not Found");
+        };
+        let Some(source) = ImageFormat::from_mime_type(hit.mime.essence_str()).filter(|f| {
+            matches!(
+                f,
+                ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::Gif | ImageFormat::WebP
+            )
+        }) else {
+            debug!(status = 415, path, mime = %hit.mime, "image request handled");
+            return text_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Unsupported Media Type");
+        };
+        let output = match params.format {
+            Some(Target::Format(format)) => format,
+            Some(Target::Auto) if accepts_webp(accept) => ImageFormat::WebP,
+            Some(Target::Auto) | None => output_format(source),
+        };
+        let negotiated = params.format == Some(Target::Auto);
+
+        let cached = self.cache_dir.join(format!(
+            "{:016x}.{}",
+            cache_key(&hit, &params, output),
+            output.extensions_str()[0]
+        ));
+        if let Ok(file) = tokio::fs::File::open(&cached).await
+            && let Ok(meta) = file.metadata().await
+        {
+            debug!(status = 200, path, cached = %cached.display(), "image request handled (cached)");
+            let body = if is_head {
+                empty_body()
+            } else {
+                stream_body(ObjectBody::File(file), 64 * 1024)
+            };
+            return image_response(output, meta.len(), negotiated, body);
+        }
+
+        let resolved = hit.path.clone();
+        let data = match read_body(hit.body, hit.size).await {
+            Ok(data) => data,
+            Err(e) => {
+                warn!(path, error = %e, "cannot read image source");
+                return text_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error");
+            }
+        };
+        let Ok(_permit) = self.encodes.acquire().await else {
+            return text_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error");
+        };
+        let task_params = params.clone();
+        let encoded =
+            tokio::task::spawn_blocking(move || transform(&data, &task_params, output)).await;
+        let encoded = match encoded {
+            Ok(Ok(bytes)) => bytes,
+            Ok(Err(e)) => {
+                warn!(path, resolved = %resolved.display(), error = %e, "cannot process image");
+                return text_response(StatusCode::UNPROCESSABLE_ENTITY, "Cannot Process Image");
+            }
+            Err(_) => {
+                return text_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error");
+            }
+        };
+
+        if let Err(e) = self.store(&cached, &encoded).await {
+            warn!(cache = %cached.display(), error = %e, "cannot write image cache");
+        }
+        debug!(
+            status = 200,
+            path,
+            ?params,
+            size = encoded.len(),
+            "image request handled"
+        );
+        let size = encoded.len() as u64;
+        let body = if is_head {
+            empty_body()
+        } else {
+            full_bytes(encoded.into())
+        };
+        image_response(output, size, negotiated, body)
+    }
+
+    /// Write via a temporary file so concurrent readers never see a partial image.
+    async fn store(&self, cached: &std::path::Path, data: &[u8]) -> io::Result<()> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        tokio::fs::create_dir_all(&self.cache_dir).await?;
+        let tmp = cached.with_extension(format!(
+            "tmp{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        tokio::fs::write(&tmp, data).await?;
+        tokio::fs::rename(&tmp, cached).await
+    }
+}
+
+/// Formats without a usable encoder are re-encoded as PNG.
+fn output_format(source: ImageFormat) -> ImageFormat {
+    match source {
+        ImageFormat::Gif => ImageFormat::Png,
+        other => other,
+    }
+}
+
+/// Whether an `Accept` header lists `image/webp` (or `image/*`) with non-zero quality.
+fn accepts_webp(accept: Option<&str>) -> bool {
+    accept.is_some_and(|accept| {
+        accept.split(',').any(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            let media = parts.next().unwrap_or("");
+            let rejected = parts.any(|p| {
+                p.strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q <= 0.0)
+            });
+            matches!(media, "image/webp" | "image/*") && !rejected
+        })
+    })
+}
+
+/// The resolved output format is part of the key so `format=auto` keeps one
+/// variant per negotiated encoding.
+fn cache_key(hit: &SearchHit, params: &ImageParams, output: ImageFormat) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hit.path.hash(&mut hasher);
+    hit.size.hash(&mut hasher);
+    hit.modified.hash(&mut hasher);
+    params.hash(&mut hasher);
+    output.hash(&mut hasher);
+    hasher.finish()
+}
+
+async fn read_body(body: ObjectBody, size: u64) -> io::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(size.min(64 * 1024 * 1024) as usize);
+    match body {
+        ObjectBody::File(mut file) => {
+            file.read_to_end(&mut data).await?;
+        }
+        ObjectBody::Stream(stream) => {
+            data = stream
+                .try_fold(data, |mut acc, chunk| async move {
+                    acc.extend_from_slice(&chunk);
+                    Ok(acc)
+                })
+                .await?;
+        }
+    }
+    Ok(data)
+}
+
+/// Blocking: decode, resize and re-encode.
+fn transform(
+    data: &[u8],
+    params: &ImageParams,
+    output: ImageFormat,
+) -> image::ImageResult<Vec<u8>> {
+    let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SOURCE_DIMENSION);
+    limits.max_image_height = Some(MAX_SOURCE_DIMENSION);
+    reader.limits(limits);
+    let img = resize(reader.decode()?, params);
+
+    let mut out = Cursor::new(Vec::new());
+    match output {
+        ImageFormat::Jpeg => {
+            let encoder = JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY);
+            DynamicImage::ImageRgb8(img.to_rgb8()).write_with_encoder(encoder)?;
+        }
+        other => img.write_to(&mut out, other)?,
+    }
+    Ok(out.into_inner())
+}
+
+fn resize(img: DynamicImage, params: &ImageParams) -> DynamicImage {
+    let filter = FilterType::Lanczos3;
+    match (params.fit, params.width, params.height) {
+        (Fit::Cover, Some(w), Some(h)) => img.resize_to_fill(w, h, filter),
+        (Fit::Fill, Some(w), Some(h)) => img.resize_exact(w, h, filter),
+        (_, w, h) => {
+            let w = w.map_or(img.width(), |w| w.min(img.width()));
+            let h = h.map_or(img.height(), |h| h.min(img.height()));
+            if (w, h) == (img.width(), img.height()) {
+                img
+            } else {
+                img.resize(w, h, filter)
+            }
+        }
+    }
+}
+
+fn image_response(
+    format: ImageFormat,
+    size: u64,
+    negotiated: bool,
+    body: ResponseBody,
+) -> Response<ResponseBody> {
+    let mut builder = Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", format.to_mime_type())
+        .header("Content-Length", size)
+        .header("X-Content-Type-Options", "nosniff");
+    if negotiated {
+        builder = builder.header("Vary", "Accept");
+    }
+    builder.body(body).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_resize_queries() {
+        assert!(ImageParams::from_query("v=2").is_none());
+        assert_eq!(
+            ImageParams::from_query("w=200&h=100&fit=cover")
+                .unwrap()
+                .unwrap(),
+            ImageParams {
+                width: Some(200),
+                height: Some(100),
+                fit: Fit::Cover,
+                format: None,
+            }
+        );
+        assert_eq!(
+            ImageParams::from_query("format=jpg").unwrap().unwrap().format,
+            Some(Target::Format(ImageFormat::Jpeg))
+        );
+        assert!(ImageParams::from_query("format=bmp").unwrap().is_err());
+        assert!(ImageParams::from_query("w=0").unwrap().is_err());
+        assert!(
+            ImageParams::from_query("w=200&fit=cover")
+                .unwrap()
+                .is_err()
+        );
+        assert!(
+            ImageParams::from_query("w=200&fit=stretch")
+                .unwrap()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn negotiates_webp_from_accept() {
+        assert!(accepts_webp(Some("image/avif,image/webp,*/*;q=0.8")));
+        assert!(accepts_webp(Some("image/*")));
+        assert!(!accepts_webp(Some("image/webp;q=0, image/png")));
+        assert!(!accepts_webp(Some("text/html,*/*")));
+        assert!(!accepts_webp(None));
+    }
+
+    #[test]
+    fn contain_never_enlarges() {
+        let img = DynamicImage::new_rgb8(400, 200);
+        let params = |w, h| ImageParams {
+            width: w,
+            height: h,
+            fit: Fit::Contain,
+            format: None,
+        };
+        let out = resize(img.clone(), &params(Some(100), None));
+        assert_eq!((out.width(), out.height()), (100, 50));
+        let out = resize(img.clone(), &params(Some(800), Some(800)));
+        assert_eq!((out.width(), out.height()), (400, 200));
+
+        let cover = ImageParams {
+            width: Some(50),
+            height: Some(50),
+            fit: Fit::Cover,
+            format: None,
+        };
+        let out = resize(img, &cover);
+        assert_eq!((out.width(), out.height()), (50, 50));
+    }
+}
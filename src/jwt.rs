@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime};
+
+use base64::Engine as _;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty, Limited};
+use hyper::Request;
+use ring::signature::{self, RsaPublicKeyComponents};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::{debug, warn};
+
+use crate::backend::remote::{self, HttpClient};
+
+/// Clock skew tolerated on `exp` and `nbf`.
+const LEEWAY_SECS: u64 = 30;
+/// A token with an unknown `kid` refetches the key set at most this often.
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(60);
+const JWKS_TIMEOUT: Duration = Duration::from_secs(5);
+const JWKS_MAX_SIZE: usize = 1024 * 1024;
+
+/// Validates `Authorization: Bearer` JWTs for one location.
+pub(crate) struct JwtValidator {
+    /// HS256 shared secret.
+    secret: Option<ring::hmac::Key>,
+    jwks: Option<Jwks>,
+    issuer: String,
+    audience: String,
+    prefix_claim: String,
+}
+
+/// RS256 keys from a JWKS endpoint, fetched on first use and again when a
+/// token names an unknown `kid`.
+struct Jwks {
+    url: String,
+    client: HttpClient,
+    state: RwLock<JwksState>,
+    refresh: tokio::sync::Mutex<()>,
+}
+
+#[derive(Default)]
+struct JwksState {
+    /// Keyed by `kid` (empty for keys without one).
+    keys: HashMap<String, RsaKey>,
+    fetched: Option<Instant>,
+}
+
+#[derive(Clone)]
+struct RsaKey {
+    n: Vec<u8>,
+    e: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+    #[serde(default)]
+    kid: String,
+}
+
+impl JwtValidator {
+    /// Empty strings leave the corresponding check or key source unset.
+    pub(crate) fn new(
+        secret: &str,
+        jwks_url: &str,
+        issuer: &str,
+        audience: &str,
+        prefix_claim: &str,
+        client: HttpClient,
+    ) -> Self {
+        Self {
+            secret: (!secret.is_empty())
+                .then(|| ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes())),
+            jwks: (!jwks_url.is_empty()).then(|| Jwks {
+                url: jwks_url.to_string(),
+                client,
+                state: RwLock::default(),
+                refresh: tokio::sync::Mutex::new(()),
+            }),
+            issuer: issuer.to_string(),
+            audience: audience.to_string(),
+            prefix_claim: prefix_claim.to_string(),
+        }
+    }
+
+    /// Check a compact JWT's signature and claims for a request to
+    /// `request_path`. `Err` carries the reason, for logs.
+    pub(crate) async fn validate(&self, token: &str, request_path: &str) -> Result<(), String> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(sig), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err("malformed token".into());
+        };
+        let signing_input = &token[..header.len() + 1 + payload.len()];
+        let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).map_err(|e| e.to_string());
+        let header: Header =
+            serde_json::from_slice(&decode(header)?).map_err(|e| format!("header: {e}"))?;
+        let sig = decode(sig)?;
+
+        match header.alg.as_str() {
+            "HS256" => {
+                let key = self.secret.as_ref().ok_or("HS256 is not accepted")?;
+                ring::hmac::verify(key, signing_input.as_bytes(), &sig)
+                    .map_err(|_| "bad signature")?;
+            }
+            "RS256" => {
+                let jwks = self.jwks.as_ref().ok_or("RS256 is not accepted")?;
+                let key = jwks.key(&header.kid).await.ok_or("unknown signing key")?;
+                RsaPublicKeyComponents {
+                    n: &key.n,
+                    e: &key.e,
+                }
+                .verify(
+                    &signature::RSA_PKCS1_2048_8192_SHA256,
+                    signing_input.as_bytes(),
+                    &sig,
+                )
+                .map_err(|_| "bad signature")?;
+            }
+            other => return Err(format!("unsupported alg {other:?}")),
+        }
+
+        let claims: Value =
+            serde_json::from_slice(&decode(payload)?).map_err(|e| format!("claims: {e}"))?;
+        self.check_claims(&claims, request_path)
+    }
+
+    fn check_claims(&self, claims: &Value, request_path: &str) -> Result<(), String> {
+        let now = crate::server::unix_secs(SystemTime::now());
+        if let Some(exp) = claims.get("exp")
+            && exp
+                .as_u64()
+                .is_none_or(|exp| exp.saturating_add(LEEWAY_SECS) <= now)
+        {
+            return Err("token expired".into());
+        }
+        if let Some(nbf) = claims.get("nbf")
+            && nbf
+                .as_u64()
+                .is_none_or(|nbf| nbf > now.saturating_add(LEEWAY_SECS))
+        {
+            return Err("token not yet valid".into());
+        }
+        if !self.issuer.is_empty()
+            && claims.get("iss").and_then(Value::as_str) != Some(&self.issuer)
+        {
+            return Err("issuer mismatch".into());
+        }
+        if !self.audience.is_empty() && !strings(claims.get("aud")).any(|a| a == self.audience) {
+            return Err("audience mismatch".into());
+        }
+        if !self.prefix_claim.is_empty()
+            && !strings(claims.get(&self.prefix_claim)).any(|p| covers(p, request_path))
+        {
+            return Err(format!(
+                "{} does not cover {request_path}",
+                self.prefix_claim
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Jwks {
+    async fn key(&self, kid: &str) -> Option<RsaKey> {
+        if let Some(key) = self.lookup(kid) {
+            return Some(key);
+        }
+        let _guard = self.refresh.lock().await;
+        // Another request may have refreshed while this one waited.
+        if let Some(key) = self.lookup(kid) {
+            return Some(key);
+        }
+        let fetched = self.state.read().unwrap().fetched;
+        if fetched.is_some_and(|t| t.elapsed() < JWKS_MIN_REFRESH) {
+            return None;
+        }
+        let keys = match self.fetch().await {
+            Ok(keys) => {
+                debug!(url = %self.url, keys = keys.len(), "JWKS fetched");
+                keys
+            }
+            Err(e) => {
+                warn!(url = %self.url, error = %e, "cannot fetch JWKS");
+                HashMap::new()
+            }
+        };
+        let mut state = self.state.write().unwrap();
+        if !keys.is_empty() {
+            state.keys = keys;
+        }
+        state.fetched = Some(Instant::now());
+        drop(state);
+        self.lookup(kid)
+    }
+
+    /// A key without a `kid` is used when the set holds exactly one key.
+    fn lookup(&self, kid: &str) -> Option<RsaKey> {
+        let state = self.state.read().unwrap();
+        match state.keys.get(kid) {
+            Some(key) => Some(key.clone()),
+            None if kid.is_empty() && state.keys.len() == 1 => state.keys.values().next().cloned(),
+            None => None,
+        }
+    }
+
+    async fn fetch(&self) -> Result<HashMap<String, RsaKey>, String> {
+        let req = Request::get(&self.url)
+            .header("Accept", "application/json")
+            .body(Empty::<Bytes>::new())
+            .map_err(|e| e.to_string())?;
+        let resp = remote::send(&self.client, req, JWKS_TIMEOUT).await?;
+        if !resp.status().is_success() {
+            return Err(format!("status {}", resp.status()));
+        }
+        let body = Limited::new(resp.into_body(), JWKS_MAX_SIZE)
+            .collect()
+            .await
+            .map_err(|e| e.to_string())?
+            .to_bytes();
+        parse_jwks(&body)
+    }
+}
+
+/// RSA signing keys from a JWKS document, by `kid`.
+fn parse_jwks(body: &[u8]) -> Result<HashMap<String, RsaKey>, String> {
+    #[derive(Deserialize)]
+    struct Document {
+        keys: Vec<Jwk>,
+    }
+    #[derive(Deserialize)]
+    struct Jwk {
+        kty: String,
+        #[serde(default)]
+        kid: String,
+        #[serde(default, rename = "use")]
+        usage: Option<String>,
+        #[serde(default)]
+        n: String,
+        #[serde(default)]
+        e: String,
+    }
+
+    let doc: Document = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+    let keys = doc
+        .keys
+        .into_iter()
+        .filter(|k| k.kty == "RSA" && k.usage.as_deref().is_none_or(|u| u == "sig"))
+        .filter_map(|k| {
+            let n = URL_SAFE_NO_PAD.decode(&k.n).ok()?;
+            let e = URL_SAFE_NO_PAD.decode(&k.e).ok()?;
+            Some((k.kid, RsaKey { n, e }))
+        })
+        .collect();
+    Ok(keys)
+}
+
+/// A claim that is a string or an array of strings.
+fn strings(value: Option<&Value>) -> impl Iterator<Item = &str> {
+    let items: Vec<&str> = match value {
+        Some(Value::String(s)) => vec![s],
+        Some(Value::Array(values)) => values.iter().filter_map(Value::as_str).collect(),
+        _ => vec![],
+    };
+    items.into_iter()
+}
+
+/// Whether `prefix` covers `path` on a segment boundary.
+fn covers(prefix: &str, path: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    prefix.is_empty()
+        || path == prefix
+        || path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hs256(secret: &str, claims: &Value) -> String {
+        let header = URL_SAFE_NO_PAD.encode(br#"{"alg":"HS256","typ":"JWT"}"#);
+        let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+        let input = format!("{header}.{payload}");
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes());
+        let sig = URL_SAFE_NO_PAD.encode(ring::hmac::sign(&key, input.as_bytes()));
+        format!("{input}.{sig}")
+    }
+
+    #[tokio::test]
+    async fn validates_hs256_signature_and_claims() {
+        let v = JwtValidator::new("s3cret", "", "idp", "files", "prefixes", remote::client());
+        let now = crate::server::unix_secs(SystemTime::now());
+        let claims = serde_json::json!({
+            "iss": "idp",
+            "aud": ["other", "files"],
+            "exp": now + 60,
+            "prefixes": ["/reports/team-a"],
+        });
+        let token = hs256("s3cret", &claims);
+        assert!(v.validate(&token, "/reports/team-a/q3.csv").await.is_ok());
+        assert!(v.validate(&token, "/reports/team-ab/q3.csv").await.is_err());
+        assert!(
+            v.validate(&hs256("wrong", &claims), "/reports/team-a/x")
+                .await
+                .is_err()
+        );
+
+        let mut expired = claims.clone();
+        expired["exp"] = (now - 120).into();
+        assert!(
+            v.validate(&hs256("s3cret", &expired), "/reports/team-a/x")
+                .await
+                .is_err()
+        );
+        // A far-future expiry is valid, not an overflow.
+        let mut lasting = claims.clone();
+        lasting["exp"] = u64::MAX.into();
+        assert!(
+            v.validate(&hs256("s3cret", &lasting), "/reports/team-a/x")
+                .await
+                .is_ok()
+        );
+        let mut foreign = claims.clone();
+        foreign["iss"] = "elsewhere".into();
+        assert!(
+            v.validate(&hs256("s3cret", &foreign), "/reports/team-a/x")
+                .await
+                .is_err()
+        );
+
+        // `alg: none` and RS256 without a key set are refused.
+        let none = format!(
+            "{}.{}.",
+            URL_SAFE_NO_PAD.encode(br#"{"alg":"none"}"#),
+            "e30"
+        );
+        assert!(v.validate(&none, "/").await.is_err());
+    }
+
+    #[test]
+    fn parses_rsa_signing_keys_from_jwks() {
+        let doc = br#"{"keys":[
+            {"kty":"RSA","kid":"a","use":"sig","n":"AQAB","e":"AQAB"},
+            {"kty":"RSA","kid":"b","use":"enc","n":"AQAB","e":"AQAB"},
+            {"kty":"EC","kid":"c","crv":"P-256","x":"","y":""}
+        ]}"#;
+        let keys = parse_jwks(doc).unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys["a"].e, vec![1, 0, 1]);
+    }
+}
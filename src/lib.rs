@@ -1,3 +1,68 @@
+#[cfg(feature = "acme")]
+pub mod acme;
+mod adaptive;
+pub mod admin;
+mod antivirus;
+#[cfg(feature = "archive")]
+mod archive;
+pub mod audit;
+mod auth;
+pub mod backend;
+mod bandwidth;
+pub mod batch;
+pub mod breaker;
+#[cfg(feature = "compression")]
+pub mod compression;
+mod conditional;
 pub mod config;
+pub mod connections;
+#[cfg(unix)]
+pub mod daemon;
+pub mod denylist;
+#[cfg(feature = "digest")]
+mod digest;
+mod disposition;
+mod fallback;
+pub mod h2c;
+pub mod health;
+#[cfg(feature = "basic-auth")]
+mod htpasswd;
+#[cfg(feature = "jwt")]
+mod jwt;
+#[cfg(feature = "images")]
+mod images;
+pub mod lint;
+#[cfg(feature = "cli")]
+pub mod logging;
+mod magic;
+pub mod meta;
+mod metrics;
+pub mod misses;
+mod multipart;
+pub mod precompress;
+#[cfg(unix)]
+pub mod privileges;
+mod problem;
+mod quarantine;
+mod range;
 pub mod ratelimit;
+pub mod report;
+#[cfg(feature = "s3-api")]
+mod s3api;
 pub mod server;
+pub mod service;
+mod shadow;
+pub mod stats;
+pub mod statsd;
+pub mod strategy;
+#[cfg(feature = "tls")]
+pub mod tls;
+mod upload;
+mod vhost;
+pub mod warmup;
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "webhooks")]
+pub mod webhooks;
+#[cfg(windows)]
+pub mod winservice;
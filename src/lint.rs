@@ -0,0 +1,334 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+use crate::config::{normalize_prefix, Config, LocationConfig, SearchPath};
+
+/// Upper bound on directory entries inspected per root when sampling files.
+const SAMPLE_LIMIT: usize = 1000;
+
+/// A suspicious-but-valid configuration finding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintWarning {
+    /// Stable short identifier, e.g. `"shadowed-subtree"`.
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.code, self.message)
+    }
+}
+
+/// Run every lint over an already-validated config.
+///
+/// Lints may touch the filesystem (to check whether roots exist or whether a
+/// subtree is hidden by a longer prefix), but never read file contents.
+pub fn lint(config: &Config) -> Vec<LintWarning> {
+    let mut out = Vec::new();
+    lint_unlimited_file_size(config, &mut out);
+    lint_compression_min_size(config, &mut out);
+    lint_statsd_tags(config, &mut out);
+    lint_shadowed_subtrees(&config.locations, &mut out);
+    for vhost in &config.vhosts {
+        lint_shadowed_subtrees(&vhost.locations, &mut out);
+    }
+    for loc in config.all_locations() {
+        let prefix = normalize_prefix(&loc.prefix);
+        lint_duplicate_roots(&prefix, &loc.paths, &mut out);
+        for sp in &loc.paths {
+            lint_root(&prefix, sp, &mut out);
+        }
+    }
+    out
+}
+
+fn warn(out: &mut Vec<LintWarning>, code: &'static str, message: String) {
+    out.push(LintWarning { code, message });
+}
+
+/// `max_file_size = 0` silently means "no limit".
+fn lint_unlimited_file_size(config: &Config, out: &mut Vec<LintWarning>) {
+    let server_unlimited = config.server.max_file_size.as_u64() == 0;
+    if server_unlimited {
+        warn(
+            out,
+            "unlimited-file-size",
+            "[server].max_file_size = 0 disables the file size limit".into(),
+        );
+    }
+    for loc in config.all_locations() {
+        if loc.max_file_size.is_some_and(|s| s.as_u64() == 0) && !server_unlimited {
+            warn(
+                out,
+                "unlimited-file-size",
+                format!(
+                    "location {:?} sets max_file_size = 0, disabling the size limit",
+                    loc.prefix,
+                ),
+            );
+        }
+    }
+}
+
+/// The compression predicate only accepts sizes up to `u16::MAX`.
+fn lint_compression_min_size(config: &Config, out: &mut Vec<LintWarning>) {
+    let comp = &config.server.compression;
+    if comp.enabled && comp.min_size.as_u64() > u16::MAX as u64 {
+        warn(
+            out,
+            "compression-min-size-clamped",
+            format!(
+                "compression.min_size = {} exceeds the supported maximum and is clamped to {}",
+                comp.min_size,
+                crate::config::ByteSize(u16::MAX as u64),
+            ),
+        );
+    }
+}
+
+/// Plain statsd has no tags, so they are not sent.
+fn lint_statsd_tags(config: &Config, out: &mut Vec<LintWarning>) {
+    let statsd = &config.server.statsd;
+    if statsd.enabled && !statsd.dogstatsd && !statsd.tags.is_empty() {
+        warn(
+            out,
+            "statsd-tags-ignored",
+            "statsd.tags are only sent with statsd.dogstatsd = true".into(),
+        );
+    }
+}
+
+/// A longer prefix hides the matching subdirectory of a shorter prefix's roots:
+/// with `/` → `/data` and `/imgs` → `/other`, `/data/imgs/*` is unreachable.
+/// Checked within each site's `locations`.
+fn lint_shadowed_subtrees(locations: &[LocationConfig], out: &mut Vec<LintWarning>) {
+    let prefixes: Vec<String> = locations
+        .iter()
+        .map(|l| normalize_prefix(&l.prefix))
+        .collect();
+
+    for (outer, outer_prefix) in locations.iter().zip(&prefixes) {
+        for inner_prefix in &prefixes {
+            let rest = if outer_prefix == "/" {
+                inner_prefix.strip_prefix('/')
+            } else {
+                inner_prefix
+                    .strip_prefix(outer_prefix.as_str())
+                    .and_then(|r| r.strip_prefix('/'))
+            };
+            let Some(rest) = rest.filter(|r| !r.is_empty()) else {
+                continue;
+            };
+
+            for sp in &outer.paths {
+                let hidden = sp.root.join(rest);
+                if hidden.is_dir() {
+                    warn(
+                        out,
+                        "shadowed-subtree",
+                        format!(
+                            "{} is unreachable via location {:?}: requests under {:?} are routed to location {:?}",
+                            hidden.display(),
+                            outer_prefix,
+                            inner_prefix,
+                            inner_prefix,
+                        ),
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn lint_duplicate_roots(prefix: &str, paths: &[SearchPath], out: &mut Vec<LintWarning>) {
+    let mut seen = HashSet::new();
+    for sp in paths {
+        let key = sp.root.canonicalize().unwrap_or_else(|_| sp.root.clone());
+        if !seen.insert(key) {
+            warn(
+                out,
+                "duplicate-root",
+                format!(
+                    "location {:?} lists root {} more than once",
+                    prefix,
+                    sp.root.display(),
+                ),
+            );
+        }
+    }
+}
+
+fn lint_root(prefix: &str, sp: &SearchPath, out: &mut Vec<LintWarning>) {
+    if !sp.is_remote() && !sp.root.is_dir() {
+        warn(
+            out,
+            "missing-root",
+            format!(
+                "location {:?}: root {} does not exist or is not a directory and will be skipped",
+                prefix,
+                sp.root.display(),
+            ),
+        );
+        return;
+    }
+
+    let Some(exts) = sp.extension_set() else {
+        return;
+    };
+
+    // `Path::extension()` only yields the last component, so entries like
+    // "tar.gz", "*" or "" can never match a request.
+    for ext in &exts {
+        if ext.is_empty() || ext.contains(['.', '*', '/']) {
+            warn(
+                out,
+                "unmatchable-extension",
+                format!(
+                    "location {:?}: extension {:?} on root {} can never match (use the last extension only, e.g. \"gz\")",
+                    prefix,
+                    ext,
+                    sp.root.display(),
+                ),
+            );
+        }
+    }
+
+    if let Some((files, matching)) = sample_files(&sp.root, &exts)
+        && files > 0
+        && matching == 0
+    {
+        warn(
+            out,
+            "extension-filter-blocks-all",
+            format!(
+                "location {:?}: none of the {} file(s) sampled in {} match its extension filter",
+                prefix,
+                files,
+                sp.root.display(),
+            ),
+        );
+    }
+}
+
+/// Count regular files at the top of `root` (bounded) and how many pass `exts`.
+///
+/// Subdirectories are not descended into, so a root that only holds
+/// directories yields `files == 0` and is never reported.
+fn sample_files(root: &Path, exts: &HashSet<String>) -> Option<(usize, usize)> {
+    let (mut files, mut matching) = (0, 0);
+    for entry in std::fs::read_dir(root).ok()?.take(SAMPLE_LIMIT).flatten() {
+        if !entry.file_type().is_ok_and(|t| t.is_file()) {
+            continue;
+        }
+        files += 1;
+        let ext = entry
+            .path()
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        if ext.is_some_and(|e| exts.contains(&e)) {
+            matching += 1;
+        }
+    }
+    Some((files, matching))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ByteSize, LocationConfig, SearchMode, ServerConfig};
+    use std::path::PathBuf;
+
+    fn location(prefix: &str, root: &Path, extensions: Vec<String>) -> LocationConfig {
+        LocationConfig {
+            prefix: prefix.into(),
+            mode: SearchMode::Sequential,
+            paths: vec![SearchPath {
+                root: root.to_path_buf(),
+                extensions,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn codes(config: &Config) -> Vec<&'static str> {
+        lint(config).into_iter().map(|w| w.code).collect()
+    }
+
+    #[test]
+    fn clean_config_has_no_warnings() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.jpg"), b"x").unwrap();
+        let cfg = Config {
+            server: ServerConfig::default(),
+            locations: vec![location("/", dir.path(), vec!["jpg".into()])],
+            vhosts: Vec::new(),
+        };
+        assert!(lint(&cfg).is_empty(), "{:?}", lint(&cfg));
+    }
+
+    #[test]
+    fn flags_unlimited_file_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cfg = Config {
+            server: ServerConfig::default(),
+            locations: vec![location("/", dir.path(), vec![])],
+            vhosts: Vec::new(),
+        };
+        cfg.locations[0].max_file_size = Some(ByteSize(0));
+        assert_eq!(codes(&cfg), vec!["unlimited-file-size"]);
+    }
+
+    #[test]
+    fn flags_shadowed_subtree() {
+        let outer = tempfile::tempdir().unwrap();
+        let inner = tempfile::tempdir().unwrap();
+        std::fs::create_dir(outer.path().join("imgs")).unwrap();
+        let cfg = Config {
+            server: ServerConfig::default(),
+            locations: vec![
+                location("/", outer.path(), vec![]),
+                location("/imgs", inner.path(), vec![]),
+            ],
+            vhosts: Vec::new(),
+        };
+        assert_eq!(codes(&cfg), vec!["shadowed-subtree"]);
+    }
+
+    #[test]
+    fn flags_missing_root() {
+        let cfg = Config {
+            server: ServerConfig::default(),
+            locations: vec![location("/", &PathBuf::from("/nonexistent/filehunter"), vec![])],
+            vhosts: Vec::new(),
+        };
+        assert_eq!(codes(&cfg), vec!["missing-root"]);
+    }
+
+    #[test]
+    fn flags_unmatchable_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = Config {
+            server: ServerConfig::default(),
+            locations: vec![location("/", dir.path(), vec!["tar.gz".into()])],
+            vhosts: Vec::new(),
+        };
+        assert_eq!(codes(&cfg), vec!["unmatchable-extension"]);
+    }
+
+    #[test]
+    fn flags_filter_blocking_every_file() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), b"x").unwrap();
+        std::fs::write(dir.path().join("b.csv"), b"x").unwrap();
+        let cfg = Config {
+            server: ServerConfig::default(),
+            locations: vec![location("/", dir.path(), vec!["jpg".into()])],
+            vhosts: Vec::new(),
+        };
+        assert_eq!(codes(&cfg), vec!["extension-filter-blocks-all"]);
+    }
+}
@@ -0,0 +1,200 @@
+//! Per-location log levels and sampling on top of `RUST_LOG`.
+//!
+//! The server runs each request of a location with `log_level` or
+//! `log_sample` inside a `location` span whose fields carry the location's
+//! level and whether the request was sampled. [`LocationFilter`] reads them
+//! back for filehunter's own events and leaves everything else to the
+//! `EnvFilter` it wraps.
+
+use std::fmt;
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Level, Metadata, Subscriber};
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::layer::{Context, Filter};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::config::Config;
+
+/// `RUST_LOG`, with locations' `log_level` and `log_sample` applied to the
+/// events of their requests.
+pub struct LocationFilter {
+    env: EnvFilter,
+    /// Whether any location overrides logging.
+    active: bool,
+    /// The most verbose `log_level` of any location.
+    max: LevelFilter,
+}
+
+/// What a `location` span says about the events inside it.
+#[derive(Clone, Copy, Default)]
+struct LocationLog {
+    level: Option<LevelFilter>,
+    sampled: bool,
+}
+
+impl Visit for LocationLog {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "log_level" {
+            self.level = value.parse().ok();
+        }
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if field.name() == "sampled" {
+            self.sampled = value;
+        }
+    }
+
+    fn record_debug(&mut self, _: &Field, _: &dyn fmt::Debug) {}
+}
+
+impl LocationFilter {
+    pub fn new(env: EnvFilter, config: &Config) -> Self {
+        let max = config
+            .all_locations()
+            .filter_map(|loc| loc.log_level)
+            .max()
+            .map_or(LevelFilter::OFF, |level| level.as_str().parse().unwrap());
+        let active = config
+            .all_locations()
+            .any(|loc| loc.log_level.is_some() || loc.log_sample > 1);
+        Self { env, active, max }
+    }
+
+    /// The overrides of the innermost `location` span `cx` is in.
+    fn location<S>(cx: &Context<'_, S>) -> Option<LocationLog>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let span = cx.lookup_current()?;
+        span.scope()
+            .find_map(|span| span.extensions().get::<LocationLog>().copied())
+    }
+}
+
+fn is_location_span(meta: &Metadata<'_>) -> bool {
+    meta.is_span() && meta.name() == "location" && meta.fields().field("sampled").is_some()
+}
+
+fn is_own_event(meta: &Metadata<'_>) -> bool {
+    meta.is_event() && meta.target().starts_with("filehunter")
+}
+
+impl<S> Filter<S> for LocationFilter
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn enabled(&self, meta: &Metadata<'_>, cx: &Context<'_, S>) -> bool {
+        if is_location_span(meta) {
+            return true;
+        }
+        let log = is_own_event(meta).then(|| Self::location(cx)).flatten();
+        let Some(log) = log else {
+            return self.env.enabled(meta, cx.clone());
+        };
+        let shown = match log.level {
+            Some(level) => level >= *meta.level(),
+            None => self.env.enabled(meta, cx.clone()),
+        };
+        shown && (log.sampled || *meta.level() < Level::DEBUG)
+    }
+
+    fn callsite_enabled(&self, meta: &'static Metadata<'static>) -> Interest {
+        // Whether an event shows depends on the span it is in.
+        if self.active && (is_location_span(meta) || is_own_event(meta)) {
+            return Interest::sometimes();
+        }
+        Filter::<S>::callsite_enabled(&self.env, meta)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        let env = Filter::<S>::max_level_hint(&self.env)?;
+        Some(if self.active { env.max(self.max) } else { env })
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if is_location_span(attrs.metadata())
+            && let Some(span) = ctx.span(id)
+        {
+            let mut log = LocationLog::default();
+            attrs.record(&mut log);
+            span.extensions_mut().insert(log);
+        }
+        self.env.on_new_span(attrs, id, ctx);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        self.env.on_record(id, values, ctx);
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        self.env.on_enter(id, ctx);
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        self.env.on_exit(id, ctx);
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        self.env.on_close(id, ctx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tracing::{debug, info, warn};
+    use tracing_subscriber::Layer;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::config::{LocationConfig, LogLevel};
+
+    struct Count(Arc<AtomicUsize>);
+
+    impl<S: Subscriber> Layer<S> for Count {
+        fn on_event(&self, _: &tracing::Event<'_>, _: Context<'_, S>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn applies_location_levels_and_sampling() {
+        let config = Config {
+            server: Default::default(),
+            locations: vec![LocationConfig {
+                log_level: Some(LogLevel::Debug),
+                ..Default::default()
+            }],
+            vhosts: Vec::new(),
+        };
+        let count = Arc::new(AtomicUsize::new(0));
+        let filter = LocationFilter::new(EnvFilter::new("filehunter=info"), &config);
+        let subscriber =
+            tracing_subscriber::registry().with(Count(count.clone()).with_filter(filter));
+        let events = |span: tracing::Span| {
+            let _entered = span.enter();
+            debug!("debug");
+            info!("info");
+            warn!("warn");
+            count.swap(0, Ordering::Relaxed)
+        };
+
+        tracing::subscriber::with_default(subscriber, || {
+            assert_eq!(events(tracing::Span::none()), 2);
+            let verbose = |sampled| tracing::error_span!("location", log_level = "debug", sampled);
+            assert_eq!(events(verbose(true)), 3);
+            // Unsampled requests keep info and above.
+            assert_eq!(events(verbose(false)), 2);
+            let quiet = tracing::error_span!("location", log_level = "warn", sampled = true);
+            assert_eq!(events(quiet), 1);
+            let unset = tracing::error_span!("location", sampled = true);
+            assert_eq!(events(unset), 2);
+        });
+    }
+}
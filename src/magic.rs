@@ -0,0 +1,162 @@
+//! Magic-byte checks: whether a file's leading bytes match the signature
+//! its extension promises, so a file named `*.png` that holds HTML is not
+//! served as an image.
+
+use std::io::{self, SeekFrom};
+
+use bytes::Bytes;
+use futures_util::StreamExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::backend::ObjectBody;
+
+/// Leading bytes read to check any known signature.
+const HEAD_LEN: usize = 16;
+
+/// Accepted signatures of an extension: `(offset, bytes)`, any may match.
+type Signatures = &'static [(usize, &'static [u8])];
+
+const JPEG: Signatures = &[(0, b"\xFF\xD8\xFF")];
+const PNG: Signatures = &[(0, b"\x89PNG\r\n\x1A\n")];
+const GIF: Signatures = &[(0, b"GIF87a"), (0, b"GIF89a")];
+const WEBP: Signatures = &[(8, b"WEBP")];
+const BMP: Signatures = &[(0, b"BM")];
+const ICO: Signatures = &[(0, b"\x00\x00\x01\x00")];
+const TIFF: Signatures = &[(0, b"II*\x00"), (0, b"MM\x00*")];
+const PDF: Signatures = &[(0, b"%PDF-")];
+/// Local file header, or the end record of an empty archive.
+const ZIP: Signatures = &[(0, b"PK\x03\x04"), (0, b"PK\x05\x06")];
+const GZIP: Signatures = &[(0, b"\x1F\x8B")];
+const SEVEN_ZIP: Signatures = &[(0, b"7z\xBC\xAF\x27\x1C")];
+const RAR: Signatures = &[(0, b"Rar!\x1A\x07")];
+/// ISO base media files (`mp4`, `mov`, `avif`...) open with an `ftyp` box.
+const ISO_MEDIA: Signatures = &[(4, b"ftyp")];
+const MATROSKA: Signatures = &[(0, b"\x1A\x45\xDF\xA3")];
+const OGG: Signatures = &[(0, b"OggS")];
+const FLAC: Signatures = &[(0, b"fLaC")];
+const WAV: Signatures = &[(8, b"WAVE")];
+const AVI: Signatures = &[(8, b"AVI ")];
+/// An ID3 tag or a frame sync (MPEG-1 layer III, with or without CRC).
+const MP3: Signatures = &[
+    (0, b"ID3"),
+    (0, b"\xFF\xFB"),
+    (0, b"\xFF\xFA"),
+    (0, b"\xFF\xF3"),
+];
+const WASM: Signatures = &[(0, b"\x00asm")];
+const EXE: Signatures = &[(0, b"MZ")];
+
+/// The signatures files with extension `ext` (lowercase) must carry, or
+/// `None` for types without one (text, unknown extensions).
+fn signatures(ext: &str) -> Option<Signatures> {
+    Some(match ext {
+        "jpg" | "jpeg" | "jpe" | "jfif" => JPEG,
+        "png" => PNG,
+        "gif" => GIF,
+        "webp" => WEBP,
+        "bmp" => BMP,
+        "ico" => ICO,
+        "tif" | "tiff" => TIFF,
+        "pdf" => PDF,
+        "zip" | "jar" | "apk" | "epub" | "docx" | "xlsx" | "pptx" | "odt" | "ods" | "odp" => ZIP,
+        "gz" | "tgz" => GZIP,
+        "7z" => SEVEN_ZIP,
+        "rar" => RAR,
+        "mp4" | "m4v" | "m4a" | "mov" | "3gp" | "avif" | "heic" => ISO_MEDIA,
+        "mkv" | "webm" => MATROSKA,
+        "ogg" | "oga" | "ogv" | "opus" => OGG,
+        "flac" => FLAC,
+        "wav" => WAV,
+        "avi" => AVI,
+        "mp3" => MP3,
+        "wasm" => WASM,
+        "exe" | "dll" => EXE,
+        _ => return None,
+    })
+}
+
+/// Whether files with extension `ext` have a signature to check.
+pub(crate) fn known(ext: &str) -> bool {
+    signatures(&ext.to_ascii_lowercase()).is_some()
+}
+
+/// Whether `head`, the leading bytes of a file, matches the signature of
+/// extension `ext`. `None` when the extension has no known signature.
+pub(crate) fn matches(ext: &str, head: &[u8]) -> Option<bool> {
+    let signatures = signatures(&ext.to_ascii_lowercase())?;
+    Some(signatures.iter().any(|(offset, magic)| {
+        head.get(*offset..offset + magic.len())
+            .is_some_and(|bytes| bytes == *magic)
+    }))
+}
+
+/// Read the leading bytes of `body`, returning them with a body that still
+/// yields the whole object.
+pub(crate) async fn read_head(body: ObjectBody) -> io::Result<(Vec<u8>, ObjectBody)> {
+    let mut head = Vec::with_capacity(HEAD_LEN);
+    match body {
+        ObjectBody::File(mut file) => {
+            (&mut file)
+                .take(HEAD_LEN as u64)
+                .read_to_end(&mut head)
+                .await?;
+            file.seek(SeekFrom::Start(0)).await?;
+            Ok((head, ObjectBody::File(file)))
+        }
+        ObjectBody::Stream(mut stream) => {
+            let mut chunks: Vec<io::Result<Bytes>> = Vec::new();
+            while head.len() < HEAD_LEN {
+                let Some(chunk) = stream.next().await else {
+                    break;
+                };
+                let chunk = chunk?;
+                let wanted = (HEAD_LEN - head.len()).min(chunk.len());
+                head.extend_from_slice(&chunk[..wanted]);
+                chunks.push(Ok(chunk));
+            }
+            let replayed = futures_util::stream::iter(chunks).chain(stream);
+            Ok((head, ObjectBody::Stream(Box::pin(replayed))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_known_signatures() {
+        assert_eq!(matches("PNG", b"\x89PNG\r\n\x1A\n\0\0\0\rIHDR"), Some(true));
+        assert_eq!(matches("png", b"<html><script>"), Some(false));
+        assert_eq!(matches("webp", b"RIFF\x10\0\0\0WEBPVP8 "), Some(true));
+        assert_eq!(matches("mp4", b"\0\0\0\x18ftypmp42"), Some(true));
+        assert_eq!(matches("pdf", b"%PD"), Some(false));
+        assert_eq!(matches("txt", b"anything"), None);
+    }
+
+    #[tokio::test]
+    async fn read_head_keeps_the_whole_body() {
+        let chunks = ["%P", "DF-1.7", " rest of the file"].map(|c| Ok(Bytes::from(c)));
+        let stream = ObjectBody::Stream(Box::pin(futures_util::stream::iter(chunks)));
+        let (head, body) = read_head(stream).await.unwrap();
+        assert_eq!(head, b"%PDF-1.7 rest of");
+        let ObjectBody::Stream(stream) = body else {
+            unreachable!();
+        };
+        let body: Vec<_> = stream.map(|c| c.unwrap()).collect().await;
+        assert_eq!(body.concat(), b"%PDF-1.7 rest of the file");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.gif");
+        std::fs::write(&path, b"GIF89a...").unwrap();
+        let file = tokio::fs::File::open(&path).await.unwrap();
+        let (head, body) = read_head(ObjectBody::File(file)).await.unwrap();
+        assert_eq!(head, b"GIF89a...");
+        let ObjectBody::File(mut file) = body else {
+            unreachable!();
+        };
+        let mut all = Vec::new();
+        file.read_to_end(&mut all).await.unwrap();
+        assert_eq!(all, b"GIF89a...");
+    }
+}
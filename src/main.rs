@@ -1,26 +1,60 @@
 use std::convert::Infallible;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use hyper::body::Incoming;
+use hyper::header::HeaderValue;
 use hyper::{Request, Response};
 use hyper_util::rt::{TokioExecutor, TokioIo};
-use hyper_util::server::conn::auto::Builder as AutoBuilder;
+use hyper_util::server::conn::auto::{Builder as AutoBuilder, Connection, UpgradeableConnection};
 use hyper_util::service::TowerToHyperService;
-use tokio::net::TcpListener;
-use http_body_util::BodyExt as _;
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::signal::unix::{Signal, SignalKind, signal};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore, watch};
+use tokio_util::either::Either;
 use tower::util::BoxCloneService;
-use tower::ServiceBuilder;
-use tower_http::compression::predicate::{DefaultPredicate, Predicate as _, SizeAbove};
-use tower_http::compression::{CompressionBody, CompressionLayer};
+use tower::{ServiceBuilder, ServiceExt as _};
 use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer, ExposeHeaders};
-use tracing::{debug, info};
-
-use filehunter::config::{CompressionConfig, Config, CorsConfig};
+use tracing::{debug, info, warn};
+use tracing_subscriber::Layer as _;
+#[cfg(windows)]
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::SubscriberExt as _;
+use tracing_subscriber::util::SubscriberInitExt as _;
+
+#[cfg(feature = "acme")]
+use filehunter::acme;
+#[cfg(feature = "compression")]
+use filehunter::compression::Compression;
+use filehunter::config::{Config, CorsConfig, H2cMode};
+use filehunter::connections::{ConnectionHandle, track_response};
+use filehunter::h2c;
+use filehunter::health;
+use filehunter::lint;
+use filehunter::logging::LocationFilter;
+use filehunter::misses;
+#[cfg(feature = "compression")]
+use filehunter::precompress;
+#[cfg(unix)]
+use filehunter::daemon;
+#[cfg(unix)]
+use filehunter::privileges::{self, Account};
+use filehunter::report::StartupReport;
 use filehunter::ratelimit::{self, KeyedLimiter};
-use filehunter::server::{handle_request, FileSearcher, ResponseBody};
+use filehunter::server::{FileSearcher, ResponseBody};
+use filehunter::service::FileHunterService;
+use filehunter::statsd;
+#[cfg(feature = "tls")]
+use filehunter::tls::TlsAcceptor;
+use filehunter::warmup;
+#[cfg(feature = "webhooks")]
+use filehunter::webhooks;
+#[cfg(windows)]
+use filehunter::winservice;
 
 #[derive(Parser)]
 #[command(
@@ -29,8 +63,78 @@ use filehunter::server::{handle_request, FileSearcher, ResponseBody};
 )]
 struct Args {
     /// Path to the TOML configuration file
-    #[arg(short, long, default_value = "config.toml")]
+    #[arg(short, long, default_value = "config.toml", global = true)]
     config: String,
+
+    /// Detach from the terminal and run in the background
+    #[cfg(unix)]
+    #[arg(long)]
+    daemon: bool,
+
+    /// Run as a Windows service; only the service control manager starts this
+    #[cfg(windows)]
+    #[arg(long)]
+    service: bool,
+
+    /// With --daemon (or --service), append output (logs) to this file instead
+    /// of discarding it
+    #[cfg(any(unix, windows))]
+    #[cfg_attr(unix, arg(long, requires = "daemon"))]
+    #[cfg_attr(windows, arg(long, requires = "service"))]
+    log_file: Option<std::path::PathBuf>,
+
+    /// Write the server's pid to this file, removing it on exit
+    #[cfg(unix)]
+    #[arg(long)]
+    pid_file: Option<std::path::PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Validate the configuration file and exit
+    Check {
+        /// Also report suspicious but valid settings (exit code 1 if any)
+        #[arg(long)]
+        lint: bool,
+    },
+    /// Write .gz/.br/.zst sidecars next to compressible files in the local roots and exit
+    #[cfg(feature = "compression")]
+    Precompress,
+}
+
+/// `filehunter check [--lint]`: validation errors surface through `main`'s
+/// error path; lint findings are printed one per line.
+fn run_check(config: &Config, lint: bool) -> std::process::ExitCode {
+    if lint {
+        let warnings = lint::lint(config);
+        for w in &warnings {
+            println!("warning: {w}");
+        }
+        if !warnings.is_empty() {
+            println!("{} warning(s)", warnings.len());
+            return std::process::ExitCode::FAILURE;
+        }
+    }
+    println!("config OK");
+    std::process::ExitCode::SUCCESS
+}
+
+/// `filehunter precompress`: sidecars that fail to write are logged, and
+/// make the exit code 1.
+#[cfg(feature = "compression")]
+fn run_precompress(config: &Config) -> std::process::ExitCode {
+    let summary = precompress::generate(config);
+    println!(
+        "{} sidecar(s) written, {} up to date, {} no smaller than their file, {} failed",
+        summary.written, summary.current, summary.skipped, summary.failed
+    );
+    if summary.failed > 0 {
+        return std::process::ExitCode::FAILURE;
+    }
+    std::process::ExitCode::SUCCESS
 }
 
 /// Build a `CorsLayer` from config.
@@ -89,64 +193,132 @@ fn build_cors_layer(cfg: &CorsConfig) -> CorsLayer {
     layer
 }
 
-/// Predicate: respect `DefaultPredicate` (skip images, tiny responses) + user `min_size`.
-type CompPredicate =
-    tower_http::compression::predicate::And<DefaultPredicate, SizeAbove>;
+type ErasedService =
+    BoxCloneService<Request<Incoming>, Response<ResponseBody>, Infallible>;
 
-/// Build a `CompressionLayer` from config.
-///
-/// Algorithm selection (`no_*`) must happen before `compress_when()` because
-/// the disabler methods are only available on `CompressionLayer<DefaultPredicate>`.
-fn build_compression_layer(cfg: &CompressionConfig) -> CompressionLayer<CompPredicate> {
-    let mut layer = CompressionLayer::new();
+fn main() -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
+    let args = Args::parse();
 
-    let algos = &cfg.algorithms;
-    if !algos.iter().any(|a| a == "gzip") {
-        layer = layer.no_gzip();
+    // No colour codes once output goes to a log file.
+    #[cfg(unix)]
+    let ansi = !args.daemon;
+    #[cfg(windows)]
+    let ansi = !args.service;
+    #[cfg(not(any(unix, windows)))]
+    let ansi = true;
+    // A service has no console to write to.
+    #[cfg(windows)]
+    let writer = match &args.log_file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("cannot open log file {}: {e}", path.display()))?;
+            BoxMakeWriter::new(Arc::new(file))
+        }
+        None => BoxMakeWriter::new(std::io::stdout),
+    };
+    #[cfg(not(windows))]
+    let writer = std::io::stdout;
+    // Loaded first: locations may override the log level.
+    let config = Config::load(&args.config)?;
+    let env = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| "filehunter=info".parse().unwrap());
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(writer)
+                .with_ansi(ansi)
+                .with_filter(LocationFilter::new(env, &config)),
+        )
+        .init();
+
+    let shadow = &config.server.shadow;
+    let candidate = shadow
+        .enabled
+        .then(|| {
+            Config::load(&shadow.config.to_string_lossy())
+                .map_err(|e| format!("shadow config {}: {e}", shadow.config.display()))
+        })
+        .transpose()?;
+
+    match args.command {
+        Some(Command::Check { lint }) => return Ok(run_check(&config, lint)),
+        #[cfg(feature = "compression")]
+        Some(Command::Precompress) => return Ok(run_precompress(&config)),
+        None => {}
     }
-    if !algos.iter().any(|a| a == "br") {
-        layer = layer.no_br();
+
+    for w in lint::lint(&config) {
+        warn!(code = w.code, "config lint: {}", w.message);
     }
-    if !algos.iter().any(|a| a == "deflate") {
-        layer = layer.no_deflate();
+
+    // Forking is only safe before the runtime starts its worker threads.
+    #[cfg(unix)]
+    if args.daemon {
+        daemon::daemonize(args.log_file.as_deref())?;
     }
-    if !algos.iter().any(|a| a == "zstd") {
-        layer = layer.no_zstd();
+    #[cfg(unix)]
+    let _pid_file = args
+        .pid_file
+        .as_deref()
+        .map(daemon::PidFile::create)
+        .transpose()?;
+
+    // The service control manager runs the server on a thread of its own.
+    #[cfg(windows)]
+    if args.service {
+        winservice::run(move || {
+            let runtime = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
+            match runtime.block_on(serve(config, candidate)) {
+                Ok(_) => Ok(()),
+                Err(e) => Err(e.to_string()),
+            }
+        })?;
+        return Ok(std::process::ExitCode::SUCCESS);
     }
 
-    let min_size = cfg.min_size.as_u64().min(u16::MAX as u64) as u16;
-    let predicate = DefaultPredicate::new().and(SizeAbove::new(min_size));
-
-    layer.compress_when(predicate)
-}
-
-/// Re-box the compressed response body back into our erased `ResponseBody` type.
-///
-/// `CompressionBody` unifies errors into `BoxError`; we wrap it back into
-/// `std::io::Error` via `Error::other()` to match our `ResponseBody` alias.
-fn rebox_response(
-    resp: Response<CompressionBody<ResponseBody>>,
-) -> Response<ResponseBody> {
-    resp.map(|body| body.map_err(std::io::Error::other).boxed())
+    tokio::runtime::Runtime::new()?.block_on(serve(config, candidate))
 }
 
-type ErasedService =
-    BoxCloneService<Request<Incoming>, Response<ResponseBody>, Infallible>;
-
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "filehunter=info".parse().unwrap()),
-        )
-        .init();
-
-    let args = Args::parse();
-
-    let config = Config::load(&args.config)?;
+async fn serve(
+    config: Config,
+    candidate: Option<Config>,
+) -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
     let addr: SocketAddr = config.server.bind.parse()?;
-    let searcher = Arc::new(FileSearcher::new(&config));
+    let listener = TcpListener::bind(addr).await?;
+    #[cfg(unix)]
+    let account = Account::lookup(&config.server.user, &config.server.group)?;
+
+    // Roots and files named in the config resolve inside the chroot.
+    #[cfg(unix)]
+    if !config.server.chroot.as_os_str().is_empty() {
+        privileges::chroot(&config.server.chroot)?;
+        info!(dir = %config.server.chroot.display(), "changed root directory");
+    }
+    let mut searcher = FileSearcher::new(&config);
+    if let Some(candidate) = &candidate {
+        let rate = config.server.shadow.sample_rate;
+        searcher = searcher.with_shadow(FileSearcher::new(candidate), rate);
+        info!(sample_rate = rate, "shadow config comparison enabled");
+    }
+    let searcher = Arc::new(searcher);
+    if config.server.strict_startup {
+        let unusable: Vec<String> = searcher
+            .status()
+            .into_iter()
+            .flat_map(|loc| loc.roots)
+            .filter_map(|root| Some(format!("{} ({})", root.path.display(), root.error?)))
+            .collect();
+        if !unusable.is_empty() {
+            return Err(format!("strict_startup: unusable roots: {}", unusable.join(", ")).into());
+        }
+    }
+    if config.server.warmup.enabled {
+        warmup::warm_roots(&searcher, &config.server.warmup).await;
+        warmup::warm_paths(&searcher).await;
+    }
 
     // Connection timeout (0 = unlimited).
     let conn_timeout = match config.server.connection_timeout {
@@ -154,6 +326,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         secs => Some(Duration::from_secs(secs)),
     };
 
+    // Idle keep-alive timeout (0 = never).
+    let idle_timeout = match config.server.keepalive_timeout {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    };
+
+    // Requests served per connection before it is drained (0 = unlimited).
+    let max_requests = match config.server.max_requests_per_connection {
+        0 => None,
+        n => Some(n),
+    };
+
+    // Concurrent connection cap (0 = unlimited).
+    let conn_slots = match config.server.max_connections {
+        0 => None,
+        n => Some(Arc::new(Semaphore::new(n))),
+    };
+
     let mut builder = AutoBuilder::new(TokioExecutor::new());
 
     builder
@@ -164,7 +354,30 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     builder
         .http2()
         .max_header_list_size(config.server.max_header_size.as_u32())
-        .max_concurrent_streams(config.server.http2_max_streams);
+        .max_concurrent_streams(config.server.http2_max_streams)
+        .initial_stream_window_size(config.server.http2_initial_stream_window.as_u32())
+        .initial_connection_window_size(config.server.http2_initial_connection_window.as_u32())
+        .max_frame_size(config.server.http2_max_frame_size.as_u32())
+        .adaptive_window(config.server.http2_adaptive_window);
+    // With TLS, ALPN picks the protocol and `h2c` does not apply.
+    let h2c = match config.server.tls.enabled {
+        true => H2cMode::PriorKnowledge,
+        false => config.server.h2c,
+    };
+    if h2c == H2cMode::Off {
+        builder = builder.http1_only();
+    }
+
+    // TLS termination (optional); certificates are loaded once, up front.
+    #[cfg(feature = "tls")]
+    let tls = match config.server.tls.enabled {
+        true => Some(TlsAcceptor::new(&config.server.tls)?),
+        false => None,
+    };
+    #[cfg(feature = "acme")]
+    if let Some(acme) = tls.as_ref().and_then(TlsAcceptor::acme) {
+        acme::start(acme.clone()).await?;
+    }
 
     // CORS layer (optional).
     let cors_layer = if config.server.cors.enabled {
@@ -173,12 +386,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         None
     };
 
-    // Compression layer (optional, default off).
-    let compression_layer = if config.server.compression.enabled {
-        Some(build_compression_layer(&config.server.compression))
-    } else {
-        None
-    };
+    // Compression (optional, default off).
+    #[cfg(feature = "compression")]
+    let compression = config
+        .server
+        .compression
+        .enabled
+        .then(|| Compression::new(&config.server.compression));
+    #[cfg(not(feature = "compression"))]
+    if config.server.compression.enabled {
+        warn!("compression.enabled is set but this build lacks the `compression` feature; ignoring");
+    }
 
     // Per-IP rate limiter (optional).
     let limiter: Option<Arc<KeyedLimiter>> = if config.server.rate_limit.enabled {
@@ -189,73 +407,185 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         None
     };
 
-    let listener = TcpListener::bind(addr).await?;
+    if config.server.health_check.enabled {
+        health::spawn_health_checks(searcher.clone(), &config.server.health_check);
+    }
+    if config.server.root_retry_interval > 0 {
+        health::spawn_root_retry(searcher.clone(), config.server.root_retry_interval);
+    }
+    if let Some(misses) = searcher.miss_log() {
+        misses::spawn_dump(misses.clone(), config.server.miss_log.dump_interval);
+    }
+    if config.server.statsd.enabled {
+        statsd::spawn_statsd(searcher.clone(), &config.server.statsd);
+    }
+    #[cfg(feature = "webhooks")]
+    if let Some(webhooks) = searcher.webhooks() {
+        webhooks::spawn_sender(webhooks.clone());
+    }
+    #[cfg(not(feature = "webhooks"))]
+    if config.server.webhooks.enabled {
+        warn!("webhooks.enabled is set but this build lacks the `webhooks` feature; ignoring");
+    }
+
+    #[cfg(unix)]
+    if let Some(account) = account {
+        account.switch_to()?;
+        let (user, group) = (&config.server.user, &config.server.group);
+        info!(user, group, "privileges dropped");
+    }
     info!(
         %addr,
-        locations = config.locations.len(),
+        locations = config.all_locations().count(),
+        vhosts = config.vhosts.len(),
         keepalive = config.server.keepalive,
         connection_timeout = config.server.connection_timeout,
+        keepalive_timeout = config.server.keepalive_timeout,
+        max_connections = config.server.max_connections,
+        max_requests_per_connection = config.server.max_requests_per_connection,
         max_header_size = %config.server.max_header_size,
         max_headers = config.server.max_headers,
         max_body_size = %config.server.max_body_size,
         http2_max_streams = config.server.http2_max_streams,
         max_file_size = %config.server.max_file_size,
         stream_buffer_size = %config.server.stream_buffer_size,
+        egress_limit = %config.server.egress_limit,
+        tls_enabled = config.server.tls.enabled,
         cors_enabled = config.server.cors.enabled,
         rate_limit_enabled = config.server.rate_limit.enabled,
         rate_limit_rps = config.server.rate_limit.requests_per_second,
         rate_limit_burst = config.server.rate_limit.burst_size,
         compression_enabled = config.server.compression.enabled,
+        health_check_enabled = config.server.health_check.enabled,
         "server listening"
     );
 
-    loop {
+    if let Some(dest) = &config.server.startup_report {
+        let report = StartupReport::new(&config, &searcher, vec![listener.local_addr()?]);
+        match report.write_to(dest) {
+            Ok(()) => info!(dest, "startup report written"),
+            Err(e) => warn!(dest, error = %e, "failed to write startup report"),
+        }
+    }
+
+    let mut signals = StopSignals::new()?;
+    let (stop_tx, stop_rx) = watch::channel(false);
+    let stop = loop {
         tokio::select! {
-            result = listener.accept() => {
-                let (stream, remote_addr) = result?;
+            result = accept(&listener, conn_slots.as_ref()) => {
+                let (stream, remote_addr, slot) = result?;
                 let searcher = searcher.clone();
                 let builder = builder.clone();
                 let cors_layer = cors_layer.clone();
-                let compression_layer = compression_layer.clone();
+                #[cfg(feature = "tls")]
+                let tls = tls.clone();
+                #[cfg(feature = "compression")]
+                let compression = compression.clone();
                 let limiter = limiter.clone();
-                let client_ip = remote_addr.ip();
+                let mut stopping = stop_rx.clone();
 
                 tokio::spawn(async move {
-                    let io = TokioIo::new(stream);
+                    let _slot = slot;
+                    let conn = Arc::new(searcher.connections().register(remote_addr));
+                    let io = conn.wrap_io(stream);
+                    #[cfg(feature = "tls")]
+                    let io = match &tls {
+                        Some(tls) => match tls.accept(io).await {
+                            Ok(io) => Either::Right(io),
+                            Err(e) => {
+                                debug!(%remote_addr, error = %e, "TLS handshake failed");
+                                return;
+                            }
+                        },
+                        None => Either::Left(io),
+                    };
+                    let io = TokioIo::new(io);
+
+                    let mut service = FileHunterService::from_searcher(searcher);
+                    if let Some(limiter) = limiter {
+                        service = service.with_limiter(limiter);
+                    }
+                    let inner = ServiceBuilder::new()
+                        .map_request(move |mut req: Request<Incoming>| {
+                            req.extensions_mut().insert(remote_addr);
+                            req
+                        })
+                        .service(service);
+
+                    let mut erased: ErasedService = BoxCloneService::new(inner);
+                    #[cfg(feature = "compression")]
+                    if let Some(compression) = &compression {
+                        erased = compression.wrap(erased);
+                    }
+                    if let Some(cors) = cors_layer {
+                        erased = BoxCloneService::new(ServiceBuilder::new().layer(cors).service(erased));
+                    }
 
-                    let inner = tower::service_fn(move |req: Request<Incoming>| {
-                        let searcher = searcher.clone();
-                        let limiter = limiter.clone();
-                        async move {
-                            handle_request(req, searcher, limiter, client_ip).await
+                    // Past `max_requests_per_connection`, the last response
+                    // asks HTTP/1 clients to close and the connection drains
+                    // (GOAWAY on HTTP/2).
+                    let drain = Arc::new(Notify::new());
+                    let tracked_conn = conn.clone();
+                    let tracked_drain = drain.clone();
+                    // An accepted h2c upgrade, picked up once HTTP/1 is done.
+                    let upgrades = match h2c {
+                        H2cMode::Upgrade => Some(Arc::new(Mutex::new(None))),
+                        _ => None,
+                    };
+                    let pending = upgrades.clone();
+                    let tracked = tower::service_fn(move |mut req: Request<Incoming>| {
+                        let upgrade = pending.as_ref().and_then(|pending| {
+                            Some((pending, h2c::Upgrade::from_request(&mut req)?))
+                        });
+                        if let Some((pending, upgrade)) = upgrade {
+                            *pending.lock().unwrap() = Some(upgrade);
+                            return Either::Left(std::future::ready(Ok(h2c::Upgrade::response())));
                         }
+                        let guard = tracked_conn.begin_stream();
+                        let last = max_requests.is_some_and(|max| tracked_conn.requests() >= max);
+                        let http1 = req.version() < hyper::Version::HTTP_2;
+                        if last {
+                            tracked_drain.notify_one();
+                        }
+                        let call = erased.clone().oneshot(req);
+                        Either::Right(async move {
+                            let mut resp = call.await?;
+                            if last && http1 {
+                                let close = HeaderValue::from_static("close");
+                                resp.headers_mut().insert(hyper::header::CONNECTION, close);
+                            }
+                            Ok::<_, Infallible>(track_response(resp, guard))
+                        })
                     });
 
-                    let erased: ErasedService = match (&cors_layer, &compression_layer) {
-                        (Some(cors), Some(comp)) => BoxCloneService::new(
-                            ServiceBuilder::new()
-                                .map_response(rebox_response)
-                                .layer(cors.clone())
-                                .layer(comp.clone())
-                                .service(inner),
-                        ),
-                        (None, Some(comp)) => BoxCloneService::new(
-                            ServiceBuilder::new()
-                                .map_response(rebox_response)
-                                .layer(comp.clone())
-                                .service(inner),
-                        ),
-                        (Some(cors), None) => BoxCloneService::new(
-                            ServiceBuilder::new()
-                                .layer(cors.clone())
-                                .service(inner),
-                        ),
-                        (None, None) => BoxCloneService::new(inner),
+                    let hyper_svc = TowerToHyperService::new(tracked);
+                    let driver = Driver { conn: &conn, idle_timeout, drain: &drain, remote_addr };
+                    let serve = async {
+                        let Some(upgrades) = upgrades else {
+                            let connection = builder.serve_connection(io, hyper_svc);
+                            tokio::pin!(connection);
+                            let shutdown = Connection::graceful_shutdown;
+                            let result = driver.drive(connection.as_mut(), shutdown, &mut stopping);
+                            return result.await.map(drop);
+                        };
+                        let svc = hyper_svc.clone();
+                        let connection = builder.serve_connection_with_upgrades(io, svc);
+                        tokio::pin!(connection);
+                        let shutdown = UpgradeableConnection::graceful_shutdown;
+                        if !driver.drive(connection.as_mut(), shutdown, &mut stopping).await? {
+                            return Ok(());
+                        }
+                        // Answer the upgraded request, and any after it, over HTTP/2.
+                        let Some(upgrade) = upgrades.lock().unwrap().take() else {
+                            return Ok(());
+                        };
+                        let io = TokioIo::new(upgrade.into_io().await?);
+                        let connection = builder.serve_connection(io, hyper_svc);
+                        tokio::pin!(connection);
+                        let shutdown = Connection::graceful_shutdown;
+                        driver.drive(connection.as_mut(), shutdown, &mut stopping).await.map(drop)
                     };
 
-                    let hyper_svc = TowerToHyperService::new(erased);
-                    let serve = builder.serve_connection(io, hyper_svc);
-
                     let result = if let Some(d) = conn_timeout {
                         match tokio::time::timeout(d, serve).await {
                             Ok(r) => r,
@@ -273,12 +603,159 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     }
                 });
             }
-            _ = tokio::signal::ctrl_c() => {
-                info!("shutting down");
-                break;
+            stop = signals.recv() => break stop,
+        }
+    };
+
+    if stop == Stop::Graceful {
+        drop(listener);
+        let open = searcher.connections().len();
+        info!(open, "stopping gracefully; waiting for open connections");
+        stop_tx.send_replace(true);
+        tokio::select! {
+            _ = drained(&searcher) => {}
+            _ = signals.recv() => info!("stop requested again; not waiting"),
+        }
+    }
+    info!("shutting down");
+
+    Ok(std::process::ExitCode::SUCCESS)
+}
+
+/// How the process was asked to stop.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Stop {
+    /// SIGTERM or SIGINT (Ctrl-C): exit now, cutting open connections.
+    Fast,
+    /// SIGQUIT, or a Windows service stop: stop accepting and let open
+    /// connections finish.
+    Graceful,
+}
+
+#[cfg(unix)]
+struct StopSignals {
+    term: Signal,
+    int: Signal,
+    quit: Signal,
+}
+
+#[cfg(unix)]
+impl StopSignals {
+    fn new() -> std::io::Result<Self> {
+        Ok(Self {
+            term: signal(SignalKind::terminate())?,
+            int: signal(SignalKind::interrupt())?,
+            quit: signal(SignalKind::quit())?,
+        })
+    }
+
+    async fn recv(&mut self) -> Stop {
+        tokio::select! {
+            _ = self.term.recv() => Stop::Fast,
+            _ = self.int.recv() => Stop::Fast,
+            _ = self.quit.recv() => Stop::Graceful,
+        }
+    }
+}
+
+/// Ctrl-C, always a fast stop; on Windows also a stop request from the
+/// service control manager, which is graceful.
+#[cfg(not(unix))]
+struct StopSignals;
+
+#[cfg(not(unix))]
+impl StopSignals {
+    fn new() -> std::io::Result<Self> {
+        Ok(Self)
+    }
+
+    async fn recv(&mut self) -> Stop {
+        #[cfg(windows)]
+        let service_stop = winservice::stop_requested();
+        #[cfg(not(windows))]
+        let service_stop = std::future::pending::<()>();
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => Stop::Fast,
+            _ = service_stop => Stop::Graceful,
+        }
+    }
+}
+
+/// Resolves once every connection has closed.
+async fn drained(searcher: &FileSearcher) {
+    while !searcher.connections().is_empty() {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Runs one connection to its end.
+struct Driver<'a> {
+    conn: &'a ConnectionHandle,
+    idle_timeout: Option<Duration>,
+    /// Notified when `max_requests_per_connection` is reached.
+    drain: &'a Notify,
+    remote_addr: SocketAddr,
+}
+
+impl Driver<'_> {
+    /// Serve `connection`, shutting it down gracefully at the request limit
+    /// or on a graceful stop. `false` when it was cut off instead, closed by
+    /// the admin API or for idling.
+    async fn drive<C, E>(
+        &self,
+        mut connection: Pin<&mut C>,
+        graceful_shutdown: fn(Pin<&mut C>),
+        stopping: &mut watch::Receiver<bool>,
+    ) -> Result<bool, E>
+    where
+        C: Future<Output = Result<(), E>>,
+    {
+        let (conn, remote_addr) = (self.conn, self.remote_addr);
+        tokio::select! {
+            result = connection.as_mut() => return result.map(|()| true),
+            _ = conn.closed() => {
+                info!(%remote_addr, id = conn.id(), "connection closed by admin");
+                return Ok(false);
+            }
+            _ = idle(conn, self.idle_timeout) => {
+                debug!(%remote_addr, "idle connection closed");
+                return Ok(false);
+            }
+            _ = self.drain.notified() => {
+                debug!(%remote_addr, "request limit reached; draining connection");
+            }
+            _ = stopping.wait_for(|stop| *stop) => {
+                debug!(%remote_addr, "stopping; draining connection");
             }
         }
+        graceful_shutdown(connection.as_mut());
+        connection.await.map(|()| true)
+    }
+}
+
+/// Resolves once `conn` has been idle for `timeout`; never without one.
+async fn idle(conn: &ConnectionHandle, timeout: Option<Duration>) {
+    match timeout {
+        Some(timeout) => conn.idle_for(timeout).await,
+        None => std::future::pending().await,
     }
+}
 
-    Ok(())
+/// Accept the next connection. With `max_connections` set, first wait for a
+/// free slot; the returned permit holds it until the connection ends.
+async fn accept(
+    listener: &TcpListener,
+    slots: Option<&Arc<Semaphore>>,
+) -> std::io::Result<(TcpStream, SocketAddr, Option<OwnedSemaphorePermit>)> {
+    let slot = match slots {
+        Some(slots) => {
+            if slots.available_permits() == 0 {
+                debug!("connection limit reached; pausing accepts");
+            }
+            Some(slots.clone().acquire_owned().await.expect("semaphore is never closed"))
+        }
+        None => None,
+    };
+    let (stream, remote_addr) = listener.accept().await?;
+    Ok((stream, remote_addr, slot))
 }
@@ -0,0 +1,47 @@
+use hyper::{Response, StatusCode};
+use serde::Serialize;
+use tracing::debug;
+
+use crate::config::SearchMode;
+use crate::server::{FileSearcher, ResponseBody, json_response, text_response, unix_secs};
+
+#[derive(Serialize)]
+struct MetaReport<'a> {
+    path: &'a str,
+    location: &'a str,
+    mode: SearchMode,
+    root: String,
+    resolved: String,
+    size: u64,
+    /// Modification time as Unix seconds.
+    modified: u64,
+    content_type: String,
+}
+
+/// `GET /_meta/<path>`: describe the file `target` resolves to instead of
+/// serving it.
+pub(crate) async fn handle(searcher: &FileSearcher, target: &str) -> Response<ResponseBody> {
+    let Some(resolved) = searcher.resolve(target).await else {
+        debug!(status = 404, path = target, "meta request handled");
+        return text_response(StatusCode::NOT_FOUND, "Not Found");
+    };
+
+    let hit = resolved.hit;
+    let report = MetaReport {
+        path: target,
+        location: resolved.prefix,
+        mode: resolved.mode,
+        root: hit.root.display().to_string(),
+        resolved: hit.path.display().to_string(),
+        size: hit.size,
+        modified: unix_secs(hit.modified),
+        content_type: hit.mime.to_string(),
+    };
+    debug!(
+        status = 200,
+        path = target,
+        root = report.root,
+        "meta request handled"
+    );
+    json_response(StatusCode::OK, &report)
+}
@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds of the search latency buckets, in seconds.
+const BUCKETS: [f64; 15] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// Search latency histograms of one location, keyed by the number of
+/// healthy roots at the time and whether the search found a file.
+#[derive(Default)]
+pub(crate) struct SearchLatency {
+    series: Mutex<BTreeMap<(usize, bool), Histogram>>,
+}
+
+struct Histogram {
+    /// Per bucket, plus one for samples above the last bound.
+    counts: [AtomicU64; BUCKETS.len() + 1],
+    sum_nanos: AtomicU64,
+}
+
+impl SearchLatency {
+    pub(crate) fn record(&self, roots: usize, hit: bool, elapsed: Duration) {
+        let mut series = self.series.lock().unwrap();
+        let histogram = series.entry((roots, hit)).or_insert_with(|| Histogram {
+            counts: Default::default(),
+            sum_nanos: AtomicU64::new(0),
+        });
+        let secs = elapsed.as_secs_f64();
+        let bucket = BUCKETS.partition_point(|&bound| bound < secs);
+        histogram.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        histogram.sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+}
+
+/// Render the search latency histograms of every `(prefix, mode, latency)`
+/// in the Prometheus text exposition format.
+pub(crate) fn render<'a>(
+    locations: impl IntoIterator<Item = (&'a str, &'static str, &'a SearchLatency)>,
+) -> String {
+    let mut out = String::from(
+        "# HELP filehunter_search_duration_seconds Time to resolve a request path in a location.\n\
+         # TYPE filehunter_search_duration_seconds histogram\n",
+    );
+    let name = "filehunter_search_duration_seconds";
+    for (prefix, mode, latency) in locations {
+        let prefix = escape_label(prefix);
+        for (&(roots, hit), histogram) in latency.series.lock().unwrap().iter() {
+            let outcome = if hit { "hit" } else { "miss" };
+            let labels =
+                format!(r#"location="{prefix}",mode="{mode}",roots="{roots}",outcome="{outcome}""#);
+            let mut cumulative = 0;
+            for (i, count) in histogram.counts.iter().enumerate() {
+                cumulative += count.load(Ordering::Relaxed);
+                let le = BUCKETS.get(i).map_or("+Inf".into(), f64::to_string);
+                let _ = writeln!(out, r#"{name}_bucket{{{labels},le="{le}"}} {cumulative}"#);
+            }
+            let sum = histogram.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+            let _ = writeln!(out, "{name}_sum{{{labels}}} {sum}");
+            let _ = writeln!(out, "{name}_count{{{labels}}} {cumulative}");
+        }
+    }
+    out
+}
+
+/// Render the aborted request count of every `(prefix, count)`.
+pub(crate) fn render_aborted<'a>(
+    out: &mut String,
+    locations: impl IntoIterator<Item = (&'a str, u64)>,
+) {
+    let _ = writeln!(
+        out,
+        "# HELP filehunter_aborted_requests_total Requests the client went away from mid-search or mid-stream.\n\
+         # TYPE filehunter_aborted_requests_total counter"
+    );
+    for (prefix, aborted) in locations {
+        let prefix = escape_label(prefix);
+        let _ = writeln!(
+            out,
+            r#"filehunter_aborted_requests_total{{location="{prefix}"}} {aborted}"#
+        );
+    }
+}
+
+pub(crate) fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_cumulative_buckets() {
+        let latency = SearchLatency::default();
+        latency.record(2, true, Duration::from_micros(50));
+        latency.record(2, true, Duration::from_millis(3));
+        latency.record(2, false, Duration::from_secs(10));
+
+        let text = render([("/imgs", "sequential", &latency)]);
+        let hit = r#"location="/imgs",mode="sequential",roots="2",outcome="hit""#;
+        assert!(text.contains(&format!(r#"_bucket{{{hit},le="0.0001"}} 1"#)));
+        assert!(text.contains(&format!(r#"_bucket{{{hit},le="0.005"}} 2"#)));
+        assert!(text.contains(&format!(r#"_bucket{{{hit},le="+Inf"}} 2"#)));
+        assert!(text.contains(&format!("_count{{{hit}}} 2")));
+        let miss = r#"roots="2",outcome="miss""#;
+        assert!(text.contains(&format!(r#"{miss},le="5"}} 0"#)));
+        assert!(text.contains(&format!(r#"{miss},le="+Inf"}} 1"#)));
+    }
+}
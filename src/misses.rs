@@ -0,0 +1,198 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+use tracing::{debug, info, warn};
+
+use crate::config::MissLogConfig;
+
+/// A request no root could answer.
+#[derive(Debug, Clone, Serialize)]
+pub struct Miss {
+    /// Unix seconds.
+    pub time: u64,
+    /// Raw request path.
+    pub path: String,
+    /// Prefix of the matched location; `None` when no location matched.
+    pub location: Option<String>,
+    pub client_ip: IpAddr,
+}
+
+/// How often a path appears among the kept misses.
+#[derive(Debug, Clone, Serialize)]
+pub struct MissCount {
+    pub path: String,
+    pub count: usize,
+}
+
+/// Bounded in-memory log of misses, optionally dumped to a file.
+pub struct MissLog {
+    capacity: usize,
+    state: Mutex<State>,
+    file: Option<Mutex<File>>,
+}
+
+struct State {
+    misses: VecDeque<Miss>,
+    /// Misses recorded since startup.
+    recorded: u64,
+    /// `recorded` as of the last dump.
+    dumped: u64,
+}
+
+impl MissLog {
+    /// Open `cfg.dump_file` for appending. An unusable file leaves the
+    /// in-memory log only.
+    pub fn open(cfg: &MissLogConfig) -> Self {
+        let file = (!cfg.dump_file.as_os_str().is_empty())
+            .then(|| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&cfg.dump_file)
+            })
+            .and_then(|opened| match opened {
+                Ok(file) => Some(Mutex::new(file)),
+                Err(e) => {
+                    warn!(path = %cfg.dump_file.display(), error = %e, "cannot open miss log file");
+                    None
+                }
+            });
+        Self {
+            capacity: cfg.capacity,
+            state: Mutex::new(State {
+                misses: VecDeque::new(),
+                recorded: 0,
+                dumped: 0,
+            }),
+            file,
+        }
+    }
+
+    pub(crate) fn record(&self, path: &str, location: Option<String>, client_ip: IpAddr) {
+        let miss = Miss {
+            time: crate::server::unix_secs(SystemTime::now()),
+            path: path.to_owned(),
+            location,
+            client_ip,
+        };
+        let mut state = self.state.lock().unwrap();
+        if state.misses.len() == self.capacity {
+            state.misses.pop_front();
+        }
+        state.misses.push_back(miss);
+        state.recorded += 1;
+    }
+
+    /// The latest `limit` misses, newest first.
+    pub fn recent(&self, limit: usize) -> Vec<Miss> {
+        let state = self.state.lock().unwrap();
+        state.misses.iter().rev().take(limit).cloned().collect()
+    }
+
+    /// The `limit` most frequently missed paths, most frequent first.
+    pub fn top(&self, limit: usize) -> Vec<MissCount> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        let state = self.state.lock().unwrap();
+        for miss in &state.misses {
+            *counts.entry(&miss.path).or_default() += 1;
+        }
+        let mut top: Vec<MissCount> = counts
+            .into_iter()
+            .map(|(path, count)| MissCount {
+                path: path.to_owned(),
+                count,
+            })
+            .collect();
+        top.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.path.cmp(&b.path)));
+        top.truncate(limit);
+        top
+    }
+
+    /// Append the misses recorded since the last dump to the file as JSON
+    /// lines. Misses already dropped from memory are lost.
+    fn dump(&self) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let mut lines = Vec::new();
+        {
+            let mut state = self.state.lock().unwrap();
+            let new = state.recorded - state.dumped;
+            let kept = state.misses.len().min(new as usize);
+            if new > kept as u64 {
+                debug!(dropped = new - kept as u64, "misses dropped before dump");
+            }
+            for miss in state.misses.iter().skip(state.misses.len() - kept) {
+                serde_json::to_writer(&mut lines, miss).expect("JSON serialization cannot fail");
+                lines.push(b'\n');
+            }
+            state.dumped = state.recorded;
+        }
+        if lines.is_empty() {
+            return;
+        }
+        if let Err(e) = file.lock().unwrap().write_all(&lines) {
+            warn!(error = %e, "cannot write miss log");
+        }
+    }
+}
+
+/// Spawn the background task that dumps `log` every `interval_secs`.
+pub fn spawn_dump(log: Arc<MissLog>, interval_secs: u64) {
+    if log.file.is_none() {
+        return;
+    }
+    let interval = Duration::from_secs(interval_secs);
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            log.dump();
+        }
+    });
+
+    info!(interval_secs, "miss log dump started");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_latest_and_dumps_new_misses() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = MissLogConfig {
+            enabled: true,
+            capacity: 3,
+            dump_file: dir.path().join("misses.jsonl"),
+            ..Default::default()
+        };
+        let log = MissLog::open(&cfg);
+        let ip: IpAddr = "192.0.2.7".parse().unwrap();
+        for path in ["/c", "/b", "/a", "/b"] {
+            log.record(path, Some("/".into()), ip);
+        }
+
+        let recent: Vec<String> = log.recent(10).into_iter().map(|m| m.path).collect();
+        assert_eq!(recent, ["/b", "/a", "/b"]);
+        let top = log.top(1);
+        assert_eq!(top.len(), 1);
+        assert_eq!((top[0].path.as_str(), top[0].count), ("/b", 2));
+
+        log.dump();
+        log.record("/d", None, ip);
+        log.dump();
+        let text = std::fs::read_to_string(&cfg.dump_file).unwrap();
+        let paths: Vec<String> = text
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .map(|record| record["path"].as_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(paths, ["/b", "/a", "/b", "/d"]);
+    }
+}
@@ -0,0 +1,238 @@
+use std::pin::Pin;
+
+use bytes::{Buf, Bytes, BytesMut};
+use http_body_util::BodyExt;
+use hyper::StatusCode;
+
+/// Largest header block accepted for one part.
+const MAX_PART_HEADERS: usize = 8 * 1024;
+
+/// Streaming reader for a `multipart/form-data` body: [`Self::next_part`]
+/// moves to the next part, [`Self::chunk`] yields its data. Reading past
+/// `limit` body bytes fails with 413, a malformed body with 400.
+pub(crate) struct Multipart<B> {
+    body: Pin<Box<B>>,
+    buf: BytesMut,
+    /// `\r\n--<boundary>`.
+    delimiter: Vec<u8>,
+    state: State,
+    read: u64,
+    limit: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// In the preamble or a part's data.
+    Data,
+    /// Just past a delimiter.
+    Delimiter,
+    /// Past the closing delimiter.
+    Done,
+}
+
+/// The headers of one part that matter here.
+pub(crate) struct Part {
+    /// From `Content-Disposition`; set for file fields only.
+    pub filename: Option<String>,
+}
+
+impl<B: hyper::body::Body> Multipart<B> {
+    pub(crate) fn new(body: B, boundary: &str, limit: u64) -> Self {
+        Self {
+            body: Box::pin(body),
+            // The first delimiter has no line break before it.
+            buf: BytesMut::from(&b"\r\n"[..]),
+            delimiter: [b"\r\n--", boundary.as_bytes()].concat(),
+            state: State::Data,
+            read: 0,
+            limit,
+        }
+    }
+
+    /// Skip the rest of the current part (or the preamble) and read the
+    /// next part's headers. `None` after the last part.
+    pub(crate) async fn next_part(&mut self) -> Result<Option<Part>, StatusCode> {
+        while self.chunk().await?.is_some() {}
+        if self.state == State::Done {
+            return Ok(None);
+        }
+        while self.buf.len() < 2 {
+            self.fill().await?;
+        }
+        if self.buf.starts_with(b"--") {
+            self.state = State::Done;
+            return Ok(None);
+        }
+        // The delimiter line may carry trailing whitespace; the headers end
+        // at the first empty line after it.
+        let line_end = self.read_until(b"\r\n").await?;
+        self.buf.advance(line_end);
+        let headers_end = self.read_until(b"\r\n\r\n").await?;
+        let headers = self.buf.split_to(headers_end + 4);
+        self.state = State::Data;
+
+        let headers = std::str::from_utf8(&headers).map_err(|_| StatusCode::BAD_REQUEST)?;
+        let disposition = headers
+            .split("\r\n")
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-disposition"))
+            .map(|(_, value)| value)
+            .ok_or(StatusCode::BAD_REQUEST)?;
+        Ok(Some(Part {
+            filename: param(disposition, "filename"),
+        }))
+    }
+
+    /// The next piece of the current part's data; `None` at its end.
+    pub(crate) async fn chunk(&mut self) -> Result<Option<Bytes>, StatusCode> {
+        if self.state != State::Data {
+            return Ok(None);
+        }
+        loop {
+            if let Some(pos) = find(&self.buf, &self.delimiter) {
+                if pos > 0 {
+                    return Ok(Some(self.buf.split_to(pos).freeze()));
+                }
+                self.buf.advance(self.delimiter.len());
+                self.state = State::Delimiter;
+                return Ok(None);
+            }
+            // The tail may be the start of a delimiter.
+            let keep = self.delimiter.len() - 1;
+            if self.buf.len() > keep {
+                let len = self.buf.len() - keep;
+                return Ok(Some(self.buf.split_to(len).freeze()));
+            }
+            self.fill().await?;
+        }
+    }
+
+    /// Offset of `needle` in the buffer, reading more of the body until it
+    /// appears within the part header limit.
+    async fn read_until(&mut self, needle: &[u8]) -> Result<usize, StatusCode> {
+        loop {
+            if let Some(pos) = find(&self.buf, needle) {
+                return Ok(pos);
+            }
+            if self.buf.len() > MAX_PART_HEADERS {
+                return Err(StatusCode::BAD_REQUEST);
+            }
+            self.fill().await?;
+        }
+    }
+
+    /// Append the next data frame to the buffer. A body that ends first is
+    /// truncated.
+    async fn fill(&mut self) -> Result<(), StatusCode> {
+        loop {
+            let mut data = match self.body.frame().await {
+                None => return Err(StatusCode::BAD_REQUEST),
+                Some(Err(_)) => return Err(StatusCode::BAD_REQUEST),
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => data,
+                    Err(_) => continue,
+                },
+            };
+            self.read += data.remaining() as u64;
+            if self.read > self.limit {
+                return Err(StatusCode::PAYLOAD_TOO_LARGE);
+            }
+            self.buf
+                .extend_from_slice(&data.copy_to_bytes(data.remaining()));
+            return Ok(());
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// The `boundary` of a `multipart/form-data` Content-Type.
+pub(crate) fn boundary(content_type: &str) -> Option<String> {
+    let (mime, _) = content_type.split_once(';')?;
+    if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    param(content_type, "boundary").filter(|b| !b.is_empty() && b.len() <= 70)
+}
+
+/// The value of parameter `name` in a header value such as
+/// `form-data; name="a"; filename="b.txt"`, unquoted.
+fn param(value: &str, name: &str) -> Option<String> {
+    let mut rest = value.split_once(';')?.1;
+    loop {
+        let (key, after) = rest.split_once('=')?;
+        let key = key.trim();
+        let after = after.trim_start();
+        let (found, next) = match after.strip_prefix('"') {
+            // Browsers send `"` in names as `%22` and backslashes as-is,
+            // so a quoted value simply runs to the next quote.
+            Some(quoted) => {
+                let end = quoted.find('"')?;
+                (quoted[..end].to_owned(), &quoted[end + 1..])
+            }
+            None => {
+                let end = after.find(';').unwrap_or(after.len());
+                (after[..end].trim_end().to_owned(), &after[end..])
+            }
+        };
+        if key.eq_ignore_ascii_case(name) {
+            return Some(found);
+        }
+        rest = next.split_once(';')?.1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+    use http_body_util::StreamBody;
+    use hyper::body::Frame;
+
+    #[tokio::test]
+    async fn reads_parts_across_frames() {
+        let body = "preamble\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"note\"\r\n\r\n\
+            hello\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"C:\\tmp\\a;b.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            line\r\n--Xy\r\n--XyZ--\r\n";
+        // One byte per frame puts every delimiter across frame boundaries.
+        let frames: Vec<Result<Frame<Bytes>, std::io::Error>> = body
+            .bytes()
+            .map(|b| Ok(Frame::data(Bytes::from(vec![b]))))
+            .collect();
+        let ct = "multipart/form-data; boundary=\"XyZ\"";
+        let mut form = Multipart::new(
+            StreamBody::new(stream::iter(frames)),
+            &boundary(ct).unwrap(),
+            1024,
+        );
+
+        let mut parts = Vec::new();
+        while let Some(part) = form.next_part().await.unwrap() {
+            let mut data = Vec::new();
+            while let Some(chunk) = form.chunk().await.unwrap() {
+                data.extend_from_slice(&chunk);
+            }
+            parts.push((part.filename, String::from_utf8(data).unwrap()));
+        }
+        assert_eq!(
+            parts,
+            [
+                (None, "hello".into()),
+                (Some("C:\\tmp\\a;b.txt".into()), "line\r\n--Xy".into()),
+            ]
+        );
+        assert_eq!(boundary("text/plain; boundary=x"), None);
+
+        let frames = vec![Ok::<_, std::io::Error>(Frame::data(Bytes::from(body)))];
+        let mut form = Multipart::new(StreamBody::new(stream::iter(frames)), "XyZ", 16);
+        assert_eq!(
+            form.next_part().await.err(),
+            Some(StatusCode::PAYLOAD_TOO_LARGE)
+        );
+    }
+}
@@ -0,0 +1,374 @@
+#[cfg(feature = "compression")]
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+#[cfg(feature = "compression")]
+use std::{fs, io};
+
+#[cfg(feature = "compression")]
+use tracing::{debug, warn};
+
+#[cfg(feature = "compression")]
+use crate::config::Config;
+use crate::config::PrecompressConfig;
+
+/// A sidecar's content coding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Brotli,
+    Zstd,
+    Gzip,
+}
+
+impl Encoding {
+    /// Most preferred first; breaks ties between equal `q` values.
+    const ALL: [Encoding; 3] = [Encoding::Brotli, Encoding::Zstd, Encoding::Gzip];
+
+    /// The `Content-Encoding` token, also its name in `algorithms`.
+    pub(crate) fn token(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// The sidecar extension, also the suffix of a sidecar's `ETag`.
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zst",
+            Encoding::Gzip => "gz",
+        }
+    }
+
+    /// `path` with the sidecar extension appended: `app.js` -> `app.js.br`.
+    fn sidecar(self, path: &Path) -> PathBuf {
+        let mut name = OsString::from(path.as_os_str());
+        name.push(".");
+        name.push(self.extension());
+        PathBuf::from(name)
+    }
+}
+
+/// The configured `algorithms`, most preferred first; empty when sidecars
+/// are not served.
+pub(crate) fn serving(cfg: &PrecompressConfig) -> Vec<Encoding> {
+    if !cfg.enabled {
+        return Vec::new();
+    }
+    configured(cfg)
+}
+
+fn configured(cfg: &PrecompressConfig) -> Vec<Encoding> {
+    Encoding::ALL
+        .into_iter()
+        .filter(|e| cfg.algorithms.iter().any(|a| a == e.token()))
+        .collect()
+}
+
+/// Text, JSON, JavaScript, XML (SVG included) and WebAssembly: the types
+/// worth a sidecar. Everything else is usually compressed already.
+pub(crate) fn compressible(mime: &mime_guess::Mime) -> bool {
+    mime.type_() == mime_guess::mime::TEXT
+        || matches!(
+            mime.subtype().as_str(),
+            "json" | "javascript" | "ecmascript" | "xml" | "wasm"
+        )
+        || matches!(mime.suffix().map(|s| s.as_str()), Some("json" | "xml"))
+}
+
+/// Open the sidecar of `path` the client prefers among `encodings`. Only a
+/// sidecar carrying the file's own mtime counts, as the generator leaves
+/// it; one made from an older version of the file is ignored.
+pub(crate) async fn open(
+    path: &Path,
+    modified: SystemTime,
+    accept_encoding: Option<&str>,
+    encodings: &[Encoding],
+) -> Option<(Encoding, tokio::fs::File, u64)> {
+    for encoding in accepted(accept_encoding?, encodings) {
+        let Ok(file) = tokio::fs::File::open(encoding.sidecar(path)).await else {
+            continue;
+        };
+        let Ok(meta) = file.metadata().await else {
+            continue;
+        };
+        if meta.is_file() && meta.modified().is_ok_and(|m| m == modified) {
+            return Some((encoding, file, meta.len()));
+        }
+    }
+    None
+}
+
+/// `encodings` an `Accept-Encoding` value allows, highest `q` first. A
+/// listed token sets its own `q`, `*` covers the rest, and `q=0` rules an
+/// encoding out.
+fn accepted(accept_encoding: &str, encodings: &[Encoding]) -> Vec<Encoding> {
+    let mut any = None;
+    let mut listed = Vec::new();
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';').map(str::trim);
+        let token = parts.next().unwrap_or("").to_ascii_lowercase();
+        let q = parts
+            .find_map(|p| p.strip_prefix("q="))
+            .map_or(1.0, |q| q.trim().parse::<f32>().unwrap_or(0.0));
+        if token == "*" {
+            any = Some(q);
+        } else {
+            listed.push((token, q));
+        }
+    }
+    let mut ranked: Vec<(Encoding, f32)> = encodings
+        .iter()
+        .filter_map(|&encoding| {
+            let q = listed
+                .iter()
+                .find(|(token, _)| token == encoding.token())
+                .map(|&(_, q)| q)
+                .or(any)?;
+            (q > 0.0).then_some((encoding, q))
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranked.into_iter().map(|(encoding, _)| encoding).collect()
+}
+
+// ---------------------------------------------------------------------------
+// Generator
+// ---------------------------------------------------------------------------
+
+/// What `filehunter precompress` did, counted in sidecars.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub written: usize,
+    /// Already present for the file's current version.
+    pub current: usize,
+    /// Not written because they came out no smaller than the file.
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+/// Blocking: write a sidecar for every `algorithms` entry next to each
+/// compressible file of at least `min_size` in the local roots. Dotfiles,
+/// symlinks and extensions a root does not serve are left alone. Each
+/// sidecar is written under a temporary name, renamed into place and given
+/// the file's mtime, which is how [`open`] tells it is current.
+#[cfg(feature = "compression")]
+pub fn generate(config: &Config) -> Summary {
+    let cfg = &config.server.precompress;
+    let encodings = configured(cfg);
+    let mut summary = Summary::default();
+    let mut seen = HashSet::new();
+
+    let roots = config.all_locations().flat_map(|loc| &loc.paths);
+    for root in roots.filter(|p| !p.is_remote()) {
+        let dir = match root.root.canonicalize() {
+            Ok(dir) => dir,
+            Err(e) => {
+                warn!(root = %root.root.display(), error = %e, "cannot precompress root");
+                continue;
+            }
+        };
+        let extensions = root.extension_set();
+        let mut pending = vec![dir];
+        while let Some(dir) = pending.pop() {
+            let Ok(read_dir) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in read_dir.flatten() {
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+                let Ok(file_type) = entry.file_type() else {
+                    continue;
+                };
+                let path = entry.path();
+                if file_type.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                let ext = path
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or("")
+                    .to_ascii_lowercase();
+                let Some(mime) = mime_guess::from_path(&path).first() else {
+                    continue;
+                };
+                let served = extensions.as_ref().is_none_or(|set| set.contains(&ext));
+                if !file_type.is_file() || !served || !compressible(&mime) {
+                    continue;
+                }
+                let Ok(meta) = entry.metadata() else {
+                    continue;
+                };
+                let Ok(modified) = meta.modified() else {
+                    continue;
+                };
+                if meta.len() < cfg.min_size.as_u64() || !seen.insert(path.clone()) {
+                    continue;
+                }
+                for &encoding in &encodings {
+                    match write_sidecar(&path, meta.len(), modified, encoding) {
+                        Ok(Written::Yes) => summary.written += 1,
+                        Ok(Written::Current) => summary.current += 1,
+                        Ok(Written::NoSmaller) => summary.skipped += 1,
+                        Err(e) => {
+                            warn!(path = %path.display(), encoding = encoding.token(), error = %e, "cannot write sidecar");
+                            summary.failed += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    summary
+}
+
+#[cfg(feature = "compression")]
+enum Written {
+    Yes,
+    Current,
+    NoSmaller,
+}
+
+#[cfg(feature = "compression")]
+fn write_sidecar(
+    path: &Path,
+    size: u64,
+    modified: SystemTime,
+    encoding: Encoding,
+) -> io::Result<Written> {
+    let target = encoding.sidecar(path);
+    let current = fs::metadata(&target).and_then(|m| m.modified());
+    if current.is_ok_and(|m| m == modified) {
+        return Ok(Written::Current);
+    }
+    let mut tmp = OsString::from(target.as_os_str());
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let encoded = fs::File::open(path)
+        .and_then(|mut source| encode(encoding, &mut source, fs::File::create(&tmp)?))
+        .and_then(|out| {
+            out.set_modified(modified)?;
+            out.metadata()
+        });
+    match encoded {
+        Ok(meta) if meta.len() < size => {
+            fs::rename(&tmp, &target)?;
+            debug!(path = %target.display(), size = meta.len(), "sidecar written");
+            Ok(Written::Yes)
+        }
+        Ok(_) => {
+            // A stale sidecar would never be served again; drop it too.
+            let _ = fs::remove_file(&tmp);
+            let _ = fs::remove_file(&target);
+            Ok(Written::NoSmaller)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+/// Compress `source` into `out` at the codec's highest level; there is no
+/// request waiting on it.
+#[cfg(feature = "compression")]
+fn encode(encoding: Encoding, source: &mut fs::File, mut out: fs::File) -> io::Result<fs::File> {
+    match encoding {
+        Encoding::Brotli => {
+            let params = brotli::enc::BrotliEncoderParams::default();
+            brotli::BrotliCompress(source, &mut out, &params)?;
+            Ok(out)
+        }
+        Encoding::Zstd => {
+            let mut encoder = zstd::stream::write::Encoder::new(out, 19)?;
+            io::copy(source, &mut encoder)?;
+            encoder.finish()
+        }
+        Encoding::Gzip => {
+            let level = flate2::Compression::best();
+            let mut encoder = flate2::write::GzEncoder::new(out, level);
+            io::copy(source, &mut encoder)?;
+            encoder.finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_accept_encoding() {
+        use Encoding::*;
+        let all = Encoding::ALL;
+        assert_eq!(
+            accepted("gzip, deflate, br, zstd", &all),
+            [Brotli, Zstd, Gzip]
+        );
+        assert_eq!(accepted("gzip, br;q=0.5", &all), [Gzip, Brotli]);
+        assert_eq!(accepted("br;q=0, *", &all), [Zstd, Gzip]);
+        assert_eq!(accepted("identity", &all), []);
+        assert_eq!(accepted("GZIP", &[Brotli, Gzip]), [Gzip]);
+        assert!(compressible(&mime_guess::mime::TEXT_CSS));
+        assert!(compressible(&"image/svg+xml".parse().unwrap()));
+        assert!(!compressible(&mime_guess::mime::IMAGE_PNG));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn generates_current_sidecars() {
+        use crate::config::{LocationConfig, SearchPath};
+
+        let dir = tempfile::tempdir().unwrap();
+        let text = "all work and no play makes jack a dull boy\n".repeat(100);
+        fs::write(dir.path().join("notes.txt"), &text).unwrap();
+        fs::write(dir.path().join("tiny.css"), "a{}").unwrap();
+        fs::write(dir.path().join("photo.png"), &text).unwrap();
+        fs::write(dir.path().join(".secret.txt"), &text).unwrap();
+
+        let mut config = Config {
+            server: Default::default(),
+            locations: vec![LocationConfig {
+                prefix: "/".into(),
+                paths: vec![SearchPath {
+                    root: dir.path().into(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            vhosts: Vec::new(),
+        };
+        config.server.precompress.algorithms = vec!["gzip".into(), "zstd".into()];
+        let first = generate(&config);
+        assert_eq!((first.written, first.current, first.failed), (2, 0, 0));
+        let again = generate(&config);
+        assert_eq!((again.written, again.current), (0, 2));
+
+        let mut names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                ".secret.txt",
+                "notes.txt",
+                "notes.txt.gz",
+                "notes.txt.zst",
+                "photo.png",
+                "tiny.css"
+            ]
+        );
+        let source = fs::metadata(dir.path().join("notes.txt")).unwrap();
+        let gz = fs::metadata(dir.path().join("notes.txt.gz")).unwrap();
+        assert_eq!(gz.modified().unwrap(), source.modified().unwrap());
+        assert!(gz.len() < source.len());
+    }
+}
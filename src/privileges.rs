@@ -0,0 +1,114 @@
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// The ids a process switches to after binding. Resolved up front, since a
+/// chroot usually hides the account database.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Account {
+    uid: Option<libc::uid_t>,
+    gid: Option<libc::gid_t>,
+}
+
+impl Account {
+    /// Resolve `user` and `group` (names or numeric ids; empty keeps the
+    /// current one). `group` defaults to the primary group of `user`.
+    /// `None` when both are empty.
+    pub fn lookup(user: &str, group: &str) -> Result<Option<Self>, String> {
+        let user = match user {
+            "" => None,
+            user => Some(lookup_user(user)?),
+        };
+        let gid = match group {
+            "" => user.map(|(_, gid)| gid),
+            group => Some(lookup_group(group)?),
+        };
+        let uid = user.map(|(uid, _)| uid);
+        Ok((uid.is_some() || gid.is_some()).then_some(Self { uid, gid }))
+    }
+
+    /// Switch the process to this account, clearing supplementary groups
+    /// along with the group change. Needs root to succeed.
+    pub fn switch_to(&self) -> Result<(), String> {
+        if let Some(gid) = self.gid {
+            // SAFETY: plain syscalls; `&gid` outlives the call.
+            if unsafe { libc::setgroups(1, &gid) } != 0 {
+                return Err(format!("setgroups: {}", io::Error::last_os_error()));
+            }
+            if unsafe { libc::setgid(gid) } != 0 {
+                return Err(format!("setgid({gid}): {}", io::Error::last_os_error()));
+            }
+        }
+        if let Some(uid) = self.uid {
+            if unsafe { libc::setuid(uid) } != 0 {
+                return Err(format!("setuid({uid}): {}", io::Error::last_os_error()));
+            }
+            // Root must not be recoverable.
+            if uid != 0 && unsafe { libc::setuid(0) } == 0 {
+                return Err("privileges could not be dropped".into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Make `dir` the process's root directory and move into it. Needs root,
+/// so it runs before [`Account::switch_to`].
+pub fn chroot(dir: &Path) -> Result<(), String> {
+    let path = CString::new(dir.as_os_str().as_bytes())
+        .map_err(|_| format!("invalid chroot {}", dir.display()))?;
+    // SAFETY: plain syscall on a NUL-terminated path.
+    if unsafe { libc::chroot(path.as_ptr()) } != 0 {
+        let e = io::Error::last_os_error();
+        return Err(format!("chroot({}): {e}", dir.display()));
+    }
+    std::env::set_current_dir("/").map_err(|e| format!("chdir(/) after chroot: {e}"))
+}
+
+/// Resolve a user name or uid to `(uid, primary gid)`.
+fn lookup_user(user: &str) -> Result<(libc::uid_t, libc::gid_t), String> {
+    let name = CString::new(user).map_err(|_| format!("invalid user {user:?}"))?;
+    // SAFETY: called during startup before other threads look up accounts;
+    // the returned record is copied out immediately.
+    let entry = unsafe { libc::getpwnam(name.as_ptr()) };
+    if !entry.is_null() {
+        return Ok(unsafe { ((*entry).pw_uid, (*entry).pw_gid) });
+    }
+    match user.parse::<libc::uid_t>() {
+        // A bare uid without a passwd entry keeps it as its own group.
+        Ok(uid) => Ok((uid, uid as libc::gid_t)),
+        Err(_) => Err(format!("unknown user {user:?}")),
+    }
+}
+
+/// Resolve a group name or gid.
+fn lookup_group(group: &str) -> Result<libc::gid_t, String> {
+    let name = CString::new(group).map_err(|_| format!("invalid group {group:?}"))?;
+    // SAFETY: as in `lookup_user`.
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if !entry.is_null() {
+        return Ok(unsafe { (*entry).gr_gid });
+    }
+    group
+        .parse::<libc::gid_t>()
+        .map_err(|_| format!("unknown group {group:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_names_and_ids() {
+        assert_eq!(lookup_user("root"), Ok((0, 0)));
+        assert_eq!(lookup_user("4242"), Ok((4242, 4242)));
+        assert!(lookup_user("no-such-user-here").is_err());
+        assert_eq!(lookup_group("4243"), Ok(4243));
+        assert!(lookup_group("no-such-group-here").is_err());
+
+        assert_eq!(Account::lookup("", ""), Ok(None));
+        let account = Account::lookup("root", "4243").unwrap().unwrap();
+        assert_eq!((account.uid, account.gid), (Some(0), Some(4243)));
+    }
+}
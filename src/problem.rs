@@ -0,0 +1,261 @@
+//! Bodies of filehunter's own error responses: the plain-text message,
+//! RFC 7807 problem details (`application/problem+json`) or an HTML page,
+//! per `error_format`. Clients whose `Accept` asks for JSON always get
+//! problem details.
+
+use std::borrow::Cow;
+use std::hash::{BuildHasher, RandomState};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use hyper::header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE, HeaderValue};
+use hyper::{HeaderMap, Request, Response, StatusCode};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::config::{ErrorFormat, LocationConfig};
+use crate::server::{ResponseBody, full_bytes};
+
+/// Longest `X-Request-Id` taken from a client.
+const MAX_REQUEST_ID: usize = 128;
+
+/// Built-in `html` error page.
+const DEFAULT_PAGE: &str = "<!DOCTYPE html>
+<html>
+<head><meta charset=\"utf-8\"><title>{{status}} {{title}}</title></head>
+<body><h1>{{status}} {{title}}</h1><p>{{message}}</p></body>
+</html>
+";
+
+/// The message of an error response filehunter generated itself, kept so
+/// the body can be rendered again in the format the client asked for.
+#[derive(Debug, Clone)]
+pub(crate) struct ErrorMessage(pub(crate) Cow<'static, str>);
+
+/// How a server or location renders its error responses.
+#[derive(Debug, Clone)]
+pub(crate) struct ErrorPages {
+    format: ErrorFormat,
+    /// `error_template`, kept for locations that switch to `html`.
+    template: PathBuf,
+    /// The `html` page, read from `template`.
+    page: Arc<str>,
+}
+
+impl Default for ErrorPages {
+    fn default() -> Self {
+        Self {
+            format: ErrorFormat::Text,
+            template: PathBuf::new(),
+            page: DEFAULT_PAGE.into(),
+        }
+    }
+}
+
+impl ErrorPages {
+    /// Read `template` when `format` is `html`. A page that cannot be read
+    /// is logged and the built-in one used.
+    pub(crate) fn new(format: ErrorFormat, template: &Path) -> Self {
+        let read = format == ErrorFormat::Html && !template.as_os_str().is_empty();
+        let page = match read.then(|| std::fs::read_to_string(template)) {
+            Some(Ok(page)) => page.into(),
+            Some(Err(e)) => {
+                warn!(template = %template.display(), error = %e, "cannot read error template");
+                DEFAULT_PAGE.into()
+            }
+            None => DEFAULT_PAGE.into(),
+        };
+        Self {
+            format,
+            template: template.to_path_buf(),
+            page,
+        }
+    }
+
+    /// These pages with `loc`'s `error_format` and `error_template`
+    /// overrides applied.
+    pub(crate) fn for_location(&self, loc: &LocationConfig) -> Self {
+        if loc.error_format.is_none() && loc.error_template.is_none() {
+            return self.clone();
+        }
+        let format = loc.error_format.unwrap_or(self.format);
+        Self::new(
+            format,
+            loc.error_template.as_deref().unwrap_or(&self.template),
+        )
+    }
+}
+
+/// How the error responses to one request are rendered.
+pub(crate) enum Rendering {
+    Text,
+    Problem(Problem),
+    Html { page: Arc<str>, path: String },
+}
+
+impl Rendering {
+    /// Problem details when the request's `Accept` lists
+    /// `application/problem+json` or `application/json`, else `pages`'
+    /// format.
+    pub(crate) fn for_request<B>(req: &Request<B>, pages: &ErrorPages) -> Self {
+        let accept = req.headers().get(ACCEPT).and_then(|v| v.to_str().ok());
+        if accept.is_some_and(accepts_json) {
+            return Self::Problem(Problem::new(req));
+        }
+        match pages.format {
+            ErrorFormat::Text => Self::Text,
+            ErrorFormat::Json => Self::Problem(Problem::new(req)),
+            ErrorFormat::Html => Self::Html {
+                page: pages.page.clone(),
+                path: req.uri().path().to_owned(),
+            },
+        }
+    }
+
+    /// Re-render `resp` if it is one of filehunter's error responses;
+    /// anything else passes unchanged.
+    pub(crate) fn render(&self, mut resp: Response<ResponseBody>) -> Response<ResponseBody> {
+        let status = resp.status();
+        if !(status.is_client_error() || status.is_server_error()) {
+            return resp;
+        }
+        let Some(ErrorMessage(message)) = resp.extensions().get::<ErrorMessage>().cloned() else {
+            return resp;
+        };
+        let body = match self {
+            Self::Text => return resp,
+            Self::Problem(problem) => problem.body(status, &message, resp.headers_mut()),
+            Self::Html { page, path } => {
+                let content_type = HeaderValue::from_static("text/html; charset=utf-8");
+                resp.headers_mut().insert(CONTENT_TYPE, content_type);
+                fill(page, status, &message, path).into_bytes()
+            }
+        };
+        resp.headers_mut().remove(CONTENT_LENGTH);
+        resp.map(|_| full_bytes(body.into()))
+    }
+}
+
+#[derive(Serialize)]
+struct Details<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: &'a str,
+    status: u16,
+    /// Omitted when it would only repeat the title.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<&'a str>,
+    /// The request path.
+    instance: &'a str,
+    request_id: &'a str,
+}
+
+/// A request that wants problem details for its errors.
+pub(crate) struct Problem {
+    instance: String,
+    request_id: String,
+}
+
+impl Problem {
+    fn new<B>(req: &Request<B>) -> Self {
+        let request_id = req
+            .headers()
+            .get("x-request-id")
+            .and_then(|v| v.to_str().ok())
+            .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID);
+        Self {
+            instance: req.uri().path().to_owned(),
+            request_id: request_id.map_or_else(new_request_id, str::to_owned),
+        }
+    }
+
+    /// The problem details of an error, setting their content type and the
+    /// request ID in `headers`.
+    fn body(&self, status: StatusCode, message: &str, headers: &mut HeaderMap) -> Vec<u8> {
+        let title = status.canonical_reason().unwrap_or_default();
+        let details = Details {
+            kind: "about:blank",
+            title,
+            status: status.as_u16(),
+            detail: Some(message).filter(|m| *m != title),
+            instance: &self.instance,
+            request_id: &self.request_id,
+        };
+        let content_type = HeaderValue::from_static("application/problem+json");
+        headers.insert(CONTENT_TYPE, content_type);
+        if let Ok(id) = HeaderValue::from_str(&self.request_id) {
+            headers.insert("x-request-id", id);
+        }
+        serde_json::to_vec(&details).expect("JSON serialization cannot fail")
+    }
+}
+
+/// `page` with its placeholders replaced, the message and path escaped.
+fn fill(page: &str, status: StatusCode, message: &str, path: &str) -> String {
+    page.replace("{{status}}", status.as_str())
+        .replace("{{title}}", status.canonical_reason().unwrap_or_default())
+        .replace("{{message}}", &escape(message))
+        .replace("{{path}}", &escape(path))
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Whether an `Accept` header lists a JSON problem type with non-zero
+/// quality. Wildcards do not count: browsers send `*/*`.
+fn accepts_json(accept: &str) -> bool {
+    accept.split(',').any(|item| {
+        let mut parts = item.split(';').map(str::trim);
+        let media = parts.next().unwrap_or("").to_ascii_lowercase();
+        let rejected = parts.any(|p| {
+            p.strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q <= 0.0)
+        });
+        let json = ["application/problem+json", "application/json"].contains(&media.as_str());
+        json && !rejected
+    })
+}
+
+/// A fresh 16-hex-digit ID for a request that brought none.
+fn new_request_id() -> String {
+    static SEQ: AtomicU64 = AtomicU64::new(0);
+    let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+    format!("{:016x}", RandomState::new().hash_one(seq))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_json_from_accept() {
+        assert!(accepts_json("application/problem+json"));
+        assert!(accepts_json("text/plain;q=0.5, Application/JSON"));
+        assert!(!accepts_json("application/json;q=0"));
+        assert!(!accepts_json("text/html,application/xhtml+xml,*/*;q=0.8"));
+        assert_ne!(new_request_id(), new_request_id());
+    }
+
+    #[test]
+    fn fills_html_template() {
+        let page = "<h1>{{status}} {{title}}</h1><p>{{message}}</p><p>{{path}}</p>";
+        assert_eq!(
+            fill(page, StatusCode::NOT_FOUND, "Not Found", "/a<b>.txt"),
+            "<h1>404 Not Found</h1><p>Not Found</p><p>/a&lt;b&gt;.txt</p>"
+        );
+    }
+}
@@ -0,0 +1,115 @@
+//! Quarantine of suspicious files: files whose content contradicts their
+//! extension, that the antivirus scan flags, or that match a location's
+//! `deny_patterns` are moved or linked into one directory for operators to
+//! review.
+
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+#[cfg(unix)]
+use tokio::fs::symlink;
+#[cfg(windows)]
+use tokio::fs::symlink_file as symlink;
+use tracing::{info, warn};
+
+use crate::config::{QuarantineAction, QuarantineConfig};
+
+/// The `quarantine` directory and what is put there.
+pub(crate) struct Quarantine {
+    dir: PathBuf,
+    action: QuarantineAction,
+    /// Files already linked, so repeated requests don't pile up entries.
+    linked: Mutex<HashSet<PathBuf>>,
+    /// Keeps entry names unique within a second.
+    seq: AtomicU64,
+}
+
+impl Quarantine {
+    pub(crate) fn new(cfg: &QuarantineConfig) -> Self {
+        Self {
+            dir: cfg.dir.clone(),
+            action: cfg.action,
+            linked: Mutex::default(),
+            seq: AtomicU64::new(0),
+        }
+    }
+
+    /// Move or link the local file `file` into the quarantine directory.
+    /// Returns the new entry, or `None` when `file` already has one.
+    pub(crate) async fn isolate(&self, file: &Path) -> io::Result<Option<PathBuf>> {
+        if self.action == QuarantineAction::Symlink
+            && !self.linked.lock().unwrap().insert(file.to_path_buf())
+        {
+            return Ok(None);
+        }
+        let result = self.place(file).await;
+        if result.is_err() {
+            self.linked.lock().unwrap().remove(file);
+        }
+        match &result {
+            Ok(entry) => {
+                info!(file = %file.display(), entry = %entry.display(), "file quarantined")
+            }
+            Err(e) => warn!(file = %file.display(), error = %e, "cannot quarantine file"),
+        }
+        result.map(Some)
+    }
+
+    async fn place(&self, file: &Path) -> io::Result<PathBuf> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        let secs = crate::server::unix_secs(SystemTime::now());
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        let entry = self.dir.join(format!("{secs}-{seq}-{name}"));
+        match self.action {
+            QuarantineAction::Symlink => symlink(file, &entry).await?,
+            QuarantineAction::Move => {
+                // Across filesystems a rename fails; copy, then remove.
+                if tokio::fs::rename(file, &entry).await.is_err() {
+                    tokio::fs::copy(file, &entry).await?;
+                    tokio::fs::remove_file(file).await?;
+                }
+            }
+        }
+        Ok(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn links_once_and_moves() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("avatar.png");
+        std::fs::write(&file, b"<html>").unwrap();
+        let mut cfg = QuarantineConfig {
+            enabled: true,
+            dir: dir.path().join("quarantine"),
+            action: QuarantineAction::Symlink,
+        };
+
+        let linking = Quarantine::new(&cfg);
+        let entry = linking.isolate(&file).await.unwrap().unwrap();
+        assert!(
+            entry
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .ends_with("-0-avatar.png")
+        );
+        assert_eq!(std::fs::read_link(&entry).unwrap(), file);
+        assert_eq!(linking.isolate(&file).await.unwrap(), None);
+
+        cfg.action = QuarantineAction::Move;
+        let entry = Quarantine::new(&cfg).isolate(&file).await.unwrap().unwrap();
+        assert!(!file.exists());
+        assert_eq!(std::fs::read(&entry).unwrap(), b"<html>");
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, RandomState};
+use std::io::{self, SeekFrom};
+use std::ops::Range;
+
+use bytes::Bytes;
+use futures_util::{TryStreamExt, stream};
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::Frame;
+use hyper::{Response, StatusCode};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::config::RangesConfig;
+use crate::server::{ResponseBody, empty_body, text_response};
+
+/// A location's caps on the ranges of one request.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RangeLimits {
+    pub max_ranges: usize,
+    /// Total bytes over all ranges; 0 = unlimited.
+    pub max_size: u64,
+}
+
+impl From<&RangesConfig> for RangeLimits {
+    fn from(cfg: &RangesConfig) -> Self {
+        Self {
+            max_ranges: cfg.max_ranges,
+            max_size: cfg.max_size.as_u64(),
+        }
+    }
+}
+
+/// How to answer a request's `Range` header.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum RangeRequest {
+    /// Absent or malformed: send the whole file.
+    Full,
+    /// Satisfiable ranges within the file, sorted, with overlapping or
+    /// adjacent ones coalesced.
+    Partial(Vec<Range<u64>>),
+    /// No range overlaps the file, or the ranges exceed the limits.
+    Unsatisfiable,
+}
+
+impl RangeRequest {
+    /// Parse a `Range` header value against a file of `size` bytes.
+    pub(crate) fn parse(header: &str, size: u64, limits: RangeLimits) -> Self {
+        let Some((unit, specs)) = header.split_once('=') else {
+            return Self::Full;
+        };
+        if !unit.trim().eq_ignore_ascii_case("bytes") {
+            return Self::Full;
+        }
+        let mut ranges = Vec::new();
+        for (i, spec) in specs.split(',').map(str::trim).enumerate() {
+            if i == limits.max_ranges {
+                return Self::Unsatisfiable;
+            }
+            let Some((first, last)) = spec.split_once('-') else {
+                return Self::Full;
+            };
+            let range = match (first, last) {
+                ("", suffix) => match suffix.parse::<u64>() {
+                    Ok(0) => None,
+                    Ok(n) => Some(size.saturating_sub(n)..size),
+                    Err(_) => return Self::Full,
+                },
+                (first, "") => match first.parse::<u64>() {
+                    Ok(start) => Some(start..size),
+                    Err(_) => return Self::Full,
+                },
+                (first, last) => match (first.parse::<u64>(), last.parse::<u64>()) {
+                    (Ok(start), Ok(end)) if start <= end => {
+                        Some(start..end.saturating_add(1).min(size))
+                    }
+                    _ => return Self::Full,
+                },
+            };
+            if let Some(range) = range.filter(|r| r.start < r.end) {
+                ranges.push(range);
+            }
+        }
+        // Coalesced as RFC 9110 §14.2 allows, so no byte is sent twice.
+        ranges.sort_by_key(|r| r.start);
+        let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        let total: u64 = merged.iter().map(|r| r.end - r.start).sum();
+        if merged.is_empty() || (limits.max_size > 0 && total > limits.max_size) {
+            Self::Unsatisfiable
+        } else {
+            Self::Partial(merged)
+        }
+    }
+}
+
+/// A piece of a partial response body.
+enum Part {
+    Bytes(Bytes),
+    File(Range<u64>),
+}
+
+/// 206 for one range, `multipart/byteranges` for several. `builder` carries
+/// the headers shared with full responses.
+pub(crate) fn respond(
+    builder: hyper::http::response::Builder,
+    file: File,
+    content_type: &str,
+    size: u64,
+    ranges: Vec<Range<u64>>,
+    buffer_size: usize,
+    is_head: bool,
+) -> Response<ResponseBody> {
+    let builder = builder.status(StatusCode::PARTIAL_CONTENT);
+    let (builder, parts) = if let [range] = ranges.as_slice() {
+        let builder = builder
+            .header("Content-Type", content_type)
+            .header("Content-Range", content_range(range, size));
+        (builder, vec![Part::File(range.clone())])
+    } else {
+        let boundary = format!("{:016x}", RandomState::new().hash_one(size));
+        let mut parts = Vec::with_capacity(ranges.len() * 2 + 1);
+        for range in ranges {
+            let head = format!(
+                "\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: {}\r\n\r\n",
+                content_range(&range, size)
+            );
+            parts.push(Part::Bytes(head.into()));
+            parts.push(Part::File(range));
+        }
+        parts.push(Part::Bytes(format!("\r\n--{boundary}--\r\n").into()));
+        let builder = builder.header(
+            "Content-Type",
+            format!("multipart/byteranges; boundary={boundary}"),
+        );
+        (builder, parts)
+    };
+
+    let length: u64 = parts
+        .iter()
+        .map(|p| match p {
+            Part::Bytes(b) => b.len() as u64,
+            Part::File(r) => r.end - r.start,
+        })
+        .sum();
+    let body = if is_head {
+        empty_body()
+    } else {
+        parts_body(file, parts.into(), buffer_size)
+    };
+    builder.header("Content-Length", length).body(body).unwrap()
+}
+
+/// 416 with the file size, as RFC 9110 asks for.
+pub(crate) fn unsatisfiable(size: u64) -> Response<ResponseBody> {
+    let status = StatusCode::RANGE_NOT_SATISFIABLE;
+    let mut resp = text_response(status, "Range Not Satisfiable");
+    let range = format!("bytes */{size}").parse().unwrap();
+    resp.headers_mut().insert("Content-Range", range);
+    resp
+}
+
+fn content_range(range: &Range<u64>, size: u64) -> String {
+    format!("bytes {}-{}/{size}", range.start, range.end - 1)
+}
+
+/// Stream `parts` in order, reading file ranges in `buffer_size` chunks.
+fn parts_body(file: File, parts: VecDeque<Part>, buffer_size: usize) -> ResponseBody {
+    let chunks = stream::try_unfold((file, parts), move |(mut file, mut parts)| async move {
+        let chunk = match parts.pop_front() {
+            None => return Ok(None),
+            Some(Part::Bytes(bytes)) => bytes,
+            Some(Part::File(range)) => {
+                let want = (range.end - range.start).min(buffer_size as u64) as usize;
+                let mut buf = vec![0u8; want];
+                file.seek(SeekFrom::Start(range.start)).await?;
+                let n = file.read(&mut buf).await?;
+                if n == 0 {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+                }
+                buf.truncate(n);
+                if range.start + (n as u64) < range.end {
+                    parts.push_front(Part::File(range.start + n as u64..range.end));
+                }
+                buf.into()
+            }
+        };
+        Ok(Some((chunk, (file, parts))))
+    });
+    StreamBody::new(chunks.map_ok(Frame::data)).boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_range_headers() {
+        use RangeRequest::*;
+        let limits = RangeLimits::from(&RangesConfig::default());
+        let parse = |header: &str| RangeRequest::parse(header, 100, limits);
+        let one = |r: Range<u64>| Partial(vec![r]);
+        assert_eq!(parse("bytes=0-9"), one(0..10));
+        assert_eq!(parse("bytes=90-"), one(90..100));
+        assert_eq!(parse("bytes=-10"), one(90..100));
+        assert_eq!(parse("bytes=50-500"), one(50..100));
+        assert_eq!(
+            parse("bytes=0-0, 200-300, -1"),
+            Partial(vec![0..1, 99..100])
+        );
+        assert_eq!(parse("bytes=100-"), Unsatisfiable);
+        assert_eq!(parse("bytes=-0"), Unsatisfiable);
+        assert_eq!(parse("bytes=9-0"), Full);
+        assert_eq!(parse("items=0-9"), Full);
+        assert_eq!(parse("bytes=a-b"), Full);
+        let many = format!("bytes={}", vec!["0-0"; limits.max_ranges + 1].join(","));
+        assert_eq!(parse(&many), Unsatisfiable);
+
+        // Overlapping and adjacent ranges are merged, in file order.
+        assert_eq!(
+            parse("bytes=50-59, 0-9, 5-14"),
+            Partial(vec![0..15, 50..60])
+        );
+        assert_eq!(parse("bytes=0-49, 50-99"), one(0..100));
+        assert_eq!(parse("bytes=0-99, 0-99, -100"), one(0..100));
+
+        let limits = RangeLimits {
+            max_ranges: 2,
+            max_size: 20,
+        };
+        let parse = |header: &str| RangeRequest::parse(header, 100, limits);
+        assert_eq!(parse("bytes=0-9,-10"), Partial(vec![0..10, 90..100]));
+        assert_eq!(parse("bytes=0-0,1-1,2-2"), Unsatisfiable);
+        assert_eq!(parse("bytes=0-20"), Unsatisfiable);
+        assert_eq!(parse("bytes=0-14,5-19"), one(0..20));
+    }
+}
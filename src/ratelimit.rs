@@ -1,25 +1,123 @@
 use std::net::IpAddr;
 use std::num::NonZeroU32;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use governor::clock::DefaultClock;
+use dashmap::DashMap;
+use governor::clock::{Clock, DefaultClock};
+use governor::middleware::{StateInformationMiddleware, StateSnapshot};
 use governor::state::keyed::DashMapStateStore;
 use governor::{Quota, RateLimiter};
+use hyper::HeaderMap;
 use tracing::{debug, info};
 
-use crate::config::RateLimitConfig;
+use crate::config::{RateLimitAlgorithm, RateLimitConfig};
 
-pub type KeyedLimiter = RateLimiter<IpAddr, DashMapStateStore<IpAddr>, DefaultClock>;
+type GcraLimiter =
+    RateLimiter<IpAddr, DashMapStateStore<IpAddr>, DefaultClock, StateInformationMiddleware>;
 
-/// Build a per-IP GCRA rate limiter from config.
+/// Per-IP rate limiter using the algorithm chosen in [`RateLimitConfig`].
+pub struct KeyedLimiter {
+    inner: Inner,
+}
+
+enum Inner {
+    Gcra(GcraLimiter),
+    Window(WindowLimiter),
+}
+
+/// A client's quota after a rate limiting decision, as sent in the
+/// `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset` headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitStatus {
+    /// Requests allowed with a fresh quota: the burst size for GCRA, the
+    /// per-window limit otherwise.
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the quota is restored; after a refusal, until the next
+    /// request may succeed.
+    pub reset: u64,
+}
+
+impl RateLimitStatus {
+    fn from_snapshot(snapshot: &StateSnapshot) -> Self {
+        let quota = snapshot.quota();
+        let limit = quota.burst_size().get();
+        let remaining = snapshot.remaining_burst_capacity();
+        let refill = quota.replenish_interval() * (limit - remaining);
+        Self {
+            limit,
+            remaining,
+            reset: ceil_secs(refill),
+        }
+    }
+
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        headers.insert("RateLimit-Limit", self.limit.into());
+        headers.insert("RateLimit-Remaining", self.remaining.into());
+        headers.insert("RateLimit-Reset", self.reset.into());
+    }
+}
+
+impl KeyedLimiter {
+    /// Count a request from `ip`. `Err` means it is over the limit; its
+    /// `reset` is the Retry-After delay (at least one second).
+    pub fn check(&self, ip: IpAddr) -> Result<RateLimitStatus, RateLimitStatus> {
+        match &self.inner {
+            Inner::Gcra(limiter) => match limiter.check_key(&ip) {
+                Ok(snapshot) => Ok(RateLimitStatus::from_snapshot(&snapshot)),
+                Err(not_until) => Err(RateLimitStatus {
+                    limit: not_until.quota().burst_size().get(),
+                    remaining: 0,
+                    reset: not_until
+                        .wait_time_from(DefaultClock::default().now())
+                        .as_secs()
+                        .max(1),
+                }),
+            },
+            Inner::Window(limiter) => limiter.check(ip, limiter.start.elapsed()),
+        }
+    }
+
+    /// Drop state for clients that have gone quiet; returns the number of
+    /// tracked clients before and after.
+    fn cleanup(&self) -> (usize, usize) {
+        match &self.inner {
+            Inner::Gcra(limiter) => {
+                let before = limiter.len();
+                limiter.retain_recent();
+                limiter.shrink_to_fit();
+                (before, limiter.len())
+            }
+            Inner::Window(limiter) => {
+                let before = limiter.counters.len();
+                limiter.retain_recent(limiter.start.elapsed());
+                (before, limiter.counters.len())
+            }
+        }
+    }
+}
+
+/// Build a per-IP rate limiter from config.
 pub fn build_limiter(cfg: &RateLimitConfig) -> Arc<KeyedLimiter> {
     let rps =
         NonZeroU32::new(cfg.requests_per_second).expect("requests_per_second validated > 0");
-    let burst = NonZeroU32::new(cfg.burst_size).expect("burst_size validated > 0");
 
-    let quota = Quota::per_second(rps).allow_burst(burst);
-    Arc::new(RateLimiter::dashmap(quota))
+    let inner = match cfg.algorithm {
+        RateLimitAlgorithm::Gcra => {
+            let burst = NonZeroU32::new(cfg.burst_size).expect("burst_size validated > 0");
+            let quota = Quota::per_second(rps).allow_burst(burst);
+            Inner::Gcra(RateLimiter::dashmap(quota).with_middleware::<StateInformationMiddleware>())
+        }
+        algorithm => Inner::Window(WindowLimiter {
+            limit: u32::try_from(u64::from(rps.get()) * cfg.window).unwrap_or(u32::MAX),
+            window: Duration::from_secs(cfg.window),
+            sliding: algorithm == RateLimitAlgorithm::SlidingWindow,
+            start: Instant::now(),
+            counters: DashMap::new(),
+        }),
+    };
+    Arc::new(KeyedLimiter { inner })
 }
 
 /// Spawn a background task that periodically cleans up expired entries.
@@ -30,10 +128,7 @@ pub fn spawn_cleanup(limiter: Arc<KeyedLimiter>, interval_secs: u64) {
         loop {
             tokio::time::sleep(interval).await;
 
-            let before = limiter.len();
-            limiter.retain_recent();
-            limiter.shrink_to_fit();
-            let after = limiter.len();
+            let (before, after) = limiter.cleanup();
 
             debug!(before, after, "rate limiter cleanup completed");
         }
@@ -41,3 +136,148 @@ pub fn spawn_cleanup(limiter: Arc<KeyedLimiter>, interval_secs: u64) {
 
     info!(interval_secs, "rate limiter cleanup task started");
 }
+
+// ---------------------------------------------------------------------------
+// Fixed and sliding windows
+// ---------------------------------------------------------------------------
+
+/// Counts requests per client in consecutive windows of `window`, aligned to
+/// `start`. The sliding variant also counts the previous window, weighted by
+/// how much of it still overlaps the last `window` of time.
+struct WindowLimiter {
+    limit: u32,
+    window: Duration,
+    sliding: bool,
+    start: Instant,
+    counters: DashMap<IpAddr, Counter>,
+}
+
+#[derive(Default)]
+struct Counter {
+    /// Index of the window `current` counts.
+    index: u64,
+    current: u32,
+    previous: u32,
+}
+
+impl WindowLimiter {
+    /// Decide at `elapsed` since `start`.
+    fn check(&self, ip: IpAddr, elapsed: Duration) -> Result<RateLimitStatus, RateLimitStatus> {
+        let window = self.window.as_nanos();
+        let index = (elapsed.as_nanos() / window) as u64;
+        let offset = elapsed.as_nanos() % window;
+        let into = offset as f64 / window as f64;
+        let left = Duration::from_nanos((window - offset) as u64);
+
+        let mut counter = self.counters.entry(ip).or_default();
+        if counter.index != index {
+            counter.previous = if counter.index + 1 == index {
+                counter.current
+            } else {
+                0
+            };
+            counter.current = 0;
+            counter.index = index;
+        }
+        let carried = if self.sliding {
+            f64::from(counter.previous) * (1.0 - into)
+        } else {
+            0.0
+        };
+        let used = carried + f64::from(counter.current);
+
+        if used + 1.0 > f64::from(self.limit) {
+            // Sliding: wait until enough of the previous window has decayed,
+            // or for the next window when this one alone is full.
+            let wait = if self.sliding && counter.current < self.limit {
+                let spare = f64::from(self.limit - counter.current - 1);
+                let needed = 1.0 - spare / f64::from(counter.previous) - into;
+                self.window.mul_f64(needed.max(0.0))
+            } else {
+                left
+            };
+            return Err(RateLimitStatus {
+                limit: self.limit,
+                remaining: 0,
+                reset: ceil_secs(wait).max(1),
+            });
+        }
+
+        counter.current += 1;
+        let remaining = (f64::from(self.limit) - used - 1.0).floor() as u32;
+        // A sliding count only clears once this window has also slid past.
+        let reset = if self.sliding {
+            left + self.window
+        } else {
+            left
+        };
+        Ok(RateLimitStatus {
+            limit: self.limit,
+            remaining,
+            reset: ceil_secs(reset),
+        })
+    }
+
+    /// Keep clients seen in this window or the one before.
+    fn retain_recent(&self, elapsed: Duration) {
+        let index = (elapsed.as_nanos() / self.window.as_nanos()) as u64;
+        self.counters.retain(|_, c| c.index + 1 >= index);
+        self.counters.shrink_to_fit();
+    }
+}
+
+fn ceil_secs(d: Duration) -> u64 {
+    d.as_secs() + u64::from(d.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(limit: u32, sliding: bool) -> WindowLimiter {
+        WindowLimiter {
+            limit,
+            window: Duration::from_secs(10),
+            sliding,
+            start: Instant::now(),
+            counters: DashMap::new(),
+        }
+    }
+
+    #[test]
+    fn fixed_window_resets_at_boundary() {
+        let limiter = window(2, false);
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let at = |s: f64| Duration::from_secs_f64(s);
+
+        let first = limiter.check(ip, at(1.0)).unwrap();
+        assert_eq!((first.limit, first.remaining, first.reset), (2, 1, 9));
+        assert_eq!(limiter.check(ip, at(2.0)).unwrap().remaining, 0);
+        let refused = limiter.check(ip, at(7.5)).unwrap_err();
+        assert_eq!(refused.reset, 3);
+        assert!(limiter.check("192.0.2.2".parse().unwrap(), at(7.5)).is_ok());
+
+        assert_eq!(limiter.check(ip, at(10.0)).unwrap().remaining, 1);
+
+        limiter.retain_recent(at(30.0));
+        assert!(limiter.counters.is_empty());
+    }
+
+    #[test]
+    fn sliding_window_weights_previous_window() {
+        let limiter = window(4, true);
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        let at = |s: f64| Duration::from_secs_f64(s);
+
+        for _ in 0..4 {
+            limiter.check(ip, at(9.0)).unwrap();
+        }
+        // A quarter into the next window, 3 of the previous 4 still count.
+        assert_eq!(limiter.check(ip, at(12.5)).unwrap().remaining, 0);
+        let refused = limiter.check(ip, at(12.5)).unwrap_err();
+        // 2 spare once the carried count decays to 2: at 15s.
+        assert_eq!(refused.reset, 3);
+        assert!(limiter.check(ip, at(15.0)).is_ok());
+        assert!(limiter.check(ip, at(15.0)).is_err());
+    }
+}
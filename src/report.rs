@@ -0,0 +1,75 @@
+use std::io::Write as _;
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::config::{Config, ServerConfig};
+use crate::server::{FileSearcher, LocationStatus};
+
+/// JSON document describing a freshly started instance, consumed by
+/// deployment tooling to verify what is actually running.
+#[derive(Debug, Serialize)]
+pub struct StartupReport<'a> {
+    pub version: &'static str,
+    pub pid: u32,
+    /// Unix seconds at which the report was generated.
+    pub started_at: u64,
+    pub listeners: Vec<SocketAddr>,
+    pub subsystems: Subsystems,
+    pub locations: Vec<LocationStatus>,
+    /// Resolved server settings (defaults filled in, secrets omitted).
+    pub server: &'a ServerConfig,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Subsystems {
+    pub cors: bool,
+    pub rate_limit: bool,
+    pub compression: bool,
+    pub admin: bool,
+    pub archive: bool,
+    pub batch: bool,
+    pub digest: bool,
+    pub health_check: bool,
+}
+
+impl<'a> StartupReport<'a> {
+    pub fn new(config: &'a Config, searcher: &FileSearcher, listeners: Vec<SocketAddr>) -> Self {
+        let server = &config.server;
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            pid: std::process::id(),
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            listeners,
+            subsystems: Subsystems {
+                cors: server.cors.enabled,
+                rate_limit: server.rate_limit.enabled,
+                compression: server.compression.enabled,
+                admin: server.admin.enabled,
+                archive: server.archive.enabled,
+                batch: server.batch.enabled,
+                digest: server.digest.enabled,
+                health_check: server.health_check.enabled,
+            },
+            locations: searcher.status(),
+            server,
+        }
+    }
+
+    /// Write the report to `dest`: `"-"` prints one line to stdout, anything
+    /// else is treated as a file path and overwritten.
+    pub fn write_to(&self, dest: &str) -> std::io::Result<()> {
+        let mut json = serde_json::to_vec(self).map_err(std::io::Error::other)?;
+        json.push(b'\n');
+        if dest == "-" {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(&json)?;
+            stdout.flush()
+        } else {
+            std::fs::write(dest, json)
+        }
+    }
+}
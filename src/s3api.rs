@@ -0,0 +1,465 @@
+use std::fmt::Write as _;
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use hyper::{Method, Response, StatusCode, Uri};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use tracing::debug;
+
+use crate::archive::{self, CollectError};
+use crate::conditional::Validators;
+use crate::config::S3ApiConfig;
+use crate::server::{FileSearcher, ResponseBody, empty_body, full_bytes, unix_secs};
+
+const XMLNS: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+
+/// Characters escaped in keys with `encoding-type=url` and in the location
+/// paths built from listing prefixes.
+const KEY: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'/');
+
+/// What [`route`] made of a request under the API prefix.
+pub(crate) enum S3Route {
+    /// Answered here: listings, bucket checks and errors.
+    Respond(Response<ResponseBody>),
+    /// `GetObject` / `HeadObject`: serve this location path as a regular
+    /// request, then pass the response through [`object_response`].
+    Object { path: Uri, resource: String },
+}
+
+/// The part of `path` below the API `prefix`, if it is mounted there.
+pub(crate) fn strip_mount<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let prefix = prefix.trim_end_matches('/');
+    let rest = path.strip_prefix(prefix)?;
+    (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+}
+
+/// The bucket name of a location: its prefix without slashes, inner ones
+/// turned into dashes; `root` for `/`.
+pub(crate) fn bucket_name(prefix: &str) -> String {
+    match prefix.trim_matches('/') {
+        "" => "root".into(),
+        name => name.replace('/', "-"),
+    }
+}
+
+/// Path-style S3 requests: `GET <prefix>/` lists buckets, `GET
+/// <prefix>/<bucket>?list-type=2` lists objects, `GET|HEAD
+/// <prefix>/<bucket>/<key>` reads one. Request signatures are not checked;
+/// locations with `auth` are not exposed, not even nested in a bucket.
+pub(crate) async fn route(
+    method: &Method,
+    uri: &Uri,
+    rest: &str,
+    searcher: &FileSearcher,
+    cfg: &S3ApiConfig,
+) -> S3Route {
+    let is_head = method == Method::HEAD;
+    if method != Method::GET && !is_head {
+        return S3Route::Respond(error(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "MethodNotAllowed",
+            rest,
+            is_head,
+        ));
+    }
+    let rest = rest.trim_start_matches('/');
+    if rest.is_empty() {
+        return S3Route::Respond(list_buckets(searcher, is_head));
+    }
+    let (bucket, key) = rest.split_once('/').unwrap_or((rest, ""));
+    let Some(prefix) = searcher
+        .s3_buckets()
+        .into_iter()
+        .find(|(name, _)| name == bucket)
+        .map(|(_, prefix)| prefix)
+    else {
+        debug!(status = 404, bucket, "s3 request handled");
+        let resource = format!("/{bucket}");
+        return S3Route::Respond(error(
+            StatusCode::NOT_FOUND,
+            "NoSuchBucket",
+            &resource,
+            is_head,
+        ));
+    };
+
+    if key.is_empty() {
+        // HeadBucket, or ListObjects(V2) for GET.
+        if is_head {
+            return S3Route::Respond(xml_response(StatusCode::OK, String::new(), true));
+        }
+        let query = ListQuery::parse(uri.query().unwrap_or(""));
+        return S3Route::Respond(list_objects(searcher, cfg, bucket, &prefix, query).await);
+    }
+
+    let path = format!("{}/{key}", prefix.trim_end_matches('/'));
+    if searcher.location_prefix(&path) != Some(prefix.as_str()) {
+        debug!(status = 403, bucket, key, "s3 request outside the bucket");
+        let resource = format!("/{bucket}/{key}");
+        return S3Route::Respond(error(
+            StatusCode::FORBIDDEN,
+            "AccessDenied",
+            &resource,
+            is_head,
+        ));
+    }
+    match path.parse() {
+        Ok(path) => S3Route::Object {
+            path,
+            resource: format!("/{bucket}/{key}"),
+        },
+        Err(_) => S3Route::Respond(error(StatusCode::BAD_REQUEST, "InvalidURI", rest, is_head)),
+    }
+}
+
+/// Turn a file response into what S3 clients expect: misses become
+/// `NoSuchKey` errors, everything else passes through.
+pub(crate) fn object_response(
+    resp: Response<ResponseBody>,
+    resource: &str,
+    is_head: bool,
+) -> Response<ResponseBody> {
+    match resp.status() {
+        StatusCode::NOT_FOUND => error(StatusCode::NOT_FOUND, "NoSuchKey", resource, is_head),
+        _ => resp,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Listings
+// ---------------------------------------------------------------------------
+
+fn list_buckets(searcher: &FileSearcher, is_head: bool) -> Response<ResponseBody> {
+    let mut xml = format!(
+        "<ListAllMyBucketsResult xmlns=\"{XMLNS}\"><Owner><ID>filehunter</ID>\
+         <DisplayName>filehunter</DisplayName></Owner><Buckets>"
+    );
+    for (name, _) in searcher.s3_buckets() {
+        let _ = write!(
+            xml,
+            "<Bucket><Name>{}</Name><CreationDate>{}</CreationDate></Bucket>",
+            escape(&name),
+            iso8601(SystemTime::UNIX_EPOCH),
+        );
+    }
+    xml.push_str("</Buckets></ListAllMyBucketsResult>");
+    debug!(status = 200, "s3 bucket list handled");
+    xml_response(StatusCode::OK, xml, is_head)
+}
+
+/// ListObjectsV2 parameters; V1 requests get the same listing.
+#[derive(Default)]
+struct ListQuery {
+    prefix: String,
+    delimiter: String,
+    max_keys: Option<usize>,
+    continuation_token: Option<String>,
+    start_after: Option<String>,
+    url_encoding: bool,
+}
+
+impl ListQuery {
+    fn parse(query: &str) -> Self {
+        let mut q = Self::default();
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_encoding::percent_decode_str(&value.replace('+', " "))
+                .decode_utf8_lossy()
+                .into_owned();
+            match key {
+                "prefix" => q.prefix = value,
+                "delimiter" => q.delimiter = value,
+                "max-keys" => q.max_keys = value.parse().ok(),
+                "continuation-token" => q.continuation_token = Some(value),
+                // V1 pagination.
+                "start-after" | "marker" => q.start_after = Some(value),
+                "encoding-type" => q.url_encoding = value == "url",
+                _ => {}
+            }
+        }
+        q
+    }
+
+    /// Keys to skip: a continuation token is the last key already listed.
+    fn after(&self) -> Option<&str> {
+        self.continuation_token
+            .as_deref()
+            .or(self.start_after.as_deref())
+    }
+
+    fn encode(&self, key: &str) -> String {
+        if self.url_encoding {
+            escape(&utf8_percent_encode(key, KEY).to_string())
+        } else {
+            escape(key)
+        }
+    }
+}
+
+/// One row of a listing, in key order.
+enum Listed {
+    Object {
+        key: String,
+        size: u64,
+        modified: SystemTime,
+    },
+    CommonPrefix(String),
+}
+
+impl Listed {
+    fn key(&self) -> &str {
+        match self {
+            Self::Object { key, .. } | Self::CommonPrefix(key) => key,
+        }
+    }
+}
+
+async fn list_objects(
+    searcher: &FileSearcher,
+    cfg: &S3ApiConfig,
+    bucket: &str,
+    location: &str,
+    query: ListQuery,
+) -> Response<ResponseBody> {
+    let resource = format!("/{bucket}");
+    // Walk the deepest directory the prefix names.
+    let dir = match query.prefix.rfind('/') {
+        Some(i) => &query.prefix[..=i],
+        None => "",
+    };
+    let dir_path = format!(
+        "{}/{}",
+        location.trim_end_matches('/'),
+        utf8_percent_encode(dir, KEY)
+    );
+    // A nested location, which may have `auth`, is not part of the bucket.
+    if searcher.location_prefix(&dir_path) != Some(location) {
+        debug!(
+            status = 403,
+            bucket,
+            prefix = query.prefix,
+            "s3 listing outside the bucket"
+        );
+        return error(StatusCode::FORBIDDEN, "AccessDenied", &resource, false);
+    }
+    let Some(plan) = searcher.archive_plan(&dir_path) else {
+        return error(StatusCode::BAD_REQUEST, "InvalidArgument", &resource, false);
+    };
+    let max_scan = cfg.max_scan;
+    let walked = tokio::task::spawn_blocking(move || archive::collect(&plan, max_scan)).await;
+    let entries = match walked {
+        Ok(Ok(entries)) => entries,
+        // A missing directory just lists nothing.
+        Ok(Err(CollectError::NotFound)) => Default::default(),
+        Ok(Err(CollectError::TooMany)) => {
+            debug!(
+                status = 400,
+                bucket,
+                prefix = query.prefix,
+                max_scan,
+                "s3 listing too broad"
+            );
+            return error(StatusCode::BAD_REQUEST, "InvalidArgument", &resource, false);
+        }
+        Err(_) => {
+            return error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "InternalError",
+                &resource,
+                false,
+            );
+        }
+    };
+
+    let max_keys = query.max_keys.unwrap_or(1000).min(1000);
+    let mut listed: Vec<Listed> = Vec::new();
+    let mut truncated = false;
+    for (relative, entry) in entries {
+        let key = format!("{dir}{relative}");
+        let Some(rest) = key.strip_prefix(query.prefix.as_str()) else {
+            continue;
+        };
+        let row = match rest.find(query.delimiter.as_str()) {
+            Some(i) if !query.delimiter.is_empty() => {
+                let common = key[..query.prefix.len() + i + query.delimiter.len()].to_owned();
+                if listed.last().is_some_and(|last| last.key() == common) {
+                    continue;
+                }
+                Listed::CommonPrefix(common)
+            }
+            _ => Listed::Object {
+                key,
+                size: entry.size,
+                modified: entry.modified,
+            },
+        };
+        if query.after().is_some_and(|after| row.key() <= after) {
+            continue;
+        }
+        if listed.len() == max_keys {
+            truncated = true;
+            break;
+        }
+        listed.push(row);
+    }
+
+    let mut xml = format!("<ListBucketResult xmlns=\"{XMLNS}\">");
+    let _ = write!(
+        xml,
+        "<Name>{}</Name><Prefix>{}</Prefix><KeyCount>{}</KeyCount><MaxKeys>{max_keys}</MaxKeys>\
+         <IsTruncated>{truncated}</IsTruncated>",
+        escape(bucket),
+        query.encode(&query.prefix),
+        listed.len(),
+    );
+    if !query.delimiter.is_empty() {
+        let _ = write!(
+            xml,
+            "<Delimiter>{}</Delimiter>",
+            query.encode(&query.delimiter)
+        );
+    }
+    if query.url_encoding {
+        xml.push_str("<EncodingType>url</EncodingType>");
+    }
+    if let Some(token) = &query.continuation_token {
+        let _ = write!(
+            xml,
+            "<ContinuationToken>{}</ContinuationToken>",
+            escape(token)
+        );
+    }
+    if truncated && let Some(last) = listed.last() {
+        let _ = write!(
+            xml,
+            "<NextContinuationToken>{}</NextContinuationToken>",
+            escape(last.key())
+        );
+    }
+    for row in &listed {
+        match row {
+            Listed::Object {
+                key,
+                size,
+                modified,
+            } => {
+                let _ = write!(
+                    xml,
+                    "<Contents><Key>{}</Key><LastModified>{}</LastModified>\
+                     <ETag>{}</ETag><Size>{size}</Size>\
+                     <StorageClass>STANDARD</StorageClass></Contents>",
+                    query.encode(key),
+                    iso8601(*modified),
+                    // The tag file responses carry in the default `etag` mode.
+                    escape(Validators::new(*size, *modified).etag().unwrap_or_default()),
+                );
+            }
+            Listed::CommonPrefix(prefix) => {
+                let _ = write!(
+                    xml,
+                    "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+                    query.encode(prefix)
+                );
+            }
+        }
+    }
+    xml.push_str("</ListBucketResult>");
+    debug!(
+        status = 200,
+        bucket,
+        prefix = query.prefix,
+        keys = listed.len(),
+        truncated,
+        "s3 listing handled"
+    );
+    xml_response(StatusCode::OK, xml, false)
+}
+
+// ---------------------------------------------------------------------------
+// XML helpers
+// ---------------------------------------------------------------------------
+
+fn error(status: StatusCode, code: &str, resource: &str, is_head: bool) -> Response<ResponseBody> {
+    let message = status.canonical_reason().unwrap_or_default();
+    let xml = format!(
+        "<Error><Code>{code}</Code><Message>{message}</Message><Resource>{}</Resource></Error>",
+        escape(resource)
+    );
+    xml_response(status, xml, is_head)
+}
+
+fn xml_response(status: StatusCode, xml: String, is_head: bool) -> Response<ResponseBody> {
+    let body = if is_head || xml.is_empty() {
+        empty_body()
+    } else {
+        let xml = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{xml}");
+        full_bytes(Bytes::from(xml))
+    };
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/xml")
+        .header("X-Content-Type-Options", "nosniff")
+        .body(body)
+        .unwrap()
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// `YYYY-MM-DDTHH:MM:SS.000Z` in UTC.
+fn iso8601(t: SystemTime) -> String {
+    let secs = unix_secs(t);
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+
+    // Civil-from-days (proleptic Gregorian calendar).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.000Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn names_buckets_and_formats_times() {
+        assert_eq!(bucket_name("/"), "root");
+        assert_eq!(bucket_name("/imgs/v2"), "imgs-v2");
+        assert_eq!(strip_mount("/_s3/imgs/a.jpg", "/_s3"), Some("/imgs/a.jpg"));
+        assert_eq!(strip_mount("/_s3x/imgs", "/_s3"), None);
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1_369_353_600);
+        assert_eq!(iso8601(t), "2013-05-24T00:00:00.000Z");
+        assert_eq!(escape("a&b<c>"), "a&amp;b&lt;c&gt;");
+    }
+}
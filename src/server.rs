@@ -1,54 +1,366 @@
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::convert::Infallible;
 use std::ffi::OsStr;
 use std::net::IpAddr;
 use std::path::{Component, Path, PathBuf};
-use std::sync::Arc;
-use std::time::SystemTime;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 
-use bytes::Bytes;
+use bytes::{Buf, Bytes};
 use futures_util::TryStreamExt;
 use http_body_util::combinators::BoxBody;
 use http_body_util::{BodyExt, Empty, Full, StreamBody};
 use hyper::body::Frame;
-use hyper::{Method, Request, Response, StatusCode};
-use tokio::fs::File;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{HeaderMap, Method, Request, Response, StatusCode};
+use ipnet::IpNet;
+use regex_automata::meta::Regex;
+use serde::Serialize;
 use tokio_util::io::ReaderStream;
-use tracing::{debug, info, warn};
+use tokio_util::task::AbortOnDropHandle;
+use tracing::{Instrument, Span, debug, info, warn};
 
-use governor::clock::Clock;
-
-use crate::config::{normalize_prefix, Config, LocationConfig, SearchMode};
+use crate::adaptive::{self, Ranking};
+use crate::admin;
+use crate::antivirus::Antivirus;
+use crate::auth;
+#[cfg(feature = "archive")]
+use crate::archive::{self, ArchiveFormat, ArchivePlan};
+use crate::audit::{AuditEvent, AuditLog};
+use crate::backend::{Connector, FoundObject, ObjectBody, ProbeError, StorageBackend};
+use crate::bandwidth::{self, TokenBucket};
+use crate::batch;
+use crate::breaker::{self, CircuitBreaker, CircuitState};
+use crate::conditional::{self, Precondition, Validators};
+use crate::connections::ConnectionRegistry;
+use crate::denylist::{Denylist, parse_net};
+use crate::disposition;
+use crate::fallback::{self, Fallback};
+#[cfg(feature = "s3-api")]
+use crate::config::S3ApiConfig;
+use crate::config::{
+    normalize_prefix, ByteSize, CacheControlConfig, CircuitBreakerConfig, Config, Disposition, ErrorFormat, EtagMode, ExtensionRule, HiddenFiles, MagicBytes, ImageConfig, LocationAuth, LocationConfig, LogLevel, SearchMode,
+    RangesConfig, RedirectRule, SearchPath, SecurityHeadersConfig, ServerConfig, SymlinkPolicy, TrailingSlash,
+};
+use crate::config::VhostConfig;
+#[cfg(feature = "digest")]
+use crate::digest::DigestCache;
+use crate::health::RootHealth;
+#[cfg(feature = "images")]
+use crate::images::{ImageParams, ImageProcessor};
+use crate::magic;
+use crate::meta;
+use crate::metrics::{self, SearchLatency};
+use crate::misses::MissLog;
+use crate::precompress::{self, Encoding};
+use crate::problem::{ErrorMessage, ErrorPages, Rendering};
+use crate::quarantine::Quarantine;
+use crate::range::{self, RangeLimits, RangeRequest};
 use crate::ratelimit::KeyedLimiter;
+#[cfg(feature = "s3-api")]
+use crate::s3api::{self, S3Route};
+use crate::shadow::Shadow;
+use crate::stats::{self, LocationStats, LocationStatsInfo, RootStatsInfo, Transfer};
+use crate::strategy::{self, SearchStrategy};
+use crate::upload;
+use crate::vhost::{self, Vhosts};
+use crate::warmup::PathList;
+#[cfg(feature = "wasm")]
+use crate::wasm::{Decision, WasmFilter};
+#[cfg(feature = "webhooks")]
+use crate::webhooks::{ServedEvent, Webhooks};
 
 pub type ResponseBody = BoxBody<Bytes, std::io::Error>;
 
-type SearchResult = (PathBuf, File, u64, SystemTime);
+#[cfg(feature = "wasm")]
+tokio::task_local! {
+    /// The root the WASM request filter picked for the request being
+    /// handled; searches skip every other root.
+    static PINNED_ROOT: Option<PathBuf>;
+}
+
+tokio::task_local! {
+    /// Where searches record their probes for the request being handled,
+    /// when it asked for debug headers.
+    static PROBE_TRACE: Arc<ProbeTrace>;
+}
 
-struct SearchRoot {
+pub(crate) struct SearchRoot {
     path: PathBuf,
     /// `None` = allow all file types; `Some(set)` = only listed extensions.
     extensions: Option<HashSet<String>>,
+    /// Updated by the background health checker; unhealthy roots are skipped.
+    health: Arc<RootHealth>,
+    /// Requests this root satisfied, for `/_admin/stats`.
+    hits: AtomicU64,
+    /// Probe record ordering roots in `SearchMode::Adaptive`.
+    ranking: Ranking,
+    /// `Some` when `circuit_breaker` is enabled.
+    breaker: Option<CircuitBreaker>,
+    soft_timeout: SoftTimeout,
+    backend: Arc<dyn StorageBackend>,
+}
+
+/// A root's `soft_timeout_ms` and what came of the probes that ran past it.
+#[derive(Default)]
+struct SoftTimeout {
+    limit: Option<Duration>,
+    finish_late: bool,
+    expired: AtomicU64,
+    late_hits: AtomicU64,
+}
+
+impl SoftTimeout {
+    fn new(entry: &SearchPath) -> Self {
+        let ms = entry.soft_timeout_ms;
+        Self {
+            limit: (ms > 0).then(|| Duration::from_millis(ms)),
+            finish_late: entry.finish_late,
+            ..Default::default()
+        }
+    }
 }
 
 impl SearchRoot {
-    fn accepts(&self, ext: &str) -> bool {
+    pub(crate) fn accepts(&self, ext: &str) -> bool {
         match &self.extensions {
             None => true,
             Some(set) => set.contains(&ext.to_ascii_lowercase()),
         }
     }
+
+    pub(crate) fn local_dir(&self) -> Option<&Path> {
+        self.backend.local_dir()
+    }
+
+    /// Whether searches use this root: it passes health checks and its
+    /// circuit is not open.
+    fn is_available(&self) -> bool {
+        let open = self.breaker.as_ref().map(CircuitBreaker::state) == Some(CircuitState::Open);
+        self.health.is_healthy() && !open
+    }
+
+    /// Probe the backend through the root's circuit breaker: a failure, or
+    /// a probe slower than `probe_timeout_ms`, counts against the root and
+    /// reads as a miss. `Err` means path traversal.
+    async fn probe(
+        &self,
+        relative: &Path,
+        max_file_size: u64,
+        request_path: &str,
+    ) -> Result<Option<FoundObject>, ()> {
+        let breaker = self.breaker.as_ref();
+        if breaker.is_some_and(|b| !b.allow()) {
+            debug!(request_path, root = %self.path.display(), "skipped (circuit open)");
+            ProbeTrace::skipped(&self.path, ProbeOutcome::CircuitOpen);
+            return Ok(None);
+        }
+        let started = Instant::now();
+        let probe = self.backend.probe(relative, request_path);
+        let result = match breaker.and_then(CircuitBreaker::probe_timeout) {
+            Some(limit) => match tokio::time::timeout(limit, probe).await {
+                Ok(result) => result,
+                Err(_) => {
+                    let error = format!("timed out after {}ms", limit.as_millis());
+                    warn!(request_path, root = %self.path.display(), error, "probe failed");
+                    Err(ProbeError::Failed(error))
+                }
+            },
+            None => probe.await,
+        };
+        if let Some(breaker) = breaker {
+            breaker.record(matches!(result, Err(ProbeError::Failed(_))), &self.path);
+        }
+        if let Ok(trace) = PROBE_TRACE.try_with(Arc::clone) {
+            let probe = RootProbe::probed(&self.path, &result, max_file_size, started.elapsed());
+            trace.probes.lock().unwrap().push(probe);
+        }
+        match result {
+            Ok(found) => {
+                Ok(found.and_then(|found| within_limit(found, max_file_size, request_path)))
+            }
+            Err(ProbeError::Traversal) => Err(()),
+            Err(ProbeError::Failed(_)) => Ok(None),
+        }
+    }
+}
+
+/// When `SearchMode::NewestWithin` serves a newer copy over the first one.
+#[derive(Debug, Clone, Copy, Default)]
+struct NewestWindow {
+    /// The newer copy's mtime is at most this far from now.
+    recent: Option<Duration>,
+    /// The newer copy leads the first one by more than this.
+    lead: Option<Duration>,
+}
+
+impl NewestWindow {
+    fn prefers(&self, newest: SystemTime, first: SystemTime) -> bool {
+        let recent = self.recent.is_some_and(|window| {
+            let now = SystemTime::now();
+            // An mtime ahead of the clock counts by how far ahead it is.
+            let age = now.duration_since(newest).unwrap_or_else(|e| e.duration());
+            age <= window
+        });
+        let lead = self
+            .lead
+            .is_some_and(|lead| newest.duration_since(first).is_ok_and(|d| d > lead));
+        newest > first && (recent || lead)
+    }
+}
+
+/// A configured root that could not be opened, retried by
+/// [`FileSearcher::retry_skipped_roots`].
+struct SkippedRoot {
+    entry: SearchPath,
+    /// Why the latest attempt failed.
+    error: String,
 }
 
 struct Location {
     prefix: String,
-    roots: Vec<SearchRoot>,
+    /// 0 for top-level locations, `n` for those of the `n`th vhost.
+    site: usize,
+    /// How reports name the location: the prefix, after the vhost's first
+    /// host name.
+    label: String,
+    /// Replaced wholesale by the admin API; searches work on a cloned snapshot.
+    roots: RwLock<Vec<Arc<SearchRoot>>>,
+    /// Configured roots that could not be resolved yet.
+    skipped: Mutex<Vec<SkippedRoot>>,
     search_mode: SearchMode,
+    /// `Some` in `SearchMode::Plugin`, when the strategy is registered.
+    strategy: Option<Arc<dyn SearchStrategy>>,
+    /// How long `SearchMode::Hedged` waits for the first root.
+    hedge_delay: Duration,
+    newest_window: NewestWindow,
     max_file_size: u64,
+    /// Fall back to members of `.zip` / `.tar` containers on a miss.
+    #[cfg_attr(not(feature = "archive"), allow(dead_code))]
+    search_archives: bool,
+    /// `Some` when `?w=&h=` resizing is enabled for this location.
+    #[cfg(feature = "images")]
+    images: Option<Arc<ImageProcessor>>,
+    /// `None` when the location serves no byte ranges.
+    ranges: Option<RangeLimits>,
+    /// Dot-prefixed path segments this location serves.
+    hidden_files: HiddenFiles,
+    /// Symlinks followed in this location's local roots.
+    symlinks: SymlinkPolicy,
+    /// `Some(status)` when `/dir` and `/dir/` redirect to the canonical form.
+    trailing_slash: Option<StatusCode>,
+    /// How the `ETag` of served files is derived.
+    etag: EtagMode,
+    /// Static redirects, first match wins.
+    redirects: Vec<RedirectRule>,
+    /// `Some` when misses are fetched from `fallback_upstream`.
+    fallback: Option<Fallback>,
+    /// Sanitized paths matching this are never served.
+    deny_patterns: Option<Regex>,
+    /// Server-wide request path limits, re-checked by `sanitize`.
+    path_limits: PathLimits,
+    /// Lowercase extensions forced to `Content-Disposition: attachment`.
+    attachment_extensions: HashSet<String>,
+    /// `extension_rules`, keyed by lowercase extension.
+    extension_rules: HashMap<String, ExtensionRule>,
+    magic_bytes: MagicBytes,
+    /// Queue a webhook event for each file served.
+    #[cfg_attr(not(feature = "webhooks"), allow(dead_code))]
+    served_events: bool,
+    /// `Cache-Control` of the files this location serves.
+    cache_control: CacheControl,
+    /// `Some` when the location sets `log_level` or `log_sample`.
+    log: Option<LogOverride>,
+    /// Circuit breaker settings for the roots, when enabled.
+    breaker: Option<CircuitBreakerConfig>,
+    /// Credentials required before searching this location.
+    auth: Option<auth::Guard>,
+    /// `Some` when `egress_limit` caps this location's response bytes.
+    egress: Option<Arc<TokenBucket>>,
+    /// `Some` when the location replaces the server's security headers
+    /// (empty when it turns them off).
+    security_headers: Option<Arc<HeaderMap>>,
+    /// How this location's error responses are rendered.
+    error_pages: ErrorPages,
+    /// Request, lookup and byte counters for `/_admin/stats`.
+    stats: Arc<LocationStats>,
+    /// Search latency histograms for `/_admin/metrics`.
+    search_latency: SearchLatency,
+    /// `Some` when the location accepts `PUT` uploads.
+    upload: Option<UploadRoot>,
+    /// Accepted methods, `GET` and `HEAD` first.
+    methods: Vec<Method>,
+}
+
+/// Methods accepted outside any location.
+const READ_METHODS: &[Method] = &[Method::GET, Method::HEAD];
+
+/// `Cache-Control` rules of one location.
+#[derive(Default)]
+struct CacheControl {
+    /// Fingerprinted paths, relative to the location, cached for a year.
+    fingerprint: Option<Regex>,
+    /// `max-age` of other files; 0 sends no header.
+    max_age: u64,
+}
+
+impl CacheControl {
+    /// The `Cache-Control` value for `relative`, if any. `max_age`
+    /// overrides the location's for files that are not fingerprinted.
+    fn value(&self, relative: &str, max_age: Option<u64>) -> Option<String> {
+        if let Some(re) = &self.fingerprint
+            && re.is_match(relative)
+        {
+            return Some("public, max-age=31536000, immutable".into());
+        }
+        let max_age = max_age.unwrap_or(self.max_age);
+        (max_age > 0).then(|| format!("public, max-age={max_age}"))
+    }
+}
+
+/// A location's log overrides, handed to the log filter through a
+/// `location` span around each of its requests.
+struct LogOverride {
+    level: Option<LogLevel>,
+    /// Every `sample`th request keeps its debug and trace events.
+    sample: u64,
+    requests: AtomicU64,
+}
+
+/// Where a writable location stores uploads.
+struct UploadRoot {
+    /// The configured `upload_root`, canonicalized on each upload.
+    path: PathBuf,
+    /// `None` = any file type may be uploaded.
+    extensions: Option<HashSet<String>>,
+    /// Other roots each upload is copied to.
+    replicas: usize,
+}
+
+/// Where one uploaded file is written.
+pub(crate) struct UploadTarget<'a> {
+    /// The location's configured `upload_root`.
+    pub root: &'a Path,
+    /// Sanitized path under each root.
+    pub relative: PathBuf,
+    /// Copies wanted besides the one in `root`.
+    pub replicas: usize,
+    /// Healthy local roots accepting the file, in config order, for the
+    /// copies; may include `root` itself.
+    pub candidates: Vec<PathBuf>,
 }
 
 impl Location {
-    fn from_config(loc: &LocationConfig, server_max_file_size: u64) -> Self {
+    fn from_config(
+        loc: &LocationConfig,
+        server_max_file_size: u64,
+        path_limits: PathLimits,
+        breaker: Option<CircuitBreakerConfig>,
+        error_pages: &ErrorPages,
+        connector: &Connector,
+    ) -> Self {
         let prefix = normalize_prefix(&loc.prefix);
 
         let max_file_size = loc
@@ -56,15 +368,15 @@ impl Location {
             .map(|bs| bs.as_u64())
             .unwrap_or(server_max_file_size);
 
-        let roots: Vec<SearchRoot> = loc
-            .paths
-            .iter()
-            .filter_map(|entry| match entry.root.canonicalize() {
-                Ok(canonical) if canonical.is_dir() => {
+        let mut roots = Vec::new();
+        let mut skipped = Vec::new();
+        for entry in &loc.paths {
+            match connector.open(entry, loc.symlinks) {
+                Ok((path, backend)) => {
                     let ext_set = entry.extension_set();
                     info!(
                         prefix = %prefix,
-                        path = %canonical.display(),
+                        path = %path.display(),
                         extensions = %ext_set.as_ref().map_or("*".into(), |s| {
                             let mut v: Vec<_> = s.iter().map(String::as_str).collect();
                             v.sort_unstable();
@@ -72,22 +384,47 @@ impl Location {
                         }),
                         "search path registered"
                     );
-                    Some(SearchRoot { path: canonical, extensions: ext_set })
-                }
-                Ok(_) => {
-                    warn!(path = %entry.root.display(), "not a directory, skipping");
-                    None
+                    roots.push(Arc::new(SearchRoot {
+                        path,
+                        extensions: ext_set,
+                        health: Arc::default(),
+                        hits: AtomicU64::default(),
+                        ranking: Ranking::default(),
+                        breaker: breaker.map(CircuitBreaker::new),
+                        soft_timeout: SoftTimeout::new(entry),
+                        backend,
+                    }));
                 }
                 Err(e) => {
-                    warn!(path = %entry.root.display(), error = %e, "cannot resolve path, skipping");
-                    None
+                    warn!(path = %entry.root.display(), error = %e, "cannot use search path, skipping");
+                    skipped.push(SkippedRoot {
+                        entry: entry.clone(),
+                        error: e,
+                    });
                 }
-            })
-            .collect();
+            }
+        }
 
         if roots.is_empty() {
             warn!(prefix = %prefix, "no valid search paths for location");
         }
+        #[cfg(not(feature = "images"))]
+        if loc.images.enabled {
+            warn!(prefix = %prefix, "images.enabled is set but this build lacks the `images` feature; ignoring");
+        }
+        #[cfg(not(feature = "archive"))]
+        if loc.search_archives {
+            warn!(prefix = %prefix, "search_archives is set but this build lacks the `archive` feature; ignoring");
+        }
+
+        let fallback = fallback(loc, &prefix, connector);
+        let strategy = match loc.mode {
+            SearchMode::Plugin => strategy::get(&loc.search_plugin),
+            _ => None,
+        };
+        if loc.mode == SearchMode::Plugin && strategy.is_none() {
+            warn!(prefix = %prefix, plugin = loc.search_plugin, "search plugin not registered; every request will miss");
+        }
 
         info!(
             prefix = %prefix, mode = ?loc.mode, roots = roots.len(),
@@ -96,33 +433,352 @@ impl Location {
         );
 
         Self {
+            label: prefix.clone(),
             prefix,
-            roots,
+            site: 0,
+            roots: RwLock::new(roots),
+            skipped: Mutex::new(skipped),
             search_mode: loc.mode,
+            strategy,
+            hedge_delay: Duration::from_millis(loc.hedge_delay_ms.unwrap_or(20)),
+            newest_window: NewestWindow {
+                recent: loc.newest_window_secs.map(Duration::from_secs),
+                lead: loc.newest_lead_secs.map(Duration::from_secs),
+            },
+            search_archives: loc.search_archives,
+            #[cfg(feature = "images")]
+            images: loc.images.enabled.then(|| Arc::new(ImageProcessor::new(&loc.images))),
+            ranges: loc.ranges.enabled.then(|| RangeLimits::from(&loc.ranges)),
+            hidden_files: loc.hidden_files.clone(),
+            symlinks: loc.symlinks,
+            trailing_slash: (loc.trailing_slash == TrailingSlash::Redirect).then_some(
+                match loc.trailing_slash_status {
+                    Some(308) => StatusCode::PERMANENT_REDIRECT,
+                    _ => StatusCode::MOVED_PERMANENTLY,
+                },
+            ),
+            etag: loc.etag,
+            redirects: loc.redirects.clone(),
+            fallback,
+            deny_patterns: loc.deny_regex().unwrap_or_default(),
+            path_limits,
+            attachment_extensions: loc.attachment_extension_set(),
+            extension_rules: loc.extension_rule_map(),
+            magic_bytes: loc.magic_bytes,
+            served_events: loc.served_events,
+            cache_control: CacheControl {
+                fingerprint: loc.cache_control.fingerprint_regex().unwrap_or_default(),
+                max_age: loc.cache_control.max_age_secs,
+            },
+            log: (loc.log_level.is_some() || loc.log_sample > 1).then(|| LogOverride {
+                level: loc.log_level,
+                sample: u64::from(loc.log_sample.max(1)),
+                requests: AtomicU64::new(0),
+            }),
+            breaker,
+            auth: loc.auth.as_ref().map(|a| auth::Guard::new(a, connector)),
+            egress: loc
+                .egress_limit
+                .filter(|limit| limit.as_u64() > 0)
+                .map(|limit| Arc::new(TokenBucket::new(limit.as_u64()))),
+            security_headers: loc
+                .security_headers
+                .as_ref()
+                .map(|security| Arc::new(security.header_map().unwrap_or_default())),
+            error_pages: error_pages.for_location(loc),
+            stats: Arc::default(),
+            search_latency: SearchLatency::default(),
+            upload: loc.writable.then(|| UploadRoot {
+                path: loc.upload_root.clone(),
+                extensions: loc
+                    .paths
+                    .iter()
+                    .find(|sp| sp.root == loc.upload_root)
+                    .and_then(SearchPath::extension_set),
+                replicas: loc.upload_replicas,
+            }),
+            methods: location_methods(loc),
             max_file_size,
         }
     }
 
+    /// Place the location in `site`, whose first host name is `host`.
+    fn in_site(mut self, site: usize, host: Option<&str>) -> Self {
+        self.site = site;
+        if let Some(host) = host {
+            self.label = format!("{host}{}", self.prefix);
+        }
+        self
+    }
+
+    fn healthy_root_count(&self) -> usize {
+        let roots = self.roots.read().unwrap();
+        roots.iter().filter(|r| r.is_available()).count()
+    }
+
+    /// Roots currently considered healthy, in config order.
+    fn active_roots(&self) -> Vec<Arc<SearchRoot>> {
+        let roots = self.roots.read().unwrap();
+        let available = roots.iter().filter(|r| r.is_available());
+        #[cfg(feature = "wasm")]
+        if let Ok(Some(pinned)) = PINNED_ROOT.try_with(Option::clone) {
+            return available.filter(|r| r.path == pinned).cloned().collect();
+        }
+        available.cloned().collect()
+    }
+
+    /// Attach a new root at runtime. Fails if the root cannot be opened or
+    /// is already part of this location.
+    fn attach_root(&self, entry: &SearchPath, connector: &Connector) -> Result<PathBuf, String> {
+        let (path, backend) = connector
+            .open(entry, self.symlinks)
+            .map_err(|e| format!("cannot use {}: {e}", entry.root.display()))?;
+        self.push_root(path.clone(), entry, backend)?;
+        Ok(path)
+    }
+
+    /// Append an opened root unless one with the same identity exists.
+    fn push_root(
+        &self,
+        path: PathBuf,
+        entry: &SearchPath,
+        backend: Arc<dyn StorageBackend>,
+    ) -> Result<(), String> {
+        let mut roots = self.roots.write().unwrap();
+        if roots.iter().any(|r| r.path == path) {
+            return Err(format!("{} is already a root of {}", path.display(), self.prefix));
+        }
+        roots.push(Arc::new(SearchRoot {
+            path,
+            extensions: entry.extension_set(),
+            health: Arc::default(),
+            hits: AtomicU64::default(),
+            ranking: Ranking::default(),
+            breaker: self.breaker.map(CircuitBreaker::new),
+            soft_timeout: SoftTimeout::new(entry),
+            backend,
+        }));
+        Ok(())
+    }
+
+    /// Try to open the skipped roots again, appending the ones that open to
+    /// the active roots. Returns how many are still skipped. Blocks on
+    /// filesystem calls for local roots.
+    fn retry_skipped(&self, connector: &Connector) -> usize {
+        let mut skipped = self.skipped.lock().unwrap();
+        skipped.retain_mut(|s| {
+            let (path, backend) = match connector.open(&s.entry, self.symlinks) {
+                Ok(opened) => opened,
+                Err(e) => {
+                    debug!(path = %s.entry.root.display(), error = %e, "search path still unavailable");
+                    s.error = e;
+                    return true;
+                }
+            };
+            match self.push_root(path.clone(), &s.entry, backend) {
+                Ok(()) => info!(prefix = %self.prefix, path = %path.display(), "search path available, registered"),
+                // Attached through the admin API in the meantime.
+                Err(e) => debug!(error = %e, "skipped search path already attached"),
+            }
+            false
+        });
+        skipped.len()
+    }
+
+    /// Detach a root at runtime, matching either its canonical or given path.
+    fn detach_root(&self, root: &Path) -> Result<PathBuf, String> {
+        let canonical = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+        let mut roots = self.roots.write().unwrap();
+        let idx = roots
+            .iter()
+            .position(|r| r.path == canonical || r.path == root)
+            .ok_or_else(|| format!("{} is not a root of {}", root.display(), self.prefix))?;
+        Ok(roots.remove(idx).path.clone())
+    }
+
+    /// The `extension_rules` entry for `ext`.
+    fn extension_rule(&self, ext: &str) -> Option<&ExtensionRule> {
+        if self.extension_rules.is_empty() {
+            return None;
+        }
+        self.extension_rules.get(&ext.to_ascii_lowercase())
+    }
+
+    /// `max_file_size` for files with extension `ext`.
+    fn max_size(&self, ext: &str) -> u64 {
+        let rule = self.extension_rule(ext).and_then(|rule| rule.max_size);
+        rule.map_or(self.max_file_size, ByteSize::as_u64)
+    }
+
+    /// Refuses what [`sanitize_path`] does and paths matching `deny_patterns`.
+    fn sanitize(&self, request_path: &str) -> Result<PathBuf, Refusal> {
+        let relative = sanitize_path(request_path, &self.hidden_files, self.path_limits)?;
+        if let Some(re) = &self.deny_patterns
+            && re.is_match(relative.to_string_lossy().as_ref())
+        {
+            debug!(request_path, "path matches deny_patterns");
+            return Err(Refusal::Blocked(AuditEvent::DeniedPattern));
+        }
+        Ok(relative)
+    }
+
     /// Search across this location's roots using its configured search mode.
-    async fn search(&self, request_path: &str) -> Option<(PathBuf, File, u64)> {
-        match self.search_mode {
+    async fn search(&self, request_path: &str) -> Option<SearchHit> {
+        let started = Instant::now();
+        let roots = self.healthy_root_count();
+        let hit = match self.search_mode {
             SearchMode::Sequential => self.search_sequential(request_path).await,
             SearchMode::Concurrent => self.search_concurrent(request_path).await,
             SearchMode::LatestModified => self.search_latest(request_path).await,
+            SearchMode::NewestWithin => self.search_newest_within(request_path).await,
+            SearchMode::Adaptive => self.search_adaptive(request_path).await,
+            SearchMode::Hedged => self.search_hedged(request_path).await,
+            SearchMode::Plugin => self.search_plugin(request_path).await,
+        };
+        #[cfg(feature = "archive")]
+        let hit = match hit {
+            None if self.search_archives => self.search_containers(request_path).await,
+            hit => hit,
+        };
+        if let Some(hit) = &hit {
+            let roots = self.roots.read().unwrap();
+            if let Some(root) = roots.iter().find(|r| r.path == hit.root) {
+                root.hits.fetch_add(1, Ordering::Relaxed);
+                if let Ok(trace) = PROBE_TRACE.try_with(Arc::clone) {
+                    let remote = root.local_dir().is_none();
+                    let winner = (hit.root.clone(), hit.path.clone(), remote);
+                    *trace.winner.lock().unwrap() = Some(winner);
+                }
+            }
+        }
+        self.search_latency
+            .record(roots, hit.is_some(), started.elapsed());
+        hit
+    }
+
+    /// After a regular miss, look inside `.zip` / `.tar` containers of the
+    /// local roots, in config order.
+    #[cfg(feature = "archive")]
+    async fn search_containers(&self, request_path: &str) -> Option<SearchHit> {
+        let relative = self.sanitize(request_path).ok()?;
+
+        let ext = relative
+            .extension()
+            .and_then(OsStr::to_str)
+            .unwrap_or("");
+
+        for root in self.active_roots() {
+            let Some(dir) = root.local_dir() else {
+                continue;
+            };
+            if !root.accepts(ext) {
+                continue;
+            }
+            let found = archive::find_member(
+                dir,
+                &relative,
+                self.max_size(ext),
+                self.symlinks,
+                request_path,
+            )
+            .await;
+            if let Some(found) = found {
+                debug!(request_path, resolved = %found.path.display(), "found inside container");
+                return Some(SearchHit::new(root.path.clone(), found));
+            }
         }
+        None
     }
 
-    async fn search_sequential(&self, request_path: &str) -> Option<(PathBuf, File, u64)> {
-        let relative = sanitize_path(request_path)?;
+    async fn search_sequential(&self, request_path: &str) -> Option<SearchHit> {
+        let relative = self.sanitize(request_path).ok()?;
 
         let ext = relative
             .extension()
             .and_then(OsStr::to_str)
             .unwrap_or("");
 
-        for root in &self.roots {
-            match try_root(root, &relative, ext, self.max_file_size, request_path).await {
-                Ok(Some((path, file, size, _mtime))) => return Some((path, file, size)),
+        for root in self.active_roots() {
+            match self.try_within(&root, &relative, ext, request_path).await {
+                Ok(Some(found)) => return Some(found),
+                Ok(None) => continue,
+                Err(()) => return None,
+            }
+        }
+
+        None
+    }
+
+    /// [`try_root`] bounded by the root's `soft_timeout_ms`: past it the
+    /// probe reads as a miss and, with `finish_late`, runs on in the
+    /// background to count whether it would have found the file.
+    async fn try_within(
+        &self,
+        root: &Arc<SearchRoot>,
+        relative: &Path,
+        ext: &str,
+        request_path: &str,
+    ) -> Result<Option<SearchHit>, ()> {
+        let max_file_size = self.max_size(ext);
+        let Some(limit) = root.soft_timeout.limit else {
+            return try_root(root, relative, ext, max_file_size, request_path).await;
+        };
+        if !root.soft_timeout.finish_late {
+            let probe = try_root(root, relative, ext, max_file_size, request_path);
+            return match tokio::time::timeout(limit, probe).await {
+                Ok(result) => result,
+                Err(_) => {
+                    root.soft_timeout.expired.fetch_add(1, Ordering::Relaxed);
+                    debug!(request_path, root = %root.path.display(), "soft timeout, moving on");
+                    ProbeTrace::timed_out(&root.path, limit);
+                    Ok(None)
+                }
+            };
+        }
+
+        // Aborted if the client goes away before the soft timeout. Run
+        // outside the request's trace, which only records the timeout.
+        let mut probe = {
+            let (root, relative) = (root.clone(), relative.to_owned());
+            let (ext, request_path) = (ext.to_owned(), request_path.to_owned());
+            AbortOnDropHandle::new(tokio::spawn(async move {
+                try_root(&root, &relative, &ext, max_file_size, &request_path).await
+            }))
+        };
+        match tokio::time::timeout(limit, &mut probe).await {
+            Ok(result) => result.unwrap_or(Ok(None)),
+            Err(_) => {
+                root.soft_timeout.expired.fetch_add(1, Ordering::Relaxed);
+                debug!(request_path, root = %root.path.display(), "soft timeout, moving on");
+                ProbeTrace::timed_out(&root.path, limit);
+                let (root, probe) = (root.clone(), probe.detach());
+                tokio::spawn(async move {
+                    if let Ok(Ok(Some(_))) = probe.await {
+                        root.soft_timeout.late_hits.fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+                Ok(None)
+            }
+        }
+    }
+
+    /// Sequential search over the roots ranked by [`adaptive::rank`],
+    /// recording each probe.
+    async fn search_adaptive(&self, request_path: &str) -> Option<SearchHit> {
+        let relative = self.sanitize(request_path).ok()?;
+
+        let ext = relative.extension().and_then(OsStr::to_str).unwrap_or("");
+
+        for root in adaptive::rank(self.active_roots(), |r| &r.ranking) {
+            let started = Instant::now();
+            let result = self.try_within(&root, &relative, ext, request_path).await;
+            if let Ok(found) = &result
+                && root.accepts(ext)
+            {
+                root.ranking.record(found.is_some(), started.elapsed());
+            }
+            match result {
+                Ok(Some(found)) => return Some(found),
                 Ok(None) => continue,
                 Err(()) => return None,
             }
@@ -131,8 +787,8 @@ impl Location {
         None
     }
 
-    async fn search_concurrent(&self, request_path: &str) -> Option<(PathBuf, File, u64)> {
-        let relative = sanitize_path(request_path)?;
+    async fn search_concurrent(&self, request_path: &str) -> Option<SearchHit> {
+        let relative = self.sanitize(request_path).ok()?;
 
         let ext = relative
             .extension()
@@ -142,49 +798,106 @@ impl Location {
 
         let mut handles = Vec::new();
 
-        for root in &self.roots {
+        for root in self.active_roots() {
             if !root.accepts(&ext) {
                 debug!(
                     request_path, root = %root.path.display(), ext,
                     "skipped (extension not allowed)"
                 );
+                ProbeTrace::skipped(&root.path, ProbeOutcome::ExtensionFiltered);
                 continue;
             }
 
-            let root_path = root.path.clone();
-            let candidate = root.path.join(&relative);
-            let max_file_size = self.max_file_size;
+            let relative = relative.clone();
+            let max_file_size = self.max_size(&ext);
             let req_path = request_path.to_owned();
 
-            handles.push(tokio::spawn(
-                probe_root(root_path, candidate, max_file_size, req_path),
-            ));
+            let probe = probe_root(root, relative, max_file_size, req_path);
+            handles.push(AbortOnDropHandle::new(tokio::spawn(traced(probe))));
+        }
+
+        race_handles(handles).await
+    }
+
+    /// Probe the first eligible root alone for `hedge_delay`, then race it
+    /// against all the others.
+    async fn search_hedged(&self, request_path: &str) -> Option<SearchHit> {
+        let relative = self.sanitize(request_path).ok()?;
+
+        let ext = relative.extension().and_then(OsStr::to_str).unwrap_or("");
+
+        let mut eligible = self.active_roots().into_iter().filter(|root| {
+            let accepted = root.accepts(ext);
+            if !accepted {
+                debug!(
+                    request_path, root = %root.path.display(), ext,
+                    "skipped (extension not allowed)"
+                );
+                ProbeTrace::skipped(&root.path, ProbeOutcome::ExtensionFiltered);
+            }
+            accepted
+        });
+        let max_file_size = self.max_size(ext);
+        let spawn = |root| {
+            let probe = probe_root(root, relative.clone(), max_file_size, request_path.into());
+            AbortOnDropHandle::new(tokio::spawn(traced(probe)))
+        };
+
+        let mut first = spawn(eligible.next()?);
+        let mut handles = Vec::new();
+        match tokio::time::timeout(self.hedge_delay, &mut first).await {
+            Ok(Ok(Some(found))) => return Some(found),
+            Ok(_) => {}
+            Err(_) => {
+                debug!(request_path, "first root slow, hedging");
+                handles.push(first);
+            }
         }
+        handles.extend(eligible.map(spawn));
+        race_handles(handles).await
+    }
+
+    /// Hand the healthy roots to the registered strategy.
+    async fn search_plugin(&self, request_path: &str) -> Option<SearchHit> {
+        let strategy = self.strategy.as_ref()?;
+        let relative = self.sanitize(request_path).ok()?;
 
-        let result = race_handles(handles).await;
-        result.map(|(path, file, size, _mtime)| (path, file, size))
+        let ext = relative.extension().and_then(OsStr::to_str).unwrap_or("");
+
+        let candidates: Vec<_> = self
+            .active_roots()
+            .into_iter()
+            .map(|root| Candidate {
+                location: self,
+                root,
+                relative: &relative,
+                ext,
+                request_path,
+            })
+            .collect();
+        strategy.search(&candidates).await
     }
 
-    async fn search_latest(&self, request_path: &str) -> Option<(PathBuf, File, u64)> {
-        let relative = sanitize_path(request_path)?;
+    async fn search_latest(&self, request_path: &str) -> Option<SearchHit> {
+        let relative = self.sanitize(request_path).ok()?;
 
         let ext = relative
             .extension()
             .and_then(OsStr::to_str)
             .unwrap_or("");
 
-        let mut best: Option<SearchResult> = None;
+        let mut best: Option<SearchHit> = None;
 
-        for root in &self.roots {
-            match try_root(root, &relative, ext, self.max_file_size, request_path).await {
+        for root in self.active_roots() {
+            match try_root(&root, &relative, ext, self.max_size(ext), request_path).await {
                 Ok(Some(found)) => {
-                    let dominated = best.as_ref().is_none_or(|b| found.3 > b.3);
+                    let dominated = best.as_ref().is_none_or(|b| found.modified > b.modified);
                     if dominated {
                         if let Some(ref prev) = best {
                             debug!(
                                 request_path,
-                                superseded = %prev.0.display(),
-                                by = %found.0.display(),
+                                superseded = %prev.path.display(),
+                                by = %found.path.display(),
                                 "newer file found, replacing previous candidate"
                             );
                         }
@@ -196,40 +909,411 @@ impl Location {
             }
         }
 
-        best.map(|(path, file, size, _mtime)| (path, file, size))
+        best
+    }
+
+    /// Check every root, then serve the newest copy only if
+    /// [`NewestWindow::prefers`] it over the first one in config order.
+    async fn search_newest_within(&self, request_path: &str) -> Option<SearchHit> {
+        let relative = self.sanitize(request_path).ok()?;
+
+        let ext = relative.extension().and_then(OsStr::to_str).unwrap_or("");
+
+        let mut first: Option<SearchHit> = None;
+        let mut newest: Option<SearchHit> = None;
+
+        for root in self.active_roots() {
+            match try_root(&root, &relative, ext, self.max_size(ext), request_path).await {
+                Ok(Some(found)) => {
+                    let Some(first) = &first else {
+                        first = Some(found);
+                        continue;
+                    };
+                    let best = newest.as_ref().unwrap_or(first);
+                    if found.modified > best.modified {
+                        newest = Some(found);
+                    }
+                }
+                Ok(None) => continue,
+                Err(()) => return None,
+            }
+        }
+
+        let first = first?;
+        match newest {
+            Some(newest) if self.newest_window.prefers(newest.modified, first.modified) => {
+                debug!(
+                    request_path,
+                    first = %first.path.display(),
+                    newest = %newest.path.display(),
+                    "newer copy within window, preferred"
+                );
+                Some(newest)
+            }
+            _ => Some(first),
+        }
+    }
+
+    /// Check every eligible root and collect all matches in config order.
+    /// Roots that reject the path (traversal, filters, size) are skipped.
+    async fn search_all(&self, request_path: &str) -> Vec<(PathBuf, PathBuf, u64, SystemTime)> {
+        let Ok(relative) = self.sanitize(request_path) else {
+            return Vec::new();
+        };
+
+        let ext = relative
+            .extension()
+            .and_then(OsStr::to_str)
+            .unwrap_or("");
+
+        let mut found = Vec::new();
+        for root in self.active_roots() {
+            if let Ok(Some(hit)) =
+                try_root(&root, &relative, ext, self.max_size(ext), request_path).await
+            {
+                found.push((hit.root, hit.path, hit.size, hit.modified));
+            }
+        }
+        found
+    }
+
+    /// Resolve `request_path` the way [`search`](Self::search) does, but
+    /// record every root's outcome in `report`. Statistics and adaptive
+    /// rankings are left untouched.
+    async fn explain(&self, request_path: &str, report: &mut Explanation) {
+        report.location = Some(self.prefix.clone());
+        report.mode = Some(self.search_mode);
+        let relative = match self.sanitize(request_path) {
+            Ok(relative) => relative,
+            Err(refusal) => {
+                report.refused = Some(refusal.as_str());
+                return;
+            }
+        };
+
+        let ext = relative.extension().and_then(OsStr::to_str).unwrap_or("");
+
+        let roots = self.roots.read().unwrap().clone();
+        let (healthy, unhealthy): (Vec<_>, Vec<_>) =
+            roots.into_iter().partition(|r| r.health.is_healthy());
+        let healthy = match self.search_mode {
+            SearchMode::Adaptive => adaptive::rank(healthy, |r| &r.ranking),
+            _ => healthy,
+        };
+
+        let mode = self.search_mode;
+        let concurrent = matches!(mode, SearchMode::Concurrent | SearchMode::Hedged);
+        // What a plugin would pick is only known by running it.
+        let exhaustive = matches!(
+            mode,
+            SearchMode::LatestModified | SearchMode::NewestWithin | SearchMode::Plugin
+        );
+        let max_file_size = self.max_size(ext);
+        let probes = if concurrent {
+            let probes = healthy
+                .iter()
+                .map(|root| explain_root(root, &relative, ext, max_file_size, request_path));
+            futures_util::future::join_all(probes).await
+        } else {
+            let mut probes = Vec::new();
+            let mut decided = false;
+            for root in &healthy {
+                if decided {
+                    probes.push((RootProbe::new(&root.path, ProbeOutcome::NotProbed), None));
+                    continue;
+                }
+                let (probe, modified) =
+                    explain_root(root, &relative, ext, max_file_size, request_path).await;
+                decided = probe.outcome == ProbeOutcome::Traversal
+                    || (probe.outcome == ProbeOutcome::Found && !exhaustive);
+                probes.push((probe, modified));
+            }
+            probes
+        };
+
+        // The winner the search mode picks. A traversal ends a one root at a
+        // time search without a result.
+        let stopped = !concurrent
+            && probes
+                .iter()
+                .any(|(probe, _)| probe.outcome == ProbeOutcome::Traversal);
+        let mut found = probes
+            .iter()
+            .enumerate()
+            .filter(|(_, (probe, _))| probe.outcome == ProbeOutcome::Found);
+        let winner = match self.search_mode {
+            _ if stopped => None,
+            SearchMode::Concurrent => found.min_by_key(|(_, (probe, _))| probe.elapsed_us),
+            SearchMode::Hedged => {
+                // The others start once the first root missed or the delay
+                // is over.
+                let timed = probes.iter().map(|(probe, _)| probe.elapsed_us);
+                let first = timed.clone().position(|us| us.is_some());
+                let delay = self.hedge_delay.as_micros() as u64;
+                let start = first.and_then(|i| probes[i].0.elapsed_us);
+                let start = start.map_or(0, |us| us.min(delay));
+                found.min_by_key(|(i, (probe, _))| {
+                    let elapsed = probe.elapsed_us.unwrap_or_default();
+                    elapsed + if Some(*i) == first { 0 } else { start }
+                })
+            }
+            // The first of equally new files wins.
+            SearchMode::LatestModified => {
+                found.max_by_key(|(i, (_, modified))| (*modified, std::cmp::Reverse(*i)))
+            }
+            SearchMode::NewestWithin => {
+                let found: Vec<_> = found.collect();
+                let newest = found
+                    .iter()
+                    .copied()
+                    .max_by_key(|(i, (_, modified))| (*modified, std::cmp::Reverse(*i)));
+                match (found.first().copied(), newest) {
+                    (Some((_, (_, Some(first)))), Some(newest @ (_, (_, Some(modified)))))
+                        if self.newest_window.prefers(*modified, *first) =>
+                    {
+                        Some(newest)
+                    }
+                    (first, _) => first,
+                }
+            }
+            SearchMode::Plugin => {
+                let root = self.search_plugin(request_path).await.map(|hit| hit.root);
+                let root = root.map(|root| root.display().to_string());
+                found.find(|(_, (probe, _))| Some(&probe.root) == root.as_ref())
+            }
+            _ => found.next(),
+        };
+        report.winner = winner.map(|(_, (probe, _))| probe.root.clone());
+
+        report.probes = probes.into_iter().map(|(probe, _)| probe).collect();
+        for root in unhealthy {
+            report
+                .probes
+                .push(RootProbe::new(&root.path, ProbeOutcome::Unhealthy));
+        }
+        for skipped in self.skipped.lock().unwrap().iter() {
+            let mut probe = RootProbe::new(&skipped.entry.root, ProbeOutcome::Inactive);
+            probe.error = Some(skipped.error.clone());
+            report.probes.push(probe);
+        }
+
+        #[cfg(feature = "archive")]
+        if report.winner.is_none()
+            && self.search_archives
+            && let Some(hit) = self.search_containers(request_path).await
+        {
+            report.winner = Some(hit.root.display().to_string());
+            report.container_member = Some(hit.path.display().to_string());
+        }
     }
 }
 
 pub struct FileSearcher {
     locations: Vec<Location>,
     max_body_size: u64,
+    path_limits: PathLimits,
     stream_buffer_size: usize,
+    /// `Some(token)` when operator endpoints are enabled.
+    admin_token: Option<String>,
+    /// `Some(limit)` when `?archive=` directory downloads are enabled.
+    #[cfg(feature = "archive")]
+    archive_max_entries: Option<usize>,
+    /// `Some(limit)` when `POST /_batch` is enabled.
+    batch_max_paths: Option<usize>,
+    /// `Some` when the S3-style API is enabled; its prefix normalized.
+    #[cfg(feature = "s3-api")]
+    s3_api: Option<S3ApiConfig>,
+    /// Serve `GET /_meta/<path>`.
+    meta_endpoint: bool,
+    /// Emit `X-Resolved-Root` on successful responses.
+    resolved_root_header: bool,
+    /// `Some(token)` when `X-Filehunter-Debug: <token>` turns on debug
+    /// headers.
+    debug_token: Option<String>,
+    /// `Some` when digest headers or content-hash ETags are enabled.
+    #[cfg(feature = "digest")]
+    digests: Option<Arc<DigestCache>>,
+    /// Send `Repr-Digest` / `Digest` headers.
+    #[cfg(feature = "digest")]
+    digest_headers: bool,
+    /// Sidecar encodings served in place of local files; empty when off.
+    precompressed: Vec<Encoding>,
+    connections: Arc<ConnectionRegistry>,
+    /// `Some` when the client denylist is enabled.
+    denylist: Option<Arc<Denylist>>,
+    /// `Some` when blocked requests are audited.
+    audit: Option<AuditLog>,
+    /// `Some` when suspicious files are quarantined.
+    quarantine: Option<Quarantine>,
+    /// `Some` when files are scanned for malware.
+    antivirus: Option<Antivirus>,
+    /// `Some` when served files are reported to `[server.webhooks]`.
+    #[cfg(feature = "webhooks")]
+    webhooks: Option<Arc<Webhooks>>,
+    /// `Some` when requests pass through a WASM filter.
+    #[cfg(feature = "wasm")]
+    wasm_filter: Option<WasmFilter>,
+    /// `Some` when 404s are logged.
+    misses: Option<Arc<MissLog>>,
+    /// Client networks the rate limiter skips.
+    rate_limit_exempt: Vec<IpNet>,
+    /// `Some` when `egress_limit` caps response bytes server-wide.
+    egress: Option<Arc<TokenBucket>>,
+    /// `Some` when `[server.security_headers]` adds any headers.
+    security_headers: Option<Arc<HeaderMap>>,
+    /// How error responses outside any location are rendered.
+    error_pages: ErrorPages,
+    /// Host names of the `[[vhosts]]` sites.
+    vhosts: Vhosts,
+    /// `Some` when a candidate config is compared against this one.
+    shadow: Option<Arc<Shadow>>,
+    /// `Some` when warm-up lists paths to load.
+    warm_list: Option<PathList>,
+    connector: Connector,
 }
 
 impl FileSearcher {
     pub fn new(config: &Config) -> Self {
         let server_max_file_size = config.server.max_file_size.as_u64();
+        let path_limits = PathLimits {
+            max_length: config.server.max_path_length,
+            max_segments: config.server.max_path_segments,
+        };
+        let connector = Connector::new(&config.server);
+        let breaker = Some(config.server.circuit_breaker).filter(|b| b.enabled);
+        let error_pages =
+            ErrorPages::new(config.server.error_format, &config.server.error_template);
 
+        let vhosts = Vhosts::new(&config.vhosts);
         let mut locations: Vec<Location> = config
-            .locations
-            .iter()
-            .map(|loc| Location::from_config(loc, server_max_file_size))
+            .site_locations()
+            .map(|(site, loc)| {
+                Location::from_config(
+                    loc,
+                    server_max_file_size,
+                    path_limits,
+                    breaker,
+                    &error_pages,
+                    &connector,
+                )
+                .in_site(site, vhosts.name(site))
+            })
             .collect();
 
         // Sort by prefix length descending (longest match first).
-        locations.sort_by(|a, b| b.prefix.len().cmp(&a.prefix.len()));
+        locations.sort_by_key(|loc| std::cmp::Reverse(loc.prefix.len()));
+
+        #[cfg(not(feature = "archive"))]
+        if config.server.archive.enabled {
+            warn!("archive.enabled is set but this build lacks the `archive` feature; ignoring");
+        }
+        #[cfg(not(feature = "s3-api"))]
+        if config.server.s3_api.enabled {
+            warn!("s3_api.enabled is set but this build lacks the `s3-api` feature; ignoring");
+        }
+        #[cfg(not(feature = "digest"))]
+        if config.server.digest.enabled {
+            warn!("digest.enabled is set but this build lacks the `digest` feature; ignoring");
+        }
+        #[cfg(not(feature = "wasm"))]
+        if config.server.wasm_filter.enabled {
+            warn!("wasm_filter.enabled is set but this build lacks the `wasm` feature; ignoring");
+        }
 
+        let admin = &config.server.admin;
+        #[cfg(feature = "archive")]
+        let archive = &config.server.archive;
+        #[cfg(feature = "digest")]
+        let hash_etags = config.all_locations().any(|loc| loc.etag == EtagMode::Hash);
         Self {
             locations,
             max_body_size: config.server.max_body_size.as_u64(),
+            path_limits,
             stream_buffer_size: config.server.stream_buffer_size.as_usize(),
+            admin_token: admin.enabled.then(|| admin.token.clone()),
+            #[cfg(feature = "archive")]
+            archive_max_entries: archive.enabled.then_some(archive.max_entries),
+            batch_max_paths: config.server.batch.enabled.then_some(config.server.batch.max_paths),
+            #[cfg(feature = "s3-api")]
+            s3_api: config.server.s3_api.enabled.then(|| S3ApiConfig {
+                prefix: normalize_prefix(&config.server.s3_api.prefix),
+                ..config.server.s3_api.clone()
+            }),
+            meta_endpoint: config.server.meta_endpoint,
+            resolved_root_header: config.server.resolved_root_header,
+            debug_token: Some(config.server.debug_token.clone()).filter(|t| !t.is_empty()),
+            #[cfg(feature = "digest")]
+            digests: (config.server.digest.enabled || hash_etags)
+                .then(|| Arc::new(DigestCache::new(&config.server.digest))),
+            #[cfg(feature = "digest")]
+            digest_headers: config.server.digest.enabled,
+            precompressed: precompress::serving(&config.server.precompress),
+            connections: Arc::default(),
+            denylist: config
+                .server
+                .denylist
+                .enabled
+                .then(|| Arc::new(Denylist::load(&config.server.denylist.file))),
+            audit: config
+                .server
+                .audit_log
+                .enabled
+                .then(|| AuditLog::open(&config.server.audit_log)),
+            quarantine: config
+                .server
+                .quarantine
+                .enabled
+                .then(|| Quarantine::new(&config.server.quarantine)),
+            antivirus: config
+                .server
+                .antivirus
+                .enabled
+                .then(|| Antivirus::new(&config.server.antivirus)),
+            #[cfg(feature = "webhooks")]
+            webhooks: config
+                .server
+                .webhooks
+                .enabled
+                .then(|| Arc::new(Webhooks::new(&config.server.webhooks))),
+            #[cfg(feature = "wasm")]
+            wasm_filter: config
+                .server
+                .wasm_filter
+                .enabled
+                .then(|| WasmFilter::load(&config.server.wasm_filter)),
+            misses: config
+                .server
+                .miss_log
+                .enabled
+                .then(|| Arc::new(MissLog::open(&config.server.miss_log))),
+            rate_limit_exempt: config
+                .server
+                .rate_limit
+                .exempt
+                .iter()
+                .filter_map(|entry| parse_net(entry).ok())
+                .collect(),
+            egress: match config.server.egress_limit.as_u64() {
+                0 => None,
+                rate => Some(Arc::new(TokenBucket::new(rate))),
+            },
+            security_headers: Some(config.server.security_headers.header_map().unwrap_or_default())
+                .filter(|headers| !headers.is_empty())
+                .map(Arc::new),
+            error_pages,
+            vhosts,
+            shadow: None,
+            warm_list: PathList::load(&config.server.warmup),
+            connector,
         }
     }
 
-    /// Match a request path to a location, returning the location and the
-    /// remaining path after stripping the prefix.
+    /// Match a request path to a location of the current request's site,
+    /// returning the location and the remaining path after stripping the
+    /// prefix.
     fn match_location<'a>(&'a self, path: &'a str) -> Option<(&'a Location, &'a str)> {
-        for loc in &self.locations {
+        let site = vhost::current();
+        for loc in self.locations.iter().filter(|loc| loc.site == site) {
             if loc.prefix == "/" {
                 return Some((loc, path));
             }
@@ -245,83 +1329,1537 @@ impl FileSearcher {
         None
     }
 
-    async fn search(&self, request_path: &str) -> Option<(PathBuf, File, u64)> {
+    /// Resolve a request path (location prefix included) to a file using the
+    /// matching location's search mode. `None` if no location matches, the
+    /// path is rejected, or no root holds the file.
+    pub async fn search(&self, request_path: &str) -> Option<SearchHit> {
         let (location, stripped_path) = self.match_location(request_path)?;
         location.search(stripped_path).await
     }
-}
-
-// ---------------------------------------------------------------------------
-// Shared search helpers
-// ---------------------------------------------------------------------------
-
-/// Core file probe: canonicalize, open, check metadata and size.
-///
-/// Returns:
-/// - `Ok(Some(...))` — file found
-/// - `Ok(None)` — not found or not a regular file
-/// - `Err(())` — path traversal detected (canonical path escaped root)
-async fn probe_candidate(
-    root_path: &Path,
-    candidate: PathBuf,
-    max_file_size: u64,
-    request_path: &str,
-) -> Result<Option<SearchResult>, ()> {
-    let canonical = match tokio::fs::canonicalize(&candidate).await {
-        Ok(c) if c.starts_with(root_path) => c,
-        Ok(_) => {
-            warn!(request_path, "path traversal blocked");
-            return Err(());
-        }
-        Err(_) => return Ok(None),
-    };
 
-    let file = match File::open(&canonical).await {
-        Ok(f) => f,
-        Err(_) => return Ok(None),
-    };
-    let meta = match file.metadata().await {
-        Ok(m) if m.is_file() => m,
-        _ => return Ok(None),
-    };
+    /// [`search`](Self::search), also reporting which location answered.
+    pub(crate) async fn resolve<'a>(&'a self, request_path: &'a str) -> Option<Resolved<'a>> {
+        let (location, stripped_path) = self.match_location(request_path)?;
+        Some(Resolved {
+            prefix: &location.prefix,
+            mode: location.search_mode,
+            hit: location.search(stripped_path).await?,
+        })
+    }
 
-    if max_file_size > 0 && meta.len() > max_file_size {
-        debug!(
-            request_path, resolved = %canonical.display(),
-            size = meta.len(), limit = max_file_size,
-            "skipped (file too large)"
-        );
-        return Ok(None);
+    /// Snapshot of every location and the state of its roots, in match order.
+    pub fn status(&self) -> Vec<LocationStatus> {
+        self.locations
+            .iter()
+            .map(|loc| {
+                let roots = loc.roots.read().unwrap().clone();
+                let active = roots.iter().map(|r| RootStatus {
+                    path: r.path.clone(),
+                    active: true,
+                    healthy: r.health.is_healthy(),
+                    circuit: r.breaker.as_ref().map(CircuitBreaker::state),
+                    error: None,
+                });
+                let skipped = loc.skipped.lock().unwrap();
+                let skipped = skipped.iter().map(|s| RootStatus {
+                    path: s.entry.root.clone(),
+                    active: false,
+                    healthy: false,
+                    circuit: None,
+                    error: Some(s.error.clone()),
+                });
+                LocationStatus {
+                    prefix: loc.label.clone(),
+                    mode: loc.search_mode,
+                    max_file_size: loc.max_file_size,
+                    roots: active.chain(skipped).collect(),
+                }
+            })
+            .collect()
     }
 
-    let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    pub(crate) fn warm_list(&self) -> Option<&PathList> {
+        self.warm_list.as_ref()
+    }
 
-    Ok(Some((canonical, file, meta.len(), modified)))
-}
+    /// The digest cache, when it hashes in the background.
+    #[cfg(feature = "digest")]
+    pub(crate) fn background_digests(&self) -> Option<Arc<DigestCache>> {
+        self.digests.clone().filter(|d| d.is_background())
+    }
 
-/// Attempt to find the file under a single search root (with extension filter).
-async fn try_root(
-    root: &SearchRoot,
-    relative: &Path,
-    ext: &str,
-    max_file_size: u64,
-    request_path: &str,
-) -> Result<Option<SearchResult>, ()> {
-    if !root.accepts(ext) {
-        debug!(
-            request_path, root = %root.path.display(), ext,
-            "skipped (extension not allowed)"
-        );
-        return Ok(None);
+    /// Compute the digest of a file found by a warm-up, as its first
+    /// request would.
+    #[cfg(feature = "digest")]
+    pub(crate) async fn prime_digest(&self, hit: &SearchHit) {
+        if let Some(digests) = &self.digests
+            && matches!(hit.body, ObjectBody::File(_))
+        {
+            digests.get(&hit.path, hit.size, hit.modified).await;
+        }
     }
-    probe_candidate(&root.path, root.path.join(relative), max_file_size, request_path).await
-}
 
-/// Wait for the first `JoinHandle` that returns `Some`, then abort all
-/// remaining handles to free resources.
-async fn race_handles(
-    mut handles: Vec<tokio::task::JoinHandle<Option<SearchResult>>>,
-) -> Option<SearchResult> {
+    /// Retry every configured root that could not be opened so far, adding
+    /// those that open to their location. Returns how many are still
+    /// skipped. Blocks on filesystem calls for local roots.
+    pub(crate) fn retry_skipped_roots(&self) -> usize {
+        self.locations
+            .iter()
+            .map(|loc| loc.retry_skipped(&self.connector))
+            .sum()
+    }
+
+    /// Usage counters of every location, in match order.
+    pub fn stats(&self) -> Vec<LocationStatsInfo> {
+        self.locations
+            .iter()
+            .map(|loc| {
+                let roots = loc.roots.read().unwrap();
+                let roots = roots.iter().map(|r| RootStatsInfo {
+                    path: r.path.clone(),
+                    hits: r.hits.load(Ordering::Relaxed),
+                    share: 0.0,
+                    soft_timeouts: r.soft_timeout.expired.load(Ordering::Relaxed),
+                    late_hits: r.soft_timeout.late_hits.load(Ordering::Relaxed),
+                });
+                loc.stats.snapshot(&loc.label, roots.collect())
+            })
+            .collect()
+    }
+
+    /// Search latency histograms and aborted request counts of every
+    /// location, in the Prometheus text exposition format.
+    pub fn metrics(&self) -> String {
+        let mut out = metrics::render(self.locations.iter().map(|loc| {
+            (
+                loc.label.as_str(),
+                loc.search_mode.as_str(),
+                &loc.search_latency,
+            )
+        }));
+        let aborted = self
+            .locations
+            .iter()
+            .map(|loc| (loc.label.as_str(), loc.stats.aborted()));
+        metrics::render_aborted(&mut out, aborted);
+        let roots: Vec<_> = self
+            .locations
+            .iter()
+            .map(|loc| (loc.label.as_str(), loc.roots.read().unwrap().clone()))
+            .collect();
+        let breakers = roots.iter().flat_map(|(prefix, roots)| {
+            roots
+                .iter()
+                .filter_map(move |r| Some((*prefix, r.path.as_path(), r.breaker.as_ref()?)))
+        });
+        if self.locations.iter().any(|loc| loc.breaker.is_some()) {
+            breaker::render(&mut out, breakers);
+        }
+        if let Some(shadow) = &self.shadow {
+            shadow.render(&mut out);
+        }
+        out
+    }
+
+    /// Also resolve `sample_rate` of file requests against `candidate`,
+    /// logging to the `filehunter::shadow` target where it disagrees with
+    /// this searcher and counting comparisons in [`metrics`](Self::metrics).
+    pub fn with_shadow(mut self, candidate: FileSearcher, sample_rate: f64) -> Self {
+        self.shadow = Some(Arc::new(Shadow::new(candidate, sample_rate)));
+        self
+    }
+
+    /// Counters of the location `request_path` falls in.
+    fn stats_for(&self, request_path: &str) -> Option<Arc<LocationStats>> {
+        self.match_location(request_path)
+            .map(|(loc, _)| loc.stats.clone())
+    }
+
+    /// Health handles for every active root, as `(path, health, backend)`.
+    pub(crate) fn root_health(&self) -> Vec<(PathBuf, Arc<RootHealth>, Arc<dyn StorageBackend>)> {
+        self.locations
+            .iter()
+            .flat_map(|loc| loc.roots.read().unwrap().clone())
+            .map(|r| (r.path.clone(), r.health.clone(), r.backend.clone()))
+            .collect()
+    }
+
+    /// Whether `ip` is in `rate_limit.exempt`.
+    fn rate_limit_exempt(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.rate_limit_exempt.iter().any(|net| net.contains(&ip))
+    }
+
+    /// The client denylist, if enabled.
+    pub fn denylist(&self) -> Option<&Arc<Denylist>> {
+        self.denylist.as_ref()
+    }
+
+    /// The 404 miss log, if enabled.
+    pub fn miss_log(&self) -> Option<&Arc<MissLog>> {
+        self.misses.as_ref()
+    }
+
+    /// The served-file event queue, if webhooks are enabled.
+    #[cfg(feature = "webhooks")]
+    pub fn webhooks(&self) -> Option<&Arc<Webhooks>> {
+        self.webhooks.as_ref()
+    }
+
+    /// Registry the server uses to account for live connections.
+    pub fn connections(&self) -> &Arc<ConnectionRegistry> {
+        &self.connections
+    }
+
+    pub(crate) fn max_body_size(&self) -> u64 {
+        self.max_body_size
+    }
+
+    /// The target of the first of its location's redirects that matches
+    /// `request_path`.
+    fn redirect_for(&self, request_path: &str) -> Option<(StatusCode, String)> {
+        let (location, _) = self.match_location(request_path)?;
+        location.redirects.iter().find_map(|rule| {
+            let status = StatusCode::from_u16(rule.status).ok()?;
+            Some((status, rule.target(request_path)?))
+        })
+    }
+
+    /// Fetch `request_path` from its location's `fallback_upstream` after
+    /// a miss, copying it to the cache root on the way when one is set.
+    async fn fetch_fallback(&self, request_path: &str) -> Option<SearchHit> {
+        let (location, stripped_path) = self.match_location(request_path)?;
+        let fallback = location.fallback.as_ref()?;
+        let relative = location.sanitize(stripped_path).ok()?;
+        let ext = relative.extension().and_then(OsStr::to_str).unwrap_or("");
+        let mut found = probe_backend(
+            fallback.backend.as_ref(),
+            &relative,
+            location.max_size(ext),
+            request_path,
+        )
+        .await
+        .ok()??;
+        debug!(request_path, resolved = %found.path.display(), "found upstream");
+        found.body = match (&fallback.cache, found.body) {
+            (Some(cache), ObjectBody::Stream(body)) => {
+                ObjectBody::Stream(fallback::cache(body, cache.clone(), relative, found.size))
+            }
+            (_, body) => body,
+        };
+        Some(SearchHit::new(fallback.identity.clone(), found))
+    }
+
+    /// Where to redirect `request_path` under its location's trailing-slash
+    /// policy: files lose the slash, local directories gain one. `found`
+    /// tells whether the path resolved to a file.
+    async fn slash_redirect(
+        &self,
+        request_path: &str,
+        found: bool,
+    ) -> Option<(StatusCode, String)> {
+        let (location, stripped_path) = self.match_location(request_path)?;
+        let status = location.trailing_slash?;
+        if request_path.ends_with('/') {
+            let canonical = request_path.trim_end_matches('/');
+            return (found && !canonical.is_empty()).then(|| (status, canonical.to_owned()));
+        }
+        if found {
+            return None;
+        }
+        let relative = match stripped_path.trim_matches('/') {
+            "" => PathBuf::new(),
+            _ => location.sanitize(stripped_path).ok()?,
+        };
+        for root in location.active_roots() {
+            let Some(dir) = root.local_dir() else {
+                continue;
+            };
+            // Not following symlinks keeps out-of-root directories unseen.
+            if tokio::fs::symlink_metadata(dir.join(&relative))
+                .await
+                .is_ok_and(|meta| meta.is_dir())
+            {
+                return Some((status, format!("{request_path}/")));
+            }
+        }
+        None
+    }
+
+    /// The methods the location `request_path` falls in accepts.
+    fn allowed_methods<'a>(&'a self, request_path: &'a str) -> &'a [Method] {
+        match self.match_location(request_path) {
+            Some((location, _)) => &location.methods,
+            None => READ_METHODS,
+        }
+    }
+
+    /// Where an upload to `request_path` is written. Refuses what
+    /// sanitization or the upload root's extension filter rejects.
+    pub(crate) fn upload_target<'a>(
+        &'a self,
+        request_path: &'a str,
+    ) -> Result<UploadTarget<'a>, StatusCode> {
+        let (location, stripped_path) = self
+            .match_location(request_path)
+            .ok_or(StatusCode::NOT_FOUND)?;
+        let upload = location
+            .upload
+            .as_ref()
+            .ok_or(StatusCode::METHOD_NOT_ALLOWED)?;
+        let relative = location
+            .sanitize(stripped_path)
+            .map_err(|refusal| match refusal {
+                Refusal::Malformed => StatusCode::BAD_REQUEST,
+                Refusal::Blocked(_) => StatusCode::FORBIDDEN,
+            })?;
+        let ext = relative.extension().and_then(OsStr::to_str).unwrap_or("");
+        if let Some(set) = &upload.extensions
+            && !set.contains(&ext.to_ascii_lowercase())
+        {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        let candidates = match upload.replicas {
+            0 => Vec::new(),
+            _ => location
+                .active_roots()
+                .iter()
+                .filter(|root| root.accepts(ext))
+                .filter_map(|root| root.local_dir().map(Path::to_path_buf))
+                .collect(),
+        };
+        Ok(UploadTarget {
+            root: &upload.path,
+            relative,
+            replicas: upload.replicas,
+            candidates,
+        })
+    }
+
+    #[cfg(feature = "archive")]
+    pub(crate) fn stream_buffer_size(&self) -> usize {
+        self.stream_buffer_size
+    }
+
+    fn location_by_prefix(&self, prefix: &str) -> Result<&Location, String> {
+        let prefix = normalize_prefix(prefix);
+        let site = vhost::current();
+        self.locations
+            .iter()
+            .find(|loc| loc.site == site && loc.prefix == prefix)
+            .ok_or_else(|| format!("no location with prefix {prefix:?}"))
+    }
+
+    /// Add a search root to the location with the given prefix.
+    /// Returns the canonical path (or URL) that was attached.
+    pub fn attach_root(&self, prefix: &str, entry: &SearchPath) -> Result<PathBuf, String> {
+        let location = self.location_by_prefix(prefix)?;
+        let attached = location.attach_root(entry, &self.connector)?;
+        info!(prefix = %location.prefix, path = %attached.display(), "search path attached");
+        Ok(attached)
+    }
+
+    /// Add a root served by a caller-provided backend to the location with
+    /// the given prefix. `identity` names the root in logs, status output and
+    /// [`detach_root`](Self::detach_root); it must be unique in the location.
+    pub fn attach_backend(
+        &self,
+        prefix: &str,
+        identity: PathBuf,
+        extensions: &[String],
+        backend: Arc<dyn StorageBackend>,
+    ) -> Result<(), String> {
+        let location = self.location_by_prefix(prefix)?;
+        let entry = SearchPath {
+            root: identity.clone(),
+            extensions: extensions.to_vec(),
+            ..Default::default()
+        };
+        location.push_root(identity.clone(), &entry, backend)?;
+        info!(prefix = %location.prefix, path = %identity.display(), "custom backend attached");
+        Ok(())
+    }
+
+    /// Remove a search root from the location with the given prefix.
+    /// In-flight searches keep using their snapshot of the old root set.
+    pub fn detach_root(&self, prefix: &str, root: &Path) -> Result<PathBuf, String> {
+        let location = self.location_by_prefix(prefix)?;
+        let detached = location.detach_root(root)?;
+        info!(prefix = %location.prefix, path = %detached.display(), "search path detached");
+        if location.roots.read().unwrap().is_empty() {
+            warn!(prefix = %location.prefix, "no search paths left for location");
+        }
+        Ok(detached)
+    }
+
+    /// `(bucket, prefix)` for each location the S3-style API exposes:
+    /// those without `auth`, by bucket name.
+    #[cfg(feature = "s3-api")]
+    pub(crate) fn s3_buckets(&self) -> Vec<(String, String)> {
+        let mut buckets: Vec<(String, String)> = self
+            .locations
+            .iter()
+            .filter(|loc| loc.site == vhost::current() && loc.auth.is_none())
+            .map(|loc| (s3api::bucket_name(&loc.prefix), loc.prefix.clone()))
+            .collect();
+        // Config validation rejects colliding names.
+        buckets.sort();
+        buckets
+    }
+
+    /// The prefix of the location `request_path` falls in.
+    #[cfg(feature = "s3-api")]
+    pub(crate) fn location_prefix<'a>(&'a self, request_path: &'a str) -> Option<&'a str> {
+        let (location, _) = self.match_location(request_path)?;
+        Some(&location.prefix)
+    }
+
+    /// What an archive download of `request_path` covers: the healthy local
+    /// roots of the matching location and the directory below them. `None`
+    /// if no location matches or the path is rejected.
+    #[cfg(feature = "archive")]
+    pub(crate) fn archive_plan(&self, request_path: &str) -> Option<ArchivePlan> {
+        let (location, stripped_path) = self.match_location(request_path)?;
+        let relative = if stripped_path.trim_matches('/').is_empty() {
+            PathBuf::new()
+        } else {
+            location.sanitize(stripped_path).ok()?
+        };
+        let name = relative
+            .file_name()
+            .or_else(|| Path::new(&location.prefix).file_name())
+            .and_then(OsStr::to_str)
+            .unwrap_or("archive")
+            .to_owned();
+        let roots = location
+            .active_roots()
+            .into_iter()
+            .filter(|root| root.local_dir().is_some())
+            .collect();
+        Some(ArchivePlan {
+            name,
+            relative,
+            roots,
+            max_file_size: location.max_file_size,
+            max_sizes: location
+                .extension_rules
+                .iter()
+                .filter_map(|(ext, rule)| Some((ext.clone(), rule.max_size?.as_u64())))
+                .collect(),
+            symlinks: location.symlinks,
+            deny_patterns: location.deny_patterns.clone(),
+            hidden_files: location.hidden_files.clone(),
+        })
+    }
+
+    /// The image processor of the location `request_path` falls in, if that
+    /// location has resizing enabled.
+    #[cfg(feature = "images")]
+    fn images_for<'a>(&'a self, request_path: &'a str) -> Option<&'a ImageProcessor> {
+        self.match_location(request_path)?.0.images.as_deref()
+    }
+
+    /// Whether a request with `headers` and `query` may search the location
+    /// `request_path` falls in. Locations without `auth` are open.
+    pub(crate) async fn authorized(
+        &self,
+        request_path: &str,
+        headers: &hyper::HeaderMap,
+        query: Option<&str>,
+    ) -> bool {
+        match self.match_location(request_path) {
+            Some((location, _)) => match &location.auth {
+                Some(guard) => guard.accepts(request_path, headers, query).await,
+                None => true,
+            },
+            None => true,
+        }
+    }
+
+    /// `WWW-Authenticate` challenge for a 401 in the location `request_path`
+    /// falls in.
+    fn auth_challenge(&self, request_path: &str) -> Option<HeaderValue> {
+        self.match_location(request_path)?.0.auth.as_ref()?.challenge()
+    }
+
+    /// Record a blocked request, if auditing is enabled.
+    fn audit(&self, event: AuditEvent, client_ip: IpAddr, request_path: &str) {
+        if let Some(audit) = &self.audit {
+            audit.record(event, client_ip, request_path);
+        }
+    }
+
+    /// Record a blocked request whose file went into quarantine as `entry`,
+    /// if auditing is enabled.
+    fn audit_file(
+        &self,
+        event: AuditEvent,
+        client_ip: IpAddr,
+        request_path: &str,
+        entry: Option<&Path>,
+    ) {
+        match (&self.audit, entry) {
+            (Some(audit), Some(entry)) => {
+                audit.record_quarantined(event, client_ip, request_path, entry);
+            }
+            _ => self.audit(event, client_ip, request_path),
+        }
+    }
+
+    /// Scan a found local file, if antivirus scanning is enabled. Infected
+    /// files are quarantined, if enabled, audited and refused with 403.
+    async fn scan_hit(
+        &self,
+        request_path: &str,
+        hit: &SearchHit,
+        client_ip: IpAddr,
+    ) -> Result<(), StatusCode> {
+        let Some(antivirus) = &self.antivirus else {
+            return Ok(());
+        };
+        // Remote objects and archive members are not scanned.
+        if !matches!(hit.body, ObjectBody::File(_)) {
+            return Ok(());
+        }
+        let verdict = antivirus.check(&hit.path, hit.size, hit.modified).await;
+        if !antivirus.infected(verdict, request_path)? {
+            return Ok(());
+        }
+        let entry = match &self.quarantine {
+            Some(quarantine) => quarantine.isolate(&hit.path).await.ok().flatten(),
+            None => None,
+        };
+        let event = AuditEvent::InfectedFile;
+        self.audit_file(event, client_ip, request_path, entry.as_deref());
+        Err(StatusCode::FORBIDDEN)
+    }
+
+    /// Scan an upload's temporary file `temp` before it is moved into place,
+    /// if `scan_uploads` is set. Infected uploads are audited and refused
+    /// with 403.
+    pub(crate) async fn scan_upload(
+        &self,
+        request_path: &str,
+        temp: &Path,
+        client_ip: IpAddr,
+    ) -> Result<(), StatusCode> {
+        let Some(antivirus) = self.antivirus.as_ref().filter(|av| av.scan_uploads) else {
+            return Ok(());
+        };
+        let verdict = antivirus.scan(temp).await;
+        if !antivirus.infected(verdict, request_path)? {
+            return Ok(());
+        }
+        self.audit(AuditEvent::InfectedFile, client_ip, request_path);
+        Err(StatusCode::FORBIDDEN)
+    }
+
+    /// Queue a served-file event if `request_path`'s location sets
+    /// `served_events`.
+    #[cfg(feature = "webhooks")]
+    fn served(
+        &self,
+        request_path: &str,
+        hit: &SearchHit,
+        client_ip: IpAddr,
+        status: StatusCode,
+        bytes: u64,
+    ) {
+        let Some(webhooks) = &self.webhooks else {
+            return;
+        };
+        let Some((location, _)) = self.match_location(request_path) else {
+            return;
+        };
+        if !location.served_events {
+            return;
+        }
+        webhooks.emit(ServedEvent {
+            time: unix_secs(SystemTime::now()),
+            client_ip,
+            location: location.label.clone(),
+            path: request_path.to_owned(),
+            root: hit.root.to_string_lossy().into_owned(),
+            file: hit.path.to_string_lossy().into_owned(),
+            status: status.as_u16(),
+            bytes,
+        });
+    }
+
+    /// Run the WASM request filter on `req`, rewriting its path if the
+    /// module asks to. Returns the root the module picked, or the status and
+    /// body to answer when it rejects the request or fails.
+    #[cfg(feature = "wasm")]
+    fn filter_request<B>(
+        &self,
+        req: &mut Request<B>,
+        client_ip: IpAddr,
+    ) -> Result<Option<PathBuf>, (StatusCode, String)> {
+        let Some(filter) = &self.wasm_filter else {
+            return Ok(None);
+        };
+        // Operators keep their endpoints whatever the module does.
+        if self.admin_token.is_some() && req.uri().path().starts_with("/_admin/") {
+            return Ok(None);
+        }
+        let uri = req.uri().clone();
+        let path = uri.path();
+        let decision = filter.run(req.method(), path, uri.query(), req.headers(), client_ip);
+        let (rewritten, root) = match decision {
+            Ok(Decision::Continue { path, root }) => (path, root),
+            Ok(Decision::Reject { status, body }) => {
+                debug!(status, path, "request rejected by wasm filter");
+                self.audit(AuditEvent::FilterRejected, client_ip, path);
+                let status = StatusCode::from_u16(status).unwrap_or(StatusCode::FORBIDDEN);
+                let reason = status.canonical_reason().unwrap_or_default();
+                return Err((status, body.unwrap_or_else(|| reason.to_owned())));
+            }
+            Err(e) if filter.fail_open => {
+                warn!(path, error = %e, "wasm filter failed; request passed unfiltered");
+                return Ok(None);
+            }
+            Err(e) => {
+                warn!(path, error = %e, "wasm filter failed");
+                let status = StatusCode::INTERNAL_SERVER_ERROR;
+                return Err((status, "Internal Server Error".into()));
+            }
+        };
+        if let Some(rewritten) = rewritten {
+            let target = match uri.query() {
+                Some(query) => format!("{rewritten}?{query}"),
+                None => rewritten,
+            };
+            let mut parts = uri.clone().into_parts();
+            parts.path_and_query = target.parse().ok();
+            let Ok(rewritten) = hyper::Uri::from_parts(parts) else {
+                warn!(path, target, "wasm filter returned an invalid path");
+                let status = StatusCode::INTERNAL_SERVER_ERROR;
+                return Err((status, "Internal Server Error".into()));
+            };
+            debug!(path, target = %rewritten, "request path rewritten by wasm filter");
+            *req.uri_mut() = rewritten;
+        }
+        Ok(root)
+    }
+
+    /// Quarantine the file a request matching `deny_patterns` names, from
+    /// the first local root that has it. Returns the quarantine entry.
+    async fn quarantine_denied(&self, request_path: &str) -> Option<PathBuf> {
+        let quarantine = self.quarantine.as_ref()?;
+        let (location, stripped_path) = self.match_location(request_path)?;
+        let hidden_files = &location.hidden_files;
+        let relative = sanitize_path(stripped_path, hidden_files, location.path_limits).ok()?;
+        for root in location.active_roots() {
+            let Some(dir) = root.local_dir() else {
+                continue;
+            };
+            let file = dir.join(&relative);
+            // Not following symlinks leaves out-of-root files alone.
+            if tokio::fs::symlink_metadata(&file)
+                .await
+                .is_ok_and(|meta| meta.is_file())
+            {
+                return quarantine.isolate(&file).await.ok().flatten();
+            }
+        }
+        None
+    }
+
+    /// The audit event for a `request_path` that sanitization refuses.
+    fn blocked_reason(&self, request_path: &str) -> Option<AuditEvent> {
+        let refusal = match self.match_location(request_path) {
+            Some((location, stripped_path)) => location.sanitize(stripped_path).err()?,
+            // Nothing is served outside the locations, but a traversal
+            // attempt is still worth recording.
+            None => {
+                sanitize_path(request_path, &HiddenFiles::Allow, PathLimits::default()).err()?
+            }
+        };
+        match refusal {
+            Refusal::Blocked(event) => Some(event),
+            Refusal::Malformed => None,
+        }
+    }
+
+    /// Token buckets a response to `request_path` is paced through: the
+    /// server-wide one, then the matching location's.
+    fn egress_for(&self, request_path: &str) -> Vec<Arc<TokenBucket>> {
+        let location = self.match_location(request_path).and_then(|(loc, _)| loc.egress.as_ref());
+        self.egress.iter().chain(location).cloned().collect()
+    }
+
+    /// Security headers for a response to `request_path`: the matching
+    /// location's override, else the server's.
+    fn security_headers_for(&self, request_path: &str) -> Option<Arc<HeaderMap>> {
+        match self.match_location(request_path) {
+            Some((loc, _)) if loc.security_headers.is_some() => loc.security_headers.clone(),
+            _ => self.security_headers.clone(),
+        }
+    }
+
+    /// How error responses to `request_path` are rendered.
+    fn error_pages_for<'a>(&'a self, request_path: &'a str) -> &'a ErrorPages {
+        match self.match_location(request_path) {
+            Some((loc, _)) => &loc.error_pages,
+            None => &self.error_pages,
+        }
+    }
+
+    /// `Cache-Control` for a file served for `request_path`, from the rules
+    /// of the location it falls in.
+    fn cache_control_for(&self, request_path: &str) -> Option<String> {
+        let (location, rest) = self.match_location(request_path)?;
+        let ext = Path::new(rest).extension().and_then(OsStr::to_str);
+        let max_age = ext.and_then(|ext| location.extension_rule(ext)?.max_age_secs);
+        let relative = rest.trim_start_matches('/');
+        location.cache_control.value(relative, max_age)
+    }
+
+    /// The `location` span a request for `request_path` runs in, carrying
+    /// its location's log level and whether this request is sampled.
+    /// Disabled for locations without log overrides.
+    fn log_span(&self, request_path: &str) -> Span {
+        let Some((location, _)) = self.match_location(request_path) else {
+            return Span::none();
+        };
+        let Some(log) = &location.log else {
+            return Span::none();
+        };
+        let sampled = log.requests.fetch_add(1, Ordering::Relaxed) % log.sample == 0;
+        tracing::error_span!(
+            "location",
+            prefix = %location.prefix,
+            log_level = log.level.map(LogLevel::as_str),
+            sampled,
+        )
+    }
+
+    /// Whether the location `request_path` falls in forces `resolved` to be
+    /// downloaded rather than displayed.
+    fn forces_attachment(&self, request_path: &str, resolved: &Path) -> bool {
+        let Some((location, _)) = self.match_location(request_path) else {
+            return false;
+        };
+        let Some(ext) = resolved.extension().and_then(OsStr::to_str) else {
+            return false;
+        };
+        let ext = ext.to_ascii_lowercase();
+        let rule = location.extension_rule(&ext);
+        match rule.and_then(|rule| rule.disposition) {
+            Some(disposition) => disposition == Disposition::Attachment,
+            None => location.attachment_extensions.contains(&ext),
+        }
+    }
+
+    /// Apply the `magic_bytes` check of `request_path`'s location to a
+    /// found file. `None` when it must not be served; otherwise the hit
+    /// and whether it is relabeled as a forced `application/octet-stream`
+    /// download because its leading bytes contradict its extension.
+    /// Mismatching local files are quarantined, if enabled, and audited.
+    async fn check_magic(
+        &self,
+        request_path: &str,
+        mut hit: SearchHit,
+        client_ip: IpAddr,
+    ) -> Option<(SearchHit, bool)> {
+        let check = match self.match_location(request_path) {
+            Some((location, _)) => location.magic_bytes,
+            None => MagicBytes::Off,
+        };
+        let ext = hit.path.extension().and_then(OsStr::to_str).unwrap_or("");
+        if check == MagicBytes::Off || !magic::known(ext) {
+            return Some((hit, false));
+        }
+        let head = match magic::read_head(hit.body).await {
+            Ok((head, body)) => {
+                hit.body = body;
+                head
+            }
+            Err(e) => {
+                warn!(request_path, resolved = %hit.path.display(), error = %e, "cannot read file");
+                return None;
+            }
+        };
+        if magic::matches(ext, &head) != Some(false) {
+            return Some((hit, false));
+        }
+        warn!(
+            request_path, resolved = %hit.path.display(), action = ?check,
+            "content does not match extension"
+        );
+        let entry = match (&self.quarantine, &hit.body) {
+            (Some(quarantine), ObjectBody::File(_)) => quarantine.isolate(&hit.path).await.ok(),
+            _ => None,
+        };
+        let (event, entry) = (AuditEvent::MislabeledFile, entry.flatten());
+        self.audit_file(event, client_ip, request_path, entry.as_deref());
+        if check == MagicBytes::Refuse {
+            return None;
+        }
+        hit.mime = mime_guess::mime::APPLICATION_OCTET_STREAM;
+        Some((hit, true))
+    }
+
+    /// Apply the checks a direct request for `request_path` gets to a local
+    /// file bound for a directory archive: `magic_bytes`, then the antivirus
+    /// scan, quarantining and auditing like [`Self::check_magic`] and
+    /// [`Self::scan_hit`]. `false` when the file must be left out; so are
+    /// mislabeled files, which an archive cannot relabel as downloads.
+    #[cfg(feature = "archive")]
+    pub(crate) async fn screen_entry(
+        &self,
+        request_path: &str,
+        entry: &archive::Entry,
+        client_ip: IpAddr,
+    ) -> bool {
+        let Ok(file) = tokio::fs::File::open(&entry.path).await else {
+            return false;
+        };
+        let hit = SearchHit {
+            root: PathBuf::new(),
+            path: entry.path.clone(),
+            size: entry.size,
+            modified: entry.modified,
+            mime: mime_guess::from_path(&entry.path).first_or_octet_stream(),
+            body: ObjectBody::File(file),
+        };
+        match self.check_magic(request_path, hit, client_ip).await {
+            Some((hit, false)) => self.scan_hit(request_path, &hit, client_ip).await.is_ok(),
+            _ => false,
+        }
+    }
+
+    /// The validators of a file found for `request_path`, under its
+    /// location's `etag` mode.
+    async fn validators(&self, request_path: &str, hit: &SearchHit) -> Validators {
+        let validators = Validators::new(hit.size, hit.modified);
+        let mode = self.match_location(request_path).map_or(EtagMode::Mtime, |(loc, _)| loc.etag);
+        match mode {
+            EtagMode::Mtime => validators,
+            EtagMode::Off => validators.with_etag(None),
+            #[cfg(feature = "digest")]
+            EtagMode::Hash => {
+                let hash = match &self.digests {
+                    Some(digests) if matches!(hit.body, ObjectBody::File(_)) => {
+                        digests.get(&hit.path, hit.size, hit.modified).await
+                    }
+                    _ => None,
+                };
+                match hash {
+                    Some(hash) => validators.with_etag(Some(format!("\"{hash}\""))),
+                    None => validators,
+                }
+            }
+            #[cfg(not(feature = "digest"))]
+            EtagMode::Hash => validators,
+        }
+    }
+
+    /// The range limits of the location `request_path` falls in; `None`
+    /// when it serves no byte ranges.
+    fn range_limits(&self, request_path: &str) -> Option<RangeLimits> {
+        self.match_location(request_path)?.0.ranges
+    }
+
+    /// Probe every root of the matching location and report all copies,
+    /// regardless of search mode. Returns `None` if no location matches.
+    pub(crate) async fn find_all<'a>(&'a self, request_path: &'a str) -> Option<AllMatches<'a>> {
+        let (location, stripped_path) = self.match_location(request_path)?;
+        Some(AllMatches {
+            prefix: &location.prefix,
+            mode: location.search_mode,
+            matches: location.search_all(stripped_path).await,
+        })
+    }
+
+    /// Trace how `request_path` resolves: the matching location and the
+    /// outcome of each of its roots, for `GET /_explain/<path>`.
+    pub(crate) async fn explain(&self, request_path: &str) -> Explanation {
+        let mut report = Explanation {
+            path: request_path.to_owned(),
+            location: None,
+            mode: None,
+            refused: None,
+            probes: Vec::new(),
+            winner: None,
+            container_member: None,
+        };
+        if let Some((location, stripped_path)) = self.match_location(request_path) {
+            location.explain(stripped_path, &mut report).await;
+        }
+        report
+    }
+
+    /// Whether `headers` carry the configured `X-Filehunter-Debug` token.
+    fn debug_requested(&self, headers: &HeaderMap) -> bool {
+        let Some(token) = &self.debug_token else {
+            return false;
+        };
+        headers
+            .get("x-filehunter-debug")
+            .is_some_and(|v| auth::constant_time_eq(v.as_bytes(), token.as_bytes()))
+    }
+}
+
+impl FileSearcher {
+    /// Start building a searcher without writing out a full [`Config`].
+    pub fn builder() -> FileSearcherBuilder {
+        FileSearcherBuilder::default()
+    }
+}
+
+/// Programmatic construction of a [`FileSearcher`]:
+///
+/// ```no_run
+/// # use filehunter::config::SearchMode;
+/// # use filehunter::server::FileSearcher;
+/// let searcher = FileSearcher::builder()
+///     .location("/imgs")
+///     .mode(SearchMode::Concurrent)
+///     .root("/data/a")
+///     .root_with_extensions("/data/b", ["jpg", "png"])
+///     .build()
+///     .unwrap();
+/// ```
+///
+/// `mode`, `max_file_size` and `root` apply to the most recent `location`;
+/// used before any `location`, they start a catch-all `"/"` location.
+/// Locations added after a [`vhost`](Self::vhost) belong to it.
+/// Server options not set via [`server`](Self::server) keep their defaults.
+#[derive(Debug, Default)]
+pub struct FileSearcherBuilder {
+    server: ServerConfig,
+    locations: Vec<LocationConfig>,
+    vhosts: Vec<VhostConfig>,
+}
+
+impl FileSearcherBuilder {
+    pub fn server(mut self, server: ServerConfig) -> Self {
+        self.server = server;
+        self
+    }
+
+    /// Start a new site served for the given `Host` names.
+    pub fn vhost<I, S>(mut self, hosts: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.vhosts.push(VhostConfig {
+            hosts: hosts.into_iter().map(Into::into).collect(),
+            locations: Vec::new(),
+        });
+        self
+    }
+
+    /// Start a new location with the given URL prefix.
+    pub fn location(mut self, prefix: impl Into<String>) -> Self {
+        self.site_locations().push(empty_location(prefix.into()));
+        self
+    }
+
+    pub fn mode(mut self, mode: SearchMode) -> Self {
+        self.current().mode = mode;
+        self
+    }
+
+    /// Search the current location with the strategy registered as `name`.
+    pub fn search_plugin(mut self, name: impl Into<String>) -> Self {
+        let location = self.current();
+        location.mode = SearchMode::Plugin;
+        location.search_plugin = name.into();
+        self
+    }
+
+    /// How long `SearchMode::Hedged` waits for the first root, in
+    /// milliseconds (default 20).
+    pub fn hedge_delay_ms(mut self, ms: u64) -> Self {
+        self.current().hedge_delay_ms = Some(ms);
+        self
+    }
+
+    /// Per-location file size limit in bytes (0 = unlimited).
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.current().max_file_size = Some(ByteSize(bytes));
+        self
+    }
+
+    /// Also serve members of `.zip` / `.tar` containers on a miss.
+    pub fn search_archives(mut self, enabled: bool) -> Self {
+        self.current().search_archives = enabled;
+        self
+    }
+
+    /// Require client credentials for the current location.
+    pub fn auth(mut self, auth: LocationAuth) -> Self {
+        self.current().auth = Some(auth);
+        self
+    }
+
+    /// Cap the current location's response body bytes per second.
+    pub fn egress_limit(mut self, limit: ByteSize) -> Self {
+        self.current().egress_limit = Some(limit);
+        self
+    }
+
+    /// Security headers for the current location, replacing the server's.
+    pub fn security_headers(mut self, security: SecurityHeadersConfig) -> Self {
+        self.current().security_headers = Some(security);
+        self
+    }
+
+    /// Error response format for the current location.
+    pub fn error_format(mut self, format: ErrorFormat) -> Self {
+        self.current().error_format = Some(format);
+        self
+    }
+
+    /// HTML error page for the current location.
+    pub fn error_template(mut self, template: impl Into<PathBuf>) -> Self {
+        self.current().error_template = Some(template.into());
+        self
+    }
+
+    /// Image resizing settings for the current location.
+    pub fn images(mut self, images: ImageConfig) -> Self {
+        self.current().images = images;
+        self
+    }
+
+    /// Add a redirect to the current location.
+    pub fn redirect(mut self, rule: RedirectRule) -> Self {
+        self.current().redirects.push(rule);
+        self
+    }
+
+    /// `ETag` scheme for the current location.
+    pub fn etag(mut self, mode: EtagMode) -> Self {
+        self.current().etag = mode;
+        self
+    }
+
+    /// Trailing-slash policy for the current location.
+    pub fn trailing_slash(mut self, policy: TrailingSlash) -> Self {
+        self.current().trailing_slash = policy;
+        self
+    }
+
+    /// Methods the current location accepts besides `GET` and `HEAD`.
+    pub fn methods(mut self, methods: Vec<String>) -> Self {
+        self.current().methods = methods;
+        self
+    }
+
+    /// Byte-range settings for the current location.
+    pub fn ranges(mut self, ranges: RangesConfig) -> Self {
+        self.current().ranges = ranges;
+        self
+    }
+
+    /// Make the current location writable, storing uploads in `root`,
+    /// which must also be added as one of its roots.
+    pub fn upload_root(mut self, root: impl Into<PathBuf>) -> Self {
+        let location = self.current();
+        location.writable = true;
+        location.upload_root = root.into();
+        self
+    }
+
+    /// Copy the current location's uploads to this many other roots.
+    pub fn upload_replicas(mut self, replicas: usize) -> Self {
+        self.current().upload_replicas = replicas;
+        self
+    }
+
+    /// Fetch the current location's misses from this `http(s)://` base URL.
+    pub fn fallback_upstream(mut self, url: impl Into<String>) -> Self {
+        self.current().fallback_upstream = url.into();
+        self
+    }
+
+    /// Keep files fetched from the fallback upstream in `root`, which must
+    /// also be added as one of the current location's roots.
+    pub fn fallback_cache_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.current().fallback_cache_root = root.into();
+        self
+    }
+
+    /// Log level for the current location's requests, replacing `RUST_LOG`.
+    pub fn log_level(mut self, level: LogLevel) -> Self {
+        self.current().log_level = Some(level);
+        self
+    }
+
+    /// Keep the debug and trace events of one in `sample` requests to the
+    /// current location.
+    pub fn log_sample(mut self, sample: u32) -> Self {
+        self.current().log_sample = sample;
+        self
+    }
+
+    /// `Cache-Control` rules for the current location.
+    pub fn cache_control(mut self, cache_control: CacheControlConfig) -> Self {
+        self.current().cache_control = cache_control;
+        self
+    }
+
+    /// Extensions the current location always serves as attachments.
+    pub fn attachment_extensions<I, S>(mut self, extensions: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.current().attachment_extensions = extensions.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Overrides for the current location's files with extension `ext`.
+    pub fn extension_rule(mut self, ext: impl Into<String>, rule: ExtensionRule) -> Self {
+        self.current().extension_rules.insert(ext.into(), rule);
+        self
+    }
+
+    /// What the current location does with files whose leading bytes
+    /// contradict their extension.
+    pub fn magic_bytes(mut self, magic_bytes: MagicBytes) -> Self {
+        self.current().magic_bytes = magic_bytes;
+        self
+    }
+
+    /// Add a root that serves every file type.
+    pub fn root(self, root: impl Into<PathBuf>) -> Self {
+        self.path(SearchPath {
+            root: root.into(),
+            ..Default::default()
+        })
+    }
+
+    /// Add a root restricted to the given extensions.
+    pub fn root_with_extensions<I, S>(self, root: impl Into<PathBuf>, extensions: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.path(SearchPath {
+            root: root.into(),
+            extensions: extensions.into_iter().map(Into::into).collect(),
+            ..Default::default()
+        })
+    }
+
+    /// Add a fully specified search path (e.g. a remote root with `auth`).
+    pub fn path(mut self, path: SearchPath) -> Self {
+        self.current().paths.push(path);
+        self
+    }
+
+    /// Validate the assembled configuration and build the searcher.
+    pub fn build(self) -> Result<FileSearcher, String> {
+        let config = Config {
+            server: self.server,
+            locations: self.locations,
+            vhosts: self.vhosts,
+        };
+        config.validate()?;
+        Ok(FileSearcher::new(&config))
+    }
+
+    fn current(&mut self) -> &mut LocationConfig {
+        let locations = self.site_locations();
+        if locations.is_empty() {
+            locations.push(empty_location("/".into()));
+        }
+        locations.last_mut().unwrap()
+    }
+
+    /// The locations of the most recent `vhost`, else the top-level ones.
+    fn site_locations(&mut self) -> &mut Vec<LocationConfig> {
+        match self.vhosts.last_mut() {
+            Some(vhost) => &mut vhost.locations,
+            None => &mut self.locations,
+        }
+    }
+}
+
+/// `GET` and `HEAD`, then the location's `methods`, or `PUT` and `POST`
+/// when it is writable and lists none. Validation has vetted the names.
+fn location_methods(loc: &LocationConfig) -> Vec<Method> {
+    let mut methods = READ_METHODS.to_vec();
+    let extra = match loc.methods.as_slice() {
+        [] if loc.writable => vec![Method::PUT, Method::POST],
+        names => names
+            .iter()
+            .filter_map(|name| name.to_ascii_uppercase().parse().ok())
+            .collect(),
+    };
+    for method in extra {
+        if !methods.contains(&method) {
+            methods.push(method);
+        }
+    }
+    methods
+}
+
+/// The location's `fallback_upstream`, if set and usable.
+fn fallback(loc: &LocationConfig, prefix: &str, connector: &Connector) -> Option<Fallback> {
+    if loc.fallback_upstream.is_empty() {
+        return None;
+    }
+    let entry = SearchPath {
+        root: PathBuf::from(&loc.fallback_upstream),
+        ..Default::default()
+    };
+    match connector.open(&entry, loc.symlinks) {
+        Ok((identity, backend)) => Some(Fallback {
+            identity,
+            backend,
+            cache: Some(loc.fallback_cache_root.clone()).filter(|p| !p.as_os_str().is_empty()),
+        }),
+        Err(e) => {
+            warn!(prefix, upstream = %loc.fallback_upstream, error = %e, "cannot use fallback_upstream, ignoring");
+            None
+        }
+    }
+}
+
+/// `Allow` header value listing `methods`.
+fn allow_header(methods: &[Method]) -> HeaderValue {
+    let names: Vec<&str> = methods.iter().map(Method::as_str).collect();
+    HeaderValue::from_str(&names.join(", ")).expect("method names are valid header values")
+}
+
+fn empty_location(prefix: String) -> LocationConfig {
+    LocationConfig {
+        prefix,
+        ..Default::default()
+    }
+}
+
+/// A file located by [`FileSearcher::search`], with its body already open.
+pub struct SearchHit {
+    /// Root the file was found under (canonical path or URL).
+    pub root: PathBuf,
+    /// Canonical path (or URL) of the file itself.
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+    /// Content type guessed from the file extension.
+    pub mime: mime_guess::Mime,
+    pub body: ObjectBody,
+}
+
+impl SearchHit {
+    fn new(root: PathBuf, found: FoundObject) -> Self {
+        Self {
+            root,
+            mime: mime_guess::from_path(&found.path).first_or_octet_stream(),
+            path: found.path,
+            size: found.size,
+            modified: found.modified,
+            body: found.body,
+        }
+    }
+}
+
+/// One of a location's healthy roots, offered to a [`SearchStrategy`] for
+/// the request being resolved.
+pub struct Candidate<'a> {
+    location: &'a Location,
+    root: Arc<SearchRoot>,
+    relative: &'a Path,
+    ext: &'a str,
+    request_path: &'a str,
+}
+
+impl Candidate<'_> {
+    /// The root (canonical path or URL).
+    pub fn root(&self) -> &Path {
+        &self.root.path
+    }
+
+    /// Whether the root is a directory on the local filesystem.
+    pub fn is_local(&self) -> bool {
+        self.root.local_dir().is_some()
+    }
+
+    /// Look the requested file up under this root, applying the root's
+    /// extension filter, the size limit, the soft timeout and the circuit
+    /// breaker as the built-in modes do.
+    pub async fn probe(&self) -> Result<Option<SearchHit>, Refused> {
+        let (relative, request_path) = (self.relative, self.request_path);
+        let probe = self
+            .location
+            .try_within(&self.root, relative, self.ext, request_path);
+        probe.await.map_err(|()| Refused)
+    }
+}
+
+/// The path escaped a root or crossed a refused symlink. The built-in
+/// modes end the search without a result, and strategies should too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Refused;
+
+/// Runtime view of a location and its roots, for reports and admin endpoints.
+#[derive(Debug, Clone, Serialize)]
+pub struct LocationStatus {
+    pub prefix: String,
+    pub mode: SearchMode,
+    pub max_file_size: u64,
+    pub roots: Vec<RootStatus>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RootStatus {
+    /// Canonical path for active roots, configured path for skipped ones.
+    pub path: PathBuf,
+    pub active: bool,
+    /// Result of the latest health probe (always `true` when checks are off).
+    pub healthy: bool,
+    /// `None` when circuit breakers are off.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit: Option<CircuitState>,
+    /// Why the root is not active.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Every root where a requested file exists, as seen by [`FileSearcher::find_all`].
+pub(crate) struct AllMatches<'a> {
+    pub prefix: &'a str,
+    pub mode: SearchMode,
+    /// `(root, resolved path, size, mtime)` in config order.
+    pub matches: Vec<(PathBuf, PathBuf, u64, SystemTime)>,
+}
+
+/// A search result with the location that produced it, as seen by
+/// [`FileSearcher::resolve`].
+pub(crate) struct Resolved<'a> {
+    pub prefix: &'a str,
+    pub mode: SearchMode,
+    pub hit: SearchHit,
+}
+
+/// How a path resolves, root by root, as traced by [`FileSearcher::explain`].
+#[derive(Debug, Serialize)]
+pub(crate) struct Explanation {
+    pub path: String,
+    /// Prefix of the matching location; `None` when no location matches.
+    pub location: Option<String>,
+    pub mode: Option<SearchMode>,
+    /// Why the path was refused before any root was probed.
+    pub refused: Option<&'static str>,
+    /// Healthy roots in probe order, then unhealthy and inactive ones.
+    pub probes: Vec<RootProbe>,
+    /// The root that serves the file.
+    pub winner: Option<String>,
+    /// Set when the file is a member of a `.zip` / `.tar` container.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container_member: Option<String>,
+}
+
+/// One root's part in an [`Explanation`].
+#[derive(Debug, Serialize)]
+pub(crate) struct RootProbe {
+    pub root: String,
+    pub outcome: ProbeOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub elapsed_us: Option<u64>,
+    /// The file the root holds (also when it is too large).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Why an inactive root could not be opened, or its probe failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl RootProbe {
+    fn new(root: &Path, outcome: ProbeOutcome) -> Self {
+        Self {
+            root: root.display().to_string(),
+            outcome,
+            elapsed_us: None,
+            resolved: None,
+            size: None,
+            error: None,
+        }
+    }
+
+    /// The outcome of a backend probe of `root` that took `elapsed`.
+    fn probed(
+        root: &Path,
+        result: &Result<Option<FoundObject>, ProbeError>,
+        max_file_size: u64,
+        elapsed: Duration,
+    ) -> Self {
+        let mut probe = Self::new(root, ProbeOutcome::NotFound);
+        probe.elapsed_us = Some(u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX));
+        probe.outcome = match result {
+            Err(ProbeError::Traversal) => ProbeOutcome::Traversal,
+            Err(ProbeError::Failed(error)) => {
+                probe.error = Some(error.clone());
+                ProbeOutcome::Failed
+            }
+            Ok(None) => ProbeOutcome::NotFound,
+            Ok(Some(found)) => {
+                probe.resolved = Some(found.path.display().to_string());
+                probe.size = Some(found.size);
+                match max_file_size > 0 && found.size > max_file_size {
+                    true => ProbeOutcome::TooLarge,
+                    false => ProbeOutcome::Found,
+                }
+            }
+        };
+        probe
+    }
+}
+
+/// What the searches for one request did, root by root, recorded while
+/// they run when the request asked for debug headers.
+#[derive(Default)]
+pub(crate) struct ProbeTrace {
+    /// In the order the probes finished.
+    probes: Mutex<Vec<RootProbe>>,
+    /// The root that served the file, the file it resolved to there, and
+    /// whether the root is remote.
+    winner: Mutex<Option<(PathBuf, PathBuf, bool)>>,
+}
+
+impl ProbeTrace {
+    /// Record a root the search passed over without probing it.
+    fn skipped(root: &Path, outcome: ProbeOutcome) {
+        let _ = PROBE_TRACE.try_with(|trace| {
+            let probe = RootProbe::new(root, outcome);
+            trace.probes.lock().unwrap().push(probe);
+        });
+    }
+
+    /// Record a probe abandoned at the root's soft timeout.
+    fn timed_out(root: &Path, limit: Duration) {
+        let _ = PROBE_TRACE.try_with(|trace| {
+            let mut probe = RootProbe::new(root, ProbeOutcome::Failed);
+            probe.elapsed_us = Some(u64::try_from(limit.as_micros()).unwrap_or(u64::MAX));
+            probe.error = Some("soft timeout".into());
+            trace.probes.lock().unwrap().push(probe);
+        });
+    }
+
+    /// Describe the request's searches, answered in `elapsed`: the winning
+    /// root in `X-Resolved-Root`, the time of each root's probe in
+    /// `Server-Timing`, and for remote roots whether the remote cache held
+    /// the file in `Cache-Status`. The response is marked `no-store` so no
+    /// shared cache keeps these headers.
+    fn add_debug_headers(&self, elapsed: Duration, headers: &mut HeaderMap) {
+        let millis = |us: u64| us as f64 / 1000.0;
+        let mut timing = vec![format!("total;dur={:.3}", elapsed.as_secs_f64() * 1000.0)];
+        for probe in self.probes.lock().unwrap().iter() {
+            let outcome = serde_json::to_value(probe.outcome).unwrap_or_default();
+            let desc = format!("{} {}", probe.root, outcome.as_str().unwrap_or_default());
+            let desc = desc.replace('\\', "\\\\").replace('"', "\\\"");
+            let mut metric = format!("probe;desc=\"{desc}\"");
+            if let Some(us) = probe.elapsed_us {
+                metric.push_str(&format!(";dur={:.3}", millis(us)));
+            }
+            timing.push(metric);
+        }
+        if let Ok(value) = HeaderValue::from_str(&timing.join(", ")) {
+            headers.insert("server-timing", value);
+        }
+
+        if let Some((root, resolved, remote)) = &*self.winner.lock().unwrap() {
+            if let Ok(value) = HeaderValue::from_str(&root.to_string_lossy()) {
+                headers.insert("x-resolved-root", value);
+            }
+            // Remote objects resolve to their URL, cached copies to a file.
+            if *remote {
+                let status = match resolved.to_string_lossy().contains("://") {
+                    true => "filehunter; fwd=miss",
+                    false => "filehunter; hit",
+                };
+                headers.insert("cache-status", HeaderValue::from_static(status));
+            }
+        }
+        headers.insert("cache-control", HeaderValue::from_static("no-store"));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ProbeOutcome {
+    Found,
+    /// The root's `extensions` do not include the file's.
+    ExtensionFiltered,
+    /// Larger than `max_file_size`.
+    TooLarge,
+    /// Missing (ENOENT) or not a regular file.
+    NotFound,
+    /// Escaped the root or crossed a refused symlink; ends the search.
+    Traversal,
+    /// Excluded by the health checker.
+    Unhealthy,
+    /// Skipped while the root's circuit breaker is open.
+    CircuitOpen,
+    /// The root could not answer (I/O error, failed upstream request).
+    Failed,
+    /// Not opened yet (see `root_retry_interval`).
+    Inactive,
+    /// Skipped because an earlier root decided the search.
+    NotProbed,
+}
+
+// ---------------------------------------------------------------------------
+// Shared search helpers
+// ---------------------------------------------------------------------------
+
+/// Probe one root's backend and enforce the size limit.
+async fn probe_backend(
+    backend: &dyn StorageBackend,
+    relative: &Path,
+    max_file_size: u64,
+    request_path: &str,
+) -> Result<Option<FoundObject>, ProbeError> {
+    let found = backend.probe(relative, request_path).await?;
+    Ok(found.and_then(|found| within_limit(found, max_file_size, request_path)))
+}
+
+/// `found`, unless it is larger than `max_file_size` (0 = no limit).
+fn within_limit(found: FoundObject, max_file_size: u64, request_path: &str) -> Option<FoundObject> {
+    if max_file_size > 0 && found.size > max_file_size {
+        debug!(
+            request_path, resolved = %found.path.display(),
+            size = found.size, limit = max_file_size,
+            "skipped (file too large)"
+        );
+        return None;
+    }
+    Some(found)
+}
+
+/// Attempt to find the file under a single search root (with extension filter).
+async fn try_root(
+    root: &SearchRoot,
+    relative: &Path,
+    ext: &str,
+    max_file_size: u64,
+    request_path: &str,
+) -> Result<Option<SearchHit>, ()> {
+    if !root.accepts(ext) {
+        debug!(
+            request_path, root = %root.path.display(), ext,
+            "skipped (extension not allowed)"
+        );
+        ProbeTrace::skipped(&root.path, ProbeOutcome::ExtensionFiltered);
+        return Ok(None);
+    }
+    let found = root.probe(relative, max_file_size, request_path).await?;
+    Ok(found.map(|obj| SearchHit::new(root.path.clone(), obj)))
+}
+
+/// Wait for the first handle that returns `Some`. The others are aborted
+/// as they are dropped: once a probe wins, or with the search itself when
+/// the client goes away.
+async fn race_handles(mut handles: Vec<AbortOnDropHandle<Option<SearchHit>>>) -> Option<SearchHit> {
     let mut result = None;
 
     while !handles.is_empty() {
@@ -330,9 +2868,7 @@ async fn race_handles(
         match finished {
             Ok(Some(found)) => {
                 result = Some(found);
-                for h in remaining {
-                    h.abort();
-                }
+                drop(remaining);
                 break;
             }
             _ => {
@@ -340,59 +2876,155 @@ async fn race_handles(
             }
         }
     }
-
-    result
+
+    result
+}
+
+/// Spawnable probe for a single root — owns all data for `tokio::spawn`.
+/// Extension filtering must be done before calling this.
+async fn probe_root(
+    root: Arc<SearchRoot>,
+    relative: PathBuf,
+    max_file_size: u64,
+    request_path: String,
+) -> Option<SearchHit> {
+    root.probe(&relative, max_file_size, &request_path)
+        .await
+        .unwrap_or_default()
+        .map(|obj| SearchHit::new(root.path.clone(), obj))
+}
+
+/// [`try_root`] for [`Location::explain`]: the outcome of one root, with
+/// the modification time of a found file.
+async fn explain_root(
+    root: &SearchRoot,
+    relative: &Path,
+    ext: &str,
+    max_file_size: u64,
+    request_path: &str,
+) -> (RootProbe, Option<SystemTime>) {
+    let mut probe = RootProbe::new(&root.path, ProbeOutcome::ExtensionFiltered);
+    if !root.accepts(ext) {
+        return (probe, None);
+    }
+    if root.breaker.as_ref().is_some_and(|b| b.state() == CircuitState::Open) {
+        probe.outcome = ProbeOutcome::CircuitOpen;
+        return (probe, None);
+    }
+    let started = Instant::now();
+    let result = root.backend.probe(relative, request_path).await;
+    let probe = RootProbe::probed(&root.path, &result, max_file_size, started.elapsed());
+    let modified = match (&result, probe.outcome) {
+        (Ok(Some(found)), ProbeOutcome::Found) => Some(found.modified),
+        _ => None,
+    };
+    (probe, modified)
+}
+
+/// `probe` run under the request's [`ProbeTrace`], if it has one, so a
+/// probe spawned off the request's task still records into it.
+fn traced<F: Future>(probe: F) -> impl Future<Output = F::Output> {
+    let trace = PROBE_TRACE.try_with(Arc::clone).ok();
+    async move {
+        match trace {
+            Some(trace) => PROBE_TRACE.scope(trace, probe).await,
+            None => probe.await,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Path sanitization
+// ---------------------------------------------------------------------------
+
+/// Value of `key` in a raw query string (first occurrence, not decoded).
+#[cfg(feature = "archive")]
+fn query_param<'a>(query: Option<&'a str>, key: &str) -> Option<&'a str> {
+    query?
+        .split('&')
+        .filter_map(|kv| kv.split_once('='))
+        .find_map(|(k, v)| (k == key).then_some(v))
+}
+
+/// Caps on the percent-decoded request path (0 = unlimited).
+#[derive(Debug, Clone, Copy, Default)]
+struct PathLimits {
+    max_length: usize,
+    max_segments: usize,
+}
+
+impl PathLimits {
+    /// The status to refuse `decoded` with: 414 when too long, 400 when it
+    /// has too many segments.
+    fn check(self, decoded: &str) -> Result<(), StatusCode> {
+        if self.max_length > 0 && decoded.len() > self.max_length {
+            return Err(StatusCode::URI_TOO_LONG);
+        }
+        if self.max_segments > 0
+            && decoded.split('/').filter(|seg| !seg.is_empty()).count() > self.max_segments
+        {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        Ok(())
+    }
 }
 
-/// Spawnable probe for a single root — owns all data for `tokio::spawn`.
-/// Extension filtering must be done before calling this.
-async fn probe_root(
-    root_path: PathBuf,
-    candidate: PathBuf,
-    max_file_size: u64,
-    request_path: String,
-) -> Option<SearchResult> {
-    probe_candidate(&root_path, candidate, max_file_size, &request_path)
-        .await
-        .unwrap_or_default()
+/// Why a request path was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Refusal {
+    /// Undecodable, empty or over the path limits.
+    Malformed,
+    /// Refused for a reason worth an audit record.
+    Blocked(AuditEvent),
 }
 
-// ---------------------------------------------------------------------------
-// Path sanitization
-// ---------------------------------------------------------------------------
+impl Refusal {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Malformed => "malformed",
+            Self::Blocked(event) => event.as_str(),
+        }
+    }
+}
 
 /// Convert a raw URL path into a safe relative filesystem path.
 ///
-/// Rejects: null bytes, `..`, `.`, dotfiles, and any non-normal component.
-fn sanitize_path(raw: &str) -> Option<PathBuf> {
+/// Rejects: paths over `limits`, null bytes, `..`, `.`, dotfiles not allowed
+/// by `hidden`, and any non-normal component.
+fn sanitize_path(raw: &str, hidden: &HiddenFiles, limits: PathLimits) -> Result<PathBuf, Refusal> {
     let decoded = percent_encoding::percent_decode_str(raw)
         .decode_utf8()
-        .ok()?;
+        .map_err(|_| Refusal::Malformed)?;
+    limits.check(&decoded).map_err(|_| Refusal::Malformed)?;
 
     // Null bytes could truncate the path at the OS level.
     if decoded.contains('\0') {
-        return None;
+        return Err(Refusal::Blocked(AuditEvent::PathTraversal));
     }
 
     let mut clean = PathBuf::new();
     for component in Path::new(decoded.as_ref()).components() {
         match component {
             Component::Normal(seg) => {
-                // Block hidden files / directories (e.g. .env, .git).
-                if seg.as_encoded_bytes().first() == Some(&b'.') {
-                    return None;
+                // Block hidden files / directories (e.g. .env, .git) unless
+                // the location allows them.
+                if seg.as_encoded_bytes().first() == Some(&b'.')
+                    && !seg.to_str().is_some_and(|name| hidden.allows(name))
+                {
+                    return Err(Refusal::Blocked(AuditEvent::HiddenFile));
                 }
                 clean.push(seg);
             }
             Component::RootDir => {}
-            _ => return None, // reject "..", prefix, etc.
+            // reject "..", prefix, etc.
+            _ => return Err(Refusal::Blocked(AuditEvent::PathTraversal)),
         }
     }
 
     if clean.as_os_str().is_empty() {
-        return None;
+        return Err(Refusal::Malformed);
     }
-    Some(clean)
+    Ok(clean)
 }
 
 // ---------------------------------------------------------------------------
@@ -405,31 +3037,139 @@ pub async fn handle_request(
     limiter: Option<Arc<KeyedLimiter>>,
     client_ip: IpAddr,
 ) -> Result<Response<ResponseBody>, Infallible> {
-    // Per-IP rate limiting (checked before anything else).
+    let site = searcher.vhosts.site(&req);
+    vhost::scope(site, handle_in_site(req, searcher, limiter, client_ip)).await
+}
+
+/// [`handle_request`], with locations matched in the request's site.
+async fn handle_in_site(
+    req: Request<impl hyper::body::Body + Send + 'static>,
+    searcher: Arc<FileSearcher>,
+    limiter: Option<Arc<KeyedLimiter>>,
+    client_ip: IpAddr,
+) -> Result<Response<ResponseBody>, Infallible> {
+    let security = searcher.security_headers_for(req.uri().path());
+    let rendering = Rendering::for_request(&req, searcher.error_pages_for(req.uri().path()));
+    let secure = |resp: Response<ResponseBody>| {
+        let mut resp = rendering.render(resp);
+        for (name, value) in security.iter().flat_map(|headers| headers.iter()) {
+            resp.headers_mut().insert(name, value.clone());
+        }
+        resp
+    };
+
+    // Denied clients are refused before they consume rate limit tokens.
+    if let Some(denylist) = &searcher.denylist
+        && denylist.contains(client_ip)
+    {
+        debug!(status = 403, %client_ip, "request handled (denylisted)");
+        searcher.audit(AuditEvent::DeniedClient, client_ip, req.uri().path());
+        return Ok(secure(text_response(StatusCode::FORBIDDEN, "Forbidden")));
+    }
+
+    // Per-IP rate limiting. Allowed responses carry the client's quota.
+    let mut quota = None;
     if let Some(ref lim) = limiter
-        && let Err(not_until) = lim.check_key(&client_ip)
+        && !searcher.rate_limit_exempt(client_ip)
     {
-        let wait = not_until.wait_time_from(governor::clock::DefaultClock::default().now());
-        let retry_after = wait.as_secs().max(1);
-        debug!(
-            status = 429, %client_ip, retry_after,
-            "request handled (rate limited)"
-        );
-        return Ok(Response::builder()
-            .status(StatusCode::TOO_MANY_REQUESTS)
-            .header("Retry-After", retry_after)
-            .header("Content-Type", "text/plain; charset=utf-8")
-            .header("X-Content-Type-Options", "nosniff")
-            .body(full_body("Too Many Requests"))
-            .unwrap());
+        match lim.check(client_ip) {
+            Ok(status) => quota = Some(status),
+            Err(status) => {
+                let retry_after = status.reset;
+                debug!(
+                    status = 429, %client_ip, retry_after,
+                    "request handled (rate limited)"
+                );
+                let mut resp = text_response(StatusCode::TOO_MANY_REQUESTS, "Too Many Requests");
+                resp.headers_mut().insert("Retry-After", retry_after.into());
+                status.apply(resp.headers_mut());
+                return Ok(secure(resp));
+            }
+        }
+    }
+
+    // The WASM filter may rewrite the path before anything else reads it.
+    #[cfg(feature = "wasm")]
+    let mut req = req;
+    #[cfg(feature = "wasm")]
+    let pinned = match searcher.filter_request(&mut req, client_ip) {
+        Ok(pinned) => pinned,
+        Err((status, body)) => return Ok(secure(text_response(status, body))),
+    };
+
+    let egress = searcher.egress_for(req.uri().path());
+    // Kept for the audit record of a failed authentication.
+    let raw_path = searcher
+        .audit
+        .is_some()
+        .then(|| req.uri().path().to_owned());
+    let span = searcher.log_span(req.uri().path());
+    let routed = route(req, searcher.clone(), client_ip);
+    #[cfg(feature = "wasm")]
+    let routed = PINNED_ROOT.scope(pinned, routed);
+    let mut resp = routed.instrument(span).await?;
+    if resp.status() == StatusCode::UNAUTHORIZED
+        && let Some(path) = &raw_path
+    {
+        searcher.audit(AuditEvent::AuthFailure, client_ip, path);
+    }
+    if let Some(quota) = quota {
+        quota.apply(resp.headers_mut());
+    }
+    Ok(bandwidth::throttle(secure(resp), egress))
+}
+
+/// Dispatch an admitted request to the admin, batch, S3-style or
+/// all-matches endpoints, or to [`serve_location`].
+async fn route(
+    req: Request<impl hyper::body::Body + Send + 'static>,
+    searcher: Arc<FileSearcher>,
+    client_ip: IpAddr,
+) -> Result<Response<ResponseBody>, Infallible> {
+    if let Some(token) = &searcher.admin_token
+        && req.uri().path().starts_with("/_admin/")
+    {
+        return Ok(admin::handle(req, &searcher, token).await);
+    }
+
+    if let Some(max_paths) = searcher.batch_max_paths
+        && req.uri().path() == "/_batch"
+    {
+        return Ok(batch::handle(req, &searcher, max_paths).await);
+    }
+
+    #[cfg(feature = "s3-api")]
+    if let Some(api) = &searcher.s3_api
+        && let Some(rest) = s3api::strip_mount(req.uri().path(), &api.prefix)
+    {
+        let is_head = req.method() == Method::HEAD;
+        let routed = s3api::route(req.method(), req.uri(), rest, &searcher, api).await;
+        let (path, resource) = match routed {
+            S3Route::Respond(resp) => return Ok(resp),
+            S3Route::Object { path, resource } => (path, resource),
+        };
+        // Served as a plain read of the location path.
+        let mut req = req;
+        *req.uri_mut() = path;
+        let resp = serve_counted(req, searcher, client_ip).await?;
+        return Ok(s3api::object_response(resp, &resource, is_head));
     }
 
-    if req.method() != Method::GET && req.method() != Method::HEAD {
+    let allowed = searcher.allowed_methods(req.uri().path());
+    if !allowed.contains(req.method()) {
         debug!(status = 405, method = %req.method(), "request handled");
-        return Ok(text_response(
-            StatusCode::METHOD_NOT_ALLOWED,
-            "Method Not Allowed",
-        ));
+        let mut resp = text_response(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed");
+        resp.headers_mut().insert("Allow", allow_header(allowed));
+        return Ok(resp);
+    }
+    if req.method() == Method::OPTIONS {
+        debug!(status = 204, method = %req.method(), path = %req.uri().path(), "request handled");
+        let resp = Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header("Allow", allow_header(allowed))
+            .body(empty_body())
+            .unwrap();
+        return Ok(resp);
     }
 
     // Reject requests with an oversized or malformed Content-Length.
@@ -448,35 +3188,333 @@ pub async fn handle_request(
         }
     }
 
+    let path = req.uri().path();
+
+    // Bound pathological paths before any filesystem work.
+    let decoded = percent_encoding::percent_decode_str(path).decode_utf8_lossy();
+    if let Err(status) = searcher.path_limits.check(&decoded) {
+        debug!(status = status.as_u16(), path, "request handled");
+        let reason = status.canonical_reason().unwrap_or_default();
+        return Ok(text_response(status, reason));
+    }
+
+    if let Some(token) = &searcher.admin_token
+        && let Some(target) = path.strip_prefix("/_matches")
+        && target.starts_with('/')
+    {
+        return Ok(admin::matches(req.headers(), &searcher, token, target).await);
+    }
+
+    if let Some(token) = &searcher.admin_token
+        && let Some(target) = path.strip_prefix("/_explain")
+        && target.starts_with('/')
+    {
+        return Ok(admin::explain(req.headers(), &searcher, token, target).await);
+    }
+
+    let trace = searcher.debug_requested(req.headers());
+    let trace = trace.then(Arc::<ProbeTrace>::default);
+    let started = Instant::now();
+    let served = serve_counted(req, searcher.clone(), client_ip);
+    let mut resp = match &trace {
+        Some(trace) => PROBE_TRACE.scope(trace.clone(), served).await?,
+        None => served.await?,
+    };
+    // Never describe a location the client failed to authenticate to.
+    if let Some(trace) = trace
+        && resp.status() != StatusCode::UNAUTHORIZED
+    {
+        trace.add_debug_headers(started.elapsed(), resp.headers_mut());
+    }
+    Ok(resp)
+}
+
+/// [`serve_location`], counted in the matching location's stats.
+async fn serve_counted(
+    req: Request<impl hyper::body::Body + Send + 'static>,
+    searcher: Arc<FileSearcher>,
+    client_ip: IpAddr,
+) -> Result<Response<ResponseBody>, Infallible> {
+    // Counted as aborted if this future or the body is dropped early.
+    let transfer = searcher.stats_for(req.uri().path()).map(|stats| {
+        stats.record_request();
+        Transfer::new(stats)
+    });
+    let resp = serve_location(req, searcher, client_ip).await?;
+    Ok(match transfer {
+        Some(transfer) => stats::count_body(resp, transfer),
+        None => resp,
+    })
+}
+
+/// Serve a request addressed to a location: authorization, then the
+/// upload, archive, image, meta or file handlers.
+async fn serve_location(
+    req: Request<impl hyper::body::Body + Send + 'static>,
+    searcher: Arc<FileSearcher>,
+    client_ip: IpAddr,
+) -> Result<Response<ResponseBody>, Infallible> {
     let path = req.uri().path();
     let is_head = req.method() == Method::HEAD;
 
-    match searcher.search(path).await {
-        Some((file_path, file, size)) => {
+    // Locations with `auth` are checked before anything is searched.
+    let guarded = match path.strip_prefix("/_meta") {
+        Some(target) if searcher.meta_endpoint && target.starts_with('/') => target,
+        _ => path,
+    };
+    if !searcher.authorized(guarded, req.headers(), req.uri().query()).await {
+        debug!(status = 401, path, "request handled (unauthorized)");
+        let mut resp = text_response(StatusCode::UNAUTHORIZED, "Unauthorized");
+        if let Some(challenge) = searcher.auth_challenge(guarded) {
+            resp.headers_mut().insert(hyper::header::WWW_AUTHENTICATE, challenge);
+        }
+        return Ok(resp);
+    }
+
+    // Probes for traversal, dotfiles and denied names still just miss, but
+    // leave an audit record. Files matching deny_patterns are quarantined.
+    if (searcher.audit.is_some() || searcher.quarantine.is_some())
+        && let Some(event) = searcher.blocked_reason(guarded)
+    {
+        let entry = match event {
+            AuditEvent::DeniedPattern => searcher.quarantine_denied(guarded).await,
+            _ => None,
+        };
+        searcher.audit_file(event, client_ip, path, entry.as_deref());
+    }
+
+    if (req.method() == Method::GET || is_head)
+        && let Some((status, target)) = searcher.redirect_for(path)
+    {
+        debug!(status = status.as_u16(), path, target, "request handled");
+        // A target with its own query replaces the request's.
+        let query = req.uri().query().filter(|_| !target.contains('?'));
+        return Ok(redirect(status, &target, query));
+    }
+
+    if req.method() == Method::PUT {
+        let path = path.to_owned();
+        return Ok(upload::handle(req.into_body(), &searcher, &path, client_ip).await);
+    }
+    if req.method() == Method::POST {
+        let (parts, body) = req.into_parts();
+        let content_type = parts
+            .headers
+            .get(hyper::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok());
+        let path = parts.uri.path();
+        return Ok(upload::handle_form(body, content_type, &searcher, path, client_ip).await);
+    }
+
+    #[cfg(feature = "archive")]
+    if let Some(max_entries) = searcher.archive_max_entries
+        && let Some(format) = query_param(req.uri().query(), "archive")
+    {
+        let Some(format) = ArchiveFormat::parse(format) else {
+            return Ok(text_response(StatusCode::BAD_REQUEST, "Unknown archive format"));
+        };
+        let resp = archive::handle(&searcher, path, format, max_entries, is_head, client_ip);
+        return Ok(resp.await);
+    }
+
+    #[cfg(feature = "images")]
+    if let Some(query) = req.uri().query()
+        && let Some(images) = searcher.images_for(path)
+        && let Some(params) = ImageParams::from_query(query)
+    {
+        let accept = req.headers().get(hyper::header::ACCEPT).and_then(|v| v.to_str().ok());
+        return Ok(images.respond(&searcher, path, params, accept, is_head).await);
+    }
+
+    if searcher.meta_endpoint
+        && let Some(target) = path.strip_prefix("/_meta")
+        && target.starts_with('/')
+    {
+        return Ok(meta::handle(&searcher, target).await);
+    }
+
+    let started = Instant::now();
+    let found = searcher.search(path).await;
+    if let Some(stats) = searcher.stats_for(path) {
+        stats.record_lookup(found.is_some(), started.elapsed());
+    }
+    if let Some(shadow) = &searcher.shadow {
+        shadow.compare(path, found.as_ref().map(|hit| hit.path.as_path()));
+    }
+    let found = match found {
+        None => searcher.fetch_fallback(path).await,
+        hit => hit,
+    };
+    if let Some((status, target)) = searcher.slash_redirect(path, found.is_some()).await {
+        debug!(status = status.as_u16(), path, target, "request handled");
+        return Ok(redirect(status, &target, req.uri().query()));
+    }
+    let found = match found {
+        Some(hit) => searcher.check_magic(path, hit, client_ip).await,
+        None => None,
+    };
+    if let Some((hit, _)) = &found
+        && let Err(status) = searcher.scan_hit(path, hit, client_ip).await
+    {
+        debug!(status = status.as_u16(), path, "request handled (scan)");
+        let reason = status.canonical_reason().unwrap_or_default();
+        return Ok(text_response(status, reason));
+    }
+    match found {
+        Some((mut hit, mislabeled)) => {
+            // Whether a sidecar may stand in for the file, which makes
+            // every response for it vary by `Accept-Encoding`.
+            let is_file = matches!(hit.body, ObjectBody::File(_));
+            let sidecars = !searcher.precompressed.is_empty()
+                && is_file
+                && precompress::compressible(&hit.mime);
+
+            // Ranges are served from local files only, where the location
+            // allows them. An If-Range that no longer matches falls back to
+            // the full file.
+            let validators = searcher.validators(path, &hit).await;
+            let limits = searcher.range_limits(path).filter(|_| is_file);
+            let if_range = req.headers().get(hyper::header::IF_RANGE);
+            let current = if_range.is_none_or(|v| v.to_str().is_ok_and(|v| validators.if_range(v)));
+            let ranges = match (req.headers().get(hyper::header::RANGE), limits) {
+                (Some(v), Some(limits)) if current => v.to_str().map_or(RangeRequest::Full, |v| {
+                    RangeRequest::parse(v, hit.size, limits)
+                }),
+                _ => RangeRequest::Full,
+            };
+
+            // A current sidecar stands in for the whole file, under its
+            // own validators; ranges are always served from the file itself.
+            let encoded = match req.headers().get(hyper::header::ACCEPT_ENCODING) {
+                Some(accept) if sidecars && ranges == RangeRequest::Full => {
+                    let accept = accept.to_str().ok();
+                    precompress::open(&hit.path, hit.modified, accept, &searcher.precompressed)
+                        .await
+                }
+                _ => None,
+            };
+            let validators = match &encoded {
+                Some((encoding, ..)) => validators.encoded(encoding.extension()),
+                None => validators,
+            };
+
+            let cache_control = searcher.cache_control_for(path);
+            match validators.evaluate(req.headers()) {
+                Precondition::Proceed => {}
+                Precondition::NotModified => {
+                    debug!(status = 304, path, resolved = %hit.path.display(), "request handled");
+                    let mut resp = validators.not_modified();
+                    if sidecars {
+                        add_vary(resp.headers_mut(), hyper::header::ACCEPT_ENCODING);
+                    }
+                    if let Some(value) = cache_control
+                        && let Ok(value) = HeaderValue::from_str(&value)
+                    {
+                        resp.headers_mut().insert("cache-control", value);
+                    }
+                    return Ok(resp);
+                }
+                Precondition::Failed => {
+                    debug!(status = 412, path, resolved = %hit.path.display(), "request handled");
+                    return Ok(conditional::failed());
+                }
+            }
+            if ranges == RangeRequest::Unsatisfiable {
+                debug!(status = 416, path, size = hit.size, "request handled");
+                return Ok(range::unsatisfiable(hit.size));
+            }
+
             debug!(
-                status = 200, path,
-                resolved = %file_path.display(), size,
+                status = if ranges == RangeRequest::Full { 200 } else { 206 }, path,
+                root = %hit.root.display(), resolved = %hit.path.display(), size = hit.size,
                 "request handled"
             );
-            let mime = mime_guess::from_path(&file_path).first_or_octet_stream();
 
-            let body = if is_head {
-                empty_body()
-            } else {
-                stream_body(file, searcher.stream_buffer_size)
-            };
+            let mut builder = Response::builder()
+                .header("Accept-Ranges", if limits.is_some() { "bytes" } else { "none" })
+                .header("Last-Modified", validators.last_modified())
+                .header("X-Content-Type-Options", "nosniff");
+            if let Some(etag) = validators.etag() {
+                builder = builder.header("ETag", etag);
+            }
+            if sidecars {
+                builder = builder.header("Vary", "Accept-Encoding");
+            }
+            if let Some(value) = cache_control {
+                builder = builder.header("Cache-Control", value);
+            }
+            if searcher.resolved_root_header
+                && let Ok(root) = HeaderValue::from_str(&hit.root.to_string_lossy())
+            {
+                builder = builder.header("X-Resolved-Root", root);
+            }
+            let filename = disposition::requested_filename(req.uri().query(), path).or_else(|| {
+                (mislabeled || searcher.forces_attachment(path, &hit.path))
+                    .then(|| disposition::path_filename(path))
+            });
+            if let Some(filename) = filename {
+                let value = disposition::header("attachment", &filename);
+                builder = builder.header("Content-Disposition", value);
+            }
+            #[cfg(feature = "digest")]
+            if let Some(digests) = &searcher.digests
+                && searcher.digest_headers
+                && is_file
+                && encoded.is_none()
+                && let Some(digest) = digests.get(&hit.path, hit.size, hit.modified).await
+            {
+                builder = builder
+                    .header("Repr-Digest", format!("sha-256=:{digest}:"))
+                    .header("Digest", format!("sha-256={digest}"));
+            }
 
-            Ok(Response::builder()
+            let mut size = hit.size;
+            if let Some((encoding, file, encoded_size)) = encoded {
+                builder = builder.header("Content-Encoding", encoding.token());
+                hit.body = ObjectBody::File(file);
+                size = encoded_size;
+            }
+            #[cfg(feature = "webhooks")]
+            if !is_head {
+                let served = match &ranges {
+                    RangeRequest::Partial(ranges) => ranges.iter().map(|r| r.end - r.start).sum(),
+                    _ => size,
+                };
+                let status = match ranges {
+                    RangeRequest::Full => StatusCode::OK,
+                    _ => StatusCode::PARTIAL_CONTENT,
+                };
+                searcher.served(path, &hit, client_ip, status, served);
+            }
+            let body = match (ranges, hit.body) {
+                (RangeRequest::Partial(ranges), ObjectBody::File(file)) => {
+                    return Ok(range::respond(
+                        builder,
+                        file,
+                        hit.mime.as_ref(),
+                        hit.size,
+                        ranges,
+                        searcher.stream_buffer_size,
+                        is_head,
+                    ));
+                }
+                _ if is_head => empty_body(),
+                (_, body) => stream_body(body, searcher.stream_buffer_size),
+            };
+            let builder = builder
                 .status(StatusCode::OK)
-                .header("Content-Type", mime.as_ref())
-                .header("Content-Length", size)
-                .header("Accept-Ranges", "none")
-                .header("X-Content-Type-Options", "nosniff")
-                .body(body)
-                .unwrap())
+                .header("Content-Type", hit.mime.as_ref())
+                .header("Content-Length", size);
+            Ok(builder.body(body).unwrap())
         }
         None => {
             debug!(status = 404, path, "request handled");
+            if let Some(misses) = &searcher.misses {
+                let location = searcher
+                    .match_location(path)
+                    .map(|(loc, _)| loc.label.clone());
+                misses.record(path, location, client_ip);
+            }
             Ok(text_response(StatusCode::NOT_FOUND, "Not Found"))
         }
     }
@@ -486,93 +3524,209 @@ pub async fn handle_request(
 // Body helpers
 // ---------------------------------------------------------------------------
 
-fn empty_body() -> ResponseBody {
+pub(crate) fn empty_body() -> ResponseBody {
     Empty::<Bytes>::new()
         .map_err(|never| match never {})
         .boxed()
 }
 
-fn full_body(data: &'static str) -> ResponseBody {
+/// Buffer a request body, failing with 413 past `limit` bytes or 400 on a
+/// transport error.
+pub(crate) async fn collect_body<B: hyper::body::Body>(body: B, limit: u64) -> Result<Bytes, StatusCode> {
+    let mut body = std::pin::pin!(body);
+    let mut buf = bytes::BytesMut::new();
+    while let Some(frame) = body.frame().await {
+        let frame = frame.map_err(|_| StatusCode::BAD_REQUEST)?;
+        if let Ok(mut data) = frame.into_data() {
+            if buf.len() as u64 + data.remaining() as u64 > limit {
+                return Err(StatusCode::PAYLOAD_TOO_LARGE);
+            }
+            buf.extend_from_slice(&data.copy_to_bytes(data.remaining()));
+        }
+    }
+    Ok(buf.freeze())
+}
+
+pub(crate) fn full_bytes(data: Bytes) -> ResponseBody {
+    Full::new(data).map_err(|never| match never {}).boxed()
+}
+
+pub(crate) fn full_body(data: &'static str) -> ResponseBody {
     Full::new(Bytes::from(data))
         .map_err(|never| match never {})
         .boxed()
 }
 
-fn stream_body(file: File, buffer_size: usize) -> ResponseBody {
-    let stream = ReaderStream::with_capacity(file, buffer_size);
-    StreamBody::new(stream.map_ok(Frame::data)).boxed()
+pub(crate) fn stream_body(body: ObjectBody, buffer_size: usize) -> ResponseBody {
+    match body {
+        ObjectBody::File(file) => {
+            let stream = ReaderStream::with_capacity(file, buffer_size);
+            StreamBody::new(stream.map_ok(Frame::data)).boxed()
+        }
+        ObjectBody::Stream(stream) => StreamBody::new(stream.map_ok(Frame::data)).boxed(),
+    }
 }
 
-fn text_response(status: StatusCode, message: &'static str) -> Response<ResponseBody> {
+/// A plain-text response. Error statuses are re-rendered as problem
+/// details for clients that ask for them.
+pub(crate) fn text_response(
+    status: StatusCode,
+    message: impl Into<Cow<'static, str>>,
+) -> Response<ResponseBody> {
+    let message = message.into();
+    let body = match message.clone() {
+        Cow::Borrowed(message) => full_body(message),
+        Cow::Owned(message) => full_bytes(message.into()),
+    };
     Response::builder()
         .status(status)
         .header("Content-Type", "text/plain; charset=utf-8")
         .header("X-Content-Type-Options", "nosniff")
-        .body(full_body(message))
+        .extension(ErrorMessage(message))
+        .body(body)
+        .unwrap()
+}
+
+/// A redirect to `path`, keeping the request's `query`.
+pub(crate) fn redirect(
+    status: StatusCode,
+    path: &str,
+    query: Option<&str>,
+) -> Response<ResponseBody> {
+    let target = match query {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_owned(),
+    };
+    let mut resp = text_response(status, status.canonical_reason().unwrap_or_default());
+    if let Ok(target) = HeaderValue::try_from(target) {
+        resp.headers_mut().insert(hyper::header::LOCATION, target);
+    }
+    resp
+}
+
+/// List `name` in the response's `Vary`, unless it (or `*`) already is.
+pub(crate) fn add_vary(headers: &mut HeaderMap, name: HeaderName) {
+    let listed = headers
+        .get_all(hyper::header::VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .any(|v| v == "*" || v.eq_ignore_ascii_case(name.as_str()));
+    if !listed {
+        headers.append(hyper::header::VARY, name.into());
+    }
+}
+
+/// Seconds since the Unix epoch (0 for pre-epoch timestamps).
+pub(crate) fn unix_secs(t: SystemTime) -> u64 {
+    t.duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+pub(crate) fn json_response(status: StatusCode, value: &impl serde::Serialize) -> Response<ResponseBody> {
+    let json = serde_json::to_vec(value).expect("JSON serialization cannot fail");
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .header("X-Content-Type-Options", "nosniff")
+        .body(full_bytes(Bytes::from(json)))
         .unwrap()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::backend::LocalBackend;
     use crate::config::{normalize_prefix, SearchMode};
 
     // -----------------------------------------------------------------------
-    // sanitize_path — security-critical (10 tests)
+    // sanitize_path — security-critical (12 tests)
     // -----------------------------------------------------------------------
 
+    fn sanitize(raw: &str) -> Option<PathBuf> {
+        sanitize_path(raw, &HiddenFiles::Deny, PathLimits::default()).ok()
+    }
+
     #[test]
     fn sanitize_normal_path() {
-        let p = sanitize_path("/foo/bar.txt").unwrap();
+        let p = sanitize("/foo/bar.txt").unwrap();
         assert_eq!(p, PathBuf::from("foo/bar.txt"));
     }
 
     #[test]
     fn sanitize_nested_path() {
-        let p = sanitize_path("/a/b/c/d.png").unwrap();
+        let p = sanitize("/a/b/c/d.png").unwrap();
         assert_eq!(p, PathBuf::from("a/b/c/d.png"));
     }
 
     #[test]
     fn sanitize_single_file() {
-        let p = sanitize_path("/readme.md").unwrap();
+        let p = sanitize("/readme.md").unwrap();
         assert_eq!(p, PathBuf::from("readme.md"));
     }
 
     #[test]
     fn sanitize_rejects_null_byte() {
-        assert!(sanitize_path("/foo\0bar").is_none());
+        assert!(sanitize("/foo\0bar").is_none());
     }
 
     #[test]
     fn sanitize_rejects_dotdot() {
-        assert!(sanitize_path("/foo/../etc/passwd").is_none());
+        assert!(sanitize("/foo/../etc/passwd").is_none());
     }
 
     #[test]
     fn sanitize_rejects_dotfile() {
-        assert!(sanitize_path("/.env").is_none());
+        assert!(sanitize("/.env").is_none());
     }
 
     #[test]
     fn sanitize_rejects_hidden_dir() {
-        assert!(sanitize_path("/.git/config").is_none());
+        assert!(sanitize("/.git/config").is_none());
+    }
+
+    #[test]
+    fn sanitize_allows_listed_hidden_segments() {
+        let well_known = HiddenFiles::Allowlist(vec![".well-known".into()]);
+        let limits = PathLimits::default();
+        let p = sanitize_path("/.well-known/security.txt", &well_known, limits).unwrap();
+        assert_eq!(p, PathBuf::from(".well-known/security.txt"));
+        let refused = sanitize_path("/.well-known/.env", &well_known, limits);
+        assert_eq!(refused, Err(Refusal::Blocked(AuditEvent::HiddenFile)));
+        assert!(sanitize_path("/.git/config", &HiddenFiles::Allow, limits).is_ok());
+        let refused = sanitize_path("/../etc", &HiddenFiles::Allow, limits);
+        assert_eq!(refused, Err(Refusal::Blocked(AuditEvent::PathTraversal)));
+    }
+
+    #[test]
+    fn sanitize_enforces_path_limits() {
+        let limits = PathLimits {
+            max_length: 8,
+            max_segments: 2,
+        };
+        assert_eq!(limits.check("/a/b.txt"), Ok(()));
+        assert_eq!(limits.check("/a/bc.txt"), Err(StatusCode::URI_TOO_LONG));
+        assert_eq!(limits.check("/a/b/c"), Err(StatusCode::BAD_REQUEST));
+        // Length is measured after percent-decoding.
+        assert!(sanitize_path("/%61/b.txt", &HiddenFiles::Deny, limits).is_ok());
+        let refused = sanitize_path("/a//b/c", &HiddenFiles::Deny, limits);
+        assert_eq!(refused, Err(Refusal::Malformed));
     }
 
     #[test]
     fn sanitize_rejects_empty() {
-        assert!(sanitize_path("/").is_none());
+        assert!(sanitize("/").is_none());
     }
 
     #[test]
     fn sanitize_url_encoded_space() {
-        let p = sanitize_path("/foo%20bar.txt").unwrap();
+        let p = sanitize("/foo%20bar.txt").unwrap();
         assert_eq!(p, PathBuf::from("foo bar.txt"));
     }
 
     #[test]
     fn sanitize_url_encoded_dotdot() {
-        assert!(sanitize_path("/%2e%2e/etc/passwd").is_none());
+        assert!(sanitize("/%2e%2e/etc/passwd").is_none());
     }
 
     // -----------------------------------------------------------------------
@@ -584,6 +3738,12 @@ mod tests {
         let root = SearchRoot {
             path: PathBuf::from("/tmp"),
             extensions: None,
+            health: Arc::default(),
+            hits: AtomicU64::default(),
+            ranking: Ranking::default(),
+            breaker: None,
+            soft_timeout: SoftTimeout::default(),
+            backend: Arc::new(LocalBackend::new(Path::new("/tmp")).unwrap()),
         };
         assert!(root.accepts("gif"));
     }
@@ -594,6 +3754,12 @@ mod tests {
         let root = SearchRoot {
             path: PathBuf::from("/tmp"),
             extensions: Some(set),
+            health: Arc::default(),
+            hits: AtomicU64::default(),
+            ranking: Ranking::default(),
+            breaker: None,
+            soft_timeout: SoftTimeout::default(),
+            backend: Arc::new(LocalBackend::new(Path::new("/tmp")).unwrap()),
         };
         assert!(root.accepts("JPG"));
     }
@@ -604,6 +3770,12 @@ mod tests {
         let root = SearchRoot {
             path: PathBuf::from("/tmp"),
             extensions: Some(set),
+            health: Arc::default(),
+            hits: AtomicU64::default(),
+            ranking: Ranking::default(),
+            breaker: None,
+            soft_timeout: SoftTimeout::default(),
+            backend: Arc::new(LocalBackend::new(Path::new("/tmp")).unwrap()),
         };
         assert!(!root.accepts("gif"));
     }
@@ -618,16 +3790,82 @@ mod tests {
             .iter()
             .map(|p| Location {
                 prefix: normalize_prefix(p),
-                roots: vec![],
+                site: 0,
+                label: normalize_prefix(p),
+                roots: RwLock::default(),
+                skipped: Mutex::default(),
                 search_mode: SearchMode::Sequential,
+                strategy: None,
+                hedge_delay: Duration::ZERO,
+                newest_window: NewestWindow::default(),
+                search_archives: false,
+                #[cfg(feature = "images")]
+                images: None,
+                ranges: None,
+                hidden_files: HiddenFiles::Deny,
+                symlinks: SymlinkPolicy::SameRoot,
+                trailing_slash: None,
+                etag: EtagMode::Mtime,
+                redirects: Vec::new(),
+                fallback: None,
+                deny_patterns: None,
+                path_limits: PathLimits::default(),
+                attachment_extensions: HashSet::new(),
+                extension_rules: HashMap::new(),
+                magic_bytes: MagicBytes::Off,
+                served_events: false,
+                cache_control: CacheControl::default(),
+                log: None,
+                breaker: None,
+                auth: None,
+                egress: None,
+                security_headers: None,
+                error_pages: ErrorPages::default(),
+                stats: Arc::default(),
+                search_latency: SearchLatency::default(),
+                upload: None,
+                methods: READ_METHODS.to_vec(),
                 max_file_size: 0,
             })
             .collect();
-        locations.sort_by(|a, b| b.prefix.len().cmp(&a.prefix.len()));
+        locations.sort_by_key(|loc| std::cmp::Reverse(loc.prefix.len()));
         FileSearcher {
             locations,
             max_body_size: 1_048_576,
+            path_limits: PathLimits::default(),
             stream_buffer_size: 65536,
+            admin_token: None,
+            #[cfg(feature = "archive")]
+            archive_max_entries: None,
+            batch_max_paths: None,
+            #[cfg(feature = "s3-api")]
+            s3_api: None,
+            meta_endpoint: false,
+            resolved_root_header: false,
+            debug_token: None,
+            #[cfg(feature = "digest")]
+            digests: None,
+            #[cfg(feature = "digest")]
+            digest_headers: false,
+            precompressed: Vec::new(),
+            connections: Arc::default(),
+            denylist: None,
+            audit: None,
+            quarantine: None,
+            antivirus: None,
+            #[cfg(feature = "webhooks")]
+            webhooks: None,
+            #[cfg(feature = "wasm")]
+            wasm_filter: None,
+            misses: None,
+            rate_limit_exempt: Vec::new(),
+            egress: None,
+            security_headers: None,
+            error_pages: ErrorPages::default(),
+            vhosts: Vhosts::default(),
+            shadow: None,
+            warm_list: None,
+            connector: Connector::new(&Default::default()),
         }
     }
 
@@ -674,4 +3912,389 @@ mod tests {
         let s = searcher_with_prefixes(&["/imgs"]);
         assert!(s.match_location("/videos/x").is_none());
     }
+
+    // -----------------------------------------------------------------------
+    // FileSearcherBuilder (2 tests)
+    // -----------------------------------------------------------------------
+
+    #[test]
+    fn builder_defaults_to_catch_all_location() {
+        let dir = tempfile::tempdir().unwrap();
+        let s = FileSearcher::builder()
+            .mode(SearchMode::LatestModified)
+            .root(dir.path())
+            .build()
+            .unwrap();
+        let status = s.status();
+        assert_eq!(status.len(), 1);
+        assert_eq!(status[0].prefix, "/");
+        assert_eq!(status[0].mode, SearchMode::LatestModified);
+        assert!(status[0].roots[0].active);
+    }
+
+    #[test]
+    fn builder_validates_locations() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = FileSearcher::builder()
+            .location("/imgs")
+            .root(dir.path())
+            .location("/empty")
+            .build()
+            .err()
+            .unwrap();
+        assert!(err.contains("at least one path"), "error: {err}");
+    }
+
+    // -----------------------------------------------------------------------
+    // Deterministic search-mode simulation (11 tests)
+    //
+    // Roots are in-memory backends with fixed latencies; tests run on a
+    // paused clock, so timings are exact and event order is reproducible.
+    // -----------------------------------------------------------------------
+
+    use crate::backend::BoxFuture;
+    use crate::backend::memory::{EventLog, MemoryBackend};
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    fn sim_location(mode: SearchMode, backends: Vec<MemoryBackend>) -> Location {
+        let roots = backends
+            .into_iter()
+            .enumerate()
+            .map(|(i, backend)| {
+                Arc::new(SearchRoot {
+                    path: PathBuf::from(format!("mem://root{i}")),
+                    extensions: None,
+                    health: Arc::default(),
+                    hits: AtomicU64::default(),
+                    ranking: Ranking::default(),
+                    breaker: None,
+                    soft_timeout: SoftTimeout::default(),
+                    backend: Arc::new(backend),
+                })
+            })
+            .collect();
+        Location {
+            prefix: "/".into(),
+            site: 0,
+            label: "/".into(),
+            roots: RwLock::new(roots),
+            skipped: Mutex::default(),
+            search_mode: mode,
+            strategy: None,
+            hedge_delay: ms(20),
+            newest_window: NewestWindow::default(),
+            search_archives: false,
+            #[cfg(feature = "images")]
+            images: None,
+            ranges: None,
+            hidden_files: HiddenFiles::Deny,
+            symlinks: SymlinkPolicy::SameRoot,
+            trailing_slash: None,
+            etag: EtagMode::Mtime,
+            redirects: Vec::new(),
+            fallback: None,
+            deny_patterns: None,
+            path_limits: PathLimits::default(),
+            attachment_extensions: HashSet::new(),
+            extension_rules: HashMap::new(),
+            magic_bytes: MagicBytes::Off,
+            served_events: false,
+            cache_control: CacheControl::default(),
+            log: None,
+            breaker: None,
+            auth: None,
+            egress: None,
+            security_headers: None,
+            error_pages: ErrorPages::default(),
+            stats: Arc::default(),
+            search_latency: SearchLatency::default(),
+            upload: None,
+            methods: READ_METHODS.to_vec(),
+            max_file_size: 0,
+        }
+    }
+
+    fn events(log: &EventLog) -> Vec<String> {
+        log.lock().unwrap().clone()
+    }
+
+    /// Let aborted tasks be dropped so their cancellation is logged.
+    async fn settle() {
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sim_sequential_stops_at_first_hit() {
+        let log = EventLog::default();
+        let t = SystemTime::UNIX_EPOCH;
+        let loc = sim_location(
+            SearchMode::Sequential,
+            vec![
+                MemoryBackend::new("a", ms(10), &log).with_file("f.txt", "a", t),
+                MemoryBackend::new("b", ms(1), &log).with_file("f.txt", "b", t),
+            ],
+        );
+
+        let start = Instant::now();
+        let found = loc.search("/f.txt").await.unwrap();
+        assert_eq!(found.path, PathBuf::from("mem://a/f.txt"));
+        assert_eq!(start.elapsed(), ms(10));
+        assert_eq!(events(&log), ["a:start", "a:found"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sim_sequential_soft_timeout_moves_on() {
+        let log = EventLog::default();
+        let t = SystemTime::UNIX_EPOCH;
+        let mut loc = sim_location(
+            SearchMode::Sequential,
+            vec![
+                MemoryBackend::new("a", ms(50), &log).with_file("f.txt", "a", t),
+                MemoryBackend::new("b", ms(5), &log).with_file("f.txt", "b", t),
+            ],
+        );
+        let slow = &mut loc.roots.get_mut().unwrap()[0];
+        Arc::get_mut(slow).unwrap().soft_timeout = SoftTimeout {
+            limit: Some(ms(20)),
+            finish_late: true,
+            ..Default::default()
+        };
+
+        let start = Instant::now();
+        let found = loc.search("/f.txt").await.unwrap();
+        assert_eq!(found.path, PathBuf::from("mem://b/f.txt"));
+        assert_eq!(start.elapsed(), ms(25));
+
+        // The abandoned probe runs on and counts its late hit.
+        tokio::time::sleep(ms(30)).await;
+        settle().await;
+        assert_eq!(events(&log), ["a:start", "b:start", "b:found", "a:found"]);
+        let stats = &loc.roots.read().unwrap()[0].soft_timeout;
+        assert_eq!(stats.expired.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.late_hits.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sim_concurrent_fastest_wins_and_cancels_rest() {
+        let log = EventLog::default();
+        let t = SystemTime::UNIX_EPOCH;
+        let loc = sim_location(
+            SearchMode::Concurrent,
+            vec![
+                MemoryBackend::new("a", ms(50), &log).with_file("f.txt", "a", t),
+                MemoryBackend::new("b", ms(5), &log).with_file("f.txt", "b", t),
+                MemoryBackend::new("c", ms(100), &log),
+            ],
+        );
+
+        let start = Instant::now();
+        let found = loc.search("/f.txt").await.unwrap();
+        assert_eq!(found.path, PathBuf::from("mem://b/f.txt"));
+        assert_eq!(found.root, PathBuf::from("mem://root1"));
+        assert_eq!(found.mime, mime_guess::mime::TEXT_PLAIN);
+        assert_eq!(start.elapsed(), ms(5));
+
+        settle().await;
+        let ev = events(&log);
+        assert!(ev.contains(&"a:cancelled".to_string()), "{ev:?}");
+        assert!(ev.contains(&"c:cancelled".to_string()), "{ev:?}");
+        assert!(!ev.contains(&"a:found".to_string()), "{ev:?}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sim_concurrent_dropped_search_cancels_probes() {
+        let log = EventLog::default();
+        let loc = sim_location(
+            SearchMode::Concurrent,
+            vec![
+                MemoryBackend::new("a", ms(50), &log),
+                MemoryBackend::new("b", ms(100), &log),
+            ],
+        );
+
+        // The client goes away mid-search: the request future is dropped.
+        let search = tokio::time::timeout(ms(10), loc.search("/f.txt")).await;
+        assert!(search.is_err());
+        settle().await;
+        tokio::time::sleep(ms(100)).await;
+        let mut ev = events(&log);
+        ev.sort();
+        assert_eq!(ev, ["a:cancelled", "a:start", "b:cancelled", "b:start"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sim_concurrent_all_miss_waits_for_slowest() {
+        let log = EventLog::default();
+        let loc = sim_location(
+            SearchMode::Concurrent,
+            vec![
+                MemoryBackend::new("a", ms(10), &log),
+                MemoryBackend::new("b", ms(30), &log),
+            ],
+        );
+
+        let start = Instant::now();
+        assert!(loc.search("/f.txt").await.is_none());
+        assert_eq!(start.elapsed(), ms(30));
+        assert_eq!(events(&log).iter().filter(|e| e.ends_with(":miss")).count(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sim_concurrent_equal_latency_prefers_config_order() {
+        let log = EventLog::default();
+        let t = SystemTime::UNIX_EPOCH;
+        let loc = sim_location(
+            SearchMode::Concurrent,
+            vec![
+                MemoryBackend::new("a", ms(5), &log).with_file("f.txt", "a", t),
+                MemoryBackend::new("b", ms(5), &log).with_file("f.txt", "b", t),
+            ],
+        );
+
+        let found = loc.search("/f.txt").await.unwrap();
+        assert_eq!(found.path, PathBuf::from("mem://a/f.txt"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sim_hedged_waits_for_first_root_then_races() {
+        let log = EventLog::default();
+        let t = SystemTime::UNIX_EPOCH;
+        let backends = |first| {
+            vec![
+                MemoryBackend::new("a", ms(first), &log).with_file("f.txt", "a", t),
+                MemoryBackend::new("b", ms(5), &log).with_file("f.txt", "b", t),
+            ]
+        };
+
+        // A first root answering within the delay wins alone.
+        let loc = sim_location(SearchMode::Hedged, backends(15));
+        let found = loc.search("/f.txt").await.unwrap();
+        assert_eq!(found.path, PathBuf::from("mem://a/f.txt"));
+        assert_eq!(events(&log), ["a:start", "a:found"]);
+
+        // A slow one is raced against the rest from the delay on.
+        log.lock().unwrap().clear();
+        let loc = sim_location(SearchMode::Hedged, backends(50));
+        let start = Instant::now();
+        let found = loc.search("/f.txt").await.unwrap();
+        assert_eq!(found.path, PathBuf::from("mem://b/f.txt"));
+        assert_eq!(start.elapsed(), ms(25));
+        settle().await;
+        let ev = events(&log);
+        assert_eq!(ev, ["a:start", "b:start", "b:found", "a:cancelled"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sim_latest_modified_picks_newest() {
+        let log = EventLog::default();
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        let new = SystemTime::UNIX_EPOCH + Duration::from_secs(200);
+        let loc = sim_location(
+            SearchMode::LatestModified,
+            vec![
+                MemoryBackend::new("a", ms(1), &log).with_file("f.txt", "a", old),
+                MemoryBackend::new("b", ms(1), &log).with_file("f.txt", "b", new),
+                MemoryBackend::new("c", ms(1), &log).with_file("f.txt", "c", old),
+            ],
+        );
+
+        let found = loc.search("/f.txt").await.unwrap();
+        assert_eq!(found.path, PathBuf::from("mem://b/f.txt"));
+        assert_eq!(events(&log).len(), 6, "every root is probed");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sim_plugin_probes_the_roots_it_picks() {
+        struct LastFirst;
+        impl SearchStrategy for LastFirst {
+            fn search<'a>(
+                &'a self,
+                roots: &'a [Candidate<'a>],
+            ) -> BoxFuture<'a, Option<SearchHit>> {
+                Box::pin(async move {
+                    for root in roots.iter().rev() {
+                        if let Some(hit) = root.probe().await.ok()? {
+                            return Some(hit);
+                        }
+                    }
+                    None
+                })
+            }
+        }
+
+        let log = EventLog::default();
+        let t = SystemTime::UNIX_EPOCH;
+        let mut loc = sim_location(
+            SearchMode::Plugin,
+            vec![
+                MemoryBackend::new("a", ms(1), &log).with_file("f.txt", "a", t),
+                MemoryBackend::new("b", ms(1), &log).with_file("f.txt", "b", t),
+            ],
+        );
+        // Unregistered: every request misses.
+        assert!(loc.search("/f.txt").await.is_none());
+
+        loc.strategy = Some(Arc::new(LastFirst));
+        let found = loc.search("/f.txt").await.unwrap();
+        assert_eq!(found.path, PathBuf::from("mem://b/f.txt"));
+        assert_eq!(events(&log), ["b:start", "b:found"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sim_newest_within_needs_recent_or_clear_lead() {
+        let log = EventLog::default();
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        let skewed = SystemTime::UNIX_EPOCH + Duration::from_secs(130);
+        let now = SystemTime::now();
+        let location = |newer| {
+            sim_location(
+                SearchMode::NewestWithin,
+                vec![
+                    MemoryBackend::new("a", ms(1), &log).with_file("f.txt", "a", old),
+                    MemoryBackend::new("b", ms(1), &log).with_file("f.txt", "b", newer),
+                ],
+            )
+        };
+        let secs = |n| Some(Duration::from_secs(n));
+
+        // 30s ahead is within the skew the lead allows for.
+        let mut loc = location(skewed);
+        loc.newest_window.lead = secs(60);
+        let found = loc.search("/f.txt").await.unwrap();
+        assert_eq!(found.path, PathBuf::from("mem://a/f.txt"));
+        loc.newest_window.lead = secs(10);
+        let found = loc.search("/f.txt").await.unwrap();
+        assert_eq!(found.path, PathBuf::from("mem://b/f.txt"));
+
+        let mut loc = location(now);
+        loc.newest_window.recent = secs(60);
+        let found = loc.search("/f.txt").await.unwrap();
+        assert_eq!(found.path, PathBuf::from("mem://b/f.txt"));
+        loc.newest_window.recent = None;
+        let found = loc.search("/f.txt").await.unwrap();
+        assert_eq!(found.path, PathBuf::from("mem://a/f.txt"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sim_latest_modified_tie_keeps_first_root() {
+        let log = EventLog::default();
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        let loc = sim_location(
+            SearchMode::LatestModified,
+            vec![
+                MemoryBackend::new("a", ms(1), &log).with_file("f.txt", "a", t),
+                MemoryBackend::new("b", ms(1), &log).with_file("f.txt", "b", t),
+            ],
+        );
+
+        let found = loc.search("/f.txt").await.unwrap();
+        assert_eq!(found.path, PathBuf::from("mem://a/f.txt"));
+    }
 }
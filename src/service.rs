@@ -0,0 +1,82 @@
+use std::convert::Infallible;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use hyper::body::Body;
+use hyper::{Request, Response};
+use tower::Service;
+
+use crate::backend::BoxFuture;
+use crate::config::Config;
+use crate::ratelimit::{self, KeyedLimiter};
+use crate::server::{handle_request, FileSearcher, ResponseBody};
+
+/// filehunter's request handling as a tower [`Service`], for embedding in
+/// another server (e.g. `axum::Router::fallback_service`).
+///
+/// The client address used for rate limiting is read from a [`SocketAddr`]
+/// request extension; requests without one share a single bucket.
+#[derive(Clone)]
+pub struct FileHunterService {
+    searcher: Arc<FileSearcher>,
+    limiter: Option<Arc<KeyedLimiter>>,
+}
+
+impl FileHunterService {
+    /// Build the searcher and, if enabled, the rate limiter from `config`.
+    ///
+    /// With rate limiting enabled this spawns the limiter cleanup task, so it
+    /// must be called inside a Tokio runtime. Health checks are not started;
+    /// see [`crate::health::spawn_health_checks`].
+    pub fn new(config: &Config) -> Self {
+        let limiter = config.server.rate_limit.enabled.then(|| {
+            let lim = ratelimit::build_limiter(&config.server.rate_limit);
+            ratelimit::spawn_cleanup(lim.clone(), config.server.rate_limit.cleanup_interval);
+            lim
+        });
+        Self {
+            searcher: Arc::new(FileSearcher::new(config)),
+            limiter,
+        }
+    }
+
+    /// Serve from an existing searcher, without rate limiting.
+    pub fn from_searcher(searcher: Arc<FileSearcher>) -> Self {
+        Self {
+            searcher,
+            limiter: None,
+        }
+    }
+
+    /// Apply a rate limiter (shared with other services if cloned).
+    pub fn with_limiter(mut self, limiter: Arc<KeyedLimiter>) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    pub fn searcher(&self) -> &Arc<FileSearcher> {
+        &self.searcher
+    }
+}
+
+impl<B> Service<Request<B>> for FileHunterService
+where
+    B: Body + Send + 'static,
+{
+    type Response = Response<ResponseBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response<ResponseBody>, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let client_ip = req
+            .extensions()
+            .get::<SocketAddr>()
+            .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), SocketAddr::ip);
+        Box::pin(handle_request(req, self.searcher.clone(), self.limiter.clone(), client_ip))
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tracing::{debug, warn};
+
+use crate::server::FileSearcher;
+
+/// A searcher built from a candidate config, compared against the live one
+/// on a sample of requests.
+pub(crate) struct Shadow {
+    candidate: Arc<FileSearcher>,
+    sample_rate: f64,
+    /// File requests seen, for sampling.
+    seen: AtomicU64,
+    compared: AtomicU64,
+    diverged: AtomicU64,
+}
+
+impl Shadow {
+    pub(crate) fn new(candidate: FileSearcher, sample_rate: f64) -> Self {
+        Self {
+            candidate: Arc::new(candidate),
+            sample_rate: sample_rate.clamp(0.0, 1.0),
+            seen: AtomicU64::new(0),
+            compared: AtomicU64::new(0),
+            diverged: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether to compare the next request. Spreads `sample_rate` evenly
+    /// over the requests instead of drawing at random.
+    fn sample(&self) -> bool {
+        let n = self.seen.fetch_add(1, Ordering::Relaxed) as f64;
+        ((n + 1.0) * self.sample_rate).floor() > (n * self.sample_rate).floor()
+    }
+
+    /// If sampled, resolve `request_path` against the candidate in the
+    /// background and compare with `live`, the file the live config served.
+    pub(crate) fn compare(self: &Arc<Self>, request_path: &str, live: Option<&Path>) {
+        if !self.sample() {
+            return;
+        }
+        let shadow = self.clone();
+        let path = request_path.to_owned();
+        let live = live.map(Path::to_path_buf);
+        tokio::spawn(async move {
+            let candidate = shadow.candidate.search(&path).await.map(|hit| hit.path);
+            shadow.compared.fetch_add(1, Ordering::Relaxed);
+            if candidate == live {
+                debug!(target: "filehunter::shadow", path, "shadow resolution agrees");
+                return;
+            }
+            shadow.diverged.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                target: "filehunter::shadow",
+                path, live = describe(&live), candidate = describe(&candidate),
+                "shadow resolution diverged"
+            );
+        });
+    }
+
+    /// Append the comparison counters in the Prometheus text format.
+    pub(crate) fn render(&self, out: &mut String) {
+        let counters = [
+            (
+                "comparisons",
+                "Requests compared with the candidate config.",
+                &self.compared,
+            ),
+            (
+                "divergences",
+                "Compared requests that resolved differently.",
+                &self.diverged,
+            ),
+        ];
+        for (name, help, counter) in counters {
+            let name = format!("filehunter_shadow_{name}_total");
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} counter");
+            let _ = writeln!(out, "{name} {}", counter.load(Ordering::Relaxed));
+        }
+    }
+}
+
+fn describe(resolved: &Option<PathBuf>) -> String {
+    match resolved {
+        Some(path) => path.display().to_string(),
+        None => "miss".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_requested_fraction() {
+        let dir = tempfile::tempdir().unwrap();
+        let searcher = FileSearcher::builder().root(dir.path()).build().unwrap();
+        let shadow = Shadow::new(searcher, 0.25);
+        let sampled = (0..100).filter(|_| shadow.sample()).count();
+        assert_eq!(sampled, 25);
+    }
+}
@@ -0,0 +1,319 @@
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use hyper::Response;
+use hyper::body::{Body, Frame, SizeHint};
+use hyper::header::CONTENT_LENGTH;
+use serde::Serialize;
+
+use crate::server::ResponseBody;
+
+/// Histogram buckets per power of two of microseconds.
+const SUB_BUCKETS: usize = 4;
+const BUCKETS: usize = 63 * SUB_BUCKETS;
+
+/// Usage counters for one location.
+pub struct LocationStats {
+    /// Requests addressed to the location (internal endpoints excluded).
+    requests: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    /// Response body bytes produced for the location's requests.
+    bytes: AtomicU64,
+    /// Requests the client went away from before the response was complete.
+    aborted: AtomicU64,
+    /// File lookup latencies, log-linear in microseconds: percentiles are
+    /// accurate to within 25%.
+    latency: Box<[AtomicU64]>,
+}
+
+/// Serializable snapshot of a location's counters.
+#[derive(Debug, Clone, Serialize)]
+pub struct LocationStatsInfo {
+    pub prefix: String,
+    pub requests: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub bytes: u64,
+    /// Requests the client went away from mid-search or mid-stream.
+    pub aborted: u64,
+    /// File lookup latency percentiles in microseconds (0 before any lookup).
+    pub p50_us: u64,
+    pub p99_us: u64,
+    /// Active roots in search order.
+    pub roots: Vec<RootStatsInfo>,
+}
+
+/// Files served by one root since it became active.
+#[derive(Debug, Clone, Serialize)]
+pub struct RootStatsInfo {
+    pub path: PathBuf,
+    pub hits: u64,
+    /// Fraction of the location's root hits (0 before any hit).
+    pub share: f64,
+    /// Probes the search gave up on after the root's `soft_timeout_ms`.
+    pub soft_timeouts: u64,
+    /// Of those, probes that went on to find the file (`finish_late`).
+    pub late_hits: u64,
+}
+
+impl Default for LocationStats {
+    fn default() -> Self {
+        Self {
+            requests: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            aborted: AtomicU64::new(0),
+            latency: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+}
+
+impl LocationStats {
+    pub(crate) fn record_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a file lookup that took `elapsed`.
+    pub(crate) fn record_lookup(&self, hit: bool, elapsed: Duration) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        let us = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        self.latency[bucket(us)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn aborted(&self) -> u64 {
+        self.aborted.load(Ordering::Relaxed)
+    }
+
+    /// Snapshot, with the counters of the active roots; their `share` is
+    /// filled in.
+    pub(crate) fn snapshot(&self, prefix: &str, roots: Vec<RootStatsInfo>) -> LocationStatsInfo {
+        let counts: Vec<u64> = self
+            .latency
+            .iter()
+            .map(|c| c.load(Ordering::Relaxed))
+            .collect();
+        LocationStatsInfo {
+            prefix: prefix.to_owned(),
+            requests: self.requests.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            aborted: self.aborted(),
+            p50_us: percentile(&counts, 0.50),
+            p99_us: percentile(&counts, 0.99),
+            roots: root_shares(roots),
+        }
+    }
+}
+
+fn root_shares(mut roots: Vec<RootStatsInfo>) -> Vec<RootStatsInfo> {
+    let total: u64 = roots.iter().map(|r| r.hits).sum();
+    for root in &mut roots {
+        root.share = if total == 0 {
+            0.0
+        } else {
+            root.hits as f64 / total as f64
+        };
+    }
+    roots
+}
+
+/// Histogram bucket for `us`: exact below `SUB_BUCKETS`, then each power of
+/// two split into `SUB_BUCKETS` equal parts.
+fn bucket(us: u64) -> usize {
+    if us < SUB_BUCKETS as u64 {
+        return us as usize;
+    }
+    let exp = 63 - us.leading_zeros() as usize;
+    let sub = (us >> (exp - 2)) as usize & (SUB_BUCKETS - 1);
+    (exp - 1) * SUB_BUCKETS + sub
+}
+
+/// Largest value that falls in bucket `i`.
+fn bucket_max(i: usize) -> u64 {
+    if i < SUB_BUCKETS {
+        return i as u64;
+    }
+    let (exp, sub) = (i / SUB_BUCKETS + 1, i % SUB_BUCKETS);
+    let end = ((SUB_BUCKETS + sub + 1) as u128) << (exp - 2);
+    u64::try_from(end - 1).unwrap_or(u64::MAX)
+}
+
+/// The `q` quantile of the histogram `counts`, as its bucket's upper value.
+fn percentile(counts: &[u64], q: f64) -> u64 {
+    let total: u64 = counts.iter().sum();
+    let rank = ((total as f64 * q).ceil() as u64).max(1);
+    let mut seen = 0;
+    for (i, count) in counts.iter().enumerate() {
+        seen += count;
+        if seen >= rank {
+            return bucket_max(i);
+        }
+    }
+    0
+}
+
+// ---------------------------------------------------------------------------
+// Response body wrapper
+// ---------------------------------------------------------------------------
+
+/// A request being answered, counted as aborted if dropped before
+/// [`count_body`] has produced all of its response.
+pub(crate) struct Transfer {
+    stats: Arc<LocationStats>,
+    done: bool,
+}
+
+impl Transfer {
+    pub(crate) fn new(stats: Arc<LocationStats>) -> Self {
+        Self { stats, done: false }
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        if !self.done {
+            self.stats.aborted.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Add the body bytes of `resp` to the transfer's stats as they are
+/// produced.
+pub(crate) fn count_body(
+    resp: Response<ResponseBody>,
+    mut transfer: Transfer,
+) -> Response<ResponseBody> {
+    // HTTP/1 stops polling a body once its Content-Length has been sent.
+    let remaining = resp
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok()?.parse().ok());
+    resp.map(|inner| {
+        transfer.done = inner.is_end_stream() || remaining == Some(0);
+        CountedBody {
+            inner,
+            transfer,
+            remaining,
+        }
+        .boxed()
+    })
+}
+
+struct CountedBody {
+    inner: ResponseBody,
+    transfer: Transfer,
+    /// Body bytes still to come, when the length is known.
+    remaining: Option<u64>,
+}
+
+impl Body for CountedBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, io::Error>>> {
+        let this = self.get_mut();
+        let polled = Pin::new(&mut this.inner).poll_frame(cx);
+        match &polled {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    let len = data.len() as u64;
+                    this.transfer.stats.bytes.fetch_add(len, Ordering::Relaxed);
+                    if let Some(remaining) = &mut this.remaining {
+                        *remaining = remaining.saturating_sub(len);
+                    }
+                }
+                this.transfer.done = this.inner.is_end_stream() || this.remaining == Some(0);
+            }
+            // A failed body is the server's doing, not an abort.
+            Poll::Ready(_) => this.transfer.done = true,
+            Poll::Pending => {}
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_bound_values() {
+        for us in [0, 1, 3, 4, 5, 9, 10, 1000, 123_456, u64::MAX] {
+            let i = bucket(us);
+            assert!(bucket_max(i) >= us, "{us} above bucket {i}");
+            if i > 0 {
+                assert!(bucket_max(i - 1) < us, "{us} fits bucket {}", i - 1);
+            }
+        }
+    }
+
+    #[test]
+    fn percentiles_from_lookups() {
+        let stats = LocationStats::default();
+        for _ in 0..98 {
+            stats.record_lookup(true, Duration::from_micros(100));
+        }
+        stats.record_lookup(false, Duration::from_millis(10));
+        stats.record_lookup(false, Duration::from_millis(10));
+
+        let info = stats.snapshot("/imgs", vec![]);
+        assert_eq!((info.hits, info.misses), (98, 2));
+        // 100us and 10ms land in [96, 112) and [8192, 10240).
+        assert_eq!(info.p50_us, 111);
+        assert_eq!(info.p99_us, 10_239);
+    }
+
+    #[tokio::test]
+    async fn counts_aborted_transfers() {
+        use crate::server::empty_body;
+        use http_body_util::StreamBody;
+
+        let stats = Arc::new(LocationStats::default());
+        let respond = |body| count_body(Response::new(body), Transfer::new(stats.clone()));
+        let chunks = || {
+            let chunks = [b"ab", b"cd"].map(|c| Ok(Frame::data(Bytes::from_static(c))));
+            StreamBody::new(futures_util::stream::iter(chunks)).boxed()
+        };
+
+        respond(chunks()).into_body().collect().await.unwrap();
+        drop(respond(empty_body()));
+        // HTTP/1 stops after Content-Length bytes, without reading the end.
+        let mut sized = Response::new(chunks());
+        sized.headers_mut().insert(CONTENT_LENGTH, 4.into());
+        let mut body = count_body(sized, Transfer::new(stats.clone())).into_body();
+        body.frame().await.unwrap().unwrap();
+        body.frame().await.unwrap().unwrap();
+        drop(body);
+        assert_eq!(stats.aborted(), 0);
+
+        // Gone mid-stream, and mid-search.
+        let mut body = respond(chunks()).into_body();
+        body.frame().await.unwrap().unwrap();
+        drop(body);
+        drop(Transfer::new(stats.clone()));
+        let info = stats.snapshot("/", vec![]);
+        assert_eq!((info.aborted, info.bytes), (2, 10));
+    }
+}
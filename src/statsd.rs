@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+
+use crate::config::StatsdConfig;
+use crate::server::FileSearcher;
+use crate::stats::LocationStatsInfo;
+
+/// Largest datagram sent; fits a 1500-byte Ethernet MTU.
+const MAX_PACKET: usize = 1432;
+
+/// Spawn the background task that pushes location counters to the statsd
+/// agent at `cfg.host` every `cfg.interval` seconds. Counters are sent as
+/// the increase since the previous push, lookup latency percentiles as
+/// gauges.
+pub fn spawn_statsd(searcher: Arc<FileSearcher>, cfg: &StatsdConfig) {
+    let interval = Duration::from_secs(cfg.interval);
+    let host = cfg.host.clone();
+    let mut encoder = Encoder::new(cfg);
+
+    tokio::spawn(async move {
+        // Counts from before the first push are not sent.
+        encoder.encode(&searcher.stats());
+        let mut socket = None;
+        loop {
+            tokio::time::sleep(interval).await;
+            let lines = encoder.encode(&searcher.stats());
+            if socket.is_none() {
+                socket = connect(&host).await;
+            }
+            let Some(sock) = &socket else {
+                continue;
+            };
+            for packet in packets(&lines) {
+                // Refused while the agent is down; the next push tries again.
+                if let Err(e) = sock.send(packet.as_bytes()).await {
+                    debug!(host, error = %e, "statsd send failed");
+                    break;
+                }
+            }
+        }
+    });
+
+    info!(
+        host = cfg.host,
+        interval_secs = cfg.interval,
+        "statsd export started"
+    );
+}
+
+/// A UDP socket connected to `host`, resolved anew on each attempt.
+async fn connect(host: &str) -> Option<UdpSocket> {
+    let result = async {
+        let addr = tokio::net::lookup_host(host)
+            .await?
+            .next()
+            .ok_or_else(|| std::io::Error::other("no addresses"))?;
+        let local: SocketAddr = if addr.is_ipv4() {
+            "0.0.0.0:0".parse().unwrap()
+        } else {
+            "[::]:0".parse().unwrap()
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(addr).await?;
+        Ok::<_, std::io::Error>(socket)
+    };
+    match result.await {
+        Ok(socket) => Some(socket),
+        Err(e) => {
+            warn!(host, error = %e, "cannot reach statsd agent");
+            None
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Encoding
+// ---------------------------------------------------------------------------
+
+/// Turns location snapshots into statsd lines, remembering the previous
+/// snapshot so counters go out as deltas.
+struct Encoder {
+    /// `cfg.prefix` with its trailing dot, or empty.
+    prefix: String,
+    /// `cfg.tags` joined with leading commas.
+    tags: String,
+    dogstatsd: bool,
+    previous: HashMap<String, LocationStatsInfo>,
+}
+
+impl Encoder {
+    fn new(cfg: &StatsdConfig) -> Self {
+        Self {
+            prefix: match cfg.prefix.trim_end_matches('.') {
+                "" => String::new(),
+                prefix => format!("{prefix}."),
+            },
+            tags: cfg.tags.iter().map(|t| format!(",{t}")).collect(),
+            dogstatsd: cfg.dogstatsd,
+            previous: HashMap::new(),
+        }
+    }
+
+    fn encode(&mut self, stats: &[LocationStatsInfo]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for loc in stats {
+            let previous = self.previous.get(&loc.prefix);
+            let delta = |count: fn(&LocationStatsInfo) -> u64| {
+                count(loc).saturating_sub(previous.map_or(0, count))
+            };
+            let (name, tags) = if self.dogstatsd {
+                let tags = format!("|#location:{}{}", tag_value(&loc.prefix), self.tags);
+                (self.prefix.clone(), tags)
+            } else {
+                let name = format!("{}{}.", self.prefix, name_segment(&loc.prefix));
+                (name, String::new())
+            };
+
+            lines.push(format!("{name}requests:{}|c{tags}", delta(|l| l.requests)));
+            lines.push(format!("{name}hits:{}|c{tags}", delta(|l| l.hits)));
+            lines.push(format!("{name}misses:{}|c{tags}", delta(|l| l.misses)));
+            lines.push(format!("{name}bytes:{}|c{tags}", delta(|l| l.bytes)));
+            lines.push(format!("{name}aborted:{}|c{tags}", delta(|l| l.aborted)));
+            lines.push(format!("{name}lookup.p50_us:{}|g{tags}", loc.p50_us));
+            lines.push(format!("{name}lookup.p99_us:{}|g{tags}", loc.p99_us));
+
+            // Roots only fit in tags.
+            if self.dogstatsd {
+                for root in &loc.roots {
+                    let before = previous
+                        .and_then(|p| p.roots.iter().find(|r| r.path == root.path))
+                        .map_or(0, |r| r.hits);
+                    lines.push(format!(
+                        "{name}root.hits:{}|c{tags},root:{}",
+                        root.hits.saturating_sub(before),
+                        tag_value(&root.path.to_string_lossy()),
+                    ));
+                }
+            }
+        }
+        self.previous = stats
+            .iter()
+            .map(|loc| (loc.prefix.clone(), loc.clone()))
+            .collect();
+        lines
+    }
+}
+
+/// `value` with the characters that delimit DogStatsD tags replaced.
+fn tag_value(value: &str) -> String {
+    value.replace([',', '|', '#'], "_")
+}
+
+/// A location prefix as one dot-separated name segment: `/imgs/v2` becomes
+/// `imgs_v2`, and `/` becomes `_`.
+fn name_segment(prefix: &str) -> String {
+    match prefix.trim_matches('/') {
+        "" => "_".into(),
+        name => name.replace(['/', '.', ':', '|', '@'], "_"),
+    }
+}
+
+/// `lines` joined by newlines into datagrams of at most `MAX_PACKET` bytes.
+fn packets(lines: &[String]) -> Vec<String> {
+    let mut packets = Vec::new();
+    let mut packet = String::new();
+    for line in lines {
+        if !packet.is_empty() && packet.len() + 1 + line.len() > MAX_PACKET {
+            packets.push(std::mem::take(&mut packet));
+        }
+        if !packet.is_empty() {
+            packet.push('\n');
+        }
+        packet.push_str(line);
+    }
+    if !packet.is_empty() {
+        packets.push(packet);
+    }
+    packets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::RootStatsInfo;
+
+    fn snapshot(requests: u64, root_hits: u64) -> LocationStatsInfo {
+        LocationStatsInfo {
+            prefix: "/imgs".into(),
+            requests,
+            hits: root_hits,
+            misses: requests - root_hits,
+            bytes: 100 * root_hits,
+            aborted: 0,
+            p50_us: 40,
+            p99_us: 900,
+            roots: vec![RootStatsInfo {
+                path: "/mnt/a".into(),
+                hits: root_hits,
+                share: 1.0,
+                soft_timeouts: 0,
+                late_hits: 0,
+            }],
+        }
+    }
+
+    #[test]
+    fn encodes_deltas_with_tags() {
+        let cfg = StatsdConfig {
+            tags: vec!["env:prod".into()],
+            ..Default::default()
+        };
+        let mut encoder = Encoder::new(&cfg);
+        encoder.encode(&[snapshot(5, 3)]);
+        let lines = encoder.encode(&[snapshot(9, 4)]);
+        let tags = "|#location:/imgs,env:prod";
+        assert_eq!(lines[0], format!("filehunter.requests:4|c{tags}"));
+        assert_eq!(lines[3], format!("filehunter.bytes:100|c{tags}"));
+        assert_eq!(lines[6], format!("filehunter.lookup.p99_us:900|g{tags}"));
+        assert_eq!(
+            lines[7],
+            format!("filehunter.root.hits:1|c{tags},root:/mnt/a")
+        );
+
+        let mut plain = Encoder::new(&StatsdConfig {
+            dogstatsd: false,
+            ..Default::default()
+        });
+        let lines = plain.encode(&[snapshot(2, 1)]);
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[1], "filehunter.imgs.hits:1|c");
+
+        let many: Vec<String> = (0..200).map(|i| format!("metric.{i}:1|c")).collect();
+        let sent = packets(&many);
+        assert!(sent.len() > 1);
+        assert!(sent.iter().all(|p| p.len() <= MAX_PACKET));
+        assert_eq!(sent.join("\n"), many.join("\n"));
+    }
+}
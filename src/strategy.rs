@@ -0,0 +1,65 @@
+//! Search strategies supplied by crates embedding filehunter. Register a
+//! [`SearchStrategy`] under a name before the [`FileSearcher`] is built,
+//! then select it with `mode = "plugin"` and `search_plugin = "<name>"`:
+//!
+//! ```no_run
+//! use filehunter::backend::BoxFuture;
+//! use filehunter::server::{Candidate, FileSearcher, SearchHit};
+//! use filehunter::strategy::{self, SearchStrategy};
+//!
+//! /// Sequential search, last root first.
+//! struct LastFirst;
+//!
+//! impl SearchStrategy for LastFirst {
+//!     fn search<'a>(&'a self, roots: &'a [Candidate<'a>]) -> BoxFuture<'a, Option<SearchHit>> {
+//!         Box::pin(async move {
+//!             for root in roots.iter().rev() {
+//!                 if let Some(hit) = root.probe().await.ok()? {
+//!                     return Some(hit);
+//!                 }
+//!             }
+//!             None
+//!         })
+//!     }
+//! }
+//!
+//! strategy::register("last_first", LastFirst);
+//! let searcher = FileSearcher::builder()
+//!     .location("/imgs")
+//!     .search_plugin("last_first")
+//!     .root("/data/a")
+//!     .root("/data/b")
+//!     .build()
+//!     .unwrap();
+//! ```
+//!
+//! [`FileSearcher`]: crate::server::FileSearcher
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use crate::backend::BoxFuture;
+use crate::server::{Candidate, SearchHit};
+
+/// How a location's roots are probed for a request.
+pub trait SearchStrategy: Send + Sync {
+    /// Resolve the request against `roots`, the location's healthy roots
+    /// in config order, each ready to be probed for the requested file.
+    /// Hits, misses and search latency are recorded by the caller.
+    fn search<'a>(&'a self, roots: &'a [Candidate<'a>]) -> BoxFuture<'a, Option<SearchHit>>;
+}
+
+static STRATEGIES: RwLock<BTreeMap<String, Arc<dyn SearchStrategy>>> = RwLock::new(BTreeMap::new());
+
+/// Make `strategy` available as `search_plugin = "<name>"`, replacing any
+/// strategy registered under the same name. Locations built earlier keep
+/// the strategy they resolved.
+pub fn register(name: impl Into<String>, strategy: impl SearchStrategy + 'static) {
+    let strategy: Arc<dyn SearchStrategy> = Arc::new(strategy);
+    STRATEGIES.write().unwrap().insert(name.into(), strategy);
+}
+
+/// The strategy registered as `name`.
+pub(crate) fn get(name: &str) -> Option<Arc<dyn SearchStrategy>> {
+    STRATEGIES.read().unwrap().get(name).cloned()
+}
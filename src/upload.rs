@@ -0,0 +1,476 @@
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytes::Buf;
+use http_body_util::BodyExt;
+use hyper::{Response, StatusCode};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use serde::Serialize;
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, info, warn};
+
+use crate::disposition;
+use crate::multipart::{self, Multipart};
+use crate::server::{
+    FileSearcher, ResponseBody, UploadTarget, empty_body, json_response, text_response,
+};
+
+/// Characters escaped when a form file name becomes a path segment.
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Distinguishes concurrent uploads' temporary files.
+static UPLOADS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Serialize)]
+struct StoredFile {
+    /// Request path the file is served at.
+    path: String,
+    size: u64,
+    replaced: bool,
+    /// The upload root, then each replica; only with `upload_replicas`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    roots: Vec<RootCopy>,
+}
+
+/// The outcome of writing one root's copy of an upload.
+#[derive(Serialize)]
+struct RootCopy {
+    root: String,
+    /// 201 or 204 as for a `PUT`; an error status if the copy failed.
+    status: u16,
+}
+
+/// `PUT <prefix>/<path>` on a writable location: stream the body into a
+/// hidden temporary file beside the target, then rename it into place so
+/// readers never see a partial file. Answers 201 for a new file and 204
+/// for a replaced one; with `upload_replicas`, 201 or 200 and a JSON report
+/// of each root's copy.
+pub(crate) async fn handle<B: hyper::body::Body>(
+    body: B,
+    searcher: &FileSearcher,
+    path: &str,
+    client_ip: IpAddr,
+) -> Response<ResponseBody> {
+    let stored = match searcher.upload_target(path) {
+        Ok(target) => match store(body, &target, searcher, path, client_ip).await {
+            Ok(stored) => Ok((target, stored)),
+            Err(status) => Err(status),
+        },
+        Err(status) => Err(status),
+    };
+    let (target, stored) = match stored {
+        Ok(stored) => stored,
+        Err(status) => {
+            debug!(status = status.as_u16(), path, "upload refused");
+            return text_response(status, status.canonical_reason().unwrap_or_default());
+        }
+    };
+    info!(path, resolved = %stored.path.display(), size = stored.size, "upload stored");
+
+    if target.replicas > 0 {
+        let report = StoredFile {
+            path: path.to_owned(),
+            size: stored.size,
+            replaced: stored.replaced,
+            roots: replicate(&target, &stored).await,
+        };
+        let status = if stored.replaced {
+            StatusCode::OK
+        } else {
+            StatusCode::CREATED
+        };
+        return json_response(status, &report);
+    }
+    if stored.replaced {
+        return Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(empty_body())
+            .unwrap();
+    }
+    text_response(StatusCode::CREATED, "Created")
+}
+
+/// Write `body` to the upload root, scanning it first if `scan_uploads`.
+async fn store<B: hyper::body::Body>(
+    body: B,
+    target: &UploadTarget<'_>,
+    searcher: &FileSearcher,
+    path: &str,
+    client_ip: IpAddr,
+) -> Result<Stored, StatusCode> {
+    let limit = searcher.max_body_size();
+    let mut pending = Pending::create(target.root, &target.relative).await?;
+    let mut body = std::pin::pin!(body);
+    loop {
+        // Copied out at once: the body's own types need not be `Send`.
+        let chunk = match body.frame().await {
+            None => break,
+            Some(Err(_)) => Err(StatusCode::BAD_REQUEST),
+            Some(Ok(frame)) => match frame.into_data() {
+                Ok(mut data) => Ok(data.copy_to_bytes(data.remaining())),
+                Err(_) => continue,
+            },
+        };
+        let result = match chunk {
+            Ok(chunk) if pending.size + chunk.len() as u64 > limit => {
+                Err(StatusCode::PAYLOAD_TOO_LARGE)
+            }
+            Ok(chunk) => pending.write(&chunk).await,
+            Err(status) => Err(status),
+        };
+        if let Err(status) = result {
+            return pending.discard(status).await;
+        }
+    }
+    pending.finish_scanned(searcher, path, client_ip).await
+}
+
+/// Copy a stored upload to up to `target.replicas` other healthy roots, in
+/// config order. A failed copy is reported, not retried; the upload itself
+/// stands.
+async fn replicate(target: &UploadTarget<'_>, stored: &Stored) -> Vec<RootCopy> {
+    let upload_root = fs::canonicalize(target.root)
+        .await
+        .unwrap_or_else(|_| target.root.to_path_buf());
+    let mut roots = vec![RootCopy {
+        root: upload_root.display().to_string(),
+        status: stored.status().as_u16(),
+    }];
+    let replicas = target.candidates.iter().filter(|dir| **dir != upload_root);
+    for dir in replicas.take(target.replicas) {
+        let copied = match Pending::create(dir, &target.relative).await {
+            Ok(mut pending) => match pending.copy_from(&stored.path).await {
+                Ok(()) => pending.finish().await,
+                Err(status) => pending.discard(status).await,
+            },
+            Err(status) => Err(status),
+        };
+        let status = match copied {
+            Ok(copy) => copy.status(),
+            Err(status) => {
+                warn!(root = %dir.display(), path = %target.relative.display(), status = status.as_u16(), "upload replica failed");
+                status
+            }
+        };
+        roots.push(RootCopy {
+            root: dir.display().to_string(),
+            status: status.as_u16(),
+        });
+    }
+    roots
+}
+
+// ---------------------------------------------------------------------------
+// Form uploads
+// ---------------------------------------------------------------------------
+
+#[derive(Serialize)]
+struct FormReport {
+    stored: Vec<StoredFile>,
+    rejected: Vec<RejectedFile>,
+}
+
+#[derive(Serialize)]
+struct RejectedFile {
+    filename: String,
+    status: u16,
+}
+
+/// `POST <prefix>/<dir>` with a `multipart/form-data` body on a writable
+/// location: store each file field as `<dir>/<filename>`, as a `PUT` would.
+/// Files the location refuses are listed as rejected; a body over
+/// `max_body_size` or a malformed one fails the request, keeping the files
+/// already stored.
+pub(crate) async fn handle_form<B: hyper::body::Body>(
+    body: B,
+    content_type: Option<&str>,
+    searcher: &FileSearcher,
+    path: &str,
+    client_ip: IpAddr,
+) -> Response<ResponseBody> {
+    let Some(boundary) = content_type.and_then(multipart::boundary) else {
+        debug!(status = 415, path, "form upload refused");
+        return text_response(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Expected multipart/form-data",
+        );
+    };
+    let mut form = Multipart::new(body, &boundary, searcher.max_body_size());
+    let dir = path.trim_end_matches('/');
+    let mut report = FormReport {
+        stored: Vec::new(),
+        rejected: Vec::new(),
+    };
+    loop {
+        let filename = match form.next_part().await {
+            Ok(Some(part)) => part.filename,
+            Ok(None) => break,
+            Err(status) => return form_failed(status, path, &report),
+        };
+        // Other fields carry no file.
+        let Some(filename) = filename else {
+            continue;
+        };
+        let Some(name) = disposition::clean(&filename) else {
+            report.rejected.push(RejectedFile {
+                filename,
+                status: StatusCode::BAD_REQUEST.as_u16(),
+            });
+            continue;
+        };
+        let path = format!("{dir}/{}", utf8_percent_encode(&name, SEGMENT));
+        let created = match searcher.upload_target(&path) {
+            Ok(target) => match Pending::create(target.root, &target.relative).await {
+                Ok(pending) => Ok((target, pending)),
+                Err(status) => Err(status),
+            },
+            Err(status) => Err(status),
+        };
+        let (target, mut pending) = match created {
+            Ok(created) => created,
+            Err(status) => {
+                debug!(status = status.as_u16(), path, "form file refused");
+                report.rejected.push(RejectedFile {
+                    filename,
+                    status: status.as_u16(),
+                });
+                continue;
+            }
+        };
+
+        let copied = loop {
+            match form.chunk().await {
+                Ok(Some(chunk)) => {
+                    if let Err(status) = pending.write(&chunk).await {
+                        break Err(status);
+                    }
+                }
+                Ok(None) => break Ok(()),
+                Err(status) => break Err(status),
+            }
+        };
+        let stored = match copied {
+            Ok(()) => pending.finish_scanned(searcher, &path, client_ip).await,
+            Err(status) => pending.discard(status).await,
+        };
+        let stored = match stored {
+            Ok(stored) => stored,
+            Err(status) => return form_failed(status, &path, &report),
+        };
+        info!(path, resolved = %stored.path.display(), size = stored.size, "upload stored");
+        let roots = match target.replicas {
+            0 => Vec::new(),
+            _ => replicate(&target, &stored).await,
+        };
+        report.stored.push(StoredFile {
+            path,
+            size: stored.size,
+            replaced: stored.replaced,
+            roots,
+        });
+    }
+    debug!(
+        status = 200,
+        path,
+        stored = report.stored.len(),
+        rejected = report.rejected.len(),
+        "form upload handled"
+    );
+    json_response(StatusCode::OK, &report)
+}
+
+fn form_failed(status: StatusCode, path: &str, report: &FormReport) -> Response<ResponseBody> {
+    debug!(
+        status = status.as_u16(),
+        path,
+        stored = report.stored.len(),
+        "form upload failed"
+    );
+    text_response(status, status.canonical_reason().unwrap_or_default())
+}
+
+// ---------------------------------------------------------------------------
+// Temporary files
+// ---------------------------------------------------------------------------
+
+/// An upload in progress: written to a hidden temporary file beside
+/// `target`, then renamed over it. Also used to fill the upstream fallback
+/// cache. Dropped before [`finish`](Self::finish) moves it into place, e.g.
+/// when the client goes away mid-upload, it removes the temporary file.
+pub(crate) struct Pending {
+    file: File,
+    temp: PathBuf,
+    target: PathBuf,
+    replaced: bool,
+    /// Bytes written so far.
+    size: u64,
+    /// Set once `temp` has been renamed to `target`.
+    stored: bool,
+}
+
+/// A file moved into place.
+pub(crate) struct Stored {
+    path: PathBuf,
+    replaced: bool,
+    size: u64,
+}
+
+impl Stored {
+    fn status(&self) -> StatusCode {
+        if self.replaced {
+            StatusCode::NO_CONTENT
+        } else {
+            StatusCode::CREATED
+        }
+    }
+}
+
+impl Pending {
+    /// Start an upload to `relative` under `root`. The parent directory must
+    /// exist and resolve inside `root`; a directory at the target is left
+    /// alone.
+    pub(crate) async fn create(root: &Path, relative: &Path) -> Result<Self, StatusCode> {
+        let root = fs::canonicalize(root)
+            .await
+            .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+        let target = root.join(relative);
+        let (Some(parent), Some(name)) = (target.parent(), target.file_name()) else {
+            return Err(StatusCode::BAD_REQUEST);
+        };
+        let parent = fs::canonicalize(parent)
+            .await
+            .map_err(|_| StatusCode::CONFLICT)?;
+        if !parent.starts_with(&root) {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        let target = parent.join(name);
+        // A symlink at the target is replaced, not written through.
+        let replaced = match fs::symlink_metadata(&target).await {
+            Ok(meta) if meta.is_dir() => return Err(StatusCode::CONFLICT),
+            Ok(_) => true,
+            Err(_) => false,
+        };
+
+        let temp = parent.join(format!(
+            ".{}.{}-{}.upload",
+            name.to_string_lossy(),
+            std::process::id(),
+            UPLOADS.fetch_add(1, Ordering::Relaxed),
+        ));
+        let file = File::create_new(&temp)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        Ok(Self {
+            file,
+            temp,
+            target,
+            replaced,
+            size: 0,
+            stored: false,
+        })
+    }
+
+    pub(crate) async fn write(&mut self, chunk: &[u8]) -> Result<(), StatusCode> {
+        self.file
+            .write_all(chunk)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        self.size += chunk.len() as u64;
+        Ok(())
+    }
+
+    /// Fill the file with the contents of `source`.
+    async fn copy_from(&mut self, source: &Path) -> Result<(), StatusCode> {
+        let copied = match File::open(source).await {
+            Ok(mut source) => tokio::io::copy(&mut source, &mut self.file).await,
+            Err(e) => Err(e),
+        };
+        self.size += copied.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        Ok(())
+    }
+
+    /// Flush the file to disk and move it into place.
+    pub(crate) async fn finish(mut self) -> Result<Stored, StatusCode> {
+        let moved = match self.file.sync_all().await {
+            Ok(()) => fs::rename(&self.temp, &self.target).await,
+            Err(e) => Err(e),
+        };
+        match moved {
+            Ok(()) => {
+                self.stored = true;
+                Ok(Stored {
+                    path: std::mem::take(&mut self.target),
+                    replaced: self.replaced,
+                    size: self.size,
+                })
+            }
+            Err(_) => self.discard(StatusCode::INTERNAL_SERVER_ERROR).await,
+        }
+    }
+
+    /// Flush the file and scan it, if `scan_uploads` is set, before moving it
+    /// into place. An infected upload is discarded.
+    async fn finish_scanned(
+        mut self,
+        searcher: &FileSearcher,
+        path: &str,
+        client_ip: IpAddr,
+    ) -> Result<Stored, StatusCode> {
+        if let Err(e) = self.file.flush().await {
+            warn!(path, error = %e, "cannot flush upload");
+            return self.discard(StatusCode::INTERNAL_SERVER_ERROR).await;
+        }
+        match searcher.scan_upload(path, &self.temp, client_ip).await {
+            Ok(()) => self.finish().await,
+            Err(status) => self.discard(status).await,
+        }
+    }
+
+    /// Remove the temporary file and fail with `status`.
+    pub(crate) async fn discard<T>(self, status: StatusCode) -> Result<T, StatusCode> {
+        drop(self);
+        Err(status)
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if !self.stored {
+            let _ = std::fs::remove_file(&self.temp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn dropped_upload_leaves_no_temp_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let create = || Pending::create(dir.path(), Path::new("a.txt"));
+        let mut pending = create().await.unwrap();
+        pending.write(b"partial").await.unwrap();
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+        // The client goes away mid-upload.
+        drop(pending);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let mut pending = create().await.unwrap();
+        pending.write(b"whole").await.unwrap();
+        let stored = pending.finish().await.unwrap();
+        let target = dir.path().canonicalize().unwrap().join("a.txt");
+        assert_eq!(stored.path, target);
+        let names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["a.txt"]);
+    }
+}
@@ -0,0 +1,152 @@
+//! Host-header virtual hosting: which `[[vhosts]]` site a request is for.
+//! Sites are numbered 0 for the top-level `[[locations]]` and `n` for the
+//! `n`th `[[vhosts]]` entry; a request is handled inside [`scope`] so that
+//! location matching only sees its site's locations.
+
+use std::collections::HashMap;
+
+use hyper::Request;
+use hyper::header::HOST;
+
+use crate::config::VhostConfig;
+
+tokio::task_local! {
+    static SITE: usize;
+}
+
+/// The site of the request being handled, or 0 outside one.
+pub(crate) fn current() -> usize {
+    SITE.try_with(|site| *site).unwrap_or(0)
+}
+
+/// Run `handled` as a request for `site`.
+pub(crate) async fn scope<F: Future>(site: usize, handled: F) -> F::Output {
+    SITE.scope(site, handled).await
+}
+
+/// Host patterns (`example.com`, `*.example.com`) mapped to values.
+/// Exact names win over wildcards, and longer wildcards over shorter.
+#[derive(Debug)]
+pub(crate) struct HostMap<T> {
+    exact: HashMap<String, T>,
+    /// `*.example.com` as `(".example.com", value)`, longest first.
+    wildcards: Vec<(String, T)>,
+}
+
+impl<T> Default for HostMap<T> {
+    fn default() -> Self {
+        Self {
+            exact: HashMap::new(),
+            wildcards: Vec::new(),
+        }
+    }
+}
+
+impl<T> HostMap<T> {
+    pub(crate) fn insert(&mut self, pattern: &str, value: T) {
+        let pattern = pattern.to_ascii_lowercase();
+        match pattern.strip_prefix('*') {
+            Some(suffix) => {
+                let at = self
+                    .wildcards
+                    .partition_point(|(s, _)| s.len() >= suffix.len());
+                self.wildcards.insert(at, (suffix.to_owned(), value));
+            }
+            None => {
+                self.exact.insert(pattern, value);
+            }
+        }
+    }
+
+    pub(crate) fn get(&self, host: &str) -> Option<&T> {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        if let Some(value) = self.exact.get(&host) {
+            return Some(value);
+        }
+        self.wildcards
+            .iter()
+            .find(|(suffix, _)| host.ends_with(suffix.as_str()))
+            .map(|(_, value)| value)
+    }
+}
+
+/// The `[[vhosts]]` host names and the sites they map to.
+#[derive(Debug, Default)]
+pub(crate) struct Vhosts {
+    hosts: HostMap<usize>,
+    /// Each vhost's first host name.
+    names: Vec<String>,
+}
+
+impl Vhosts {
+    pub(crate) fn new(vhosts: &[VhostConfig]) -> Self {
+        let mut out = Self::default();
+        for (i, vhost) in vhosts.iter().enumerate() {
+            for host in &vhost.hosts {
+                out.hosts.insert(host, i + 1);
+            }
+            out.names
+                .push(vhost.hosts.first().cloned().unwrap_or_default());
+        }
+        out
+    }
+
+    /// The site `req` is for, from its URI authority (HTTP/2, absolute
+    /// form) or `Host` header. Unknown or missing hosts get site 0.
+    pub(crate) fn site<B>(&self, req: &Request<B>) -> usize {
+        if self.names.is_empty() {
+            return 0;
+        }
+        let host = req.uri().host().or_else(|| {
+            let host = req.headers().get(HOST)?.to_str().ok()?;
+            Some(strip_port(host))
+        });
+        host.map_or(0, |host| self.lookup(host))
+    }
+
+    fn lookup(&self, host: &str) -> usize {
+        self.hosts.get(host).copied().unwrap_or(0)
+    }
+
+    /// The first host name of `site`, `None` for the top-level site.
+    pub(crate) fn name(&self, site: usize) -> Option<&str> {
+        let i = site.checked_sub(1)?;
+        self.names.get(i).map(String::as_str)
+    }
+}
+
+/// `host` without a trailing `:port`; IPv6 literals keep their brackets.
+fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_hosts_to_sites() {
+        let vhost = |hosts: &[&str]| VhostConfig {
+            hosts: hosts.iter().map(|h| h.to_string()).collect(),
+            ..Default::default()
+        };
+        let vhosts = Vhosts::new(&[
+            vhost(&["img.example.com"]),
+            vhost(&["*.example.com", "files.example.org"]),
+        ]);
+        let site = |host: &str| {
+            let req = Request::builder().header(HOST, host).body(()).unwrap();
+            vhosts.site(&req)
+        };
+        assert_eq!(site("IMG.example.com:8080"), 1);
+        assert_eq!(site("cdn.example.com."), 2);
+        assert_eq!(site("files.example.org"), 2);
+        assert_eq!(site("example.com"), 0);
+        assert_eq!(site("[::1]:8080"), 0);
+        assert_eq!(vhosts.name(2), Some("*.example.com"));
+        assert_eq!(vhosts.name(0), None);
+    }
+}
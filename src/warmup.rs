@@ -0,0 +1,295 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use futures_util::StreamExt;
+use futures_util::stream::FuturesUnordered;
+use tracing::{debug, info, warn};
+
+use crate::backend::{ObjectBody, StorageBackend};
+use crate::config::WarmupConfig;
+use crate::server::FileSearcher;
+
+/// Called for each regular file a walk finds, with its size and mtime.
+type FileHook = Arc<dyn Fn(PathBuf, u64, SystemTime) + Send + Sync>;
+
+/// Files read at once by [`warm_paths`].
+const PATH_CONCURRENCY: usize = 8;
+
+/// The request paths [`warm_paths`] loads, and how long it may take.
+pub(crate) struct PathList {
+    paths: Vec<String>,
+    timeout: Duration,
+}
+
+impl PathList {
+    /// `cfg.paths` followed by those in `cfg.paths_file`; `None` when
+    /// warm-up is disabled or lists no paths. An unreadable file is logged
+    /// and skipped.
+    pub(crate) fn load(cfg: &WarmupConfig) -> Option<Self> {
+        if !cfg.enabled {
+            return None;
+        }
+        let mut paths = cfg.paths.clone();
+        if !cfg.paths_file.as_os_str().is_empty() {
+            match std::fs::read_to_string(&cfg.paths_file) {
+                Ok(text) => paths.extend(parse_paths(&text)),
+                Err(e) => {
+                    warn!(file = %cfg.paths_file.display(), error = %e, "cannot read warmup.paths_file")
+                }
+            }
+        }
+        (!paths.is_empty()).then(|| Self {
+            paths,
+            timeout: Duration::from_millis(cfg.timeout_ms),
+        })
+    }
+}
+
+/// One path per line; blank lines and `#` comments are skipped, as are
+/// lines not starting with `/`.
+fn parse_paths(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            if line.starts_with('/') {
+                return Some(line.to_owned());
+            }
+            warn!(path = line, "warm-up path must start with '/', skipping");
+            None
+        })
+        .collect()
+}
+
+/// Warm every active root concurrently, logging each one's timing: local
+/// roots are canonicalized and stat'ed (and walked with `cfg.walk`), remote
+/// roots run their health probe. With background digests, walks queue
+/// every file they find for hashing. Returns after `cfg.timeout_ms` at the
+/// latest; unfinished walks carry on in the background.
+pub async fn warm_roots(searcher: &FileSearcher, cfg: &WarmupConfig) {
+    let walk = cfg.walk.then_some(cfg.max_entries);
+    #[cfg(feature = "digest")]
+    let on_file = searcher.background_digests().map(|digests| {
+        Arc::new(move |path, size, modified| digests.queue(path, size, modified)) as FileHook
+    });
+    #[cfg(not(feature = "digest"))]
+    let on_file = None;
+    let mut seen = HashSet::new();
+    let warms: Vec<_> = searcher
+        .root_health()
+        .into_iter()
+        .filter(|(path, ..)| seen.insert(path.clone()))
+        .map(|(path, _, backend)| warm_root(path, backend, walk, on_file.clone()))
+        .collect();
+    let roots = warms.len();
+
+    let start = Instant::now();
+    let timeout = Duration::from_millis(cfg.timeout_ms);
+    match tokio::time::timeout(timeout, futures_util::future::join_all(warms)).await {
+        Ok(_) => {
+            let elapsed_ms = start.elapsed().as_millis() as u64;
+            info!(roots, elapsed_ms, "root warm-up finished");
+        }
+        Err(_) => warn!(roots, timeout_ms = cfg.timeout_ms, "root warm-up timed out"),
+    }
+}
+
+async fn warm_root(
+    path: PathBuf,
+    backend: Arc<dyn StorageBackend>,
+    walk: Option<usize>,
+    on_file: Option<FileHook>,
+) {
+    let start = Instant::now();
+    let result = match backend.local_dir() {
+        Some(dir) => {
+            let dir = dir.to_path_buf();
+            let walked =
+                tokio::task::spawn_blocking(move || warm_dir(&dir, walk, on_file.as_deref()));
+            match walked.await {
+                Ok(r) => r.map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        None => backend.check().await.map(|()| 0),
+    };
+
+    let elapsed_ms = start.elapsed().as_millis() as u64;
+    match result {
+        Ok(entries) => info!(path = %path.display(), elapsed_ms, entries, "root warmed"),
+        Err(error) => warn!(path = %path.display(), elapsed_ms, error, "root warm-up failed"),
+    }
+}
+
+/// Resolve every configured warm-up path and read the file in full, so its
+/// lookup, the page cache, the remote cache and (with digests enabled) its
+/// digest are warm for the first request. Returns how many paths were
+/// found; gives up after `timeout_ms`.
+pub async fn warm_paths(searcher: &FileSearcher) -> usize {
+    let Some(list) = searcher.warm_list() else {
+        return 0;
+    };
+    let start = Instant::now();
+    let mut loaded = 0;
+    let warm = async {
+        let mut queued = list.paths.iter();
+        let mut running = FuturesUnordered::new();
+        loop {
+            while running.len() < PATH_CONCURRENCY
+                && let Some(path) = queued.next()
+            {
+                running.push(warm_path(searcher, path));
+            }
+            match running.next().await {
+                Some(found) => loaded += usize::from(found),
+                None => break,
+            }
+        }
+    };
+    let timed_out = tokio::time::timeout(list.timeout, warm).await.is_err();
+    let (paths, elapsed_ms) = (list.paths.len(), start.elapsed().as_millis() as u64);
+    if timed_out {
+        warn!(paths, loaded, elapsed_ms, "path warm-up timed out");
+    } else {
+        info!(paths, loaded, elapsed_ms, "path warm-up finished");
+    }
+    loaded
+}
+
+async fn warm_path(searcher: &FileSearcher, path: &str) -> bool {
+    let Some(hit) = searcher.search(path).await else {
+        warn!(path, "warm-up path not found");
+        return false;
+    };
+    #[cfg(feature = "digest")]
+    searcher.prime_digest(&hit).await;
+    let read = match hit.body {
+        ObjectBody::File(mut file) => tokio::io::copy(&mut file, &mut tokio::io::sink()).await,
+        ObjectBody::Stream(mut body) => {
+            let mut read = 0;
+            loop {
+                match body.next().await {
+                    Some(Ok(chunk)) => read += chunk.len() as u64,
+                    Some(Err(e)) => break Err(e),
+                    None => break Ok(read),
+                }
+            }
+        }
+    };
+    match read {
+        Ok(bytes) => debug!(path, root = %hit.root.display(), bytes, "path warmed"),
+        Err(e) => warn!(path, error = %e, "cannot read warm-up path"),
+    }
+    true
+}
+
+/// Canonicalize and stat `dir`; with `walk`, also stat up to that many
+/// entries below it, passing regular files to `on_file`. Returns the
+/// number of entries stat'ed by the walk.
+fn warm_dir(
+    dir: &Path,
+    walk: Option<usize>,
+    on_file: Option<&(dyn Fn(PathBuf, u64, SystemTime) + Send + Sync)>,
+) -> io::Result<usize> {
+    let dir = dir.canonicalize()?;
+    std::fs::metadata(&dir)?;
+    let Some(max_entries) = walk else {
+        return Ok(0);
+    };
+
+    let mut pending = vec![dir];
+    let mut entries = 0;
+    while let Some(dir) = pending.pop() {
+        // Unreadable subdirectories are skipped, not fatal.
+        let Ok(list) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in list.flatten() {
+            if entries == max_entries {
+                return Ok(entries);
+            }
+            entries += 1;
+            // Does not follow symlinks, so the walk stays inside the tree.
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            if meta.is_dir() {
+                pending.push(entry.path());
+            } else if let Some(on_file) = on_file
+                && meta.is_file()
+                && let Ok(modified) = meta.modified()
+            {
+                on_file(entry.path(), meta.len(), modified);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walk_stats_entries_up_to_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        std::fs::write(dir.path().join("a/b/c.txt"), b"c").unwrap();
+        std::fs::write(dir.path().join("d.txt"), b"d").unwrap();
+
+        assert_eq!(warm_dir(dir.path(), None, None).unwrap(), 0);
+        assert_eq!(warm_dir(dir.path(), Some(100), None).unwrap(), 4);
+        assert_eq!(warm_dir(dir.path(), Some(2), None).unwrap(), 2);
+        assert!(warm_dir(&dir.path().join("missing"), Some(100), None).is_err());
+
+        let files = std::sync::Mutex::new(Vec::new());
+        let record = |path: PathBuf, size, _| files.lock().unwrap().push((path, size));
+        warm_dir(dir.path(), Some(100), Some(&record)).unwrap();
+        let mut files = files.into_inner().unwrap();
+        files.sort();
+        let root = dir.path().canonicalize().unwrap();
+        assert_eq!(
+            files,
+            [(root.join("a/b/c.txt"), 1), (root.join("d.txt"), 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn warms_listed_paths() {
+        use crate::config::ServerConfig;
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("css")).unwrap();
+        std::fs::write(dir.path().join("css/site.css"), b"body{}").unwrap();
+        std::fs::write(dir.path().join("logo.png"), b"png").unwrap();
+        let list = dir.path().join("warm.txt");
+        std::fs::write(
+            &list,
+            "# critical assets\n\n/logo.png\nmissing.txt\n/gone.txt\n",
+        )
+        .unwrap();
+
+        let server = ServerConfig {
+            warmup: WarmupConfig {
+                enabled: true,
+                paths: vec!["/css/site.css".into()],
+                paths_file: list,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let searcher = FileSearcher::builder()
+            .server(server)
+            .root(dir.path())
+            .build()
+            .unwrap();
+        assert_eq!(
+            searcher.warm_list().unwrap().paths,
+            ["/css/site.css", "/logo.png", "/gone.txt"]
+        );
+        assert_eq!(warm_paths(&searcher).await, 2);
+    }
+}
@@ -0,0 +1,238 @@
+//! WASM request filters: a module run on each request before it is routed,
+//! which may rewrite the path, reject the request, or pick the root it is
+//! served from — tenant mapping or in-house auth without forking.
+//!
+//! The module imports nothing and exports `memory`, `alloc(len: i32) ->
+//! i32` and `filter(ptr: i32, len: i32) -> i64`. The request is written as
+//! JSON (`method`, `path`, `query`, `client_ip`, `headers`) into a buffer
+//! from `alloc`; `filter` returns `0` to let it through unchanged, or
+//! `ptr << 32 | len` of a JSON decision:
+//!
+//! ```json
+//! {"action": "continue", "path": "/acme/logo.png", "root": "/srv/acme"}
+//! {"action": "reject", "status": 403, "body": "Forbidden"}
+//! ```
+//!
+//! Each call gets a fresh instance, so no state survives between requests.
+
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use hyper::{HeaderMap, Method};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use wasmtime::{Config, Engine, InstancePre, Linker, Module, Store, StoreLimits};
+use wasmtime::{Error, StoreLimitsBuilder};
+
+use crate::config::WasmFilterConfig;
+
+/// Longest decision read back from the module.
+const MAX_DECISION: usize = 64 * 1024;
+
+/// What the module decided about a request.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub(crate) enum Decision {
+    /// Route the request, as `path` and searching only `root` when set.
+    Continue {
+        path: Option<String>,
+        root: Option<PathBuf>,
+    },
+    Reject {
+        #[serde(default = "forbidden")]
+        status: u16,
+        body: Option<String>,
+    },
+}
+
+fn forbidden() -> u16 {
+    403
+}
+
+#[derive(Serialize)]
+struct FilterRequest<'a> {
+    method: &'a str,
+    path: &'a str,
+    query: Option<&'a str>,
+    client_ip: IpAddr,
+    /// Lowercase names; repeated headers joined with `, `.
+    headers: BTreeMap<&'a str, String>,
+}
+
+/// The compiled `[server.wasm_filter]` module.
+pub(crate) struct WasmFilter {
+    /// `None` when the module could not be loaded; every call then fails.
+    module: Option<InstancePre<StoreLimits>>,
+    fuel: u64,
+    max_memory: usize,
+    /// Let requests through when the module fails.
+    pub(crate) fail_open: bool,
+}
+
+impl WasmFilter {
+    /// Compile `cfg.module`. A module that cannot be loaded is logged and
+    /// leaves a filter whose every call fails.
+    pub(crate) fn load(cfg: &WasmFilterConfig) -> Self {
+        let module = compile(cfg);
+        match &module {
+            Ok(_) => info!(module = %cfg.module.display(), "wasm filter loaded"),
+            Err(e) => warn!(module = %cfg.module.display(), error = %e, "cannot load wasm filter"),
+        }
+        Self {
+            module: module.ok(),
+            fuel: cfg.fuel,
+            max_memory: cfg.max_memory.as_usize(),
+            fail_open: cfg.fail_open,
+        }
+    }
+
+    /// Ask the module about a request.
+    pub(crate) fn run(
+        &self,
+        method: &Method,
+        path: &str,
+        query: Option<&str>,
+        headers: &HeaderMap,
+        client_ip: IpAddr,
+    ) -> Result<Decision, String> {
+        let module = self.module.as_ref().ok_or("module not loaded")?;
+        let mut joined: BTreeMap<&str, String> = BTreeMap::new();
+        for (name, value) in headers {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            joined
+                .entry(name.as_str())
+                .and_modify(|v| {
+                    v.push_str(", ");
+                    v.push_str(&value);
+                })
+                .or_insert_with(|| value.into_owned());
+        }
+        let request = FilterRequest {
+            method: method.as_str(),
+            path,
+            query,
+            client_ip,
+            headers: joined,
+        };
+        let request = serde_json::to_vec(&request).expect("JSON serialization cannot fail");
+        let decision = self.call(module, &request).map_err(|e| format!("{e:#}"))?;
+        let decision = match decision {
+            None => Decision::Continue {
+                path: None,
+                root: None,
+            },
+            Some(json) => serde_json::from_slice(&json).map_err(|e| format!("decision: {e}"))?,
+        };
+        match &decision {
+            Decision::Continue {
+                path: Some(path), ..
+            } if !path.starts_with('/') => Err(format!("path {path:?} must start with '/'")),
+            Decision::Reject { status, .. } if !(400..600).contains(status) => {
+                Err(format!("status {status} is not an error status"))
+            }
+            _ => Ok(decision),
+        }
+    }
+
+    /// Run `filter` on `request` in a fresh instance, returning the bytes
+    /// of its decision or `None` for `0`.
+    fn call(
+        &self,
+        module: &InstancePre<StoreLimits>,
+        request: &[u8],
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.max_memory)
+            .build();
+        let mut store = Store::new(module.module().engine(), limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(self.fuel)?;
+        let instance = module.instantiate(&mut store)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| Error::msg("module exports no memory"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let filter = instance.get_typed_func::<(i32, i32), i64>(&mut store, "filter")?;
+
+        let len = i32::try_from(request.len())?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, ptr as u32 as usize, request)?;
+        let packed = filter.call(&mut store, (ptr, len))? as u64;
+        if packed == 0 {
+            return Ok(None);
+        }
+        let (ptr, len) = ((packed >> 32) as usize, (packed & 0xFFFF_FFFF) as usize);
+        if len > MAX_DECISION {
+            return Err(Error::msg(format!("decision of {len} bytes is too long")));
+        }
+        let mut decision = vec![0u8; len];
+        memory.read(&store, ptr, &mut decision)?;
+        Ok(Some(decision))
+    }
+}
+
+fn compile(cfg: &WasmFilterConfig) -> Result<InstancePre<StoreLimits>, Error> {
+    let mut config = Config::new();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config)?;
+    let module = Module::from_file(&engine, &cfg.module)?;
+    Linker::new(&engine).instantiate_pre(&module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A filter whose `filter` returns `decision`, or loops forever when
+    /// `None`.
+    fn filter(dir: &std::path::Path, decision: Option<&str>) -> WasmFilter {
+        let body = match decision {
+            Some(d) => format!("(i64.const {})", (16u64 << 32) | d.len() as u64),
+            None => "(loop $spin (br $spin)) (i64.const 0)".into(),
+        };
+        let wat = format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (data (i32.const 16) "{}")
+                (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+                (func (export "filter") (param i32 i32) (result i64) {body}))"#,
+            decision.unwrap_or_default().replace('"', "\\\"")
+        );
+        let path = dir.join("filter.wat");
+        std::fs::write(&path, wat).unwrap();
+        WasmFilter::load(&WasmFilterConfig {
+            enabled: true,
+            module: path,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn runs_decisions_within_fuel() {
+        let dir = tempfile::tempdir().unwrap();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let run = |filter: WasmFilter| filter.run(&Method::GET, "/a", None, &HeaderMap::new(), ip);
+
+        let rewrite = r#"{"action": "continue", "path": "/b", "root": "/srv/b"}"#;
+        assert_eq!(
+            run(filter(dir.path(), Some(rewrite))).unwrap(),
+            Decision::Continue {
+                path: Some("/b".into()),
+                root: Some("/srv/b".into())
+            }
+        );
+        let reject = r#"{"action": "reject"}"#;
+        assert_eq!(
+            run(filter(dir.path(), Some(reject))).unwrap(),
+            Decision::Reject {
+                status: 403,
+                body: None
+            }
+        );
+        let relative = r#"{"action": "continue", "path": "b"}"#;
+        assert!(run(filter(dir.path(), Some(relative))).is_err());
+        // Out of fuel.
+        assert!(run(filter(dir.path(), None)).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+//! Served-file events: one JSON record per file served from a location with
+//! `served_events`, queued and POSTed in batches to `[server.webhooks]`.
+
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::Request;
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::TokioExecutor;
+use serde::Serialize;
+use tokio::sync::Notify;
+use tracing::{debug, info, warn};
+
+use crate::backend::remote;
+use crate::config::{WebhookFormat, WebhooksConfig};
+
+type PostClient = Client<HttpsConnector<HttpConnector>, Full<Bytes>>;
+
+/// A file served to a client.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServedEvent {
+    /// Unix seconds.
+    pub time: u64,
+    pub client_ip: IpAddr,
+    /// Prefix of the location the file was served from.
+    pub location: String,
+    /// Raw request path.
+    pub path: String,
+    /// Root the file was found under (canonical path or URL).
+    pub root: String,
+    /// The file itself (canonical path or URL).
+    pub file: String,
+    /// 200, or 206 for byte ranges.
+    pub status: u16,
+    /// Bytes of the file sent: the whole file, the ranges, or the size of
+    /// a precompressed sidecar.
+    pub bytes: u64,
+}
+
+/// The queue of events waiting for the sink.
+pub struct Webhooks {
+    cfg: WebhooksConfig,
+    queue: Mutex<VecDeque<ServedEvent>>,
+    /// Woken when a full batch is queued.
+    full: Notify,
+    /// Events dropped because the queue was full.
+    dropped: AtomicU64,
+}
+
+impl Webhooks {
+    pub fn new(cfg: &WebhooksConfig) -> Self {
+        Self {
+            cfg: cfg.clone(),
+            queue: Mutex::default(),
+            full: Notify::new(),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Queue `event` for the next batch.
+    pub(crate) fn emit(&self, event: ServedEvent) {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.len() >= self.cfg.queue_capacity {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            debug!(path = event.path, "webhook queue full, event dropped");
+            return;
+        }
+        queue.push_back(event);
+        if queue.len() >= self.cfg.batch_size {
+            self.full.notify_one();
+        }
+    }
+
+    /// Events dropped so far because the sink could not keep up.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Take up to one batch off the queue.
+    fn take_batch(&self) -> Vec<ServedEvent> {
+        let mut queue = self.queue.lock().unwrap();
+        let n = queue.len().min(self.cfg.batch_size);
+        queue.drain(..n).collect()
+    }
+
+    /// Put a batch the sink refused back at the front, as far as it fits.
+    fn requeue(&self, batch: Vec<ServedEvent>) {
+        let mut queue = self.queue.lock().unwrap();
+        let room = self.cfg.queue_capacity.saturating_sub(queue.len());
+        let dropped = batch.len().saturating_sub(room);
+        self.dropped.fetch_add(dropped as u64, Ordering::Relaxed);
+        for event in batch.into_iter().take(room).rev() {
+            queue.push_front(event);
+        }
+    }
+
+    /// POST `batch` to the sink.
+    async fn send(&self, client: &PostClient, batch: &[ServedEvent]) -> Result<(), String> {
+        let (content_type, body) = encode(self.cfg.format, batch);
+        let mut req = Request::post(&self.cfg.url).header("Content-Type", content_type);
+        for (name, value) in &self.cfg.headers {
+            req = req.header(name, value);
+        }
+        let req = req.body(Full::new(body)).map_err(|e| e.to_string())?;
+        let timeout = Duration::from_millis(self.cfg.timeout_ms);
+        let resp = match tokio::time::timeout(timeout, client.request(req)).await {
+            Ok(Ok(resp)) => resp,
+            Ok(Err(e)) => return Err(e.to_string()),
+            Err(_) => return Err(format!("timed out after {}ms", timeout.as_millis())),
+        };
+        if !resp.status().is_success() {
+            return Err(format!("sink answered {}", resp.status()));
+        }
+        Ok(())
+    }
+}
+
+/// The content type and body of a batch in `format`.
+fn encode(format: WebhookFormat, batch: &[ServedEvent]) -> (&'static str, Bytes) {
+    match format {
+        WebhookFormat::Array => ("application/json", json(&batch).into()),
+        WebhookFormat::Ndjson => {
+            let mut body = Vec::new();
+            for event in batch {
+                body.extend(json(event));
+                body.push(b'\n');
+            }
+            ("application/x-ndjson", body.into())
+        }
+        WebhookFormat::KafkaRest => {
+            #[derive(Serialize)]
+            struct Record<'a> {
+                value: &'a ServedEvent,
+            }
+            #[derive(Serialize)]
+            struct Records<'a> {
+                records: Vec<Record<'a>>,
+            }
+            let records = batch.iter().map(|value| Record { value }).collect();
+            let body = json(&Records { records });
+            ("application/vnd.kafka.json.v2+json", body.into())
+        }
+    }
+}
+
+fn json(value: &impl Serialize) -> Vec<u8> {
+    serde_json::to_vec(value).expect("JSON serialization cannot fail")
+}
+
+/// Spawn the background task that sends a batch once `batch_size` events
+/// are queued or `flush_interval_ms` has passed. A batch the sink refuses
+/// is kept for the next attempt.
+pub fn spawn_sender(webhooks: Arc<Webhooks>) {
+    let client: PostClient = Client::builder(TokioExecutor::new()).build(remote::connector());
+    let interval = Duration::from_millis(webhooks.cfg.flush_interval_ms);
+    info!(url = webhooks.cfg.url, "webhook sender started");
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = webhooks.full.notified() => {}
+            }
+            loop {
+                let batch = webhooks.take_batch();
+                if batch.is_empty() {
+                    break;
+                }
+                let full = batch.len() == webhooks.cfg.batch_size;
+                if let Err(e) = webhooks.send(&client, &batch).await {
+                    warn!(events = batch.len(), error = %e, "cannot send webhook batch");
+                    webhooks.requeue(batch);
+                    break;
+                }
+                debug!(events = batch.len(), "webhook batch sent");
+                if !full {
+                    break;
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,109 @@
+//! Running as a Windows service. The service control manager starts
+//! `filehunter --service`, which hands the main thread to it; the server
+//! then runs on the service's own thread until a Stop (or a system
+//! shutdown) asks it to stop gracefully.
+
+use std::ffi::OsString;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tracing::error;
+use windows_service::service::{
+    ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{
+    self, ServiceControlHandlerResult, ServiceStatusHandle,
+};
+use windows_service::{define_windows_service, service_dispatcher};
+
+/// The name the service is registered under (`sc create filehunter ...`).
+pub const SERVICE_NAME: &str = "filehunter";
+
+/// How long open connections may take to finish before the service
+/// control manager considers the stop stuck.
+const STOP_WAIT_HINT: Duration = Duration::from_secs(30);
+
+/// What the service runs, handed over by [`run`].
+type Body = Box<dyn FnOnce() -> Result<(), String> + Send>;
+
+static BODY: Mutex<Option<Body>> = Mutex::new(None);
+static STATUS: OnceLock<ServiceStatusHandle> = OnceLock::new();
+static STOP: Notify = Notify::const_new();
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Run `body` as the service, reporting it running until it returns.
+/// Blocks until the service has stopped; fails when the process was not
+/// started by the service control manager.
+pub fn run(body: impl FnOnce() -> Result<(), String> + Send + 'static) -> Result<(), String> {
+    *BODY.lock().unwrap() = Some(Box::new(body));
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+        .map_err(|e| format!("cannot start as a service: {e}"))
+}
+
+/// Resolves once the service control manager asks the service to stop.
+pub async fn stop_requested() {
+    STOP.notified().await;
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    let Some(body) = BODY.lock().unwrap().take() else {
+        return;
+    };
+    let handler = |control: ServiceControl| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            report(ServiceState::StopPending, ServiceExitCode::NO_ERROR);
+            STOP.notify_one();
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+    match service_control_handler::register(SERVICE_NAME, handler) {
+        Ok(status) => {
+            let _ = STATUS.set(status);
+        }
+        Err(e) => {
+            error!(error = %e, "cannot register the service control handler");
+            return;
+        }
+    }
+
+    report(ServiceState::Running, ServiceExitCode::NO_ERROR);
+    let exit_code = match body() {
+        Ok(()) => ServiceExitCode::NO_ERROR,
+        Err(e) => {
+            error!(error = %e, "service failed");
+            ServiceExitCode::ServiceSpecific(1)
+        }
+    };
+    report(ServiceState::Stopped, exit_code);
+}
+
+/// Tell the service control manager the service is now in `state`.
+fn report(state: ServiceState, exit_code: ServiceExitCode) {
+    let Some(status) = STATUS.get() else {
+        return;
+    };
+    let controls_accepted = match state {
+        ServiceState::Running => ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        _ => ServiceControlAccept::empty(),
+    };
+    let wait_hint = match state {
+        ServiceState::StopPending => STOP_WAIT_HINT,
+        _ => Duration::ZERO,
+    };
+    let result = status.set_service_status(ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted,
+        exit_code,
+        checkpoint: 0,
+        wait_hint,
+        process_id: None,
+    });
+    if let Err(e) = result {
+        error!(error = %e, ?state, "cannot report service status");
+    }
+}
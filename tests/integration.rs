@@ -8,6 +8,7 @@ use http_body_util::{BodyExt, Empty};
 use hyper::{Request, StatusCode};
 use tempfile::TempDir;
 
+use filehunter::backend::{FoundObject, ProbeError};
 use filehunter::config::*;
 use filehunter::server::{handle_request, FileSearcher, ResponseBody};
 
@@ -46,24 +47,15 @@ fn setup_single_root(
         fs::write(&path, content).unwrap();
     }
 
-    let config = Config {
-        server: ServerConfig::default(),
-        locations: vec![LocationConfig {
-            prefix: "/".into(),
-            mode: SearchMode::Sequential,
-            max_file_size: None,
-            paths: vec![SearchPath {
-                root: dir.path().to_path_buf(),
-                extensions,
-            }],
-        }],
-    };
-    let searcher = Arc::new(FileSearcher::new(&config));
-    (dir, searcher)
+    let searcher = FileSearcher::builder()
+        .root_with_extensions(dir.path(), extensions)
+        .build()
+        .unwrap();
+    (dir, Arc::new(searcher))
 }
 
 // ---------------------------------------------------------------------------
-// HTTP method & status code (5 tests)
+// HTTP method & status code (9 tests)
 // ---------------------------------------------------------------------------
 
 #[tokio::test]
@@ -118,6 +110,140 @@ async fn post_returns_405() {
     let req = make_request("POST", "/test.txt");
     let resp = handle_request(req, searcher, None, localhost()).await.unwrap();
     assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(resp.headers()["Allow"], "GET, HEAD");
+}
+
+#[tokio::test]
+async fn errors_negotiate_problem_json() {
+    let (_dir, searcher) = setup_single_root(&[("test.txt", b"hello")], vec![]);
+    let request = |method: &str, accept: &str| {
+        let mut req = make_request(method, "/missing.txt");
+        let headers = req.headers_mut();
+        headers.insert("Accept", accept.parse().unwrap());
+        headers.insert("X-Request-Id", "req-7".parse().unwrap());
+        handle_request(req, searcher.clone(), None, localhost())
+    };
+
+    let resp = request("GET", "text/html, */*;q=0.8").await.unwrap();
+    assert_eq!(resp.headers()["Content-Type"], "text/plain; charset=utf-8");
+    assert_eq!(body_string(resp).await, "Not Found");
+
+    let resp = request("GET", "application/problem+json").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(resp.headers()["Content-Type"], "application/problem+json");
+    assert_eq!(resp.headers()["X-Request-Id"], "req-7");
+    let body: serde_json::Value = serde_json::from_str(&body_string(resp).await).unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!({
+            "type": "about:blank",
+            "title": "Not Found",
+            "status": 404,
+            "instance": "/missing.txt",
+            "request_id": "req-7",
+        })
+    );
+
+    // Headers of the original response are kept.
+    let resp = request("POST", "application/json").await.unwrap();
+    assert_eq!(resp.headers()["Allow"], "GET, HEAD");
+    let body: serde_json::Value = serde_json::from_str(&body_string(resp).await).unwrap();
+    assert_eq!(body["status"], 405);
+}
+
+#[tokio::test]
+async fn error_format_renders_json_and_html_pages() {
+    let dir = tempfile::tempdir().unwrap();
+    let template = dir.path().join("error.html");
+    let page = "<title>{{status}} {{title}}</title><p>{{path}}</p>";
+    fs::write(&template, page).unwrap();
+    let server = ServerConfig {
+        error_format: ErrorFormat::Json,
+        ..Default::default()
+    };
+    let searcher = FileSearcher::builder()
+        .server(server)
+        .location("/api")
+        .root(dir.path())
+        .location("/site")
+        .error_format(ErrorFormat::Html)
+        .error_template(&template)
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let request = |method: &str, uri: &str, accept: &str| {
+        let mut req = make_request(method, uri);
+        req.headers_mut().insert("Accept", accept.parse().unwrap());
+        handle_request(req, searcher.clone(), None, localhost())
+    };
+
+    let resp = request("GET", "/api/missing.txt", "*/*").await.unwrap();
+    assert_eq!(resp.headers()["Content-Type"], "application/problem+json");
+    let body: serde_json::Value = serde_json::from_str(&body_string(resp).await).unwrap();
+    assert_eq!(body["instance"], "/api/missing.txt");
+
+    let resp = request("GET", "/site/a&b.txt", "text/html").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(resp.headers()["Content-Type"], "text/html; charset=utf-8");
+    assert_eq!(
+        body_string(resp).await,
+        "<title>404 Not Found</title><p>/site/a&amp;b.txt</p>"
+    );
+
+    let resp = request("POST", "/site/a.txt", "text/html").await.unwrap();
+    assert_eq!(resp.headers()["Allow"], "GET, HEAD");
+    let body = body_string(resp).await;
+    assert!(body.starts_with("<title>405 Method Not Allowed"));
+
+    // A client asking for JSON still gets problem details.
+    let resp = request("GET", "/site/a.txt", "application/json");
+    let resp = resp.await.unwrap();
+    assert_eq!(resp.headers()["Content-Type"], "application/problem+json");
+}
+
+#[tokio::test]
+async fn location_methods_set_allow_and_options() {
+    let dir = tempfile::tempdir().unwrap();
+    let searcher = FileSearcher::builder()
+        .location("/docs")
+        .methods(vec!["OPTIONS".into()])
+        .root(dir.path())
+        .location("/files")
+        .auth(LocationAuth::ApiKey {
+            keys: vec!["k1".into()],
+            header: "X-Api-Key".into(),
+            query_param: String::new(),
+        })
+        .upload_root(dir.path())
+        .methods(vec!["PUT".into()])
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let request = |method: &'static str, uri: &'static str| {
+        let searcher = searcher.clone();
+        async move {
+            handle_request(make_request(method, uri), searcher, None, localhost())
+                .await
+                .unwrap()
+        }
+    };
+
+    let resp = request("OPTIONS", "/docs/a.txt").await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    assert_eq!(resp.headers()["Allow"], "GET, HEAD, OPTIONS");
+    let resp = request("PUT", "/docs/a.txt").await;
+    assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(resp.headers()["Allow"], "GET, HEAD, OPTIONS");
+
+    // The writable location takes PUT only, so forms are refused.
+    let resp = request("POST", "/files/").await;
+    assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(resp.headers()["Allow"], "GET, HEAD, PUT");
+    let resp = request("OPTIONS", "/files/a.txt").await;
+    assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(request("PUT", "/files/a.txt").await.status(), StatusCode::UNAUTHORIZED);
 }
 
 #[tokio::test]
@@ -133,6 +259,35 @@ async fn oversized_content_length_413() {
     assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
 }
 
+#[tokio::test]
+async fn overlong_or_deep_path_414_400() {
+    // Unlimited by default.
+    let (dir, searcher) = setup_single_root(&[("x", b"tiny")], vec![]);
+    let long = format!("/{}", "%61".repeat(1024));
+    let req = make_request("GET", &long);
+    let resp = handle_request(req, searcher, None, localhost()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let searcher = FileSearcher::builder()
+        .server(ServerConfig {
+            max_path_length: 1024,
+            max_path_segments: 32,
+            ..Default::default()
+        })
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let req = make_request("GET", &long);
+    let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::URI_TOO_LONG);
+
+    let deep = "/a".repeat(33);
+    let req = make_request("GET", &deep);
+    let resp = handle_request(req, searcher, None, localhost()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
 // ---------------------------------------------------------------------------
 // MIME types (2 tests)
 // ---------------------------------------------------------------------------
@@ -180,7 +335,96 @@ async fn filter_allows_matching() {
 }
 
 // ---------------------------------------------------------------------------
-// Search modes (2 tests)
+// Symlink policy (1 test)
+// ---------------------------------------------------------------------------
+
+#[cfg(unix)]
+#[tokio::test]
+async fn symlink_policy_controls_which_links_are_followed() {
+    let root = tempfile::tempdir().unwrap();
+    let outside = tempfile::tempdir().unwrap();
+    fs::write(root.path().join("real.txt"), b"real").unwrap();
+    fs::write(outside.path().join("secret.txt"), b"secret").unwrap();
+    std::os::unix::fs::symlink("real.txt", root.path().join("inside.txt")).unwrap();
+    std::os::unix::fs::symlink(
+        outside.path().join("secret.txt"),
+        root.path().join("escape.txt"),
+    )
+    .unwrap();
+
+    let status = |symlinks: SymlinkPolicy, uri: &'static str| {
+        let config = Config {
+            server: ServerConfig::default(),
+            locations: vec![LocationConfig {
+                prefix: "/".into(),
+                paths: vec![SearchPath {
+                    root: root.path().to_path_buf(),
+                    ..Default::default()
+                }],
+                symlinks,
+                ..Default::default()
+            }],
+            vhosts: Vec::new(),
+        };
+        let searcher = Arc::new(FileSearcher::new(&config));
+        async move {
+            let req = make_request("GET", uri);
+            let resp = handle_request(req, searcher, None, localhost()).await.unwrap();
+            resp.status()
+        }
+    };
+
+    use SymlinkPolicy::{Allow, Deny, SameRoot};
+    assert_eq!(status(SameRoot, "/inside.txt").await, StatusCode::OK);
+    assert_eq!(status(SameRoot, "/escape.txt").await, StatusCode::NOT_FOUND);
+    assert_eq!(status(Deny, "/real.txt").await, StatusCode::OK);
+    assert_eq!(status(Deny, "/inside.txt").await, StatusCode::NOT_FOUND);
+    assert_eq!(status(Allow, "/escape.txt").await, StatusCode::OK);
+}
+
+// ---------------------------------------------------------------------------
+// Deny patterns (1 test)
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn deny_patterns_hide_matching_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("docs")).unwrap();
+    for name in ["report.txt", "report.txt.bak", "report.txt~"] {
+        fs::write(dir.path().join("docs").join(name), b"data").unwrap();
+    }
+    fs::write(dir.path().join("passwords.csv"), b"data").unwrap();
+    let config = Config {
+        server: ServerConfig::default(),
+        locations: vec![LocationConfig {
+            prefix: "/files".into(),
+            paths: vec![SearchPath {
+                root: dir.path().to_path_buf(),
+                ..Default::default()
+            }],
+            deny_patterns: vec![r"\.bak$".into(), "~$".into(), "^password".into()],
+            ..Default::default()
+        }],
+        vhosts: Vec::new(),
+    };
+    let searcher = Arc::new(FileSearcher::new(&config));
+
+    for (uri, expected) in [
+        ("/files/docs/report.txt", StatusCode::OK),
+        ("/files/docs/report.txt.bak", StatusCode::NOT_FOUND),
+        ("/files/docs/report.txt~", StatusCode::NOT_FOUND),
+        ("/files/passwords.csv", StatusCode::NOT_FOUND),
+    ] {
+        let req = make_request("GET", uri);
+        let resp = handle_request(req, searcher.clone(), None, localhost())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), expected, "{uri}");
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Search modes (3 tests)
 // ---------------------------------------------------------------------------
 
 #[tokio::test]
@@ -195,18 +439,21 @@ async fn sequential_returns_first_root() {
         locations: vec![LocationConfig {
             prefix: "/".into(),
             mode: SearchMode::Sequential,
-            max_file_size: None,
             paths: vec![
                 SearchPath {
                     root: dir1.path().to_path_buf(),
                     extensions: vec![],
+                    ..Default::default()
                 },
                 SearchPath {
                     root: dir2.path().to_path_buf(),
                     extensions: vec![],
+                    ..Default::default()
                 },
             ],
+            ..Default::default()
         }],
+        vhosts: Vec::new(),
     };
     let searcher = Arc::new(FileSearcher::new(&config));
 
@@ -239,18 +486,21 @@ async fn latest_modified_returns_newer() {
         locations: vec![LocationConfig {
             prefix: "/".into(),
             mode: SearchMode::LatestModified,
-            max_file_size: None,
             paths: vec![
                 SearchPath {
                     root: dir1.path().to_path_buf(),
                     extensions: vec![],
+                    ..Default::default()
                 },
                 SearchPath {
                     root: dir2.path().to_path_buf(),
                     extensions: vec![],
+                    ..Default::default()
                 },
             ],
+            ..Default::default()
         }],
+        vhosts: Vec::new(),
     };
     let searcher = Arc::new(FileSearcher::new(&config));
 
@@ -261,8 +511,55 @@ async fn latest_modified_returns_newer() {
     assert_eq!(body, "new");
 }
 
+#[tokio::test]
+async fn adaptive_prefers_root_that_keeps_winning() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+    fs::write(dir1.path().join("data.txt"), b"first").unwrap();
+    fs::write(dir2.path().join("data.txt"), b"second").unwrap();
+    fs::write(dir2.path().join("popular.txt"), b"popular").unwrap();
+
+    let config = Config {
+        server: ServerConfig::default(),
+        locations: vec![LocationConfig {
+            prefix: "/".into(),
+            mode: SearchMode::Adaptive,
+            paths: vec![
+                SearchPath {
+                    root: dir1.path().to_path_buf(),
+                    extensions: vec![],
+                    ..Default::default()
+                },
+                SearchPath {
+                    root: dir2.path().to_path_buf(),
+                    extensions: vec![],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }],
+        vhosts: Vec::new(),
+    };
+    let searcher = Arc::new(FileSearcher::new(&config));
+    let get = |uri: &'static str| {
+        let searcher = searcher.clone();
+        async move {
+            let req = make_request("GET", uri);
+            let resp = handle_request(req, searcher, None, localhost()).await.unwrap();
+            body_string(resp).await
+        }
+    };
+
+    // Config order until the second root has proven itself.
+    assert_eq!(get("/data.txt").await, "first");
+    for _ in 0..60 {
+        assert_eq!(get("/popular.txt").await, "popular");
+    }
+    assert_eq!(get("/data.txt").await, "second");
+}
+
 // ---------------------------------------------------------------------------
-// Routing integration (1 test)
+// Routing integration (2 tests)
 // ---------------------------------------------------------------------------
 
 #[tokio::test]
@@ -279,22 +576,25 @@ async fn longest_prefix_routing() {
             LocationConfig {
                 prefix: "/img".into(),
                 mode: SearchMode::Sequential,
-                max_file_size: None,
                 paths: vec![SearchPath {
                     root: img_dir.path().to_path_buf(),
                     extensions: vec![],
+                    ..Default::default()
                 }],
+                ..Default::default()
             },
             LocationConfig {
                 prefix: "/".into(),
                 mode: SearchMode::Sequential,
-                max_file_size: None,
                 paths: vec![SearchPath {
                     root: root_dir.path().to_path_buf(),
                     extensions: vec![],
+                    ..Default::default()
                 }],
+                ..Default::default()
             },
         ],
+        vhosts: Vec::new(),
     };
     let searcher = Arc::new(FileSearcher::new(&config));
 
@@ -305,8 +605,133 @@ async fn longest_prefix_routing() {
     assert_eq!(body, "img-content");
 }
 
+#[tokio::test]
+async fn vhosts_route_by_host_header() {
+    let dirs: Vec<TempDir> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+    for (dir, body) in dirs.iter().zip(["default", "img", "files"]) {
+        fs::write(dir.path().join("index.txt"), body).unwrap();
+    }
+    fs::write(dirs[1].path().join("logo.png"), b"png").unwrap();
+    let searcher = FileSearcher::builder()
+        .root(dirs[0].path())
+        .vhost(["img.example.com"])
+        .location("/")
+        .root(dirs[1].path())
+        .vhost(["files.example.com", "*.files.example.com"])
+        .location("/")
+        .root(dirs[2].path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let get = |host: &str, uri: &str| {
+        let mut req = make_request("GET", uri);
+        req.headers_mut().insert("Host", host.parse().unwrap());
+        handle_request(req, searcher.clone(), None, localhost())
+    };
+
+    for (host, body) in [
+        ("img.example.com", "img"),
+        ("IMG.example.com:8080", "img"),
+        ("eu.files.example.com", "files"),
+        ("other.example.com", "default"),
+    ] {
+        let resp = get(host, "/index.txt").await.unwrap();
+        assert_eq!(body_string(resp).await, body, "host {host}");
+    }
+    // Sites are isolated: a file of one is not found from another.
+    let resp = get("files.example.com", "/logo.png").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let labels: Vec<String> = searcher.status().into_iter().map(|l| l.prefix).collect();
+    assert_eq!(labels, ["/", "img.example.com/", "files.example.com/"]);
+}
+
+// ---------------------------------------------------------------------------
+// Redirects (2 tests)
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn trailing_slash_policy_redirects_or_ignores() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("docs")).unwrap();
+    fs::write(dir.path().join("docs/a.txt"), b"alpha").unwrap();
+    let searcher = FileSearcher::builder()
+        .location("/canon")
+        .trailing_slash(TrailingSlash::Redirect)
+        .root(dir.path())
+        .location("/loose")
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let get = |uri: &'static str| {
+        let searcher = searcher.clone();
+        async move {
+            handle_request(make_request("GET", uri), searcher, None, localhost())
+                .await
+                .unwrap()
+        }
+    };
+
+    let resp = get("/canon/docs/a.txt/?v=2").await;
+    assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(resp.headers()["Location"], "/canon/docs/a.txt?v=2");
+    let resp = get("/canon/docs").await;
+    assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(resp.headers()["Location"], "/canon/docs/");
+    assert_eq!(get("/canon/docs/a.txt").await.status(), StatusCode::OK);
+    assert_eq!(get("/canon/docs/").await.status(), StatusCode::NOT_FOUND);
+    assert_eq!(get("/canon/nope").await.status(), StatusCode::NOT_FOUND);
+
+    let resp = get("/loose/docs/a.txt/").await;
+    assert_eq!(body_string(resp).await, "alpha");
+    assert_eq!(get("/loose/docs").await.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn redirect_rules_send_moved_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("old")).unwrap();
+    fs::write(dir.path().join("old/a.css"), b"stale").unwrap();
+    let searcher = FileSearcher::builder()
+        .location("/assets")
+        .redirect(RedirectRule {
+            from: "/assets/old".into(),
+            to: "/assets/v2/".into(),
+            prefix: true,
+            status: 301,
+        })
+        .redirect(RedirectRule {
+            from: "/assets/logo.png".into(),
+            to: "https://cdn.example.com/logo.png?v=3".into(),
+            prefix: false,
+            status: 307,
+        })
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let get = |uri: &'static str| {
+        let searcher = searcher.clone();
+        async move {
+            handle_request(make_request("GET", uri), searcher, None, localhost())
+                .await
+                .unwrap()
+        }
+    };
+
+    let resp = get("/assets/old/a.css?x=1").await;
+    assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(resp.headers()["Location"], "/assets/v2/a.css?x=1");
+    let resp = get("/assets/logo.png?x=1").await;
+    assert_eq!(resp.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(resp.headers()["Location"], "https://cdn.example.com/logo.png?v=3");
+    assert_eq!(get("/assets/older/a.css").await.status(), StatusCode::NOT_FOUND);
+    assert_eq!(get("/assets/logo.png/x").await.status(), StatusCode::NOT_FOUND);
+}
+
 // ---------------------------------------------------------------------------
-// Rate limiting (1 test)
+// Rate limiting (3 tests)
 // ---------------------------------------------------------------------------
 
 #[tokio::test]
@@ -318,6 +743,7 @@ async fn rate_limited_returns_429() {
         requests_per_second: 1,
         burst_size: 1,
         cleanup_interval: 600,
+        ..Default::default()
     };
     let limiter = filehunter::ratelimit::build_limiter(&limiter_config);
 
@@ -327,6 +753,9 @@ async fn rate_limited_returns_429() {
         .await
         .unwrap();
     assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["RateLimit-Limit"], "1");
+    assert_eq!(resp.headers()["RateLimit-Remaining"], "0");
+    assert_eq!(resp.headers()["RateLimit-Reset"], "1");
 
     // Second request should be rate-limited.
     let req = make_request("GET", "/test.txt");
@@ -335,4 +764,2706 @@ async fn rate_limited_returns_429() {
         .unwrap();
     assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
     assert!(resp.headers().contains_key("Retry-After"));
+    assert_eq!(resp.headers()["RateLimit-Remaining"], "0");
+    assert_eq!(resp.headers()["RateLimit-Reset"], resp.headers()["Retry-After"]);
+}
+
+#[tokio::test]
+async fn rate_limit_exempt_clients_are_never_throttled() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), b"a").unwrap();
+    let rate_limit = RateLimitConfig {
+        enabled: true,
+        requests_per_second: 1,
+        burst_size: 1,
+        cleanup_interval: 600,
+        exempt: vec!["10.0.0.0/8".into(), "127.0.0.1".into()],
+        ..Default::default()
+    };
+    let limiter = filehunter::ratelimit::build_limiter(&rate_limit);
+    let searcher = FileSearcher::builder()
+        .server(ServerConfig {
+            rate_limit,
+            ..Default::default()
+        })
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let get = |ip: &'static str| {
+        let (searcher, limiter) = (searcher.clone(), limiter.clone());
+        async move {
+            let req = make_request("GET", "/a.txt");
+            handle_request(req, searcher, Some(limiter), ip.parse().unwrap())
+                .await
+                .unwrap()
+                .status()
+        }
+    };
+
+    for ip in ["10.1.2.3", "127.0.0.1", "::ffff:10.9.9.9"] {
+        for _ in 0..3 {
+            assert_eq!(get(ip).await, StatusCode::OK, "{ip}");
+        }
+    }
+    assert_eq!(get("192.0.2.1").await, StatusCode::OK);
+    assert_eq!(get("192.0.2.1").await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn fixed_window_limits_requests_per_window() {
+    let (_dir, searcher) = setup_single_root(&[("test.txt", b"hello")], vec![]);
+    let limiter = filehunter::ratelimit::build_limiter(&RateLimitConfig {
+        enabled: true,
+        requests_per_second: 1,
+        algorithm: RateLimitAlgorithm::FixedWindow,
+        window: 60,
+        ..Default::default()
+    });
+
+    for remaining in (0..60).rev() {
+        let req = make_request("GET", "/test.txt");
+        let resp = handle_request(req, searcher.clone(), Some(limiter.clone()), localhost())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["RateLimit-Limit"], "60");
+        assert_eq!(resp.headers()["RateLimit-Remaining"], remaining.to_string().as_str());
+    }
+    let req = make_request("GET", "/test.txt");
+    let resp = handle_request(req, searcher, Some(limiter), localhost()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+// ---------------------------------------------------------------------------
+// All-matches inspection (2 tests)
+// ---------------------------------------------------------------------------
+
+fn matches_searcher(dir1: &TempDir, dir2: &TempDir) -> Arc<FileSearcher> {
+    let config = Config {
+        server: ServerConfig {
+            admin: AdminConfig {
+                enabled: true,
+                token: "secret".into(),
+            },
+            ..ServerConfig::default()
+        },
+        locations: vec![LocationConfig {
+            prefix: "/".into(),
+            mode: SearchMode::Sequential,
+            paths: vec![
+                SearchPath {
+                    root: dir1.path().to_path_buf(),
+                    extensions: vec![],
+                    ..Default::default()
+                },
+                SearchPath {
+                    root: dir2.path().to_path_buf(),
+                    extensions: vec![],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }],
+        vhosts: Vec::new(),
+    };
+    Arc::new(FileSearcher::new(&config))
+}
+
+#[tokio::test]
+async fn matches_lists_every_root() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+    fs::write(dir1.path().join("data.txt"), b"first").unwrap();
+    fs::write(dir2.path().join("data.txt"), b"second!").unwrap();
+    let searcher = matches_searcher(&dir1, &dir2);
+
+    let req = Request::builder()
+        .method("GET")
+        .uri("/_matches/data.txt")
+        .header("Authorization", "Bearer secret")
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+    let resp = handle_request(req, searcher, None, localhost()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let json: serde_json::Value = serde_json::from_str(&body_string(resp).await).unwrap();
+    let matches = json["matches"].as_array().unwrap();
+    assert_eq!(matches.len(), 2);
+    assert_eq!(matches[0]["size"], 5);
+    assert_eq!(matches[1]["size"], 7);
+}
+
+#[tokio::test]
+async fn matches_requires_token() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+    fs::write(dir1.path().join("data.txt"), b"first").unwrap();
+    let searcher = matches_searcher(&dir1, &dir2);
+
+    let req = make_request("GET", "/_matches/data.txt");
+    let resp = handle_request(req, searcher, None, localhost()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+// ---------------------------------------------------------------------------
+// Resolution trace (1 test)
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn explain_reports_each_root_outcome() {
+    let dirs: Vec<TempDir> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+    fs::write(dirs[1].path().join("data.txt"), b"too large").unwrap();
+    fs::write(dirs[2].path().join("data.txt"), b"fits").unwrap();
+    let missing = dirs[0].path().join("missing");
+    let searcher = FileSearcher::builder()
+        .server(ServerConfig {
+            admin: AdminConfig {
+                enabled: true,
+                token: "secret".into(),
+            },
+            ..Default::default()
+        })
+        .max_file_size(8)
+        .root_with_extensions(dirs[0].path(), ["jpg"])
+        .root(dirs[1].path())
+        .root(dirs[2].path())
+        .root(&missing)
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+
+    let req = admin_request("GET", "/_explain/data.txt", "");
+    let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let json: serde_json::Value = serde_json::from_str(&body_string(resp).await).unwrap();
+    assert_eq!(json["location"], "/");
+    assert_eq!(json["mode"], "sequential");
+    let outcomes: Vec<&str> = json["probes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|probe| probe["outcome"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        outcomes,
+        ["extension_filtered", "too_large", "found", "inactive"]
+    );
+    assert_eq!(json["probes"][1]["size"], 9);
+    let winner = dirs[2].path().canonicalize().unwrap();
+    assert_eq!(json["winner"], winner.to_str().unwrap());
+
+    let req = admin_request("GET", "/_explain/.env", "");
+    let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+    let json: serde_json::Value = serde_json::from_str(&body_string(resp).await).unwrap();
+    assert_eq!(json["refused"], "hidden_file");
+    assert!(json["winner"].is_null());
+
+    let req = make_request("GET", "/_explain/data.txt");
+    let resp = handle_request(req, searcher, None, localhost()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+// ---------------------------------------------------------------------------
+// Startup report (2 tests)
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn startup_report_lists_active_and_skipped_roots() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        server: ServerConfig::default(),
+        locations: vec![LocationConfig {
+            prefix: "/".into(),
+            mode: SearchMode::Sequential,
+            paths: vec![
+                SearchPath {
+                    root: dir.path().to_path_buf(),
+                    extensions: vec![],
+                    ..Default::default()
+                },
+                SearchPath {
+                    root: "/nonexistent/filehunter".into(),
+                    extensions: vec![],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }],
+        vhosts: Vec::new(),
+    };
+    let searcher = FileSearcher::new(&config);
+    let addr = "127.0.0.1:8080".parse().unwrap();
+    let report = filehunter::report::StartupReport::new(&config, &searcher, vec![addr]);
+
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!(json["listeners"][0], "127.0.0.1:8080");
+    let roots = json["locations"][0]["roots"].as_array().unwrap();
+    assert_eq!(roots.len(), 2);
+    assert_eq!(roots[0]["active"], true);
+    assert_eq!(roots[1]["active"], false);
+    assert!(json["server"]["admin"].get("token").is_none());
+}
+
+#[tokio::test]
+async fn startup_report_omits_webhook_headers() {
+    let mut server = ServerConfig::default();
+    server.webhooks.url = "https://audit.example.com/events".into();
+    let secret = "Bearer s3cr3t-webhook-token";
+    let headers = &mut server.webhooks.headers;
+    headers.insert("Authorization".into(), secret.into());
+    let config = Config {
+        server,
+        locations: Vec::new(),
+        vhosts: Vec::new(),
+    };
+    let searcher = FileSearcher::new(&config);
+    let report = filehunter::report::StartupReport::new(&config, &searcher, vec![]);
+
+    let json = serde_json::to_string(&report).unwrap();
+    assert!(json.contains("audit.example.com"));
+    assert!(!json.contains(secret));
+}
+
+// ---------------------------------------------------------------------------
+// Admin root management (1 test)
+// ---------------------------------------------------------------------------
+
+fn admin_request(method: &str, uri: &str, body: &str) -> Request<http_body_util::Full<Bytes>> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Authorization", "Bearer secret")
+        .body(http_body_util::Full::new(Bytes::from(body.to_owned())))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn admin_attach_and_detach_root() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+    let extra = tempfile::tempdir().unwrap();
+    fs::write(extra.path().join("late.txt"), b"late").unwrap();
+    let searcher = matches_searcher(&dir1, &dir2);
+
+    let resp = handle_request(make_request("GET", "/late.txt"), searcher.clone(), None, localhost())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let change = serde_json::json!({ "location": "/", "root": extra.path() }).to_string();
+    let req = admin_request("POST", "/_admin/roots", &change);
+    let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = handle_request(make_request("GET", "/late.txt"), searcher.clone(), None, localhost())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let req = admin_request("DELETE", "/_admin/roots", &change);
+    let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = handle_request(make_request("GET", "/late.txt"), searcher, None, localhost())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+// ---------------------------------------------------------------------------
+// Location statistics (2 tests)
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn admin_stats_count_hits_misses_and_bytes() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+    fs::write(dir1.path().join("data.txt"), b"12345").unwrap();
+    let searcher = matches_searcher(&dir1, &dir2);
+
+    for uri in ["/data.txt", "/data.txt", "/missing.txt"] {
+        let req = make_request("GET", uri);
+        let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+        body_string(resp).await;
+    }
+
+    let req = admin_request("GET", "/_admin/stats", "");
+    let resp = handle_request(req, searcher, None, localhost()).await.unwrap();
+    let json: serde_json::Value = serde_json::from_str(&body_string(resp).await).unwrap();
+    let stats = &json[0];
+    assert_eq!(stats["prefix"], "/");
+    assert_eq!(stats["requests"], 3);
+    assert_eq!(stats["hits"], 2);
+    assert_eq!(stats["misses"], 1);
+    // Two 5-byte files and the 9-byte "Not Found".
+    assert_eq!(stats["bytes"], 19);
+    assert_eq!(stats["aborted"], 0);
+    assert!(stats["p99_us"].as_u64().unwrap() >= stats["p50_us"].as_u64().unwrap());
+    let roots = stats["roots"].as_array().unwrap();
+    assert_eq!(roots.len(), 2);
+    assert_eq!(roots[0]["hits"], 2);
+    assert_eq!(roots[0]["share"], 1.0);
+    assert_eq!(roots[1]["hits"], 0);
+    assert_eq!(roots[1]["share"], 0.0);
+}
+
+#[tokio::test]
+async fn admin_metrics_export_search_latency() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+    fs::write(dir1.path().join("data.txt"), b"12345").unwrap();
+    let searcher = matches_searcher(&dir1, &dir2);
+
+    for uri in ["/data.txt", "/missing.txt", "/missing.txt"] {
+        let req = make_request("GET", uri);
+        let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+        body_string(resp).await;
+    }
+
+    let req = admin_request("GET", "/_admin/metrics", "");
+    let resp = handle_request(req, searcher, None, localhost()).await.unwrap();
+    let content_type = resp.headers()["content-type"].to_str().unwrap();
+    assert!(content_type.starts_with("text/plain; version=0.0.4"));
+    let text = body_string(resp).await;
+    let hit = r#"location="/",mode="sequential",roots="2",outcome="hit""#;
+    let miss = r#"location="/",mode="sequential",roots="2",outcome="miss""#;
+    assert!(text.contains("# TYPE filehunter_search_duration_seconds histogram"));
+    assert!(text.contains(&format!("_count{{{hit}}} 1\n")));
+    assert!(text.contains(&format!(r#"_bucket{{{miss},le="+Inf"}} 2"#)));
+    assert!(text.contains(r#"filehunter_aborted_requests_total{location="/"} 0"#));
+}
+
+// ---------------------------------------------------------------------------
+// Shadow configuration (1 test)
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn shadow_config_counts_divergent_resolutions() {
+    let live = tempfile::tempdir().unwrap();
+    let candidate = tempfile::tempdir().unwrap();
+    fs::write(live.path().join("a.txt"), b"a").unwrap();
+    fs::write(candidate.path().join("a.txt"), b"a").unwrap();
+    fs::write(live.path().join("b.txt"), b"b").unwrap();
+    let searcher = FileSearcher::builder()
+        .server(ServerConfig {
+            admin: AdminConfig {
+                enabled: true,
+                token: "secret".into(),
+            },
+            ..Default::default()
+        })
+        .root(live.path())
+        .build()
+        .unwrap();
+    // The candidate searches a new root ahead of the live one.
+    let shadow = FileSearcher::builder()
+        .root(candidate.path())
+        .root(live.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher.with_shadow(shadow, 1.0));
+
+    for uri in ["/a.txt", "/b.txt", "/missing.txt"] {
+        let req = make_request("GET", uri);
+        let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+        body_string(resp).await;
+    }
+
+    // Comparisons finish in the background.
+    let mut text = String::new();
+    for _ in 0..100 {
+        let req = admin_request("GET", "/_admin/metrics", "");
+        let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+        text = body_string(resp).await;
+        if text.contains("filehunter_shadow_comparisons_total 3\n") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(text.contains("filehunter_shadow_comparisons_total 3\n"), "{text}");
+    // Only a.txt, which the candidate serves from the new root.
+    assert!(text.contains("filehunter_shadow_divergences_total 1\n"), "{text}");
+}
+
+// ---------------------------------------------------------------------------
+// Miss log (1 test)
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn miss_log_lists_top_and_recent_misses() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), b"a").unwrap();
+    let searcher = FileSearcher::builder()
+        .server(ServerConfig {
+            admin: AdminConfig {
+                enabled: true,
+                token: "secret".into(),
+            },
+            miss_log: MissLogConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .location("/docs")
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+
+    for uri in ["/docs/x.txt", "/docs/a.txt", "/docs/x.txt", "/other/y.txt"] {
+        let req = make_request("GET", uri);
+        let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+        body_string(resp).await;
+    }
+
+    let req = admin_request("GET", "/_admin/misses?limit=2", "");
+    let resp = handle_request(req, searcher, None, localhost()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let json: serde_json::Value = serde_json::from_str(&body_string(resp).await).unwrap();
+    assert_eq!(json["top"][0]["path"], "/docs/x.txt");
+    assert_eq!(json["top"][0]["count"], 2);
+    let recent = json["recent"].as_array().unwrap();
+    assert_eq!(recent.len(), 2);
+    assert_eq!(recent[0]["path"], "/other/y.txt");
+    assert!(recent[0]["location"].is_null());
+    assert_eq!(recent[1]["location"], "/docs");
+    assert_eq!(recent[1]["client_ip"], "127.0.0.1");
+}
+
+// ---------------------------------------------------------------------------
+// Client denylist (1 test)
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn denylist_blocks_clients_and_reloads_via_admin() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), b"a").unwrap();
+    let deny = dir.path().join("deny.txt");
+    fs::write(&deny, "203.0.113.0/24\n").unwrap();
+    let searcher = FileSearcher::builder()
+        .server(ServerConfig {
+            admin: AdminConfig {
+                enabled: true,
+                token: "secret".into(),
+            },
+            denylist: DenylistConfig {
+                enabled: true,
+                file: deny.clone(),
+            },
+            ..Default::default()
+        })
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let get = |ip: &'static str| {
+        let searcher = searcher.clone();
+        async move {
+            let req = make_request("GET", "/a.txt");
+            handle_request(req, searcher, None, ip.parse().unwrap()).await.unwrap()
+        }
+    };
+
+    assert_eq!(get("203.0.113.9").await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(get("198.51.100.7").await.status(), StatusCode::OK);
+
+    fs::write(&deny, "198.51.100.7\n").unwrap();
+    let req = admin_request("POST", "/_admin/denylist/reload", "");
+    let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+    assert_eq!(body_string(resp).await, r#"{"entries":1}"#);
+    assert_eq!(get("203.0.113.9").await.status(), StatusCode::OK);
+    assert_eq!(get("198.51.100.7").await.status(), StatusCode::FORBIDDEN);
+}
+
+// ---------------------------------------------------------------------------
+// Audit log (2 tests)
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn audit_log_records_blocked_requests() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), b"a").unwrap();
+    let log = dir.path().join("audit.jsonl");
+    let searcher = FileSearcher::builder()
+        .server(ServerConfig {
+            admin: AdminConfig {
+                enabled: true,
+                token: "secret".into(),
+            },
+            audit_log: AuditLogConfig {
+                enabled: true,
+                file: log.clone(),
+            },
+            ..Default::default()
+        })
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+
+    for uri in ["/a.txt", "/../etc/passwd", "/.env", "/_admin/roots"] {
+        let req = make_request("GET", uri);
+        handle_request(req, searcher.clone(), None, "192.0.2.7".parse().unwrap())
+            .await
+            .unwrap();
+    }
+
+    let records: Vec<serde_json::Value> = fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let events: Vec<_> = records
+        .iter()
+        .map(|r| (r["event"].as_str().unwrap(), r["path"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        events,
+        [
+            ("path_traversal", "/../etc/passwd"),
+            ("hidden_file", "/.env"),
+            ("auth_failure", "/_admin/roots"),
+        ]
+    );
+    assert!(records.iter().all(|r| r["client_ip"] == "192.0.2.7"));
+}
+
+#[tokio::test]
+async fn quarantine_moves_flagged_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("root");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("avatar.png"), b"<html><script>").unwrap();
+    fs::write(root.join("dump.sql.bak"), b"secrets").unwrap();
+    let log = dir.path().join("audit.jsonl");
+    let quarantine = dir.path().join("quarantine");
+    let location = |prefix: &str| LocationConfig {
+        prefix: prefix.into(),
+        paths: vec![SearchPath {
+            root: root.clone(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let config = Config {
+        server: ServerConfig {
+            audit_log: AuditLogConfig {
+                enabled: true,
+                file: log.clone(),
+            },
+            quarantine: QuarantineConfig {
+                enabled: true,
+                dir: quarantine.clone(),
+                action: QuarantineAction::Move,
+            },
+            ..Default::default()
+        },
+        locations: vec![
+            LocationConfig {
+                magic_bytes: MagicBytes::Refuse,
+                ..location("/imgs")
+            },
+            LocationConfig {
+                deny_patterns: vec![r"\.bak$".into()],
+                ..location("/files")
+            },
+        ],
+        vhosts: Vec::new(),
+    };
+    let searcher = Arc::new(FileSearcher::new(&config));
+
+    let (png, bak) = ("/imgs/avatar.png", "/files/dump.sql.bak");
+    for uri in [png, bak, png] {
+        let req = make_request("GET", uri);
+        let resp = handle_request(req, searcher.clone(), None, localhost())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{uri}");
+    }
+
+    assert!(fs::read_dir(&root).unwrap().next().is_none());
+    let mut entries: Vec<_> = fs::read_dir(&quarantine)
+        .unwrap()
+        .map(|entry| fs::read(entry.unwrap().path()).unwrap())
+        .collect();
+    entries.sort();
+    assert_eq!(entries, [&b"<html><script>"[..], b"secrets"]);
+
+    let records: Vec<serde_json::Value> = fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let events: Vec<_> = records.iter().map(|r| &r["event"]).collect();
+    // The moved file is no longer found on the second request.
+    assert_eq!(events, ["mislabeled_file", "denied_pattern"]);
+    assert!(records.iter().all(|r| r["quarantined"].is_string()));
+}
+
+// ---------------------------------------------------------------------------
+// Antivirus (1 test)
+// ---------------------------------------------------------------------------
+
+/// A clamd that finds "EICAR" in streamed files. Returns its address and
+/// the number of scans it ran.
+async fn fake_clamd() -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let scans = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counter = scans.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let mut command = [0u8; 10];
+            stream.read_exact(&mut command).await.unwrap();
+            let mut content = Vec::new();
+            loop {
+                let len = stream.read_u32().await.unwrap() as usize;
+                if len == 0 {
+                    break;
+                }
+                let mut chunk = vec![0u8; len];
+                stream.read_exact(&mut chunk).await.unwrap();
+                content.extend(chunk);
+            }
+            let infected = content.windows(5).any(|w| w == b"EICAR");
+            let reply: &[u8] = match infected {
+                true => b"stream: Eicar FOUND\0",
+                false => b"stream: OK\0",
+            };
+            stream.write_all(reply).await.unwrap();
+        }
+    });
+    (address, scans)
+}
+
+#[tokio::test]
+async fn antivirus_refuses_infected_files() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("clean.txt"), b"hello").unwrap();
+    fs::write(dir.path().join("eicar.txt"), b"X5O!P%@AP EICAR test").unwrap();
+    let (address, scans) = fake_clamd().await;
+    let log = dir.path().join("audit.jsonl");
+    let searcher = FileSearcher::builder()
+        .server(ServerConfig {
+            antivirus: AntivirusConfig {
+                enabled: true,
+                address,
+                scan_uploads: true,
+                ..Default::default()
+            },
+            audit_log: AuditLogConfig {
+                enabled: true,
+                file: log.clone(),
+            },
+            ..Default::default()
+        })
+        .auth(LocationAuth::ApiKey {
+            keys: vec!["k1".into()],
+            header: "X-Api-Key".into(),
+            query_param: String::new(),
+        })
+        .upload_root(dir.path())
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let send = |method: &'static str, uri: &'static str, body: &'static str| {
+        let searcher = searcher.clone();
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("X-Api-Key", "k1")
+            .body(http_body_util::Full::new(Bytes::from(body)))
+            .unwrap();
+        async move {
+            handle_request(req, searcher, None, localhost())
+                .await
+                .unwrap()
+        }
+    };
+
+    // Verdicts are cached until the file changes.
+    for _ in 0..2 {
+        let resp = send("GET", "/clean.txt", "").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+    assert_eq!(scans.load(std::sync::atomic::Ordering::Relaxed), 1);
+    let resp = send("GET", "/eicar.txt", "").await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let resp = send("PUT", "/upload.txt", "more EICAR").await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert!(!dir.path().join("upload.txt").exists());
+    let resp = send("PUT", "/upload.txt", "fine").await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+
+    let audit = fs::read_to_string(&log).unwrap();
+    let events: Vec<serde_json::Value> = audit
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(events.len(), 2);
+    assert!(events.iter().all(|e| e["event"] == "infected_file"));
+}
+
+// ---------------------------------------------------------------------------
+// Webhooks (1 test)
+// ---------------------------------------------------------------------------
+
+#[cfg(feature = "webhooks")]
+#[tokio::test]
+async fn webhooks_report_served_files_in_batches() {
+    use hyper::service::service_fn;
+    use hyper_util::rt::TokioIo;
+
+    // A sink forwarding each request body.
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Bytes>();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let sink = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let tx = tx.clone();
+            let svc = service_fn(move |req: Request<hyper::body::Incoming>| {
+                let tx = tx.clone();
+                async move {
+                    let body = req.into_body().collect().await.unwrap().to_bytes();
+                    tx.send(body).unwrap();
+                    let resp = hyper::Response::new(http_body_util::Full::new(Bytes::new()));
+                    Ok::<_, std::convert::Infallible>(resp)
+                }
+            });
+            let io = TokioIo::new(stream);
+            tokio::spawn(hyper::server::conn::http1::Builder::new().serve_connection(io, svc));
+        }
+    });
+
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("report.pdf"), b"0123456789").unwrap();
+    let location = |prefix: &str, served_events| LocationConfig {
+        prefix: prefix.into(),
+        paths: vec![SearchPath {
+            root: dir.path().to_path_buf(),
+            ..Default::default()
+        }],
+        served_events,
+        ..Default::default()
+    };
+    let config = Config {
+        server: ServerConfig {
+            webhooks: WebhooksConfig {
+                enabled: true,
+                url: format!("http://{sink}/events"),
+                format: WebhookFormat::Ndjson,
+                batch_size: 2,
+                flush_interval_ms: 50,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        locations: vec![location("/dl", true), location("/public", false)],
+        vhosts: Vec::new(),
+    };
+    let searcher = Arc::new(FileSearcher::new(&config));
+    filehunter::webhooks::spawn_sender(searcher.webhooks().unwrap().clone());
+
+    let send = |method: &str, uri: &str, range: Option<&str>| {
+        let mut req = make_request(method, uri);
+        if let Some(range) = range {
+            req.headers_mut().insert("Range", range.parse().unwrap());
+        }
+        handle_request(req, searcher.clone(), None, "192.0.2.7".parse().unwrap())
+    };
+    send("GET", "/dl/report.pdf", None).await.unwrap();
+    send("GET", "/public/report.pdf", None).await.unwrap();
+    send("HEAD", "/dl/report.pdf", None).await.unwrap();
+    let range = Some("bytes=0-3");
+    send("GET", "/dl/report.pdf", range).await.unwrap();
+    send("GET", "/dl/missing.pdf", None).await.unwrap();
+    send("GET", "/dl/report.pdf", None).await.unwrap();
+
+    // The first two events fill a batch; the third waits for the interval.
+    let mut events = Vec::new();
+    while events.len() < 3 {
+        let body = tokio::time::timeout(Duration::from_secs(5), rx.recv());
+        let body = body.await.unwrap().unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let parsed = body.lines().map(|l| serde_json::from_str(l).unwrap());
+        events.extend(parsed.collect::<Vec<serde_json::Value>>());
+    }
+    let served: Vec<_> = events
+        .iter()
+        .map(|e| (e["status"].as_u64().unwrap(), e["bytes"].as_u64().unwrap()))
+        .collect();
+    assert_eq!(served, [(200, 10), (206, 4), (200, 10)]);
+    let event = &events[0];
+    assert_eq!(event["client_ip"], "192.0.2.7");
+    assert_eq!(event["location"], "/dl");
+    assert_eq!(event["path"], "/dl/report.pdf");
+    assert!(event["file"].as_str().unwrap().ends_with("report.pdf"));
+}
+
+// ---------------------------------------------------------------------------
+// WASM request filter (1 test)
+// ---------------------------------------------------------------------------
+
+/// A filter module answering with the decision of the first rule whose
+/// needle occurs in the request JSON, or `0` when none does.
+#[cfg(feature = "wasm")]
+fn filter_module(rules: &[(&str, &str)]) -> String {
+    let mut data = String::new();
+    let mut offset = 0;
+    let mut place = |s: &str| {
+        let at = offset;
+        let escaped = s.replace('\\', "\\\\").replace('"', "\\\"");
+        data += &format!("(data (i32.const {at}) \"{escaped}\")\n");
+        offset += s.len();
+        at
+    };
+    let mut checks = String::new();
+    for (needle, decision) in rules {
+        let (at, len) = (place(needle), needle.len());
+        let packed = ((place(decision) as u64) << 32) | decision.len() as u64;
+        checks += &format!(
+            "(if (call $contains (local.get 0) (local.get 1) (i32.const {at}) (i32.const {len}))\n\
+             (then (return (i64.const {packed}))))\n"
+        );
+    }
+    format!(
+        r#"(module
+        (memory (export "memory") 1)
+        (global $heap (mut i32) (i32.const 4096))
+        {data}
+        (func (export "alloc") (param $len i32) (result i32)
+            (global.get $heap)
+            (global.set $heap (i32.add (global.get $heap) (local.get $len))))
+        (func $contains (param $p i32) (param $n i32) (param $q i32) (param $m i32) (result i32)
+            (local $i i32) (local $j i32)
+            (block $none
+                (loop $outer
+                    (br_if $none (i32.gt_u (i32.add (local.get $i) (local.get $m)) (local.get $n)))
+                    (local.set $j (i32.const 0))
+                    (block $mismatch
+                        (loop $inner
+                            (if (i32.eq (local.get $j) (local.get $m))
+                                (then (return (i32.const 1))))
+                            (br_if $mismatch (i32.ne
+                                (i32.load8_u (i32.add (local.get $p) (i32.add (local.get $i) (local.get $j))))
+                                (i32.load8_u (i32.add (local.get $q) (local.get $j)))))
+                            (local.set $j (i32.add (local.get $j) (i32.const 1)))
+                            (br $inner)))
+                    (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                    (br $outer)))
+            (i32.const 0))
+        (func (export "filter") (param i32 i32) (result i64)
+            {checks}
+            (i64.const 0)))"#
+    )
+}
+
+#[cfg(feature = "wasm")]
+#[tokio::test]
+async fn wasm_filter_rewrites_rejects_and_pins_roots() {
+    let (a, b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    fs::write(a.path().join("logo.png"), b"from a").unwrap();
+    fs::write(b.path().join("logo.png"), b"from b").unwrap();
+    fs::write(a.path().join("secret.txt"), b"hidden").unwrap();
+    let root_b = b.path().canonicalize().unwrap();
+    let pin_b = format!(r#"{{"action":"continue","root":"{}"}}"#, root_b.display());
+    let pin_missing = r#"{"action":"continue","root":"/nowhere"}"#;
+    let reject = r#"{"action":"reject","status":451,"body":"Unavailable here"}"#;
+    let module = filter_module(&[
+        (r#""x-tenant":"b""#, &pin_b),
+        (r#""x-tenant":"c""#, pin_missing),
+        ("/old/", r#"{"action":"continue","path":"/img/logo.png"}"#),
+        ("/secret", reject),
+    ]);
+    let module_path = a.path().join("filter.wat");
+    fs::write(&module_path, module).unwrap();
+
+    let roots = [&a, &b].map(|dir| SearchPath {
+        root: dir.path().to_path_buf(),
+        ..Default::default()
+    });
+    let config = Config {
+        server: ServerConfig {
+            wasm_filter: WasmFilterConfig {
+                enabled: true,
+                module: module_path,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        locations: vec![LocationConfig {
+            prefix: "/img".into(),
+            paths: roots.to_vec(),
+            ..Default::default()
+        }],
+        vhosts: Vec::new(),
+    };
+    let searcher = Arc::new(FileSearcher::new(&config));
+    let send = |uri: &str, tenant: Option<&str>| {
+        let mut req = make_request("GET", uri);
+        if let Some(tenant) = tenant {
+            let tenant = tenant.parse().unwrap();
+            req.headers_mut().insert("X-Tenant", tenant);
+        }
+        handle_request(req, searcher.clone(), None, localhost())
+    };
+
+    let resp = send("/img/logo.png", None).await.unwrap();
+    assert_eq!(body_string(resp).await, "from a");
+    let resp = send("/img/logo.png", Some("b")).await.unwrap();
+    assert_eq!(body_string(resp).await, "from b");
+    let resp = send("/img/logo.png", Some("c")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = send("/old/logo.png", None).await.unwrap();
+    assert_eq!(body_string(resp).await, "from a");
+    let resp = send("/img/secret.txt", None).await.unwrap();
+    assert_eq!(resp.status().as_u16(), 451);
+    assert_eq!(body_string(resp).await, "Unavailable here");
+}
+
+// ---------------------------------------------------------------------------
+// HTTP upstream roots (2 tests)
+// ---------------------------------------------------------------------------
+
+/// Serve `files` over plain HTTP/1 on an ephemeral port; everything else is 404.
+#[cfg(feature = "upstream")]
+async fn spawn_upstream(files: &'static [(&'static str, &'static str)]) -> std::net::SocketAddr {
+    use hyper::service::service_fn;
+    use hyper_util::rt::TokioIo;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let svc = service_fn(move |req: Request<hyper::body::Incoming>| async move {
+                let body = files.iter().find(|(p, _)| *p == req.uri().path()).map(|(_, b)| *b);
+                let resp = match body {
+                    Some(b) => hyper::Response::builder()
+                        .header("Last-Modified", "Wed, 21 Oct 2015 07:28:00 GMT")
+                        .body(http_body_util::Full::new(Bytes::from(b))),
+                    None => hyper::Response::builder()
+                        .status(404)
+                        .body(http_body_util::Full::new(Bytes::new())),
+                };
+                Ok::<_, std::convert::Infallible>(resp.unwrap())
+            });
+            let conn = hyper::server::conn::http1::Builder::new().serve_connection(TokioIo::new(stream), svc);
+            tokio::spawn(conn);
+        }
+    });
+    addr
+}
+
+#[cfg(feature = "upstream")]
+#[tokio::test]
+async fn upstream_root_serves_after_local_miss() {
+    let local = tempfile::tempdir().unwrap();
+    fs::write(local.path().join("a.txt"), b"local").unwrap();
+    let upstream =
+        spawn_upstream(&[("/files/a.txt", "remote-a"), ("/files/b%20c.txt", "remote-bc")]).await;
+
+    let config = Config {
+        server: ServerConfig::default(),
+        locations: vec![LocationConfig {
+            prefix: "/".into(),
+            mode: SearchMode::Sequential,
+            paths: vec![
+                SearchPath {
+                    root: local.path().to_path_buf(),
+                    extensions: vec![],
+                    ..Default::default()
+                },
+                SearchPath {
+                    root: format!("http://{upstream}/files/").into(),
+                    extensions: vec![],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }],
+        vhosts: Vec::new(),
+    };
+    let searcher = Arc::new(FileSearcher::new(&config));
+
+    // Local roots still win.
+    let resp = handle_request(make_request("GET", "/a.txt"), searcher.clone(), None, localhost())
+        .await
+        .unwrap();
+    assert_eq!(body_string(resp).await, "local");
+
+    let resp = handle_request(make_request("GET", "/b%20c.txt"), searcher.clone(), None, localhost())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["Content-Length"], "9");
+    assert_eq!(resp.headers()["Content-Type"], "text/plain");
+    assert_eq!(body_string(resp).await, "remote-bc");
+
+    let resp = handle_request(make_request("GET", "/missing.txt"), searcher, None, localhost())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[cfg(feature = "upstream")]
+#[tokio::test]
+async fn fallback_upstream_fills_cache() {
+    let cache = tempfile::tempdir().unwrap();
+    let upstream = spawn_upstream(&[("/origin/img/a.txt", "fetched")]).await;
+    let searcher = Arc::new(
+        FileSearcher::builder()
+            .root(cache.path())
+            .fallback_upstream(format!("http://{upstream}/origin/"))
+            .fallback_cache_root(cache.path())
+            .build()
+            .unwrap(),
+    );
+
+    let resp = handle_request(make_request("GET", "/img/a.txt"), searcher.clone(), None, localhost())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(body_string(resp).await, "fetched");
+
+    // The copy is moved into place once the body has been streamed.
+    let cached = cache.path().join("img/a.txt");
+    for _ in 0..100 {
+        if cached.exists() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(fs::read_to_string(&cached).unwrap(), "fetched");
+
+    let resp = handle_request(make_request("GET", "/img/b.txt"), searcher, None, localhost())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert!(!cache.path().join("img/b.txt").exists());
+}
+
+// ---------------------------------------------------------------------------
+// Custom backends (2 tests)
+// ---------------------------------------------------------------------------
+
+/// Serves every `*.txt` request with a fixed body.
+struct StaticBackend;
+
+/// Fails every probe, counting them.
+struct FailingBackend(Arc<std::sync::atomic::AtomicUsize>);
+
+impl filehunter::backend::StorageBackend for StaticBackend {
+    fn probe<'a>(
+        &'a self,
+        relative: &'a std::path::Path,
+        _request_path: &'a str,
+    ) -> filehunter::backend::BoxFuture<'a, Result<Option<FoundObject>, ProbeError>> {
+        use filehunter::backend::ObjectBody;
+
+        Box::pin(async move {
+            if relative.extension().is_none_or(|e| e != "txt") {
+                return Ok(None);
+            }
+            let chunk: std::io::Result<Bytes> = Ok(Bytes::from_static(b"generated"));
+            Ok(Some(FoundObject {
+                path: std::path::Path::new("static://").join(relative),
+                size: 9,
+                modified: SystemTime::UNIX_EPOCH,
+                body: ObjectBody::Stream(Box::pin(futures_util::stream::once(async { chunk }))),
+            }))
+        })
+    }
+}
+
+#[tokio::test]
+async fn custom_backend_joins_location() {
+    let (_dir, searcher) = setup_single_root(&[("local.txt", b"local")], vec![]);
+    searcher
+        .attach_backend("/", "static://".into(), &[], Arc::new(StaticBackend))
+        .unwrap();
+    assert!(searcher
+        .attach_backend("/", "static://".into(), &[], Arc::new(StaticBackend))
+        .is_err());
+
+    let resp = handle_request(make_request("GET", "/local.txt"), searcher.clone(), None, localhost())
+        .await
+        .unwrap();
+    assert_eq!(body_string(resp).await, "local");
+
+    let resp = handle_request(make_request("GET", "/any.txt"), searcher.clone(), None, localhost())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(body_string(resp).await, "generated");
+
+    let resp = handle_request(make_request("GET", "/any.bin"), searcher, None, localhost())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+impl filehunter::backend::StorageBackend for FailingBackend {
+    fn probe<'a>(
+        &'a self,
+        _relative: &'a std::path::Path,
+        _request_path: &'a str,
+    ) -> filehunter::backend::BoxFuture<'a, Result<Option<FoundObject>, ProbeError>> {
+        self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Box::pin(async { Err(ProbeError::Failed("unreachable".into())) })
+    }
+}
+
+#[tokio::test]
+async fn circuit_breaker_skips_failing_root() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), b"local").unwrap();
+    let mut config = Config {
+        server: ServerConfig::default(),
+        locations: vec![LocationConfig {
+            prefix: "/".into(),
+            paths: vec![SearchPath {
+                root: dir.path().to_path_buf(),
+                extensions: vec![],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }],
+        vhosts: Vec::new(),
+    };
+    config.server.circuit_breaker = CircuitBreakerConfig {
+        enabled: true,
+        window: 2,
+        min_probes: 2,
+        ..Default::default()
+    };
+    let searcher = Arc::new(FileSearcher::new(&config));
+    let probes = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let backend = Arc::new(FailingBackend(probes.clone()));
+    let root = "failing://".into();
+    searcher.attach_backend("/", root, &[], backend).unwrap();
+
+    // Failed probes read as misses until the circuit opens.
+    for _ in 0..3 {
+        let req = make_request("GET", "/missing.txt");
+        let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+    assert_eq!(probes.load(std::sync::atomic::Ordering::Relaxed), 2);
+
+    let req = make_request("GET", "/a.txt");
+    let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+    assert_eq!(body_string(resp).await, "local");
+
+    let metrics = searcher.metrics();
+    let labels = r#"{location="/",root="failing://"}"#;
+    assert!(metrics.contains(&format!("filehunter_root_circuit_state{labels} 1")));
+    assert!(metrics.contains(&format!("filehunter_root_circuit_opens_total{labels} 1")));
+}
+
+// ---------------------------------------------------------------------------
+// Tower service (1 test)
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn service_limits_per_client_address() {
+    use filehunter::service::FileHunterService;
+    use tower::ServiceExt as _;
+
+    let (_dir, searcher) = setup_single_root(&[("test.txt", b"hello")], vec![]);
+    let limiter = filehunter::ratelimit::build_limiter(&RateLimitConfig {
+        enabled: true,
+        requests_per_second: 1,
+        burst_size: 1,
+        cleanup_interval: 600,
+        ..Default::default()
+    });
+    let service = FileHunterService::from_searcher(searcher).with_limiter(limiter);
+
+    let from = |port: u16| {
+        let mut req = make_request("GET", "/test.txt");
+        req.extensions_mut()
+            .insert(std::net::SocketAddr::from(([10, 0, 0, port as u8], port)));
+        req
+    };
+
+    let resp = service.clone().oneshot(from(1)).await.unwrap();
+    assert_eq!(body_string(resp).await, "hello");
+    let resp = service.clone().oneshot(from(1)).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    let resp = service.oneshot(from(2)).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+// ---------------------------------------------------------------------------
+// Resolved root (3 tests)
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn resolved_root_header_names_serving_root() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+    fs::write(dir2.path().join("data.txt"), b"second").unwrap();
+
+    let build = |header: bool| {
+        let searcher = FileSearcher::builder()
+            .server(ServerConfig {
+                resolved_root_header: header,
+                ..Default::default()
+            })
+            .root(dir1.path())
+            .root(dir2.path())
+            .build()
+            .unwrap();
+        Arc::new(searcher)
+    };
+
+    let searcher = build(true);
+    let hit = searcher.search("/data.txt").await.unwrap();
+    let root = dir2.path().canonicalize().unwrap();
+    assert_eq!(hit.root, root);
+    assert_eq!(hit.path, root.join("data.txt"));
+    assert_eq!(hit.size, 6);
+
+    let resp = handle_request(make_request("GET", "/data.txt"), searcher, None, localhost())
+        .await
+        .unwrap();
+    assert_eq!(resp.headers()["X-Resolved-Root"], root.to_str().unwrap());
+
+    let resp = handle_request(make_request("GET", "/data.txt"), build(false), None, localhost())
+        .await
+        .unwrap();
+    assert!(!resp.headers().contains_key("X-Resolved-Root"));
+}
+
+#[tokio::test]
+async fn debug_token_adds_debug_headers() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+    fs::write(dir2.path().join("data.txt"), b"second").unwrap();
+    let searcher = FileSearcher::builder()
+        .server(ServerConfig {
+            debug_token: "s3cret".into(),
+            ..Default::default()
+        })
+        .root(dir1.path())
+        .root(dir2.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let get = |token: Option<&'static str>| {
+        let mut req = make_request("GET", "/data.txt");
+        if let Some(token) = token {
+            let value = token.parse().unwrap();
+            req.headers_mut().insert("X-Filehunter-Debug", value);
+        }
+        let searcher = searcher.clone();
+        async move { handle_request(req, searcher, None, localhost()).await.unwrap() }
+    };
+
+    for token in [None, Some("guess")] {
+        let resp = get(token).await;
+        assert!(!resp.headers().contains_key("Server-Timing"));
+        assert!(!resp.headers().contains_key("X-Resolved-Root"));
+    }
+
+    let resp = get(Some("s3cret")).await;
+    let root = dir2.path().canonicalize().unwrap();
+    assert_eq!(resp.headers()["X-Resolved-Root"], root.to_str().unwrap());
+    assert_eq!(resp.headers()["Cache-Control"], "no-store");
+    // Local roots have no remote cache to report on.
+    assert!(!resp.headers().contains_key("Cache-Status"));
+    let timing = resp.headers()["Server-Timing"].to_str().unwrap();
+    assert!(timing.starts_with("total;dur="), "{timing}");
+    let probe = |root: &std::path::Path, outcome| {
+        format!("probe;desc=\"{} {outcome}\";dur=", root.display())
+    };
+    let root1 = dir1.path().canonicalize().unwrap();
+    assert!(timing.contains(&probe(&root1, "not_found")), "{timing}");
+    assert!(timing.contains(&probe(&root, "found")), "{timing}");
+    assert_eq!(body_string(resp).await, "second");
+}
+
+/// Probes the last root only, counting its searches.
+struct LastRootOnly(Arc<std::sync::atomic::AtomicUsize>);
+
+impl filehunter::strategy::SearchStrategy for LastRootOnly {
+    fn search<'a>(
+        &'a self,
+        roots: &'a [filehunter::server::Candidate<'a>],
+    ) -> filehunter::backend::BoxFuture<'a, Option<filehunter::server::SearchHit>> {
+        self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Box::pin(async move { roots.last()?.probe().await.ok()? })
+    }
+}
+
+#[tokio::test]
+async fn debug_headers_describe_the_search_that_served() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+    fs::write(dir1.path().join("data.txt"), b"first").unwrap();
+    fs::write(dir2.path().join("data.txt"), b"second").unwrap();
+    let searches = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    filehunter::strategy::register("debug_last_root", LastRootOnly(searches.clone()));
+    let searcher = FileSearcher::builder()
+        .server(ServerConfig {
+            debug_token: "s3cret".into(),
+            ..Default::default()
+        })
+        .location("/")
+        .search_plugin("debug_last_root")
+        .root(dir1.path())
+        .root(dir2.path())
+        .build()
+        .unwrap();
+    let mut req = make_request("GET", "/data.txt");
+    req.headers_mut()
+        .insert("X-Filehunter-Debug", "s3cret".parse().unwrap());
+    let resp = handle_request(req, Arc::new(searcher), None, localhost())
+        .await
+        .unwrap();
+
+    // The headers come from the one search that served the file, which
+    // never probed the first root.
+    assert_eq!(searches.load(std::sync::atomic::Ordering::Relaxed), 1);
+    let root2 = dir2.path().canonicalize().unwrap();
+    assert_eq!(resp.headers()["X-Resolved-Root"], root2.to_str().unwrap());
+    let timing = resp.headers()["Server-Timing"].to_str().unwrap();
+    let root1 = dir1.path().canonicalize().unwrap();
+    assert!(!timing.contains(root1.to_str().unwrap()), "{timing}");
+    let probe = format!("probe;desc=\"{} found\";dur=", root2.display());
+    assert!(timing.contains(&probe), "{timing}");
+    assert_eq!(body_string(resp).await, "second");
+}
+
+// ---------------------------------------------------------------------------
+// Batch resolution (1 test)
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn batch_reports_each_path_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a.jpg"), b"jpeg").unwrap();
+    fs::write(dir.path().join("b.txt"), b"hello").unwrap();
+    let searcher = Arc::new(
+        FileSearcher::builder()
+            .server(ServerConfig {
+                batch: BatchConfig {
+                    enabled: true,
+                    max_paths: 3,
+                },
+                ..Default::default()
+            })
+            .root(dir.path())
+            .build()
+            .unwrap(),
+    );
+    let batch = |body: &str| {
+        Request::builder()
+            .method("POST")
+            .uri("/_batch")
+            .body(http_body_util::Full::new(Bytes::from(body.to_owned())))
+            .unwrap()
+    };
+
+    let req = batch(r#"["/b.txt", "/missing.png", "/a.jpg"]"#);
+    let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let json: serde_json::Value = serde_json::from_str(&body_string(resp).await).unwrap();
+    assert_eq!(json[0]["path"], "/b.txt");
+    assert_eq!(json[0]["size"], 5);
+    assert_eq!(json[0]["content_type"], "text/plain");
+    assert_eq!(json[1], serde_json::json!({ "path": "/missing.png", "exists": false }));
+    assert_eq!(json[2]["exists"], true);
+    assert_eq!(json[2]["content_type"], "image/jpeg");
+
+    let req = batch(r#"["/1", "/2", "/3", "/4"]"#);
+    let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let resp = handle_request(make_request("GET", "/_batch"), searcher, None, localhost())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+}
+
+// ---------------------------------------------------------------------------
+// Metadata endpoint (1 test)
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn meta_describes_resolved_file() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("sub/pic.png"), b"png!").unwrap();
+    let build = |meta_endpoint: bool| {
+        let searcher = FileSearcher::builder()
+            .server(ServerConfig {
+                meta_endpoint,
+                ..Default::default()
+            })
+            .location("/imgs")
+            .mode(SearchMode::Concurrent)
+            .root(dir.path())
+            .build()
+            .unwrap();
+        Arc::new(searcher)
+    };
+    let searcher = build(true);
+
+    let req = make_request("GET", "/_meta/imgs/sub/pic.png");
+    let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let json: serde_json::Value = serde_json::from_str(&body_string(resp).await).unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    assert_eq!(json["path"], "/imgs/sub/pic.png");
+    assert_eq!(json["location"], "/imgs");
+    assert_eq!(json["mode"], "concurrent");
+    assert_eq!(json["root"], root.to_str().unwrap());
+    assert_eq!(json["resolved"], root.join("sub/pic.png").to_str().unwrap());
+    assert_eq!(json["size"], 4);
+    assert_eq!(json["content_type"], "image/png");
+
+    let req = make_request("GET", "/_meta/imgs/none.png");
+    let resp = handle_request(req, searcher, None, localhost()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let req = make_request("GET", "/_meta/imgs/sub/pic.png");
+    let resp = handle_request(req, build(false), None, localhost()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+// ---------------------------------------------------------------------------
+// Digest headers (1 test)
+// ---------------------------------------------------------------------------
+
+#[cfg(feature = "digest")]
+#[tokio::test]
+async fn digest_headers_on_local_files() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("hello.txt"), b"hello").unwrap();
+    let searcher = FileSearcher::builder()
+        .server(ServerConfig {
+            digest: DigestConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+
+    for method in ["GET", "HEAD"] {
+        let req = make_request(method, "/hello.txt");
+        let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+        let expected = "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=";
+        assert_eq!(resp.headers()["Repr-Digest"], format!("sha-256=:{expected}:"));
+        assert_eq!(resp.headers()["Digest"], format!("sha-256={expected}"));
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Location auth (2 tests)
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn api_key_guards_location() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("q3.csv"), b"revenue").unwrap();
+    let searcher = FileSearcher::builder()
+        .location("/reports")
+        .auth(LocationAuth::ApiKey {
+            keys: vec!["k1".into()],
+            header: "X-Api-Key".into(),
+            query_param: "api_key".into(),
+        })
+        .root(dir.path())
+        .location("/public")
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let get = |uri: &'static str, key: Option<&'static str>| {
+        let searcher = searcher.clone();
+        async move {
+            let mut req = make_request("GET", uri);
+            if let Some(key) = key {
+                req.headers_mut().insert("X-Api-Key", key.parse().unwrap());
+            }
+            handle_request(req, searcher, None, localhost()).await.unwrap()
+        }
+    };
+
+    // Missing files are not revealed without a key either.
+    for uri in ["/reports/q3.csv", "/reports/nope.csv"] {
+        assert_eq!(get(uri, None).await.status(), StatusCode::UNAUTHORIZED);
+    }
+    assert_eq!(get("/reports/q3.csv", Some("wrong")).await.status(), StatusCode::UNAUTHORIZED);
+    let resp = get("/reports/q3.csv", Some("k1")).await;
+    assert_eq!(body_string(resp).await, "revenue");
+    assert_eq!(get("/reports/q3.csv?api_key=k1", None).await.status(), StatusCode::OK);
+    assert_eq!(get("/public/q3.csv", None).await.status(), StatusCode::OK);
+}
+
+#[cfg(feature = "basic-auth")]
+#[tokio::test]
+async fn basic_auth_challenges_and_accepts_htpasswd_users() {
+    use base64::Engine as _;
+
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("notes.txt"), b"team notes").unwrap();
+    let htpasswd = dir.path().join(".htpasswd");
+    let hash = bcrypt::hash("hunter2", 4).unwrap();
+    fs::write(&htpasswd, format!("alice:{hash}\n")).unwrap();
+    let searcher = FileSearcher::builder()
+        .location("/team")
+        .auth(LocationAuth::Basic {
+            htpasswd,
+            realm: "Team files".into(),
+        })
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let get = |credentials: Option<&str>| {
+        let searcher = searcher.clone();
+        let mut req = make_request("GET", "/team/notes.txt");
+        if let Some(c) = credentials {
+            let value = format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(c));
+            req.headers_mut().insert("Authorization", value.parse().unwrap());
+        }
+        async move { handle_request(req, searcher, None, localhost()).await.unwrap() }
+    };
+
+    let resp = get(None).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        resp.headers()["WWW-Authenticate"],
+        "Basic realm=\"Team files\", charset=\"UTF-8\""
+    );
+    assert_eq!(get(Some("alice:wrong")).await.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(body_string(get(Some("alice:hunter2")).await).await, "team notes");
+}
+
+// ---------------------------------------------------------------------------
+// Uploads (3 tests)
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn put_stores_file_in_upload_root() {
+    let uploads = tempfile::tempdir().unwrap();
+    let mirror = tempfile::tempdir().unwrap();
+    fs::create_dir(uploads.path().join("docs")).unwrap();
+    let searcher = FileSearcher::builder()
+        .location("/files")
+        .auth(LocationAuth::ApiKey {
+            keys: vec!["k1".into()],
+            header: "X-Api-Key".into(),
+            query_param: String::new(),
+        })
+        .upload_root(uploads.path())
+        .root_with_extensions(uploads.path(), ["txt"])
+        .root(mirror.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let put = |uri: &'static str, key: Option<&'static str>, body: &'static str| {
+        let searcher = searcher.clone();
+        let mut req = Request::builder()
+            .method("PUT")
+            .uri(uri)
+            .body(http_body_util::Full::new(Bytes::from(body)))
+            .unwrap();
+        if let Some(key) = key {
+            req.headers_mut().insert("X-Api-Key", key.parse().unwrap());
+        }
+        async move { handle_request(req, searcher, None, localhost()).await.unwrap() }
+    };
+
+    assert_eq!(put("/files/docs/a.txt", None, "x").await.status(), StatusCode::UNAUTHORIZED);
+    let resp = put("/files/docs/a.txt", Some("k1"), "first").await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let resp = put("/files/docs/a.txt", Some("k1"), "second").await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    assert_eq!(fs::read_to_string(uploads.path().join("docs/a.txt")).unwrap(), "second");
+    assert_eq!(fs::read_dir(uploads.path().join("docs")).unwrap().count(), 1);
+
+    let mut req = make_request("GET", "/files/docs/a.txt");
+    req.headers_mut().insert("X-Api-Key", "k1".parse().unwrap());
+    let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+    assert_eq!(body_string(resp).await, "second");
+
+    // Refused: the upload root's extension filter, traversal, a missing
+    // directory.
+    assert_eq!(put("/files/run.sh", Some("k1"), "x").await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(put("/files/../a.txt", Some("k1"), "x").await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(put("/files/new/a.txt", Some("k1"), "x").await.status(), StatusCode::CONFLICT);
+    assert_eq!(fs::read_dir(mirror.path()).unwrap().count(), 0);
+}
+
+#[tokio::test]
+async fn form_post_stores_each_file() {
+    let uploads = tempfile::tempdir().unwrap();
+    let searcher = FileSearcher::builder()
+        .location("/files")
+        .auth(LocationAuth::ApiKey {
+            keys: vec!["k1".into()],
+            header: "X-Api-Key".into(),
+            query_param: String::new(),
+        })
+        .upload_root(uploads.path())
+        .root_with_extensions(uploads.path(), ["txt", "csv"])
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let body = "--b0undary\r\n\
+        Content-Disposition: form-data; name=\"note\"\r\n\r\n\
+        ignored\r\n--b0undary\r\n\
+        Content-Disposition: form-data; name=\"f\"; filename=\"a b.txt\"\r\n\r\n\
+        alpha\r\n--b0undary\r\n\
+        Content-Disposition: form-data; name=\"f\"; filename=\"run.sh\"\r\n\r\n\
+        echo\r\n--b0undary\r\n\
+        Content-Disposition: form-data; name=\"f\"; filename=\"data.csv\"\r\n\r\n\
+        1,2\r\n--b0undary--\r\n";
+    let req = Request::builder()
+        .method("POST")
+        .uri("/files/")
+        .header("X-Api-Key", "k1")
+        .header("Content-Type", "multipart/form-data; boundary=b0undary")
+        .body(http_body_util::Full::new(Bytes::from(body)))
+        .unwrap();
+    let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let report: serde_json::Value = serde_json::from_str(&body_string(resp).await).unwrap();
+    assert_eq!(report["stored"][0]["path"], "/files/a%20b.txt");
+    assert_eq!(report["stored"][1]["size"], 3);
+    assert_eq!(report["rejected"][0]["filename"], "run.sh");
+    assert_eq!(report["rejected"][0]["status"], 403);
+    assert_eq!(fs::read_to_string(uploads.path().join("a b.txt")).unwrap(), "alpha");
+    assert_eq!(fs::read_dir(uploads.path()).unwrap().count(), 2);
+
+    let req = Request::builder()
+        .method("POST")
+        .uri("/files/")
+        .header("X-Api-Key", "k1")
+        .body(http_body_util::Full::new(Bytes::from("x")))
+        .unwrap();
+    let resp = handle_request(req, searcher, None, localhost()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
+
+#[tokio::test]
+async fn put_replicates_to_other_roots() {
+    let dirs: Vec<TempDir> = (0..4).map(|_| tempfile::tempdir().unwrap()).collect();
+    for dir in &dirs[..3] {
+        fs::create_dir(dir.path().join("docs")).unwrap();
+    }
+    let searcher = FileSearcher::builder()
+        .location("/files")
+        .auth(LocationAuth::ApiKey {
+            keys: vec!["k1".into()],
+            header: "X-Api-Key".into(),
+            query_param: String::new(),
+        })
+        .upload_root(dirs[1].path())
+        .upload_replicas(2)
+        .root_with_extensions(dirs[0].path(), ["jpg"])
+        .root(dirs[1].path())
+        .root(dirs[2].path())
+        .root(dirs[3].path())
+        .build()
+        .unwrap();
+    let req = Request::builder()
+        .method("PUT")
+        .uri("/files/docs/a.txt")
+        .header("X-Api-Key", "k1")
+        .body(http_body_util::Full::new(Bytes::from("shared")))
+        .unwrap();
+    let resp = handle_request(req, Arc::new(searcher), None, localhost()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let report: serde_json::Value = serde_json::from_str(&body_string(resp).await).unwrap();
+
+    // dirs[0] refuses .txt; dirs[3] lacks the docs directory.
+    let statuses: Vec<u64> = report["roots"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["status"].as_u64().unwrap())
+        .collect();
+    assert_eq!(statuses, [201, 201, 409]);
+    assert_eq!(fs::read_to_string(dirs[2].path().join("docs/a.txt")).unwrap(), "shared");
+    assert!(!dirs[0].path().join("docs/a.txt").exists());
+}
+
+// ---------------------------------------------------------------------------
+// Security headers (1 test)
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn security_headers_apply_with_location_override() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), b"a").unwrap();
+    let mut custom = std::collections::BTreeMap::new();
+    custom.insert("X-Robots-Tag".to_string(), "noindex".to_string());
+    let searcher = FileSearcher::builder()
+        .server(ServerConfig {
+            security_headers: SecurityHeadersConfig {
+                enabled: true,
+                strict_transport_security: "max-age=31536000".into(),
+                custom,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .location("/private")
+        .root(dir.path())
+        .location("/embed")
+        .security_headers(SecurityHeadersConfig {
+            enabled: true,
+            x_frame_options: String::new(),
+            cross_origin_resource_policy: "cross-origin".into(),
+            ..Default::default()
+        })
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+
+    let req = make_request("GET", "/private/a.txt");
+    let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+    assert_eq!(resp.headers()["Strict-Transport-Security"], "max-age=31536000");
+    assert_eq!(resp.headers()["X-Frame-Options"], "DENY");
+    assert_eq!(resp.headers()["X-Robots-Tag"], "noindex");
+
+    let req = make_request("GET", "/private/missing.txt");
+    let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(resp.headers()["X-Frame-Options"], "DENY");
+
+    let req = make_request("GET", "/embed/a.txt");
+    let resp = handle_request(req, searcher, None, localhost()).await.unwrap();
+    assert_eq!(resp.headers()["Cross-Origin-Resource-Policy"], "cross-origin");
+    assert!(!resp.headers().contains_key("X-Frame-Options"));
+    assert!(!resp.headers().contains_key("Strict-Transport-Security"));
+}
+
+// ---------------------------------------------------------------------------
+// Egress limits (1 test)
+// ---------------------------------------------------------------------------
+
+#[tokio::test(start_paused = true)]
+async fn location_egress_limit_paces_only_that_location() {
+    let archives = tempfile::tempdir().unwrap();
+    let thumbs = tempfile::tempdir().unwrap();
+    fs::write(archives.path().join("big.bin"), vec![0u8; 3000]).unwrap();
+    fs::write(thumbs.path().join("t.png"), vec![0u8; 3000]).unwrap();
+    let searcher = FileSearcher::builder()
+        .location("/archives")
+        .egress_limit(ByteSize(1000))
+        .root(archives.path())
+        .location("/thumbnails")
+        .root(thumbs.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+
+    let fetch = |path: &'static str| {
+        let searcher = searcher.clone();
+        async move {
+            let start = tokio::time::Instant::now();
+            let resp = handle_request(make_request("GET", path), searcher, None, localhost())
+                .await
+                .unwrap();
+            assert_eq!(body_string(resp).await.len(), 3000);
+            start.elapsed().as_secs()
+        }
+    };
+
+    assert_eq!(fetch("/thumbnails/t.png").await, 0);
+    // One second of burst, then 2000 bytes at 1000 B/s.
+    assert_eq!(fetch("/archives/big.bin").await, 2);
+}
+
+// ---------------------------------------------------------------------------
+// Download names (4 tests)
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn download_query_sets_attachment_disposition() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("report.pdf"), b"%PDF").unwrap();
+    let searcher = Arc::new(FileSearcher::builder().root(dir.path()).build().unwrap());
+    let get = |uri: &'static str| {
+        let searcher = searcher.clone();
+        async move {
+            handle_request(make_request("GET", uri), searcher, None, localhost())
+                .await
+                .unwrap()
+        }
+    };
+
+    let resp = get("/report.pdf").await;
+    assert!(resp.headers().get("Content-Disposition").is_none());
+    let resp = get("/report.pdf?download").await;
+    assert_eq!(
+        resp.headers()["Content-Disposition"],
+        "attachment; filename=\"report.pdf\"; filename*=UTF-8''report.pdf"
+    );
+    let resp = get("/report.pdf?filename=Q3%20summary.pdf").await;
+    assert_eq!(
+        resp.headers()["Content-Disposition"],
+        "attachment; filename=\"Q3 summary.pdf\"; filename*=UTF-8''Q3%20summary.pdf"
+    );
+    assert_eq!(body_string(resp).await, "%PDF");
+}
+
+#[tokio::test]
+async fn location_forces_attachment_for_listed_extensions() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("page.HTML"), b"<script></script>").unwrap();
+    fs::write(dir.path().join("a+b.png"), b"png").unwrap();
+    let searcher = FileSearcher::builder()
+        .location("/uploads")
+        .attachment_extensions(["html", ".svg"])
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+
+    let req = make_request("GET", "/uploads/page.HTML");
+    let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+    assert_eq!(
+        resp.headers()["Content-Disposition"],
+        "attachment; filename=\"page.HTML\"; filename*=UTF-8''page.HTML"
+    );
+    let req = make_request("GET", "/uploads/a+b.png");
+    let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+    assert!(resp.headers().get("Content-Disposition").is_none());
+    let req = make_request("GET", "/uploads/a+b.png?download");
+    let resp = handle_request(req, searcher, None, localhost()).await.unwrap();
+    assert_eq!(
+        resp.headers()["Content-Disposition"],
+        "attachment; filename=\"a+b.png\"; filename*=UTF-8''a+b.png"
+    );
+}
+
+#[tokio::test]
+async fn extension_rules_override_location_defaults() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("report.pdf"), b"%PDF-1.7 report").unwrap();
+    fs::write(dir.path().join("logo.svg"), b"<svg>").unwrap();
+    fs::write(dir.path().join("notes.txt"), b"too large").unwrap();
+    let pdf = ExtensionRule {
+        max_size: Some(ByteSize(1024)),
+        max_age_secs: Some(3600),
+        disposition: Some(Disposition::Attachment),
+    };
+    let svg = ExtensionRule {
+        disposition: Some(Disposition::Inline),
+        ..Default::default()
+    };
+    let searcher = FileSearcher::builder()
+        .location("/docs")
+        .max_file_size(5)
+        .attachment_extensions(["svg"])
+        .cache_control(CacheControlConfig {
+            max_age_secs: 60,
+            ..Default::default()
+        })
+        .extension_rule("PDF", pdf)
+        .extension_rule(".svg", svg)
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let get = |uri: &'static str| {
+        let searcher = searcher.clone();
+        async move {
+            handle_request(make_request("GET", uri), searcher, None, localhost())
+                .await
+                .unwrap()
+        }
+    };
+
+    let resp = get("/docs/report.pdf").await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["Cache-Control"], "public, max-age=3600");
+    let disposition = resp.headers()["Content-Disposition"].to_str().unwrap();
+    assert!(disposition.starts_with("attachment;"), "{disposition}");
+
+    let resp = get("/docs/logo.svg").await;
+    assert_eq!(resp.headers()["Cache-Control"], "public, max-age=60");
+    assert!(resp.headers().get("Content-Disposition").is_none());
+
+    let resp = get("/docs/notes.txt").await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn magic_bytes_catch_mislabeled_files() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("avatar.png"), b"<html><script>").unwrap();
+    fs::write(dir.path().join("real.png"), b"\x89PNG\r\n\x1A\n....").unwrap();
+    let searcher = FileSearcher::builder()
+        .location("/strict")
+        .magic_bytes(MagicBytes::Refuse)
+        .root(dir.path())
+        .location("/lenient")
+        .magic_bytes(MagicBytes::Download)
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let get = |uri: &'static str| {
+        let searcher = searcher.clone();
+        async move {
+            handle_request(make_request("GET", uri), searcher, None, localhost())
+                .await
+                .unwrap()
+        }
+    };
+
+    let resp = get("/strict/avatar.png").await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = get("/strict/real.png").await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["Content-Type"], "image/png");
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body, &b"\x89PNG\r\n\x1A\n...."[..]);
+
+    let resp = get("/lenient/avatar.png").await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["Content-Type"], "application/octet-stream");
+    let disposition = resp.headers()["Content-Disposition"].to_str().unwrap();
+    assert!(disposition.starts_with("attachment;"), "{disposition}");
+    assert_eq!(body_string(resp).await, "<html><script>");
+}
+
+// ---------------------------------------------------------------------------
+// Byte ranges (2 tests)
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn serves_single_and_multipart_ranges() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("digits.txt"), b"0123456789").unwrap();
+    let searcher = Arc::new(FileSearcher::builder().root(dir.path()).build().unwrap());
+    let get = |range: &'static str| {
+        let searcher = searcher.clone();
+        async move {
+            let mut req = make_request("GET", "/digits.txt");
+            req.headers_mut().insert("Range", range.parse().unwrap());
+            handle_request(req, searcher, None, localhost()).await.unwrap()
+        }
+    };
+
+    let resp = get("bytes=2-4").await;
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(resp.headers()["Accept-Ranges"], "bytes");
+    assert_eq!(resp.headers()["Content-Range"], "bytes 2-4/10");
+    assert_eq!(body_string(resp).await, "234");
+
+    let resp = get("bytes=0-1,-2").await;
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    let content_type = resp.headers()["Content-Type"].to_str().unwrap().to_string();
+    let boundary = content_type
+        .strip_prefix("multipart/byteranges; boundary=")
+        .unwrap();
+    let length: usize = resp.headers()["Content-Length"].to_str().unwrap().parse().unwrap();
+    let body = body_string(resp).await;
+    assert_eq!(body.len(), length);
+    assert_eq!(
+        body,
+        format!(
+            "\r\n--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+             \r\n--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n89\
+             \r\n--{boundary}--\r\n"
+        )
+    );
+
+    let resp = get("bytes=20-").await;
+    assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(resp.headers()["Content-Range"], "bytes */10");
+    assert_eq!(get("bytes=5-1").await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn location_range_policy_disables_and_caps_ranges() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("digits.txt"), b"0123456789").unwrap();
+    let searcher = FileSearcher::builder()
+        .location("/icons")
+        .ranges(RangesConfig {
+            enabled: false,
+            ..Default::default()
+        })
+        .root(dir.path())
+        .location("/media")
+        .ranges(RangesConfig {
+            max_ranges: 2,
+            max_size: ByteSize(4),
+            ..Default::default()
+        })
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let get = |uri: &'static str, range: &'static str| {
+        let mut req = make_request("GET", uri);
+        req.headers_mut().insert("Range", range.parse().unwrap());
+        let searcher = searcher.clone();
+        async move { handle_request(req, searcher, None, localhost()).await.unwrap() }
+    };
+
+    let resp = get("/icons/digits.txt", "bytes=2-4").await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["Accept-Ranges"], "none");
+    assert_eq!(body_string(resp).await, "0123456789");
+
+    let resp = get("/media/digits.txt", "bytes=0-1,-2").await;
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(resp.headers()["Accept-Ranges"], "bytes");
+    let resp = get("/media/digits.txt", "bytes=0-0,2-2,4-4").await;
+    assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(resp.headers()["Content-Range"], "bytes */10");
+    let resp = get("/media/digits.txt", "bytes=0-4").await;
+    assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+}
+
+// ---------------------------------------------------------------------------
+// Conditional requests (3 tests)
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn evaluates_preconditions_against_validators() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("digits.txt"), b"0123456789").unwrap();
+    let searcher = Arc::new(FileSearcher::builder().root(dir.path()).build().unwrap());
+    let get = |headers: Vec<(&'static str, String)>| {
+        let mut req = make_request("GET", "/digits.txt");
+        for (name, value) in headers {
+            req.headers_mut().insert(name, value.parse().unwrap());
+        }
+        let searcher = searcher.clone();
+        async move { handle_request(req, searcher, None, localhost()).await.unwrap() }
+    };
+
+    let resp = get(vec![]).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let etag = resp.headers()["ETag"].to_str().unwrap().to_string();
+    let modified = resp.headers()["Last-Modified"].to_str().unwrap().to_string();
+    let stale = "Thu, 01 Jan 1970 00:00:00 GMT".to_string();
+
+    let resp = get(vec![("If-None-Match", etag.clone())]).await;
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(resp.headers()["ETag"], etag.as_str());
+    assert_eq!(body_string(resp).await, "");
+    let resp = get(vec![("If-Modified-Since", modified.clone())]).await;
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+    let resp = get(vec![("If-Match", "\"other\"".into())]).await;
+    assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+    let resp = get(vec![("If-Unmodified-Since", stale.clone())]).await;
+    assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+    // The default ETag is weak, so it never satisfies If-Match.
+    assert!(etag.starts_with("W/"));
+    let resp = get(vec![("If-Match", etag.clone())]).await;
+    assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+    // If-Match takes precedence over If-Unmodified-Since.
+    let both = vec![
+        ("If-Match", "*".into()),
+        ("If-Unmodified-Since", stale.clone()),
+    ];
+    assert_eq!(get(both).await.status(), StatusCode::OK);
+
+    // A weak ETag in If-Range sends the whole file.
+    let range = |if_range: String| vec![("Range", "bytes=0-1".into()), ("If-Range", if_range)];
+    assert_eq!(get(range(etag.clone())).await.status(), StatusCode::OK);
+    let strong = etag.trim_start_matches("W/").to_string();
+    assert_eq!(get(range(strong)).await.status(), StatusCode::OK);
+    assert_eq!(get(range(modified)).await.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(get(range(stale)).await.status(), StatusCode::OK);
+}
+
+#[cfg(feature = "digest")]
+#[tokio::test]
+async fn etag_mode_per_location() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("digits.txt"), b"0123456789").unwrap();
+    let searcher = Arc::new(
+        FileSearcher::builder()
+            .location("/hashed")
+            .root(dir.path())
+            .etag(EtagMode::Hash)
+            .location("/plain")
+            .root(dir.path())
+            .etag(EtagMode::Off)
+            .build()
+            .unwrap(),
+    );
+    let get = |path: &'static str, condition: Option<(&'static str, String)>| {
+        let mut req = make_request("GET", path);
+        if let Some((name, value)) = condition {
+            req.headers_mut().insert(name, value.parse().unwrap());
+        }
+        let searcher = searcher.clone();
+        async move { handle_request(req, searcher, None, localhost()).await.unwrap() }
+    };
+
+    let resp = get("/hashed/digits.txt", None).await;
+    let etag = "\"hNiYd/DUBB77a/kaFvAkjy/Vc+avBcGflr7bn4gveII=\"";
+    assert_eq!(resp.headers()["ETag"], etag);
+    // Content-hash ETags do not turn on digest headers.
+    assert!(resp.headers().get("Repr-Digest").is_none());
+    let resp = get("/hashed/digits.txt", Some(("If-None-Match", etag.into()))).await;
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+    let resp = get("/plain/digits.txt", None).await;
+    assert!(resp.headers().get("ETag").is_none());
+    let modified = resp.headers()["Last-Modified"].to_str().unwrap().to_string();
+    let resp = get("/plain/digits.txt", Some(("If-None-Match", etag.into()))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = get("/plain/digits.txt", Some(("If-Modified-Since", modified))).await;
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    assert!(resp.headers().get("ETag").is_none());
+}
+
+#[tokio::test]
+async fn cache_control_per_location() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("app.3f9a1c2e.js"), b"js").unwrap();
+    fs::write(dir.path().join("index.html"), b"<html>").unwrap();
+    let searcher = FileSearcher::builder()
+        .location("/assets")
+        .root(dir.path())
+        .cache_control(CacheControlConfig {
+            fingerprint: r"\.[0-9a-f]{8,}\.".into(),
+            max_age_secs: 60,
+        })
+        .location("/plain")
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let get = |path: &'static str, headers: Vec<(&'static str, String)>| {
+        let mut req = make_request("GET", path);
+        for (name, value) in headers {
+            req.headers_mut().insert(name, value.parse().unwrap());
+        }
+        let searcher = searcher.clone();
+        async move { handle_request(req, searcher, None, localhost()).await.unwrap() }
+    };
+
+    let immutable = "public, max-age=31536000, immutable";
+    let resp = get("/assets/app.3f9a1c2e.js", vec![]).await;
+    assert_eq!(resp.headers()["Cache-Control"], immutable);
+    let etag = resp.headers()["ETag"].to_str().unwrap().to_string();
+    // Revalidations carry it too, so caches keep their policy.
+    let resp = get("/assets/app.3f9a1c2e.js", vec![("If-None-Match", etag)]).await;
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(resp.headers()["Cache-Control"], immutable);
+
+    let resp = get("/assets/index.html", vec![]).await;
+    assert_eq!(resp.headers()["Cache-Control"], "public, max-age=60");
+    let resp = get("/plain/app.3f9a1c2e.js", vec![]).await;
+    assert!(resp.headers().get("Cache-Control").is_none());
+}
+
+// ---------------------------------------------------------------------------
+// Precompressed sidecars (1 test)
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn serves_current_sidecars() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("app.js"), "console.log(1);".repeat(10)).unwrap();
+    fs::write(dir.path().join("app.js.br"), b"brotli bytes").unwrap();
+    fs::write(dir.path().join("app.js.gz"), b"gzip bytes").unwrap();
+    // Only sidecars carrying the file's mtime are current.
+    let modified = fs::metadata(dir.path().join("app.js"))
+        .unwrap()
+        .modified()
+        .unwrap();
+    let stamp = |name: &str, time: SystemTime| {
+        let file = fs::File::options()
+            .write(true)
+            .open(dir.path().join(name))
+            .unwrap();
+        file.set_modified(time).unwrap();
+    };
+    stamp("app.js.br", modified);
+    stamp("app.js.gz", modified - Duration::from_secs(60));
+    let searcher = Arc::new(
+        FileSearcher::builder()
+            .server(ServerConfig {
+                precompress: PrecompressConfig {
+                    enabled: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .location("/")
+            .root(dir.path())
+            .build()
+            .unwrap(),
+    );
+    let get = |headers: &[(&'static str, &str)]| {
+        let mut req = make_request("GET", "/app.js");
+        for (name, value) in headers {
+            req.headers_mut().insert(*name, value.parse().unwrap());
+        }
+        let searcher = searcher.clone();
+        async move { handle_request(req, searcher, None, localhost()).await.unwrap() }
+    };
+
+    let resp = get(&[("Accept-Encoding", "gzip, br")]).await;
+    assert_eq!(resp.headers()["Content-Encoding"], "br");
+    assert_eq!(resp.headers()["Content-Length"], "12");
+    assert_eq!(resp.headers()["Vary"], "Accept-Encoding");
+    assert_eq!(resp.headers()["Content-Type"], "text/javascript");
+    let since = resp.headers()["Last-Modified"].clone();
+    let etag = resp.headers()["ETag"].to_str().unwrap().to_string();
+    assert!(etag.ends_with("-br\""));
+    assert_eq!(body_string(resp).await, "brotli bytes");
+    // So does the 304 standing in for it.
+    let since = since.to_str().unwrap();
+    let resp = get(&[("Accept-Encoding", "br"), ("If-Modified-Since", since)]).await;
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(resp.headers()["Vary"], "accept-encoding");
+    assert_eq!(resp.headers()["ETag"], etag.as_str());
+
+    // The sidecar's ETag is not the file's: neither validates the other.
+    let resp = get(&[("Accept-Encoding", "br"), ("If-None-Match", &etag)]).await;
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    let resp = get(&[("If-None-Match", &etag)]).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let identity = resp.headers()["ETag"].to_str().unwrap().to_string();
+    assert_ne!(identity, etag);
+    let resp = get(&[("Accept-Encoding", "br"), ("If-None-Match", &identity)]).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["Content-Encoding"], "br");
+
+    // The stale .gz is never served.
+    let resp = get(&[("Accept-Encoding", "gzip")]).await;
+    assert!(resp.headers().get("Content-Encoding").is_none());
+    assert_eq!(resp.headers()["Vary"], "Accept-Encoding");
+    assert_eq!(resp.headers()["Content-Length"], "150");
+
+    // Ranges come from the file itself.
+    let resp = get(&[("Accept-Encoding", "br"), ("Range", "bytes=0-6")]).await;
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    assert!(resp.headers().get("Content-Encoding").is_none());
+    assert_eq!(body_string(resp).await, "console");
+}
+
+// ---------------------------------------------------------------------------
+// Directory archives (2 tests)
+// ---------------------------------------------------------------------------
+
+#[cfg(feature = "archive")]
+#[tokio::test]
+async fn archive_merges_roots_and_applies_filters() {
+    use std::io::Read as _;
+
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir1.path().join("album/sub")).unwrap();
+    fs::create_dir_all(dir2.path().join("album")).unwrap();
+    fs::write(dir1.path().join("album/a.jpg"), b"first").unwrap();
+    fs::write(dir1.path().join("album/sub/b.jpg"), b"nested").unwrap();
+    fs::write(dir1.path().join("album/notes.txt"), b"filtered").unwrap();
+    fs::write(dir1.path().join("album/.hidden.jpg"), b"dotfile").unwrap();
+    fs::write(dir2.path().join("album/a.jpg"), b"second").unwrap();
+    fs::write(dir2.path().join("album/c.jpg"), b"only in dir2").unwrap();
+
+    let searcher = FileSearcher::builder()
+        .server(ServerConfig {
+            archive: ArchiveConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .root_with_extensions(dir1.path(), vec!["jpg".to_string()])
+        .root(dir2.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let fetch = |uri: &'static str| {
+        let searcher = searcher.clone();
+        async move {
+            handle_request(make_request("GET", uri), searcher, None, localhost())
+                .await
+                .unwrap()
+        }
+    };
+
+    let resp = fetch("/album?archive=tar").await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["Content-Type"], "application/x-tar");
+    assert_eq!(resp.headers()["Content-Disposition"], "attachment; filename=\"album.tar\"");
+    let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+    let mut tar = tar::Archive::new(&bytes[..]);
+    let mut files = Vec::new();
+    for entry in tar.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let mut body = String::new();
+        entry.read_to_string(&mut body).unwrap();
+        files.push((entry.path().unwrap().display().to_string(), body));
+    }
+    assert_eq!(
+        files,
+        [
+            ("album/a.jpg".to_string(), "first".to_string()),
+            ("album/c.jpg".to_string(), "only in dir2".to_string()),
+            ("album/sub/b.jpg".to_string(), "nested".to_string()),
+        ]
+    );
+
+    let resp = fetch("/album/?archive=zip").await;
+    assert_eq!(resp.headers()["Content-Type"], "application/zip");
+    let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+    let mut zip = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+    assert_eq!(zip.len(), 3);
+    let mut body = String::new();
+    zip.by_name("album/sub/b.jpg").unwrap().read_to_string(&mut body).unwrap();
+    assert_eq!(body, "nested");
+
+    assert_eq!(fetch("/album?archive=rar").await.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(fetch("/missing?archive=tar").await.status(), StatusCode::NOT_FOUND);
+    assert_eq!(fetch("/../etc?archive=tar").await.status(), StatusCode::NOT_FOUND);
+}
+
+#[cfg(feature = "archive")]
+#[tokio::test]
+async fn archive_applies_location_policies() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("root");
+    fs::create_dir_all(root.join("album/.well-known")).unwrap();
+    fs::write(root.join("album/a.txt"), b"clean").unwrap();
+    fs::write(root.join("album/.env"), b"secrets").unwrap();
+    fs::write(root.join("album/.well-known/security.txt"), b"contact").unwrap();
+    fs::write(root.join("album/dump.bin"), b"over the bin limit").unwrap();
+    fs::write(root.join("album/eicar.txt"), b"X5O!P%@AP EICAR test").unwrap();
+    fs::write(root.join("album/avatar.png"), b"<html><script>").unwrap();
+    let quarantine = dir.path().join("quarantine");
+    let (address, _) = fake_clamd().await;
+    let config = Config {
+        server: ServerConfig {
+            archive: ArchiveConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            antivirus: AntivirusConfig {
+                enabled: true,
+                address,
+                ..Default::default()
+            },
+            quarantine: QuarantineConfig {
+                enabled: true,
+                dir: quarantine.clone(),
+                action: QuarantineAction::Move,
+            },
+            ..Default::default()
+        },
+        locations: vec![LocationConfig {
+            prefix: "/".into(),
+            paths: vec![SearchPath {
+                root: root.clone(),
+                ..Default::default()
+            }],
+            magic_bytes: MagicBytes::Refuse,
+            hidden_files: HiddenFiles::Allowlist(vec![".well-known".into()]),
+            extension_rules: [(
+                "bin".to_string(),
+                ExtensionRule {
+                    max_size: Some(ByteSize(4)),
+                    ..Default::default()
+                },
+            )]
+            .into(),
+            ..Default::default()
+        }],
+        vhosts: Vec::new(),
+    };
+    let searcher = Arc::new(FileSearcher::new(&config));
+
+    let req = make_request("GET", "/album?archive=tar");
+    let resp = handle_request(req, searcher, None, localhost())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+    let mut tar = tar::Archive::new(&bytes[..]);
+    let names: Vec<_> = tar
+        .entries()
+        .unwrap()
+        .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+        .collect();
+    assert_eq!(names, ["album/.well-known/security.txt", "album/a.txt"]);
+    assert_eq!(fs::read_dir(&quarantine).unwrap().count(), 2);
+}
+
+// ---------------------------------------------------------------------------
+// Container members (1 test)
+// ---------------------------------------------------------------------------
+
+#[cfg(feature = "archive")]
+#[tokio::test]
+async fn serves_members_of_zip_and_tar_containers() {
+    use std::io::Write as _;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut zip = zip::ZipWriter::new(fs::File::create(dir.path().join("foo.zip")).unwrap());
+    zip.start_file("img/a.png", zip::write::SimpleFileOptions::default()).unwrap();
+    zip.write_all(b"zipped png").unwrap();
+    zip.finish().unwrap();
+
+    let mut tar = tar::Builder::new(fs::File::create(dir.path().join("bar.tar")).unwrap());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(10);
+    header.set_mode(0o644);
+    tar.append_data(&mut header, "notes.txt", &b"tarred txt"[..]).unwrap();
+    tar.finish().unwrap();
+
+    let build = |enabled: bool| {
+        let searcher = FileSearcher::builder()
+            .location("/bundles")
+            .search_archives(enabled)
+            .root(dir.path())
+            .build()
+            .unwrap();
+        Arc::new(searcher)
+    };
+    let searcher = build(true);
+    let get = |uri: &'static str, searcher: Arc<FileSearcher>| async move {
+        handle_request(make_request("GET", uri), searcher, None, localhost()).await.unwrap()
+    };
+
+    let resp = get("/bundles/foo/img/a.png", searcher.clone()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["Content-Type"], "image/png");
+    assert_eq!(resp.headers()["Content-Length"], "10");
+    assert_eq!(body_string(resp).await, "zipped png");
+
+    let resp = get("/bundles/bar/notes.txt", searcher.clone()).await;
+    assert_eq!(body_string(resp).await, "tarred txt");
+
+    let resp = get("/bundles/foo/missing.png", searcher).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = get("/bundles/foo/img/a.png", build(false)).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+// ---------------------------------------------------------------------------
+// S3-style API (2 tests)
+// ---------------------------------------------------------------------------
+
+#[cfg(feature = "s3-api")]
+#[tokio::test]
+async fn s3_api_lists_and_reads_public_locations() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("2024/jan")).unwrap();
+    fs::write(dir.path().join("2024/a.jpg"), b"aaa").unwrap();
+    fs::write(dir.path().join("2024/jan/b.jpg"), b"bb").unwrap();
+    fs::write(dir.path().join("top.jpg"), b"t").unwrap();
+
+    let searcher = FileSearcher::builder()
+        .server(ServerConfig {
+            s3_api: S3ApiConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .location("/photos/raw")
+        .root(dir.path())
+        .location("/private")
+        .auth(LocationAuth::ApiKey {
+            keys: vec!["k1".into()],
+            header: "X-Api-Key".into(),
+            query_param: "api_key".into(),
+        })
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let request = |method: &str, uri: &str| {
+        let req = make_request(method, uri);
+        let searcher = searcher.clone();
+        async move { handle_request(req, searcher, None, localhost()).await.unwrap() }
+    };
+    let list = |query: &str| {
+        let resp = request("GET", &format!("/_s3/photos-raw?list-type=2{query}"));
+        async move { body_string(resp.await).await }
+    };
+
+    let resp = request("GET", "/_s3/").await;
+    assert_eq!(resp.headers()["Content-Type"], "application/xml");
+    let body = body_string(resp).await;
+    assert!(body.contains("<Name>photos-raw</Name>"));
+    assert!(!body.contains("private"));
+
+    let body = list("").await;
+    assert!(body.contains("<KeyCount>3</KeyCount>"));
+    assert!(body.contains("<Key>2024/jan/b.jpg</Key>"));
+    assert!(body.contains("<Size>2</Size>"));
+
+    let body = list("&prefix=2024/&delimiter=/").await;
+    assert!(body.contains("<Contents><Key>2024/a.jpg</Key>"));
+    assert!(body.contains("<CommonPrefixes><Prefix>2024/jan/</Prefix></CommonPrefixes>"));
+    assert!(!body.contains("top.jpg"));
+
+    let body = list("&max-keys=1").await;
+    assert!(body.contains("<IsTruncated>true</IsTruncated>"));
+    assert!(body.contains("<NextContinuationToken>2024/a.jpg</NextContinuationToken>"));
+    let body = list("&continuation-token=2024/jan/b.jpg").await;
+    assert!(body.contains("<KeyCount>1</KeyCount><MaxKeys>1000</MaxKeys>"));
+    assert!(body.contains("<Key>top.jpg</Key>"));
+
+    let resp = request("GET", "/_s3/photos-raw/2024/jan/b.jpg").await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(body_string(resp).await, "bb");
+    let resp = request("HEAD", "/_s3/photos-raw/top.jpg").await;
+    assert_eq!(resp.headers()["Content-Length"], "1");
+
+    let resp = request("GET", "/_s3/photos-raw/nope.jpg").await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert!(body_string(resp).await.contains("<Code>NoSuchKey</Code>"));
+    let body = body_string(request("GET", "/_s3/private/top.jpg").await).await;
+    assert!(body.contains("<Code>NoSuchBucket</Code>"));
+    let resp = request("PUT", "/_s3/photos-raw/new.jpg").await;
+    assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+}
+
+#[cfg(feature = "s3-api")]
+#[tokio::test]
+async fn s3_api_keeps_nested_auth_locations_out_of_buckets() {
+    let public = tempfile::tempdir().unwrap();
+    let private = tempfile::tempdir().unwrap();
+    fs::write(public.path().join("open.txt"), b"open").unwrap();
+    fs::write(private.path().join("secret.txt"), b"classified").unwrap();
+
+    let searcher = FileSearcher::builder()
+        .server(ServerConfig {
+            s3_api: S3ApiConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .location("/")
+        .root(public.path())
+        .location("/private")
+        .auth(LocationAuth::ApiKey {
+            keys: vec!["k1".into()],
+            header: "X-Api-Key".into(),
+            query_param: String::new(),
+        })
+        .root(private.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let request = |uri: &'static str| {
+        let searcher = searcher.clone();
+        async move {
+            let req = make_request("GET", uri);
+            handle_request(req, searcher, None, localhost())
+                .await
+                .unwrap()
+        }
+    };
+
+    let body = body_string(request("/_s3/root?list-type=2").await).await;
+    assert!(body.contains("<Key>open.txt</Key>"));
+    for uri in [
+        "/_s3/root?list-type=2&prefix=private/",
+        "/_s3/root/private/secret.txt",
+    ] {
+        let resp = request(uri).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN, "{uri}");
+        let body = body_string(resp).await;
+        assert!(body.contains("<Code>AccessDenied</Code>"));
+        assert!(!body.contains("<Contents>") && !body.contains("classified"));
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Image resizing (1 test)
+// ---------------------------------------------------------------------------
+
+#[cfg(feature = "images")]
+#[tokio::test]
+async fn resizes_images_and_caches_results() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = tempfile::tempdir().unwrap();
+    image::RgbImage::new(400, 200).save(dir.path().join("pic.png")).unwrap();
+    fs::write(dir.path().join("notes.txt"), b"text").unwrap();
+
+    let searcher = FileSearcher::builder()
+        .location("/thumbs")
+        .images(ImageConfig {
+            enabled: true,
+            cache_dir: cache.path().to_path_buf(),
+            max_dimension: 1000,
+            max_concurrent_encodes: 2,
+        })
+        .root(dir.path())
+        .location("/plain")
+        .root(dir.path())
+        .build()
+        .unwrap();
+    let searcher = Arc::new(searcher);
+    let get = |uri: &'static str| {
+        let searcher = searcher.clone();
+        async move {
+            handle_request(make_request("GET", uri), searcher, None, localhost())
+                .await
+                .unwrap()
+        }
+    };
+
+    for _ in 0..2 {
+        let resp = get("/thumbs/pic.png?w=100").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["Content-Type"], "image/png");
+        let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+        let img = image::load_from_memory(&bytes).unwrap();
+        assert_eq!((img.width(), img.height()), (100, 50));
+    }
+    assert_eq!(fs::read_dir(cache.path()).unwrap().count(), 1);
+
+    let resp = get("/thumbs/pic.png?format=jpeg").await;
+    assert_eq!(resp.headers()["Content-Type"], "image/jpeg");
+    let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(image::load_from_memory(&bytes).unwrap().width(), 400);
+
+    let mut req = make_request("GET", "/thumbs/pic.png?w=100&format=auto");
+    req.headers_mut().insert("Accept", "image/webp,*/*".parse().unwrap());
+    let resp = handle_request(req, searcher.clone(), None, localhost()).await.unwrap();
+    assert_eq!(resp.headers()["Content-Type"], "image/webp");
+    assert_eq!(resp.headers()["Vary"], "Accept");
+    let resp = get("/thumbs/pic.png?w=100&format=auto").await;
+    assert_eq!(resp.headers()["Content-Type"], "image/png");
+    assert_eq!(fs::read_dir(cache.path()).unwrap().count(), 4);
+
+    assert_eq!(get("/thumbs/pic.png?w=5000").await.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        get("/thumbs/notes.txt?w=10").await.status(),
+        StatusCode::UNSUPPORTED_MEDIA_TYPE
+    );
+    let resp = get("/plain/pic.png?w=100").await;
+    let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(image::load_from_memory(&bytes).unwrap().width(), 400);
 }